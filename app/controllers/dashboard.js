@@ -42,7 +42,7 @@ export default Ember.Controller.extend({
                     this.store.push(this.store.normalize('version', version)));
 
                 this.get('myFeed').pushObjects(versions);
-                this.set('hasMore', data.meta.more);
+                this.set('hasMore', !!data.meta.next_page);
             }).finally(() => {
                 this.set('loadingMore', false);
             });
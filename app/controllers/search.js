@@ -13,6 +13,10 @@ export default Ember.Controller.extend(PaginationMixin, {
     totalItems: computed.readOnly('model.meta.total'),
 
     currentSortBy: computed('sort', function() {
-        return (this.get('sort') === 'downloads') ? 'Downloads' : 'Relevance';
+        switch (this.get('sort')) {
+            case 'downloads': return 'Downloads';
+            case 'dependents': return 'Dependents';
+            default: return 'Relevance';
+        }
     }),
 });
@@ -4,6 +4,7 @@ import Ember from 'ember';
 export default DS.Model.extend({
     name: DS.attr('string'),
     downloads: DS.attr('number'),
+    dependents_cnt: DS.attr('number'),
     created_at: DS.attr('date'),
     updated_at: DS.attr('date'),
     max_version: DS.attr('string'),
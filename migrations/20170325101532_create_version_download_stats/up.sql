@@ -0,0 +1,8 @@
+CREATE TABLE version_download_stats (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    date DATE NOT NULL DEFAULT CURRENT_DATE,
+    user_agent VARCHAR NOT NULL,
+    country VARCHAR NOT NULL,
+    downloads INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (version_id, date, user_agent, country)
+);
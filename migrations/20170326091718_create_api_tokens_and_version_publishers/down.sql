@@ -0,0 +1,4 @@
+ALTER TABLE versions
+    DROP COLUMN published_by,
+    DROP COLUMN published_with_token_id;
+DROP TABLE api_tokens;
@@ -0,0 +1,13 @@
+CREATE TABLE api_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token VARCHAR NOT NULL UNIQUE DEFAULT random_string(32),
+    name VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+CREATE INDEX ON api_tokens (user_id);
+
+ALTER TABLE versions
+    ADD COLUMN published_by INTEGER REFERENCES users (id),
+    ADD COLUMN published_with_token_id INTEGER REFERENCES api_tokens (id);
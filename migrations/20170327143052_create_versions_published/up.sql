@@ -0,0 +1,13 @@
+CREATE TABLE versions_published (
+    crate_name VARCHAR NOT NULL,
+    num VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (crate_name, num)
+);
+CREATE UNIQUE INDEX versions_published_canon_crate_name_num
+    ON versions_published (canon_crate_name(crate_name), num);
+
+INSERT INTO versions_published (crate_name, num, created_at)
+    SELECT crates.name, versions.num, versions.created_at
+      FROM versions
+     INNER JOIN crates ON crates.id = versions.crate_id;
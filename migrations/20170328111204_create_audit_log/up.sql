@@ -0,0 +1,10 @@
+CREATE TABLE audit_log (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    action VARCHAR NOT NULL,
+    crate_name VARCHAR,
+    details VARCHAR NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX ON audit_log (created_at);
+CREATE INDEX ON audit_log (crate_name);
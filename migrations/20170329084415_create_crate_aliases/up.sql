@@ -0,0 +1,6 @@
+CREATE TABLE crate_aliases (
+    alias VARCHAR PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX ON crate_aliases (canon_crate_name(alias));
@@ -0,0 +1,36 @@
+ALTER TABLE crates ADD COLUMN readme_in_search BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Reduces a README to the text a reader would see, dropping code blocks,
+-- link targets and inline HTML which only add noise to the search index.
+CREATE FUNCTION markdown_to_text(md TEXT) RETURNS TEXT AS $$
+    SELECT regexp_replace(
+               regexp_replace(
+                   regexp_replace(md, '```.*?```', ' ', 'g'),
+                   '\]\([^)]*\)', ']', 'g'),
+               '<[^>]*>', ' ', 'g')
+$$ LANGUAGE SQL IMMUTABLE;
+
+-- The README is only part of the search document of crates which opted in.
+-- Existing documents are updated the next time their crate is touched.
+CREATE OR REPLACE FUNCTION trigger_crates_name_search() RETURNS trigger AS $$
+DECLARE kws TEXT;
+begin
+  SELECT array_to_string(array_agg(keyword), ',') INTO kws
+    FROM keywords INNER JOIN crates_keywords
+    ON keywords.id = crates_keywords.keyword_id
+    WHERE crates_keywords.crate_id = new.id;
+  new.textsearchable_index_col :=
+     setweight(to_tsvector('pg_catalog.english',
+                           coalesce(new.name, '')), 'A') ||
+     setweight(to_tsvector('pg_catalog.english',
+                           coalesce(kws, '')), 'B') ||
+     setweight(to_tsvector('pg_catalog.english',
+                           coalesce(new.description, '')), 'C');
+  IF new.readme_in_search THEN
+    new.textsearchable_index_col := new.textsearchable_index_col ||
+       setweight(to_tsvector('pg_catalog.english',
+                             markdown_to_text(coalesce(new.readme, ''))), 'D');
+  END IF;
+  return new;
+end
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,2 @@
+DROP INDEX index_crates_name_trgm;
+DROP EXTENSION IF EXISTS pg_trgm;
@@ -0,0 +1,3 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX index_crates_name_trgm ON crates
+    USING gin (canon_crate_name(name) gin_trgm_ops);
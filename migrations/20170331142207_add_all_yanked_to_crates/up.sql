@@ -0,0 +1,10 @@
+ALTER TABLE crates ADD COLUMN all_yanked BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Backfilling shouldn't make every crate look like it was just updated
+ALTER TABLE crates DISABLE TRIGGER trigger_crates_set_updated_at;
+UPDATE crates SET all_yanked = TRUE
+ WHERE EXISTS (SELECT 1 FROM versions WHERE versions.crate_id = crates.id)
+   AND NOT EXISTS (SELECT 1 FROM versions
+                    WHERE versions.crate_id = crates.id
+                      AND NOT versions.yanked);
+ALTER TABLE crates ENABLE TRIGGER trigger_crates_set_updated_at;
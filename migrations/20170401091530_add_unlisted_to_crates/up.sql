@@ -0,0 +1 @@
+ALTER TABLE crates ADD COLUMN unlisted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+DROP TABLE crate_permissions;
+ALTER TABLE crates DROP COLUMN private;
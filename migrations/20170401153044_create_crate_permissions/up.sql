@@ -0,0 +1,10 @@
+ALTER TABLE crates ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE crate_permissions (
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    owner_id INTEGER NOT NULL,
+    owner_kind INTEGER NOT NULL,
+    created_by INTEGER NOT NULL REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (crate_id, owner_id, owner_kind)
+);
@@ -0,0 +1,18 @@
+CREATE TABLE organizations (
+    id SERIAL PRIMARY KEY,
+    login VARCHAR NOT NULL,
+    name VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX index_organizations_login ON organizations (lower(login));
+
+CREATE TABLE organization_members (
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    role INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (organization_id, user_id)
+);
+
+ALTER TABLE api_tokens ADD COLUMN organization_id INTEGER
+    REFERENCES organizations (id) ON DELETE CASCADE;
@@ -0,0 +1 @@
+ALTER TABLE crate_owners DROP COLUMN role;
@@ -0,0 +1 @@
+ALTER TABLE crate_owners ADD COLUMN role INTEGER NOT NULL DEFAULT 0;
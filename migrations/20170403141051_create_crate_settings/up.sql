@@ -0,0 +1,6 @@
+CREATE TABLE crate_settings (
+    crate_id INTEGER PRIMARY KEY REFERENCES crates (id) ON DELETE CASCADE,
+    deprecation_notice VARCHAR,
+    webhook_urls VARCHAR[] NOT NULL DEFAULT '{}',
+    download_thresholds INTEGER[] NOT NULL DEFAULT '{}'
+);
@@ -0,0 +1,2 @@
+DROP TABLE download_alerts;
+ALTER TABLE crate_settings DROP COLUMN spike_alerts;
@@ -0,0 +1,18 @@
+ALTER TABLE crate_settings ADD COLUMN spike_alerts BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE download_alerts (
+    id SERIAL PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    version_id INTEGER REFERENCES versions (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL,
+    threshold INTEGER,
+    date DATE,
+    downloads INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+-- Each milestone is only reported once, and each version can spike at most
+-- once a day
+CREATE UNIQUE INDEX index_download_alerts_milestone
+    ON download_alerts (crate_id, threshold) WHERE kind = 'milestone';
+CREATE UNIQUE INDEX index_download_alerts_spike
+    ON download_alerts (version_id, date) WHERE kind = 'spike';
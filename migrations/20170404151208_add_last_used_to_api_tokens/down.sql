@@ -0,0 +1,3 @@
+ALTER TABLE api_tokens DROP COLUMN last_used_at;
+ALTER TABLE api_tokens DROP COLUMN last_used_ip;
+ALTER TABLE api_tokens DROP COLUMN last_used_user_agent;
@@ -0,0 +1,3 @@
+ALTER TABLE api_tokens ADD COLUMN last_used_at TIMESTAMP;
+ALTER TABLE api_tokens ADD COLUMN last_used_ip VARCHAR;
+ALTER TABLE api_tokens ADD COLUMN last_used_user_agent VARCHAR;
@@ -0,0 +1,2 @@
+DROP TABLE quarantined_versions;
+ALTER TABLE versions DROP COLUMN quarantined;
@@ -0,0 +1,8 @@
+ALTER TABLE versions ADD COLUMN quarantined BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE quarantined_versions (
+    version_id INTEGER PRIMARY KEY REFERENCES versions (id) ON DELETE CASCADE,
+    reasons VARCHAR[] NOT NULL,
+    index_entry VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,11 @@
+CREATE TABLE version_findings (
+    id SERIAL PRIMARY KEY,
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    scanner VARCHAR NOT NULL,
+    severity VARCHAR NOT NULL,
+    path VARCHAR,
+    message VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX version_findings_version_id ON version_findings (version_id);
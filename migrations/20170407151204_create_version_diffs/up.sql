@@ -0,0 +1,9 @@
+CREATE TABLE version_diffs (
+    from_version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    to_version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    patches VARCHAR,
+    requested_at TIMESTAMP NOT NULL DEFAULT now(),
+    computed_at TIMESTAMP,
+    PRIMARY KEY (from_version_id, to_version_id)
+);
+CREATE INDEX version_diffs_pending ON version_diffs (requested_at) WHERE patches IS NULL;
@@ -0,0 +1,9 @@
+CREATE TABLE version_signatures (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL,
+    signature VARCHAR NOT NULL,
+    key_fingerprint VARCHAR,
+    uploaded_by INTEGER REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (version_id, kind)
+);
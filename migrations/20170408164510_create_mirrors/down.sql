@@ -0,0 +1,2 @@
+DROP TABLE mirror_downloads;
+DROP TABLE mirrors;
@@ -0,0 +1,13 @@
+CREATE TABLE mirrors (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    token VARCHAR NOT NULL UNIQUE DEFAULT random_string(32),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+CREATE TABLE mirror_downloads (
+    mirror_id INTEGER NOT NULL REFERENCES mirrors (id) ON DELETE CASCADE,
+    date DATE NOT NULL DEFAULT CURRENT_DATE,
+    downloads INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (mirror_id, date)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE registry_stats (
+    date DATE PRIMARY KEY DEFAULT CURRENT_DATE,
+    total_crates BIGINT NOT NULL,
+    total_versions BIGINT NOT NULL,
+    total_downloads BIGINT NOT NULL,
+    total_users BIGINT NOT NULL,
+    new_users BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
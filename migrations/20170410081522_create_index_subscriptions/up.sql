@@ -0,0 +1,9 @@
+CREATE TABLE index_subscriptions (
+    id SERIAL PRIMARY KEY,
+    mirror_id INTEGER NOT NULL REFERENCES mirrors (id) ON DELETE CASCADE,
+    callback VARCHAR NOT NULL,
+    secret VARCHAR NOT NULL,
+    last_notified_seq INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    UNIQUE (mirror_id, callback)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE api_token_usage (
+    token_id INTEGER NOT NULL REFERENCES api_tokens (id) ON DELETE CASCADE,
+    date DATE NOT NULL DEFAULT CURRENT_DATE,
+    endpoint VARCHAR NOT NULL,
+    requests INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (token_id, date, endpoint)
+);
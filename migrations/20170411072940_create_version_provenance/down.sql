@@ -0,0 +1,2 @@
+DROP TABLE version_provenance;
+ALTER TABLE versions DROP COLUMN verified_build;
@@ -0,0 +1,10 @@
+ALTER TABLE versions ADD COLUMN verified_build BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE version_provenance (
+    version_id INTEGER PRIMARY KEY REFERENCES versions (id) ON DELETE CASCADE,
+    ci_provider VARCHAR NOT NULL,
+    build_url VARCHAR NOT NULL,
+    commit_sha VARCHAR NOT NULL,
+    verified BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
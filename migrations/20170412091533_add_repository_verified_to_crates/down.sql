@@ -0,0 +1,3 @@
+DROP TRIGGER trigger_reset_repository_verified ON crates;
+DROP FUNCTION reset_repository_verified();
+ALTER TABLE crates DROP COLUMN repository_verified;
@@ -0,0 +1,15 @@
+ALTER TABLE crates ADD COLUMN repository_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- A verification is only good for the repository it was done for
+CREATE FUNCTION reset_repository_verified() RETURNS trigger AS $$
+BEGIN
+    IF NEW.repository IS DISTINCT FROM OLD.repository THEN
+        NEW.repository_verified := FALSE;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_reset_repository_verified
+BEFORE UPDATE ON crates
+FOR EACH ROW EXECUTE PROCEDURE reset_repository_verified();
@@ -0,0 +1,4 @@
+DROP TRIGGER trigger_set_crate_first_published ON versions;
+DROP FUNCTION set_crate_first_published();
+ALTER TABLE crates DROP COLUMN first_published_at,
+                   DROP COLUMN first_published_by;
@@ -0,0 +1,26 @@
+-- Unlike `crates.created_at`, these always describe the crate's first version
+ALTER TABLE crates ADD COLUMN first_published_at TIMESTAMP,
+                   ADD COLUMN first_published_by INTEGER REFERENCES users (id);
+
+-- Backfilling shouldn't make every crate look like it was just updated
+ALTER TABLE crates DISABLE TRIGGER trigger_crates_set_updated_at;
+UPDATE crates SET first_published_at = first.created_at,
+                  first_published_by = first.published_by
+  FROM (SELECT DISTINCT ON (crate_id) crate_id, created_at, published_by
+          FROM versions
+         ORDER BY crate_id, created_at, id) first
+ WHERE first.crate_id = crates.id;
+ALTER TABLE crates ENABLE TRIGGER trigger_crates_set_updated_at;
+
+CREATE FUNCTION set_crate_first_published() RETURNS trigger AS $$
+BEGIN
+    UPDATE crates SET first_published_at = NEW.created_at,
+                      first_published_by = NEW.published_by
+     WHERE id = NEW.crate_id AND first_published_at IS NULL;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trigger_set_crate_first_published
+AFTER INSERT ON versions
+FOR EACH ROW EXECUTE PROCEDURE set_crate_first_published();
@@ -0,0 +1,10 @@
+CREATE TABLE crate_tag_snapshots (
+    id SERIAL PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    keywords VARCHAR[] NOT NULL,
+    categories VARCHAR[] NOT NULL,
+    replaced_by_version_id INTEGER REFERENCES versions (id) ON DELETE SET NULL,
+    replaced_by INTEGER NOT NULL REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX ON crate_tag_snapshots (crate_id, id);
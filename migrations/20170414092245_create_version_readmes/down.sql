@@ -0,0 +1,2 @@
+DROP TABLE readme_backfills;
+DROP TABLE version_readmes;
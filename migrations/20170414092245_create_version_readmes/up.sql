@@ -0,0 +1,18 @@
+CREATE TABLE version_readmes (
+    version_id INTEGER PRIMARY KEY REFERENCES versions (id) ON DELETE CASCADE,
+    path VARCHAR NOT NULL,
+    html TEXT NOT NULL,
+    rendered_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE TABLE readme_backfills (
+    id SERIAL PRIMARY KEY,
+    requested_by INTEGER NOT NULL REFERENCES users (id),
+    batch_size INTEGER NOT NULL,
+    delay_ms INTEGER NOT NULL,
+    last_version_id INTEGER NOT NULL DEFAULT 0,
+    rendered INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    finished_at TIMESTAMP
+);
@@ -0,0 +1,6 @@
+CREATE TABLE version_downloads_shadow (
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    date DATE NOT NULL DEFAULT CURRENT_DATE,
+    downloads INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (version_id, date)
+);
@@ -0,0 +1,2 @@
+DROP TABLE owner_approvals;
+ALTER TABLE crate_settings DROP COLUMN require_owner_approval;
@@ -0,0 +1,14 @@
+ALTER TABLE crate_settings ADD COLUMN require_owner_approval BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE owner_approvals (
+    id SERIAL PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    action VARCHAR NOT NULL,
+    login VARCHAR NOT NULL,
+    requested_by INTEGER NOT NULL REFERENCES users (id),
+    state VARCHAR NOT NULL DEFAULT 'pending',
+    resolved_by INTEGER REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMP
+);
+CREATE INDEX index_owner_approvals_crate_id_state ON owner_approvals (crate_id, state);
@@ -0,0 +1,19 @@
+ALTER TABLE crates ADD COLUMN dependents_cnt INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX index_crates_dependents_cnt ON crates (dependents_cnt);
+
+UPDATE crates SET dependents_cnt = counts.cnt
+  FROM (
+    SELECT dependencies.crate_id, COUNT(DISTINCT versions.crate_id) cnt
+      FROM dependencies
+     INNER JOIN (
+        SELECT id, crate_id, row_number() OVER (
+            PARTITION BY crate_id
+            ORDER BY to_semver_no_prerelease(num) DESC NULLS LAST, id DESC
+        ) rn
+          FROM versions
+         WHERE NOT yanked
+    ) versions ON versions.id = dependencies.version_id
+     WHERE rn = 1
+     GROUP BY dependencies.crate_id
+  ) counts
+ WHERE crates.id = counts.crate_id;
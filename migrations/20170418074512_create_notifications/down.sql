@@ -0,0 +1,2 @@
+DROP TABLE notifications;
+ALTER TABLE follows DROP COLUMN legal_notifications;
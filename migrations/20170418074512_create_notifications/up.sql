@@ -0,0 +1,11 @@
+ALTER TABLE follows ADD COLUMN legal_notifications BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE notifications (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL,
+    details VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX index_notifications_user_id ON notifications (user_id);
@@ -0,0 +1,8 @@
+CREATE TABLE category_stats (
+    category_id INTEGER NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+    month DATE NOT NULL,
+    crates BIGINT NOT NULL,
+    downloads BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (category_id, month)
+);
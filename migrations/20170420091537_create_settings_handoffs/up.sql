@@ -0,0 +1,7 @@
+CREATE TABLE settings_handoffs (
+    crate_id INTEGER PRIMARY KEY REFERENCES crates (id) ON DELETE CASCADE,
+    settings VARCHAR[] NOT NULL,
+    reason VARCHAR NOT NULL,
+    started_by INTEGER NOT NULL REFERENCES users (id),
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
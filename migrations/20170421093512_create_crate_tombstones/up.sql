@@ -0,0 +1,9 @@
+CREATE TABLE crate_tombstones (
+    id SERIAL PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    previous_owners VARCHAR[] NOT NULL,
+    new_owner VARCHAR NOT NULL,
+    moved_to VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX crate_tombstones_crate_id ON crate_tombstones (crate_id);
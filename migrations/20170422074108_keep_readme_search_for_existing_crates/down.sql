@@ -0,0 +1,3 @@
+ALTER TABLE crates DISABLE TRIGGER trigger_crates_set_updated_at;
+UPDATE crates SET readme_in_search = FALSE;
+ALTER TABLE crates ENABLE TRIGGER trigger_crates_set_updated_at;
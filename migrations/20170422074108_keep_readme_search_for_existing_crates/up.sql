@@ -0,0 +1,9 @@
+-- Crates published before READMEs became opt-in keep them in their search
+-- document. Updating the flag also rebuilds the document through
+-- `trigger_crates_name_search`, dropping the code blocks, link targets and
+-- HTML from READMEs. Only crates published from now on have to opt in.
+
+-- Backfilling shouldn't make every crate look like it was just updated
+ALTER TABLE crates DISABLE TRIGGER trigger_crates_set_updated_at;
+UPDATE crates SET readme_in_search = TRUE;
+ALTER TABLE crates ENABLE TRIGGER trigger_crates_set_updated_at;
@@ -0,0 +1,3 @@
+DROP INDEX index_download_alerts_undelivered;
+ALTER TABLE download_alerts DROP COLUMN delivered;
+ALTER TABLE crate_settings DROP COLUMN email_alerts;
@@ -0,0 +1,6 @@
+ALTER TABLE crate_settings ADD COLUMN email_alerts BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Alerts raised so far were posted as they were raised, they're not retried
+ALTER TABLE download_alerts ADD COLUMN delivered BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE download_alerts ALTER COLUMN delivered SET DEFAULT FALSE;
+CREATE INDEX index_download_alerts_undelivered ON download_alerts (id) WHERE NOT delivered;
@@ -0,0 +1,9 @@
+ALTER TABLE versions ADD COLUMN license VARCHAR;
+
+-- Versions published so far are assumed to have the crate's current license,
+-- without making every version look like it was just updated
+ALTER TABLE versions DISABLE TRIGGER trigger_versions_set_updated_at;
+UPDATE versions SET license = crates.license
+  FROM crates
+ WHERE crates.id = versions.crate_id;
+ALTER TABLE versions ENABLE TRIGGER trigger_versions_set_updated_at;
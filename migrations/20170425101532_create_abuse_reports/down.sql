@@ -0,0 +1 @@
+DROP TABLE abuse_reports;
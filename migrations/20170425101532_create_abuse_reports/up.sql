@@ -0,0 +1,12 @@
+CREATE TABLE abuse_reports (
+    id SERIAL PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates (id) ON DELETE CASCADE,
+    reported_by INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reason VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMP,
+    resolved_by INTEGER REFERENCES users (id)
+);
+-- Each user can only have one open report about a crate
+CREATE UNIQUE INDEX index_abuse_reports_open
+    ON abuse_reports (crate_id, reported_by) WHERE resolved_at IS NULL;
@@ -0,0 +1,20 @@
+CREATE TABLE version_yanks (
+    id SERIAL PRIMARY KEY,
+    version_id INTEGER NOT NULL REFERENCES versions (id) ON DELETE CASCADE,
+    yanked BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX index_version_yanks_version_id ON version_yanks (version_id);
+-- Yanks so far are only in the audit log, bulk yanks listing the versions
+-- they matched after the range, like `< 0.9.3: 0.9.0, 0.9.2`
+INSERT INTO version_yanks (version_id, yanked, created_at)
+    SELECT versions.id, audit_log.action <> 'unyank_version', audit_log.created_at
+      FROM audit_log
+      JOIN crates ON crates.name = audit_log.crate_name
+      JOIN versions ON versions.crate_id = crates.id
+     WHERE (audit_log.action IN ('yank_version', 'unyank_version')
+            AND versions.num = audit_log.details)
+        OR (audit_log.action = 'yank_versions'
+            AND versions.num = ANY(string_to_array(split_part(audit_log.details, ': ', 2),
+                                                   ', ')))
+     ORDER BY audit_log.id;
@@ -0,0 +1,3 @@
+DROP INDEX index_users_gh_login_trgm;
+DROP INDEX index_teams_login_trgm;
+DROP INDEX index_keywords_keyword_trgm;
@@ -0,0 +1,7 @@
+-- For `GET /search`, which matches these by their name containing the query
+CREATE INDEX index_users_gh_login_trgm ON users
+    USING gin (lower(gh_login) gin_trgm_ops);
+CREATE INDEX index_teams_login_trgm ON teams
+    USING gin (lower(login) gin_trgm_ops);
+CREATE INDEX index_keywords_keyword_trgm ON keywords
+    USING gin (lower(keyword) gin_trgm_ops);
@@ -0,0 +1,758 @@
+//! Endpoints for registry administrators.
+//!
+//! Admins are configured by GitHub id (see `Config::admin_gh_ids`) and every
+//! action taken through these endpoints is recorded in the audit log. Besides
+//! acting on crates, they review recent publishes, quarantined versions and
+//! the abuse reports filed by users (see the `report` module).
+
+use std::collections::HashMap;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::pg::upsert::*;
+use diesel::prelude::*;
+use rustc_serialize::json;
+use time::Timespec;
+
+use app::{App, RequestApp};
+use audit::{self, AuditEntry, EncodableAuditEntry};
+use db::{InstrumentedConnection, RequestTransaction};
+use dependency;
+use git;
+use handoff;
+use krate::canon_crate_name;
+use mirror::{EncodableMirror, Mirror};
+use owner::{CrateOwner, Owner, OwnerKind, OwnerRole};
+use quarantine::{self, QuarantinedVersion};
+use report::{AbuseReport, EncodableAbuseReport};
+use schema::*;
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, ChainError, decode_body, human, internal};
+use version::{record_yanks, update_all_yanked, EncodableVersion};
+use {Crate, User, Version};
+
+/// How many days back `GET /admin/publishes?flagged=1` looks for flagged
+/// publishes.
+const FLAGGED_PUBLISHES_DAYS: i32 = 30;
+
+/// Whether `user` is one of the registry administrators.
+pub fn is_admin(app: &App, user: &User) -> bool {
+    app.config.admin_gh_ids.contains(&user.gh_id)
+}
+
+/// Returns the current user if they are an admin, and an error otherwise.
+pub fn require_admin(req: &Request) -> CargoResult<User> {
+    let user = req.user()?;
+    if !is_admin(req.app(), user) {
+        return Err(human("must be an admin to perform that action"))
+    }
+    Ok(user.clone())
+}
+
+#[derive(RustcDecodable)]
+struct Reservation {
+    name: String,
+    reason: String,
+}
+
+/// Handles the `PUT /admin/reserved_names` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "name": "core", "reason": "confusable with the standard library" }
+/// ```
+pub fn reserve_name(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let reservation: Reservation = decode_body(req)?;
+    let name = reservation.name.trim();
+    if !Crate::valid_name(name) {
+        return Err(human(&format_args!("`{}` is not a valid crate name", name)))
+    }
+
+    let conn = req.db_conn()?;
+    conn.transaction(|| {
+        if Crate::by_name(name).first::<Crate>(&*conn).optional()?.is_some() {
+            return Err(human(&format_args!("crate `{}` already exists, transfer it \
+                                            instead of reserving its name", name)))
+        }
+
+        let reserved = diesel::insert(
+                &ReservedName { name: name }.on_conflict_do_nothing()
+            ).into(reserved_crate_names::table)
+            .execute(&*conn)?;
+        if reserved == 0 {
+            return Err(human(&format_args!("`{}` is already reserved", name)))
+        }
+        audit::record(&conn, admin.id, "reserve_name", Some(name), &reservation.reason)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `DELETE /admin/reserved_names/:name` route.
+pub fn unreserve_name(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let name = req.params()["name"].clone();
+    let conn = req.db_conn()?;
+    conn.transaction(|| {
+        let target = reserved_crate_names::table
+            .filter(canon_crate_name(reserved_crate_names::name).eq(canon_crate_name(&name)));
+        if diesel::delete(target).execute(&*conn)? == 0 {
+            return Err(human(&format_args!("`{}` is not reserved", name)))
+        }
+        audit::record(&conn, admin.id, "unreserve_name", Some(&name), "")
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(Insertable)]
+#[table_name="reserved_crate_names"]
+struct ReservedName<'a> {
+    name: &'a str,
+}
+
+#[derive(RustcDecodable)]
+struct Transfer {
+    owner: String,
+    reason: String,
+    moved_to: Option<String>,
+}
+
+/// What's left on the page of a crate after it was transferred, pointing
+/// its visitors to where the previous maintainers' project went if it moved.
+#[derive(Clone, Debug, Queryable)]
+pub struct Tombstone {
+    pub id: i32,
+    pub crate_id: i32,
+    pub previous_owners: Vec<String>,
+    pub new_owner: String,
+    pub moved_to: Option<String>,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="crate_tombstones"]
+struct NewTombstone<'a> {
+    crate_id: i32,
+    previous_owners: Vec<String>,
+    new_owner: &'a str,
+    moved_to: Option<&'a str>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct EncodableTombstone {
+    pub previous_owners: Vec<String>,
+    pub new_owner: String,
+    /// The crate the previous maintainers' project continues as
+    pub moved_to: Option<String>,
+    pub transferred_at: String,
+}
+
+impl Tombstone {
+    /// The tombstone of the crate's latest transfer, if it was transferred.
+    pub fn latest(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<Option<Tombstone>> {
+        let tombstone = crate_tombstones::table
+            .filter(crate_tombstones::crate_id.eq(crate_id))
+            .order(crate_tombstones::id.desc())
+            .first(conn)
+            .optional()?;
+        Ok(tombstone)
+    }
+
+    pub fn encodable(self) -> EncodableTombstone {
+        EncodableTombstone {
+            previous_owners: self.previous_owners,
+            new_owner: self.new_owner,
+            moved_to: self.moved_to,
+            transferred_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+/// Handles the `PUT /admin/crates/:crate_id/transfer` route.
+///
+/// Hands an abandoned crate over to a new maintainer: every current owner
+/// is removed and `owner` becomes the sole owner. The reason is mandatory
+/// and should reference the request which was reviewed under the crate
+/// transfer policy.
+///
+/// A tombstone is left on the crate's page telling who it was transferred
+/// from and, when the previous maintainers carry on under another name, the
+/// crate named by `moved_to` which their users are sent to.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// {
+///     "owner": "new-maintainer",
+///     "reason": "rust-lang/crates.io#1234",
+///     "moved_to": "old-project-legacy"
+/// }
+/// ```
+pub fn transfer(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let transfer: Transfer = decode_body(req)?;
+    if transfer.reason.trim().is_empty() {
+        return Err(human("a reason is required to transfer a crate"))
+    }
+
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(&*conn)?;
+    let new_owner = match Owner::find_by_login(&conn, &transfer.owner)? {
+        Owner::User(user) => user,
+        Owner::Team(..) | Owner::Org(..) => {
+            return Err(human("crates can only be transferred to a user"))
+        }
+    };
+    let moved_to = match transfer.moved_to {
+        Some(ref name) => {
+            let moved_to = Crate::by_name(name).first::<Crate>(&*conn).optional()?
+                .ok_or_else(|| human(&format_args!("crate `{}` does not exist", name)))?;
+            if moved_to.id == krate.id {
+                return Err(human("a crate can't be moved to itself"))
+            }
+            Some(moved_to.name)
+        }
+        None => None,
+    };
+
+    conn.transaction(|| {
+        let previous = krate.owners(&conn)?
+            .iter()
+            .map(|owner| owner.login().to_string())
+            .collect::<Vec<_>>();
+
+        diesel::update(crate_owners::table.filter(crate_owners::crate_id.eq(krate.id)))
+            .set(crate_owners::deleted.eq(true))
+            .execute(&*conn)?;
+        let crate_owner = CrateOwner {
+            crate_id: krate.id,
+            owner_id: new_owner.id,
+            created_by: admin.id,
+            owner_kind: OwnerKind::User as i32,
+            role: OwnerRole::Owner as i32,
+        };
+        diesel::insert(&crate_owner.on_conflict(
+                crate_owners::table.primary_key(),
+                do_update().set((crate_owners::deleted.eq(false),
+                                 crate_owners::role.eq(OwnerRole::Owner as i32))),
+            )).into(crate_owners::table)
+            .execute(&*conn)?;
+
+        let tombstone = NewTombstone {
+            crate_id: krate.id,
+            previous_owners: previous.clone(),
+            new_owner: &new_owner.gh_login,
+            moved_to: moved_to.as_ref().map(|s| &s[..]),
+        };
+        diesel::insert(&tombstone).into(crate_tombstones::table)
+            .execute(&*conn)?;
+
+        let mut details = format!("from {} to {}: {}", previous.join(", "),
+                                  new_owner.gh_login, transfer.reason);
+        if let Some(ref moved_to) = moved_to {
+            details.push_str(&format!(" (moved to {})", moved_to));
+        }
+        audit::record(&conn, admin.id, "transfer_crate", Some(&krate.name), &details)?;
+        handoff::start(&conn, &krate, admin.id,
+                       &format!("transferred to {}", new_owner.gh_login))
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(RustcDecodable)]
+struct Alias {
+    alias: String,
+    target: String,
+    reason: String,
+}
+
+/// Handles the `PUT /admin/crate_aliases` route.
+///
+/// Makes requests for the crate `alias` permanently redirect to `target`. This
+/// is the last step when renaming a crate, and the alias can't be published
+/// to afterwards.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "alias": "old-name", "target": "new-name", "reason": "renamed upstream" }
+/// ```
+pub fn add_alias(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let alias: Alias = decode_body(req)?;
+    let name = alias.alias.trim();
+    if !Crate::valid_name(name) {
+        return Err(human(&format_args!("`{}` is not a valid crate name", name)))
+    }
+
+    let conn = req.db_conn()?;
+    conn.transaction(|| {
+        let krate = Crate::by_name(&alias.target).first::<Crate>(&*conn)?;
+        if Crate::by_name(name).first::<Crate>(&*conn).optional()?.is_some() {
+            return Err(human(&format_args!("crate `{}` still exists, only names \
+                                            which are no longer used can be \
+                                            aliases", name)))
+        }
+
+        let added = diesel::insert(
+                &NewAlias { alias: name, crate_id: krate.id }.on_conflict_do_nothing()
+            ).into(crate_aliases::table)
+            .execute(&*conn)?;
+        if added == 0 {
+            return Err(human(&format_args!("`{}` is already an alias", name)))
+        }
+        let details = format!("alias of {}: {}", krate.name, alias.reason);
+        audit::record(&conn, admin.id, "add_alias", Some(name), &details)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `DELETE /admin/crate_aliases/:alias` route.
+pub fn remove_alias(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let name = req.params()["alias"].clone();
+    let conn = req.db_conn()?;
+    conn.transaction(|| {
+        let target = crate_aliases::table
+            .filter(canon_crate_name(crate_aliases::alias).eq(canon_crate_name(&name)));
+        if diesel::delete(target).execute(&*conn)? == 0 {
+            return Err(human(&format_args!("`{}` is not an alias", name)))
+        }
+        audit::record(&conn, admin.id, "remove_alias", Some(&name), "")
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(Insertable)]
+#[table_name="crate_aliases"]
+struct NewAlias<'a> {
+    alias: &'a str,
+    crate_id: i32,
+}
+
+/// Handles the `GET /admin/audit_log` route.
+///
+/// Entries can be limited to a single crate with the `crate` parameter.
+pub fn audit_log(req: &mut Request) -> CargoResult<Response> {
+    require_admin(req)?;
+    let (offset, limit) = req.pagination(20, 100)?;
+    let crate_name = req.query().get("crate").cloned();
+    let conn = req.db_conn()?;
+
+    let mut query = audit_log::table
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .offset(offset)
+        .into_boxed();
+    if let Some(name) = crate_name {
+        query = query.filter(audit_log::crate_name.eq(name));
+    }
+    let entries = query.load::<AuditEntry>(&*conn)?
+        .into_iter()
+        .map(AuditEntry::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { entries: Vec<EncodableAuditEntry> }
+    Ok(req.json(&R { entries: entries }))
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodablePublish {
+    pub version_id: i32,
+    pub crate_name: String,
+    pub num: String,
+    pub published_by: Option<String>,
+    pub created_at: String,
+    /// Why the publish might need a closer look: `new_publisher`,
+    /// `similar_name` and `quarantined`
+    pub flags: Vec<String>,
+    /// The popular crate the name is similar to
+    pub similar_to: Option<String>,
+}
+
+/// Handles the `GET /admin/publishes` route.
+///
+/// Lists the most recent publishes, newest first, along with the reasons they
+/// may be suspicious and whether they are quarantined. Passing `flagged=1`
+/// only lists publishes with at least one flag, out of the publishes of the
+/// last `FLAGGED_PUBLISHES_DAYS`.
+pub fn publishes(req: &mut Request) -> CargoResult<Response> {
+    require_admin(req)?;
+    let (offset, limit) = req.pagination(20, 100)?;
+    let flagged_only = req.query().get("flagged").map(|s| s == "1" || s == "true")
+        .unwrap_or(false);
+
+    // Flagging a publish takes a couple of subqueries, so only the page being
+    // shown is flagged. Listing flagged publishes needs the flags to page
+    // through, which is why it's limited to recent publishes.
+    let (page, paging) = if flagged_only {
+        ("SELECT * FROM versions
+           WHERE created_at > now() - $4::integer * INTERVAL '1 day'",
+         "WHERE new_publisher OR similar_to IS NOT NULL OR quarantined
+          ORDER BY created_at DESC, id DESC
+         OFFSET $5 LIMIT $6")
+    } else {
+        ("SELECT * FROM versions
+           ORDER BY created_at DESC, id DESC
+          OFFSET $4 LIMIT $5",
+         "ORDER BY created_at DESC, id DESC")
+    };
+    let sql = format!("\
+        WITH popular AS (
+            SELECT id, name, downloads FROM crates ORDER BY downloads DESC LIMIT $1
+        ), page AS (
+            {}
+        ), publishes AS (
+            SELECT page.id, page.num, page.created_at, page.quarantined,
+                   crates.name AS crate_name, users.gh_login AS published_by,
+                   COALESCE((SELECT MIN(earlier.created_at)
+                               FROM versions earlier
+                              WHERE earlier.published_by = page.published_by)
+                            > page.created_at - $2::integer * INTERVAL '1 day',
+                            FALSE) AS new_publisher,
+                   (SELECT popular.name
+                      FROM popular
+                     WHERE popular.downloads > crates.downloads
+                       AND similarity(popular.name, crates.name) >= $3::real
+                     ORDER BY similarity(popular.name, crates.name) DESC
+                     LIMIT 1) AS similar_to
+              FROM page
+             INNER JOIN crates ON crates.id = page.crate_id
+              LEFT JOIN users ON users.id = page.published_by
+        )
+        SELECT * FROM publishes
+        {}", page, paging);
+    let tx = req.tx()?;
+    let rows = if flagged_only {
+        tx.query(&sql, &[&quarantine::POPULAR_CRATES, &quarantine::NEW_PUBLISHER_DAYS,
+                         &quarantine::SIMILAR_NAME_THRESHOLD, &FLAGGED_PUBLISHES_DAYS,
+                         &offset, &limit])?
+    } else {
+        tx.query(&sql, &[&quarantine::POPULAR_CRATES, &quarantine::NEW_PUBLISHER_DAYS,
+                         &quarantine::SIMILAR_NAME_THRESHOLD, &offset, &limit])?
+    };
+    let publishes = rows.iter().map(|row| {
+        let similar_to: Option<String> = row.get("similar_to");
+        let mut flags = Vec::new();
+        if row.get::<_, bool>("new_publisher") {
+            flags.push("new_publisher".to_string());
+        }
+        if similar_to.is_some() {
+            flags.push("similar_name".to_string());
+        }
+        if row.get::<_, bool>("quarantined") {
+            flags.push("quarantined".to_string());
+        }
+        EncodablePublish {
+            version_id: row.get("id"),
+            crate_name: row.get("crate_name"),
+            num: row.get("num"),
+            published_by: row.get("published_by"),
+            created_at: ::encode_time(row.get("created_at")),
+            flags: flags,
+            similar_to: similar_to,
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { publishes: Vec<EncodablePublish> }
+    Ok(req.json(&R { publishes: publishes }))
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableQuarantinedVersion {
+    pub version: EncodableVersion,
+    pub reasons: Vec<String>,
+    pub created_at: String,
+}
+
+/// Handles the `GET /admin/quarantine` route.
+///
+/// Lists the versions waiting for review, oldest first.
+pub fn quarantined(req: &mut Request) -> CargoResult<Response> {
+    require_admin(req)?;
+    let (offset, limit) = req.pagination(20, 100)?;
+    let conn = req.db_conn()?;
+
+    let held = quarantined_versions::table
+        .order(quarantined_versions::created_at.asc())
+        .limit(limit)
+        .offset(offset)
+        .load::<QuarantinedVersion>(&*conn)?;
+    let version_ids = held.iter().map(|h| h.version_id).collect::<Vec<_>>();
+    let versions = versions::table
+        .filter(versions::id.eq_any(version_ids))
+        .load::<Version>(&*conn)?;
+    let crate_ids = versions.iter().map(|v| v.crate_id).collect::<Vec<_>>();
+    let crate_names = crates::table
+        .filter(crates::id.eq_any(crate_ids))
+        .select((crates::id, crates::name))
+        .load::<(i32, String)>(&*conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut versions = versions.into_iter()
+        .map(|v| (v.id, v))
+        .collect::<HashMap<_, _>>();
+
+    let versions = held.into_iter().filter_map(|held| {
+        let version = match versions.remove(&held.version_id) {
+            Some(version) => version,
+            None => return None,
+        };
+        crate_names.get(&version.crate_id).map(|crate_name| {
+            EncodableQuarantinedVersion {
+                version: version.encodable(crate_name),
+                reasons: held.reasons,
+                created_at: ::encode_time(held.created_at),
+            }
+        })
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { versions: Vec<EncodableQuarantinedVersion> }
+    Ok(req.json(&R { versions: versions }))
+}
+
+fn held_version(req: &Request, conn: &InstrumentedConnection)
+                -> CargoResult<(QuarantinedVersion, Version, Crate)> {
+    let version_id = req.params()["version_id"].parse::<i32>().map_err(|_| {
+        human("invalid version id")
+    })?;
+    let held = quarantined_versions::table.find(version_id)
+        .first::<QuarantinedVersion>(conn)
+        .optional()?
+        .ok_or_else(|| human("that version is not waiting for review"))?;
+    let version = versions::table.find(version_id).first::<Version>(conn)?;
+    let krate = Crate::all().filter(crates::id.eq(version.crate_id)).first::<Crate>(conn)?;
+    Ok((held, version, krate))
+}
+
+/// Handles the `PUT /admin/quarantine/:version_id/approve` route.
+///
+/// Releases the version: it's added to the index and can be downloaded.
+pub fn approve_version(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let conn = req.db_conn()?;
+    let (held, version, krate) = held_version(req, &conn)?;
+    let entry: git::Crate = json::decode(&held.index_entry).map_err(|_| {
+        internal(&format_args!("invalid index entry for version {}", version.id))
+    })?;
+
+    conn.transaction(|| {
+        diesel::update(versions::table.find(version.id))
+            .set(versions::quarantined.eq(false))
+            .execute(&*conn)?;
+        diesel::delete(quarantined_versions::table.find(version.id)).execute(&*conn)?;
+        let details = format!("version {}", version.num);
+        audit::record(&conn, admin.id, "approve_version", Some(&krate.name), &details)?;
+        git::add_crate(&**req.app(), &entry).chain_error(|| {
+            internal(&format_args!("could not add crate `{}` to the git repo", krate.name))
+        })
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(RustcDecodable)]
+struct Rejection {
+    reason: String,
+}
+
+/// Handles the `DELETE /admin/quarantine/:version_id` route.
+///
+/// The version stays out of the index and is yanked, so that it's clear to
+/// its owners that it won't become available.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "reason": "contains a cryptocurrency miner" }
+/// ```
+pub fn reject_version(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let rejection: Rejection = decode_body(req)?;
+    if rejection.reason.trim().is_empty() {
+        return Err(human("a reason is required to reject a version"))
+    }
+    let conn = req.db_conn()?;
+    let (_, version, krate) = held_version(req, &conn)?;
+
+    conn.transaction(|| {
+        let dependencies_before = dependency::newest_dependencies(&conn, krate.id)?;
+        diesel::update(versions::table.find(version.id))
+            .set(versions::yanked.eq(true))
+            .execute(&*conn)?;
+        record_yanks(&conn, &[version.id], true)?;
+        update_all_yanked(&conn, krate.id)?;
+        let dependencies_after = dependency::newest_dependencies(&conn, krate.id)?;
+        dependency::update_dependents_cnt(&conn, &dependencies_before, &dependencies_after)?;
+        diesel::delete(quarantined_versions::table.find(version.id)).execute(&*conn)?;
+        let details = format!("version {}: {}", version.num, rejection.reason);
+        audit::record(&conn, admin.id, "reject_version", Some(&krate.name), &details)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `GET /admin/reports` route.
+///
+/// Lists the open abuse reports, oldest first.
+pub fn reports(req: &mut Request) -> CargoResult<Response> {
+    require_admin(req)?;
+    let (offset, limit) = req.pagination(20, 100)?;
+    let conn = req.db_conn()?;
+
+    let open = abuse_reports::table
+        .filter(abuse_reports::resolved_at.is_null())
+        .order((abuse_reports::created_at.asc(), abuse_reports::id.asc()))
+        .limit(limit)
+        .offset(offset)
+        .load::<AbuseReport>(&*conn)?;
+    let crate_ids = open.iter().map(|r| r.crate_id).collect::<Vec<_>>();
+    let crate_names = crates::table
+        .filter(crates::id.eq_any(crate_ids))
+        .select((crates::id, crates::name))
+        .load::<(i32, String)>(&*conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let user_ids = open.iter().map(|r| r.reported_by).collect::<Vec<_>>();
+    let logins = users::table
+        .filter(users::id.eq_any(user_ids))
+        .select((users::id, users::gh_login))
+        .load::<(i32, String)>(&*conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let reports = open.into_iter().filter_map(|report| {
+        let crate_name = crate_names.get(&report.crate_id).cloned();
+        let login = logins.get(&report.reported_by).cloned();
+        match (crate_name, login) {
+            (Some(crate_name), Some(login)) => Some(report.encodable(crate_name, login)),
+            _ => None,
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { reports: Vec<EncodableAbuseReport> }
+    Ok(req.json(&R { reports: reports }))
+}
+
+#[derive(RustcDecodable)]
+struct Resolution {
+    resolution: String,
+}
+
+/// Handles the `PUT /admin/reports/:report_id/resolve` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "resolution": "transferred the crate, see rust-lang/crates.io#1234" }
+/// ```
+pub fn resolve_report(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let resolution: Resolution = decode_body(req)?;
+    if resolution.resolution.trim().is_empty() {
+        return Err(human("a resolution is required to resolve a report"))
+    }
+    let id = req.params()["report_id"].parse::<i32>().map_err(|_| {
+        human("invalid report id")
+    })?;
+    let conn = req.db_conn()?;
+
+    conn.transaction(|| {
+        let report = AbuseReport::find_open(&conn, id)?
+            .ok_or_else(|| human("that report is not open"))?;
+        diesel::update(abuse_reports::table.find(id))
+            .set((abuse_reports::resolved_at.eq(Some(::now())),
+                  abuse_reports::resolved_by.eq(Some(admin.id))))
+            .execute(&*conn)?;
+        let krate = Crate::all().filter(crates::id.eq(report.crate_id)).first::<Crate>(&*conn)?;
+        let details = format!("report {}: {}", id, resolution.resolution.trim());
+        audit::record(&conn, admin.id, "resolve_report", Some(&krate.name), &details)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(RustcDecodable)]
+struct NewMirror {
+    name: String,
+}
+
+/// Handles the `GET /admin/mirrors` route.
+pub fn mirrors(req: &mut Request) -> CargoResult<Response> {
+    require_admin(req)?;
+    let mirrors = Mirror::all(req.tx()?)?.into_iter()
+        .map(|(mirror, downloads)| mirror.encodable(downloads, false))
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { mirrors: Vec<EncodableMirror> }
+    Ok(req.json(&R { mirrors: mirrors }))
+}
+
+/// Handles the `PUT /admin/mirrors` route.
+///
+/// The response includes the token the mirror has to send in the
+/// `X-Mirror-Token` header, which is never shown again.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "name": "mirror.example.com" }
+/// ```
+pub fn register_mirror(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let new: NewMirror = decode_body(req)?;
+    let name = new.name.trim();
+    if name.is_empty() {
+        return Err(human("mirrors need a name"))
+    }
+    let mirror = Mirror::create(req.tx()?, name)?;
+    audit::record(&*req.db_conn()?, admin.id, "register_mirror", None, name)?;
+
+    #[derive(RustcEncodable)]
+    struct R { mirror: EncodableMirror }
+    Ok(req.json(&R { mirror: mirror.encodable(0, true) }))
+}
+
+/// Handles the `DELETE /admin/mirrors/:mirror_id` route.
+///
+/// Downloads with the mirror's token fail from then on, rather than being
+/// counted as regular downloads.
+pub fn revoke_mirror(req: &mut Request) -> CargoResult<Response> {
+    let admin = require_admin(req)?;
+    let id = req.params()["mirror_id"].parse::<i32>().map_err(|_| {
+        human("invalid mirror id")
+    })?;
+    let name = Mirror::revoke(req.tx()?, id)?;
+    audit::record(&*req.db_conn()?, admin.id, "revoke_mirror", None, &name)?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
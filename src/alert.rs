@@ -0,0 +1,362 @@
+//! Download alerts for crate owners.
+//!
+//! Owners pick download milestones and can opt into spike detection through
+//! the crate settings. The `update-downloads` job evaluates both after it
+//! rolls up the download counts and records every alert in `download_alerts`
+//! so that it's only raised once. The alerts are then posted to the crate's
+//! webhooks and, if the owners asked for it with `email_alerts`, emailed to
+//! them. Owners can also list past alerts with
+//! `GET /crates/:crate_id/download_alerts`.
+
+use std::env;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use curl;
+use curl::easy::Easy;
+use pg::GenericConnection;
+use pg::rows::Row;
+use rustc_serialize::json;
+use time::Timespec;
+use url::{form_urlencoded, Url};
+
+use db::RequestTransaction;
+use http;
+use owner::{request_rights, OwnerKind, Rights};
+use util::{RequestUtils, CargoResult, check_public_host, human};
+use {Crate, Model};
+
+/// A version's downloads on a day have to be this many times its average
+/// daily downloads over the previous two weeks to count as a spike.
+const SPIKE_FACTOR: i32 = 10;
+/// Days with fewer downloads than this are never reported as spikes, so that
+/// barely used crates don't raise alerts for a handful of downloads.
+const SPIKE_MIN_DOWNLOADS: i32 = 100;
+/// Alerts which couldn't be delivered are retried for this many days.
+const RETRY_DAYS: i32 = 3;
+
+pub struct DownloadAlert {
+    pub id: i32,
+    pub crate_id: i32,
+    pub version_id: Option<i32>,
+    /// Either "milestone" or "spike"
+    pub kind: String,
+    /// The milestone which was crossed, for milestone alerts
+    pub threshold: Option<i32>,
+    /// The day the downloads spiked, for spike alerts
+    pub date: Option<NaiveDate>,
+    /// The total downloads of the crate for milestone alerts, and the
+    /// downloads of the version that day for spike alerts
+    pub downloads: i32,
+    pub created_at: Timespec,
+    /// Whether the alert reached every webhook and owner it was meant for,
+    /// see `deliver_pending`
+    pub delivered: bool,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableDownloadAlert {
+    pub id: i32,
+    pub kind: String,
+    pub version_id: Option<i32>,
+    pub threshold: Option<i32>,
+    pub date: Option<String>,
+    pub downloads: i32,
+    pub created_at: String,
+}
+
+impl DownloadAlert {
+    pub fn encodable(self) -> EncodableDownloadAlert {
+        EncodableDownloadAlert {
+            id: self.id,
+            kind: self.kind,
+            version_id: self.version_id,
+            threshold: self.threshold,
+            date: self.date.map(|date| date.to_string()),
+            downloads: self.downloads,
+            created_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+impl Model for DownloadAlert {
+    fn from_row(row: &Row) -> DownloadAlert {
+        DownloadAlert {
+            id: row.get("id"),
+            crate_id: row.get("crate_id"),
+            version_id: row.get("version_id"),
+            kind: row.get("kind"),
+            threshold: row.get("threshold"),
+            date: row.get("date"),
+            downloads: row.get("downloads"),
+            created_at: row.get("created_at"),
+            delivered: row.get("delivered"),
+        }
+    }
+
+    fn table_name(_: Option<DownloadAlert>) -> &'static str { "download_alerts" }
+}
+
+/// Records the alerts which weren't raised yet and returns them.
+///
+/// Milestones are reached once a crate's total downloads are at least the
+/// threshold. Spikes are only looked for on the last complete day.
+pub fn evaluate(conn: &GenericConnection) -> CargoResult<Vec<DownloadAlert>> {
+    let rows = conn.query("\
+        INSERT INTO download_alerts (crate_id, kind, threshold, downloads)
+        SELECT crates.id, 'milestone', t.threshold, crates.downloads
+          FROM crate_settings
+         INNER JOIN crates ON crates.id = crate_settings.crate_id
+         CROSS JOIN LATERAL unnest(crate_settings.download_thresholds) AS t (threshold)
+         WHERE crates.downloads >= t.threshold
+            ON CONFLICT DO NOTHING
+        RETURNING *", &[])?;
+    let mut alerts = rows
+        .iter()
+        .map(|row| Model::from_row(&row))
+        .collect::<Vec<DownloadAlert>>();
+
+    let rows = conn.query("\
+        INSERT INTO download_alerts (crate_id, version_id, kind, date, downloads)
+        SELECT versions.crate_id, day.version_id, 'spike', day.date, day.downloads
+          FROM version_downloads day
+         INNER JOIN versions ON versions.id = day.version_id
+         INNER JOIN crate_settings ON crate_settings.crate_id = versions.crate_id
+         WHERE crate_settings.spike_alerts
+           AND day.date = CURRENT_DATE - 1
+           AND day.downloads >= $1
+           AND day.downloads >= $2::integer * GREATEST(1, (
+               SELECT COALESCE(SUM(before.downloads), 0) / 14.0
+                 FROM version_downloads before
+                WHERE before.version_id = day.version_id
+                  AND before.date >= day.date - 14
+                  AND before.date < day.date))
+            ON CONFLICT DO NOTHING
+        RETURNING *", &[&SPIKE_MIN_DOWNLOADS, &SPIKE_FACTOR])?;
+    alerts.extend(rows
+        .iter()
+        .map(|row| Model::from_row(&row)));
+    Ok(alerts)
+}
+
+/// Delivers the alerts which weren't delivered yet, returning how many
+/// were.
+///
+/// An alert is delivered once every webhook of its crate took it and every
+/// owner was emailed, if they asked for it. Until then it's retried every
+/// time this runs, for `RETRY_DAYS`. Webhooks which already took it get it
+/// again, and can tell by its id. Alerts stay available through the API
+/// either way.
+pub fn deliver_pending(conn: &GenericConnection, mailer: Option<&Mailer>) -> CargoResult<usize> {
+    let rows = conn.query("SELECT * FROM download_alerts
+                            WHERE NOT delivered
+                              AND created_at > now() - $1::int4 * INTERVAL '1 day'
+                            ORDER BY id", &[&RETRY_DAYS])?;
+    let alerts = rows.iter()
+        .map(|row| Model::from_row(&row))
+        .collect::<Vec<DownloadAlert>>();
+
+    let mut delivered = 0;
+    for alert in &alerts {
+        match deliver(conn, mailer, alert) {
+            Ok(true) => {
+                conn.execute("UPDATE download_alerts SET delivered = TRUE WHERE id = $1",
+                             &[&alert.id])?;
+                delivered += 1;
+            }
+            Ok(false) => {}
+            Err(e) => warn!("failed to deliver download alert {}: {}", alert.id, e),
+        }
+    }
+    Ok(delivered)
+}
+
+/// Posts an alert to the webhooks of its crate and emails it to the crate's
+/// owners if they asked for it, returning whether all of them got it.
+///
+/// The webhooks of crates whose settings are waiting to be handed off to new
+/// owners are skipped, see the `handoff` module. Emails are skipped when no
+/// mailer is configured.
+fn deliver(conn: &GenericConnection,
+           mailer: Option<&Mailer>,
+           alert: &DownloadAlert) -> CargoResult<bool> {
+    #[derive(RustcEncodable)]
+    struct Payload<'a> {
+        id: i32,
+        crate_name: &'a str,
+        version: Option<&'a str>,
+        kind: &'a str,
+        threshold: Option<i32>,
+        date: Option<String>,
+        downloads: i32,
+    }
+
+    let rows = conn.query("\
+        SELECT crates.name, versions.num, crate_settings.webhook_urls,
+               crate_settings.email_alerts,
+               EXISTS (SELECT 1 FROM settings_handoffs
+                        WHERE settings_handoffs.crate_id = crates.id) AS handing_off
+          FROM crates
+         INNER JOIN crate_settings ON crate_settings.crate_id = crates.id
+          LEFT JOIN versions ON versions.id = $2
+         WHERE crates.id = $1", &[&alert.crate_id, &alert.version_id])?;
+    let row = match rows.iter().next() {
+        Some(row) => row,
+        None => return Ok(true),
+    };
+    let name: String = row.get("name");
+    let num: Option<String> = row.get("num");
+    let num = num.as_ref().map(|s| &s[..]);
+    let mut delivered = true;
+
+    let handing_off: bool = row.get("handing_off");
+    let urls: Vec<String> = if handing_off { Vec::new() } else { row.get("webhook_urls") };
+    let payload = json::encode(&Payload {
+        id: alert.id,
+        crate_name: &name,
+        version: num,
+        kind: &alert.kind,
+        threshold: alert.threshold,
+        date: alert.date.map(|date| date.to_string()),
+        downloads: alert.downloads,
+    }).unwrap();
+    for url in &urls {
+        // Checked again as what the host resolves to may have changed since
+        // the url was saved
+        let checked = Url::parse(url).map_err(|e| human(&e))
+            .and_then(|parsed| check_public_host(&parsed));
+        if let Err(e) = checked {
+            warn!("skipped webhook {} for {}: {}", url, name, e);
+            delivered = false;
+            continue
+        }
+        match http::post_json(url, payload.as_bytes(),
+                              &["User-Agent: crates.io download alerts"]) {
+            Ok(200...299) => {}
+            Ok(code) => {
+                warn!("webhook {} for {} responded with {}", url, name, code);
+                delivered = false;
+            }
+            Err(e) => {
+                warn!("failed to call webhook {} for {}: {}", url, name, e);
+                delivered = false;
+            }
+        }
+    }
+
+    let email_alerts: bool = row.get("email_alerts");
+    let mailer = match mailer {
+        Some(mailer) if email_alerts => mailer,
+        _ => return Ok(delivered),
+    };
+    let (subject, text) = email(&name, num, alert);
+    let rows = conn.query("SELECT users.email FROM crate_owners
+                            INNER JOIN users ON users.id = crate_owners.owner_id
+                            WHERE crate_owners.crate_id = $1
+                              AND crate_owners.owner_kind = $2
+                              AND NOT crate_owners.deleted
+                              AND users.email IS NOT NULL",
+                          &[&alert.crate_id, &(OwnerKind::User as i32)])?;
+    for row in rows.iter() {
+        let to: String = row.get("email");
+        match mailer.send(&to, &subject, &text) {
+            Ok(200...299) => {}
+            Ok(code) => {
+                warn!("emailing an owner of {} failed with {}", name, code);
+                delivered = false;
+            }
+            Err(e) => {
+                warn!("failed to email an owner of {}: {}", name, e);
+                delivered = false;
+            }
+        }
+    }
+    Ok(delivered)
+}
+
+/// The subject and text of the email about an alert.
+fn email(name: &str, num: Option<&str>, alert: &DownloadAlert) -> (String, String) {
+    match (alert.threshold, alert.date) {
+        (Some(threshold), _) => {
+            (format!("{} reached {} downloads", name, threshold),
+             format!("{} has been downloaded {} times, crossing the milestone of {} \
+                      downloads you asked to be alerted about.\n",
+                     name, alert.downloads, threshold))
+        }
+        (None, date) => {
+            let date = date.map(|date| date.to_string()).unwrap_or_else(String::new);
+            let version = num.unwrap_or("?");
+            (format!("{} {} had a spike in downloads", name, version),
+             format!("{} {} was downloaded {} times on {}, far more than it usually \
+                      is. If you don't know why, check that nobody is downloading it \
+                      in place of another crate.\n",
+                     name, version, alert.downloads, date))
+        }
+    }
+}
+
+/// Sends alert emails through Mailgun's API.
+pub struct Mailer {
+    api_key: String,
+    domain: String,
+}
+
+impl Mailer {
+    /// The mailer set up by `MAILGUN_API_KEY` and `MAILGUN_DOMAIN`, the
+    /// variables Mailgun's Heroku add-on sets, if both are set.
+    pub fn from_env() -> Option<Mailer> {
+        match (env::var("MAILGUN_API_KEY"), env::var("MAILGUN_DOMAIN")) {
+            (Ok(api_key), Ok(domain)) => {
+                if api_key.is_empty() || domain.is_empty() {
+                    return None
+                }
+                Some(Mailer { api_key: api_key, domain: domain })
+            }
+            _ => None,
+        }
+    }
+
+    fn send(&self, to: &str, subject: &str, text: &str) -> Result<u32, curl::Error> {
+        let body = form_urlencoded::Serializer::new(String::new())
+            .append_pair("from", &format!("crates.io <noreply@{}>", self.domain))
+            .append_pair("to", to)
+            .append_pair("subject", subject)
+            .append_pair("text", text)
+            .finish();
+
+        let mut handle = Easy::new();
+        handle.url(&format!("https://api.mailgun.net/v3/{}/messages", self.domain))?;
+        handle.username("api")?;
+        handle.password(&self.api_key)?;
+        handle.post(true)?;
+        handle.post_fields_copy(body.as_bytes())?;
+        handle.timeout(Duration::from_secs(10))?;
+        http::perform(handle)
+    }
+}
+
+/// Handles the `GET /crates/:crate_id/download_alerts` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = &req.params()["crate_id"];
+    let tx = req.tx()?;
+    let krate = Crate::find_by_name(tx, crate_name)?;
+    let owners = krate.owners_old(tx)?;
+    if request_rights(req, &*req.db_conn()?, &owners)? < Rights::Publish {
+        return Err(human("only owners of a crate can see its download alerts"))
+    }
+    let (offset, limit) = req.pagination(20, 100)?;
+
+    let alerts = tx.query("SELECT * FROM download_alerts
+                            WHERE crate_id = $1
+                            ORDER BY id DESC
+                            OFFSET $2 LIMIT $3", &[&krate.id, &offset, &limit])?
+        .iter()
+        .map(|row| DownloadAlert::from_row(&row).encodable())
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { alerts: Vec<EncodableDownloadAlert> }
+    Ok(req.json(&R { alerts: alerts }))
+}
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use conduit::{Request, Response};
 use conduit_middleware::Middleware;
@@ -9,6 +10,9 @@ use oauth2;
 use r2d2;
 use curl::easy::Easy;
 
+use download::{self, PendingDownloadStats, PendingDownloads, RecentDownloads};
+use token::PendingTokenUses;
+use util::TimedCache;
 use {db, Config};
 
 /// The `App` struct holds the main components of the application like
@@ -27,6 +31,28 @@ pub struct App {
     pub git_repo: Mutex<git2::Repository>,
     pub git_repo_checkout: PathBuf,
 
+    /// Download counts waiting to be written to the database
+    pub pending_downloads: PendingDownloads,
+
+    /// Download counts waiting to be written to `version_downloads_shadow`,
+    /// while `Config::async_downloads` is `Shadow`
+    pub shadow_downloads: PendingDownloads,
+
+    /// Download statistics waiting to be written to the database
+    pub pending_download_stats: PendingDownloadStats,
+
+    /// The clients whose downloads were counted within the dedupe window
+    pub recent_downloads: RecentDownloads,
+
+    /// API token uses waiting to be written to the database
+    pub pending_token_uses: PendingTokenUses,
+
+    /// Crate ids and recent download counts for `/crates/most_downloaded`
+    pub most_downloaded: TimedCache<Vec<(i32, i64)>>,
+
+    /// Crate ids with this and last week's downloads for `/crates/trending`
+    pub trending: TimedCache<Vec<(i32, i64, i64)>>,
+
     /// The server configuration
     pub config: Config,
 }
@@ -57,6 +83,14 @@ impl App {
             .helper_threads(if config.env == ::Env::Production {3} else {1})
             .build();
 
+        // Tests expect downloads and token uses to be visible as soon as the
+        // request is done, so don't hold on to anything there.
+        let flush_interval = if config.env == ::Env::Test {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs(download::FLUSH_INTERVAL_SECS)
+        };
+
         let repo = git2::Repository::open(&config.git_repo_checkout).unwrap();
         App {
             database: db::pool(&config.db_url, db_config),
@@ -65,6 +99,14 @@ impl App {
             session_key: config.session_key.clone(),
             git_repo: Mutex::new(repo),
             git_repo_checkout: config.git_repo_checkout.clone(),
+            pending_downloads: PendingDownloads::new(flush_interval, 1000),
+            shadow_downloads: PendingDownloads::shadow(flush_interval, 1000),
+            pending_download_stats: PendingDownloadStats::new(flush_interval, 1000),
+            recent_downloads: RecentDownloads::new(
+                Duration::from_secs(config.download_dedupe_secs), 100_000),
+            pending_token_uses: PendingTokenUses::new(flush_interval, 1000),
+            most_downloaded: TimedCache::new(Duration::from_secs(60 * 60)),
+            trending: TimedCache::new(Duration::from_secs(60 * 60)),
             config: config.clone(),
         }
     }
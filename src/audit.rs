@@ -0,0 +1,245 @@
+//! A log of privileged actions, such as the ones taken by admins through the
+//! `/admin` endpoints, so that they can be reviewed later on.
+//!
+//! What owners do to their crates is logged too, and makes up the crate's
+//! public activity timeline as well as the registry-wide event stream that
+//! mirrors follow.
+
+use std::collections::HashMap;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use time::Timespec;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use permission;
+use schema::{audit_log, crates, users};
+use util::{RequestUtils, CargoResult, human};
+
+/// The actions which show up in a crate's activity timeline.
+pub const CRATE_ACTIVITY: &'static [&'static str] = &[
+    "publish_version",
+    "yank_version",
+    "unyank_version",
+    "yank_versions",
+    "add_owner",
+    "add_collaborator",
+    "remove_owner",
+    "transfer_crate",
+    "update_deprecation_notice",
+    "update_version_metadata",
+    "license_change",
+];
+
+/// The actions published on the registry-wide event stream, i.e. the ones
+/// which change the index or who can publish to a crate.
+pub const REGISTRY_EVENTS: &'static [&'static str] = &[
+    "publish_version",
+    "approve_version",
+    "yank_version",
+    "unyank_version",
+    "yank_versions",
+    "add_owner",
+    "add_collaborator",
+    "remove_owner",
+    "transfer_crate",
+];
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name="audit_log"]
+pub struct AuditEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub action: String,
+    pub crate_name: Option<String>,
+    pub details: String,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="audit_log"]
+struct NewAuditEntry<'a> {
+    user_id: i32,
+    action: &'a str,
+    crate_name: Option<&'a str>,
+    details: &'a str,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableActivity {
+    /// One of `CRATE_ACTIVITY`
+    pub action: String,
+    /// The login of whoever did it
+    pub user: Option<String>,
+    /// The version for publishes and yanks, the range and the versions it
+    /// matched like `< 0.9.3: 0.9.1, 0.9.2` for bulk yanks, the login of the
+    /// owner for owner
+    /// changes, the new notice for deprecation edits, the version and the
+    /// fields changed for metadata edits and the version and both licenses
+    /// for license changes
+    pub details: String,
+    pub created_at: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableEvent {
+    /// The position of the event in the stream, to pass as `since` to get
+    /// the events which happened after it
+    pub seq: i32,
+    /// One of `REGISTRY_EVENTS`
+    pub action: String,
+    pub crate_name: String,
+    /// The login of whoever did it
+    pub user: Option<String>,
+    /// The same as `EncodableActivity::details`
+    pub details: String,
+    pub created_at: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableAuditEntry {
+    pub id: i32,
+    pub user_id: i32,
+    pub action: String,
+    pub crate_name: Option<String>,
+    pub details: String,
+    pub created_at: String,
+}
+
+impl AuditEntry {
+    pub fn encodable(self) -> EncodableAuditEntry {
+        EncodableAuditEntry {
+            id: self.id,
+            user_id: self.user_id,
+            action: self.action,
+            crate_name: self.crate_name,
+            details: self.details,
+            created_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+/// Records that `user_id` performed `action`, optionally on the crate named
+/// `crate_name`. `details` is free form text explaining why.
+pub fn record(conn: &InstrumentedConnection,
+              user_id: i32,
+              action: &str,
+              crate_name: Option<&str>,
+              details: &str) -> CargoResult<()> {
+    let entry = NewAuditEntry {
+        user_id: user_id,
+        action: action,
+        crate_name: crate_name,
+        details: details,
+    };
+    diesel::insert(&entry).into(audit_log::table).execute(conn)?;
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/activity` route.
+///
+/// Lists what happened to the crate, newest first: publishes, yanks, owner
+/// changes, license changes and edits of its deprecation notice.
+pub fn activity(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = req.params()["crate_id"].to_string();
+    permission::ensure_readable(req, &crate_name)?;
+    let (offset, limit) = req.pagination(20, 100)?;
+    let conn = req.db_conn()?;
+
+    let entries = audit_log::table
+        .filter(audit_log::crate_name.eq(&crate_name))
+        .filter(audit_log::action.eq_any(CRATE_ACTIVITY.to_vec()))
+        .order(audit_log::id.desc())
+        .limit(limit)
+        .offset(offset)
+        .load::<AuditEntry>(&*conn)?;
+    let logins = logins(&conn, &entries)?;
+
+    let activity = entries.into_iter().map(|e| {
+        EncodableActivity {
+            user: logins.get(&e.user_id).cloned(),
+            action: e.action,
+            details: e.details,
+            created_at: ::encode_time(e.created_at),
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { activity: Vec<EncodableActivity> }
+    Ok(req.json(&R { activity: activity }))
+}
+
+/// Handles the `GET /events` route.
+///
+/// Lists what happened across the registry after the event numbered `since`
+/// (0 by default), oldest first: publishes, yanks and owner changes. A
+/// version held for review shows up as `publish_version` when it's uploaded
+/// and only reaches the index on `approve_version`. Clients keep asking with
+/// `meta.next_since` until no events are returned.
+pub fn events(req: &mut Request) -> CargoResult<Response> {
+    let since = match req.query().get("since") {
+        Some(since) => since.parse::<i32>().ok().and_then(|since| {
+            if since >= 0 { Some(since) } else { None }
+        }).ok_or_else(|| {
+            human(&format_args!("since must be a positive integer, got `{}`", since))
+        })?,
+        None => 0,
+    };
+    let (_, limit) = req.pagination(100, 100)?;
+    let conn = req.db_conn()?;
+
+    let entries = audit_log::table
+        .filter(audit_log::id.gt(since))
+        .filter(audit_log::action.eq_any(REGISTRY_EVENTS.to_vec()))
+        .order(audit_log::id.asc())
+        .limit(limit)
+        .load::<AuditEntry>(&*conn)?;
+    let next_since = entries.last().map(|e| e.id).unwrap_or(since);
+
+    // Events about private crates are skipped, but still move the cursor
+    let names = entries.iter()
+        .filter_map(|e| e.crate_name.clone())
+        .collect::<Vec<_>>();
+    let private = crates::table
+        .filter(crates::name.eq_any(names))
+        .filter(crates::private.eq(true))
+        .select(crates::name)
+        .load::<String>(&*conn)?;
+    let logins = logins(&conn, &entries)?;
+
+    let events = entries.into_iter().filter_map(|e| {
+        let crate_name = match e.crate_name {
+            Some(ref name) if !private.contains(name) => name.clone(),
+            _ => return None,
+        };
+        Some(EncodableEvent {
+            seq: e.id,
+            user: logins.get(&e.user_id).cloned(),
+            crate_name: crate_name,
+            action: e.action,
+            details: e.details,
+            created_at: ::encode_time(e.created_at),
+        })
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { events: Vec<EncodableEvent>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta { next_since: i32 }
+    Ok(req.json(&R { events: events, meta: Meta { next_since: next_since } }))
+}
+
+/// The logins of the users who made `entries`, by id.
+fn logins(conn: &InstrumentedConnection,
+          entries: &[AuditEntry]) -> CargoResult<HashMap<i32, String>> {
+    let user_ids = entries.iter().map(|e| e.user_id).collect::<Vec<_>>();
+    let logins = users::table
+        .filter(users::id.eq_any(user_ids))
+        .select((users::id, users::gh_login))
+        .load::<(i32, String)>(conn)?
+        .into_iter()
+        .collect();
+    Ok(logins)
+}
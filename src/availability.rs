@@ -0,0 +1,184 @@
+//! Whether a crate name can still be published.
+//!
+//! `GET /crates/:crate_id/availability` tells what publishing under a name
+//! would run into, so that tools scaffolding a new project can check before
+//! its first publish fails. Names are compared the way publishes compare
+//! them, ignoring case and whether words are separated by `-` or `_`: a name
+//! is `taken` by a crate published under exactly that name, `collision` if a
+//! crate was published under another spelling of it, and `reserved` if an
+//! admin reserved it or it's the former name of a renamed crate. Names which
+//! aren't free come with suggestions of similar names which are.
+
+use std::collections::HashSet;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel::expression::dsl::any;
+use diesel::prelude::*;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use krate::canon_crate_name;
+use schema::*;
+use util::{RequestUtils, CargoResult, human};
+use Crate;
+
+/// How many free names are suggested at most.
+pub const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Availability {
+    Free,
+    Taken,
+    Reserved,
+    Collision,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableAvailability {
+    pub name: String,
+    /// `free`, `taken`, `reserved` or `collision`
+    pub status: String,
+    /// The crate published under another spelling of the name for
+    /// collisions, or the crate which used to be named this for the former
+    /// names of renamed crates
+    pub conflict: Option<String>,
+    /// Similar names which are free, empty if the name itself is
+    pub suggestions: Vec<String>,
+}
+
+impl Availability {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Availability::Free => "free",
+            Availability::Taken => "taken",
+            Availability::Reserved => "reserved",
+            Availability::Collision => "collision",
+        }
+    }
+}
+
+/// The name publishes compare `name` by, as the `canon_crate_name` SQL
+/// function computes it.
+pub fn canonical_name(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// Whether `name` can be published, and if not the crate it conflicts with,
+/// if any.
+pub fn check(conn: &InstrumentedConnection,
+             name: &str) -> CargoResult<(Availability, Option<String>)> {
+    use diesel::select;
+    use diesel::expression::dsl::exists;
+
+    let existing = crates::table
+        .filter(canon_crate_name(crates::name).eq(canon_crate_name(name)))
+        .select(crates::name)
+        .first::<String>(conn)
+        .optional()?;
+    if let Some(existing) = existing {
+        return Ok(if existing == name {
+            (Availability::Taken, None)
+        } else {
+            (Availability::Collision, Some(existing))
+        })
+    }
+
+    let reserved = select(exists(reserved_crate_names::table
+        .filter(canon_crate_name(reserved_crate_names::name).eq(canon_crate_name(name)))
+        )).get_result::<bool>(conn)?;
+    if reserved {
+        return Ok((Availability::Reserved, None))
+    }
+
+    let renamed = crate_aliases::table
+        .filter(canon_crate_name(crate_aliases::alias).eq(canon_crate_name(name)))
+        .select(crate_aliases::crate_id)
+        .first::<i32>(conn)
+        .optional()?;
+    if let Some(crate_id) = renamed {
+        let current = crates::table.find(crate_id)
+            .select(crates::name)
+            .first::<String>(conn)?;
+        return Ok((Availability::Reserved, Some(current)))
+    }
+
+    Ok((Availability::Free, None))
+}
+
+/// Names similar to `name` which could be published, at most
+/// `MAX_SUGGESTIONS` of them.
+pub fn suggestions(conn: &InstrumentedConnection, name: &str) -> CargoResult<Vec<String>> {
+    let sep = if name.contains('_') && !name.contains('-') { "_" } else { "-" };
+    let candidates = vec![
+        format!("{}{}rs", name, sep),
+        format!("{}{}rust", name, sep),
+        format!("rust{}{}", sep, name),
+        format!("{}2", name),
+        format!("{}{}lib", name, sep),
+        format!("{}{}cli", name, sep),
+        format!("{}{}core", name, sep),
+        format!("lib{}", name),
+    ];
+    let candidates = candidates.into_iter()
+        .filter(|c| Crate::valid_name(c))
+        .collect::<Vec<_>>();
+    let canonical = candidates.iter().map(|c| canonical_name(c)).collect::<Vec<_>>();
+
+    let mut unavailable = HashSet::new();
+    unavailable.extend(crates::table
+        .filter(canon_crate_name(crates::name).eq(any(canonical.clone())))
+        .select(canon_crate_name(crates::name))
+        .load::<String>(conn)?);
+    unavailable.extend(reserved_crate_names::table
+        .filter(canon_crate_name(reserved_crate_names::name).eq(any(canonical.clone())))
+        .select(canon_crate_name(reserved_crate_names::name))
+        .load::<String>(conn)?);
+    unavailable.extend(crate_aliases::table
+        .filter(canon_crate_name(crate_aliases::alias).eq(any(canonical.clone())))
+        .select(canon_crate_name(crate_aliases::alias))
+        .load::<String>(conn)?);
+
+    Ok(candidates.into_iter().zip(canonical)
+        .filter(|&(_, ref canonical)| !unavailable.contains(canonical))
+        .map(|(candidate, _)| candidate)
+        .take(MAX_SUGGESTIONS)
+        .collect())
+}
+
+/// Handles the `GET /crates/:crate_id/availability` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let name = &req.params()["crate_id"];
+    if !Crate::valid_name(name) {
+        return Err(human(&format_args!("`{}` is not a valid crate name", name)))
+    }
+
+    let conn = req.db_conn()?;
+    let (availability, conflict) = check(&*conn, name)?;
+    let suggested = if availability == Availability::Free {
+        Vec::new()
+    } else {
+        suggestions(&*conn, name)?
+    };
+
+    #[derive(RustcEncodable)]
+    struct R { availability: EncodableAvailability }
+    Ok(req.json(&R {
+        availability: EncodableAvailability {
+            name: name.to_string(),
+            status: availability.as_str().to_string(),
+            conflict: conflict,
+            suggestions: suggested,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::canonical_name;
+
+    #[test]
+    fn canonical_names() {
+        assert_eq!(canonical_name("Serde-JSON"), "serde_json");
+        assert_eq!(canonical_name("serde_json"), "serde_json");
+    }
+}
@@ -1,9 +1,10 @@
 use Model;
+use db::InstrumentedConnection;
 use krate::Crate;
 use schema::badges;
 use util::CargoResult;
 
-use diesel::pg::{Pg, PgConnection};
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use pg::GenericConnection;
 use pg::rows::Row;
@@ -67,7 +68,7 @@ impl Badge {
         }
     }
 
-    pub fn update_crate<'a>(conn: &PgConnection,
+    pub fn update_crate<'a>(conn: &InstrumentedConnection,
                             krate: &Crate,
                             badges: Option<&'a HashMap<String, HashMap<String, String>>>)
                             -> CargoResult<Vec<&'a str>> {
@@ -0,0 +1,67 @@
+// Check that the batched download counts written to
+// `version_downloads_shadow` while `FLAG_ASYNC_DOWNLOADS=shadow` add up to
+// the ones written one by one to `version_downloads`, before switching the
+// flag to `on`.
+//
+// By default every day from the one after the shadow started up to yesterday
+// is compared, as the first day has downloads counted before the shadow and
+// today's batches may still be pending. Exits with a non-zero status if any
+// count differs.
+//
+// Usage:
+//      cargo run --bin compare-download-counts [<since> [<until>]]
+//
+// with dates written like `2017-04-15`.
+
+#![deny(warnings)]
+
+extern crate cargo_registry;
+extern crate chrono;
+
+use std::env;
+use std::process;
+
+use cargo_registry::download;
+use chrono::{Duration, NaiveDate, UTC};
+
+#[allow(dead_code)]
+fn main() {
+    let conn = cargo_registry::db::connect_now();
+    let since = match env::args().nth(1) {
+        Some(s) => date(&s),
+        None => match download::shadow_started(&conn).unwrap() {
+            Some(started) => started + Duration::days(1),
+            None => {
+                println!("there are no shadow downloads to compare");
+                process::exit(1);
+            }
+        },
+    };
+    let until = match env::args().nth(2) {
+        Some(s) => date(&s),
+        None => UTC::now().naive_utc().date() - Duration::days(1),
+    };
+    if since > until {
+        println!("no whole day of shadow downloads to compare yet");
+        process::exit(1);
+    }
+
+    let mismatches = download::shadow_mismatches(&conn, since, until).unwrap();
+    for m in &mismatches {
+        println!("{} version {}: {} downloads, {} in the shadow",
+                 m.date, m.version_id, m.downloads, m.shadow_downloads);
+    }
+    if mismatches.is_empty() {
+        println!("download counts from {} to {} match", since, until);
+    } else {
+        println!("{} counts from {} to {} differ", mismatches.len(), since, until);
+        process::exit(1);
+    }
+}
+
+fn date(s: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap_or_else(|_| {
+        println!("`{}` isn't a date like 2017-04-15", s);
+        process::exit(2);
+    })
+}
@@ -0,0 +1,43 @@
+// Compute the patches between versions which were asked for through
+// `GET /api/v1/crates/:crate_id/:version/diff/:other_version?patches=1`.
+//
+// Crate files are read from the S3 bucket named by `S3_BUCKET`, or from
+// `dist/local_uploads` if it isn't set.
+//
+// Usage:
+//      cargo run --bin compute-diffs [daemon <seconds between runs>]
+
+#![deny(warnings)]
+
+extern crate cargo_registry;
+extern crate curl;
+
+use std::env;
+use std::time::Duration;
+
+use cargo_registry::{diff, util, Uploader};
+use cargo_registry::util::{CargoResult, TarballFile};
+use curl::easy::Easy;
+
+#[allow(dead_code)]
+fn main() {
+    let daemon = env::args().nth(1).as_ref().map(|s| &s[..]) == Some("daemon");
+    let sleep = env::args().nth(2).map(|s| s.parse().unwrap());
+    let uploader = Uploader::from_env();
+
+    loop {
+        let conn = cargo_registry::db::connect_now();
+        let fetch = |name: &str, num: &str| -> CargoResult<Vec<TarballFile>> {
+            let tarball = uploader.fetch(&mut Easy::new(), name, num)?;
+            util::unpack(&tarball)
+        };
+        let n = diff::compute_pending(&conn, &fetch).unwrap();
+        println!("computed {} diffs", n);
+        drop(conn);
+        if daemon {
+            std::thread::sleep(Duration::new(sleep.unwrap(), 0));
+        } else {
+            break
+        }
+    }
+}
@@ -15,6 +15,7 @@ extern crate rustc_serialize;
 use std::path::PathBuf;
 
 use cargo_registry::{http, env, App, Replica};
+use cargo_registry::config::Rollout;
 use cargo_registry::util::{CargoResult, human};
 
 #[allow(dead_code)]
@@ -33,6 +34,24 @@ fn main() {
         max_upload_size: 0,
         mirror: Replica::Primary,
         api_protocol: api_protocol,
+        download_stats: false,
+        admin_gh_ids: Vec::new(),
+        search_weights: Default::default(),
+        private_crates: false,
+        stale_token_days: None,
+        trusted_proxies: Vec::new(),
+        scanners: Vec::new(),
+        scan_policy: Default::default(),
+        signing_key: None,
+        index_v2: false,
+        slow_query_ms: None,
+        max_per_page: 100,
+        download_url_template: None,
+        serve_downloads: false,
+        async_downloads: Rollout::On,
+        download_dedupe_secs: 0,
+        max_body_size: 1024 * 1024,
+        body_timeout_secs: 30,
     };
     let app = cargo_registry::App::new(&config);
     {
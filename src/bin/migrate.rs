@@ -1,3 +1,16 @@
+// Apply, revert or list the database migrations in `migrations`.
+//
+// Every migration runs in its own transaction, so a migration which fails
+// leaves nothing of it behind and the ones before it applied. Only one
+// instance runs migrations against a database at a time: the others wait
+// for it to be done, and then find nothing left to do.
+//
+// Usage:
+//      cargo run --bin migrate             apply the pending migrations
+//      cargo run --bin migrate --status    list the migrations and whether they're applied
+//      cargo run --bin migrate --redo      revert the latest migration and apply it again
+//      cargo run --bin migrate --down N    revert the latest N migrations
+
 #![deny(warnings)]
 
 #[macro_use]
@@ -5,10 +18,14 @@ extern crate diesel;
 extern crate dotenv;
 
 use diesel::*;
-use diesel::migrations::setup_database;
+use diesel::expression::dsl::sql;
+use diesel::migrations::{self, Migration, MigrationConnection, MigrationError};
+use diesel::migrations::RunMigrationsError;
 use diesel::pg::PgConnection;
+use diesel::types::Bool;
 use dotenv::dotenv;
 use std::env;
+use std::process;
 
 table! {
     information_schema.tables (table_name) {
@@ -16,31 +33,170 @@ table! {
     }
 }
 
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+/// The first half of the key of the advisory lock held while migrating.
+/// Keys starting with 1 are taken by publishes, see
+/// `Crate::lock_for_publish`, and keys starting with 3 by the README
+/// backfill.
+const MIGRATION_LOCK_NAMESPACE: i32 = 2;
+
+enum Command {
+    Run,
+    Status,
+    Redo,
+    Down(usize),
+}
+
 fn main() {
     let _ = dotenv();
-    // This code will eventually break, as it relies on internal details of Diesel.
-    // If this function stops working, it's probably better to just delete it.
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let command = match (args.get(0).map(|s| &s[..]), args.len()) {
+        (None, _) => Command::Run,
+        (Some("--status"), 1) => Command::Status,
+        (Some("--redo"), 1) => Command::Redo,
+        (Some("--down"), 2) => match args[1].parse() {
+            Ok(n) => Command::Down(n),
+            Err(..) => usage(),
+        },
+        _ => usage(),
+    };
+
     let conn = PgConnection::establish(&env::var("DATABASE_URL").unwrap()).unwrap();
+    if let Err(e) = lock(&conn).and_then(|_| run(&conn, command)) {
+        println!("migration failed: {}", e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: migrate [--status | --redo | --down <number of migrations>]");
+    process::exit(2);
+}
 
-    if !table_exists("__diesel_schema_migrations", &conn) {
-        setup_database(&conn).unwrap();
+/// Waits until no other instance is migrating the database. The lock is
+/// released when the connection is closed.
+fn lock(conn: &PgConnection) -> Result<(), RunMigrationsError> {
+    let try_lock = format!("pg_try_advisory_lock({}, 0)", MIGRATION_LOCK_NAMESPACE);
+    if !select(sql::<Bool>(&try_lock)).get_result::<bool>(conn)? {
+        println!("waiting for another instance to finish migrating");
+        conn.execute(&format!("SELECT pg_advisory_lock({}, 0)", MIGRATION_LOCK_NAMESPACE))?;
     }
+    Ok(())
+}
+
+fn run(conn: &PgConnection, command: Command) -> Result<(), RunMigrationsError> {
+    setup(conn)?;
+    let available = available_migrations()?;
+    match command {
+        Command::Run => {
+            let applied = conn.previously_run_migration_versions()?;
+            for migration in available.iter().filter(|m| !applied.contains(m.version())) {
+                apply(conn, &**migration)?;
+            }
+        }
+        Command::Status => {
+            let applied = conn.previously_run_migration_versions()?;
+            for migration in &available {
+                let mark = if applied.contains(migration.version()) { "X" } else { " " };
+                println!("[{}] {}", mark, migration.version());
+            }
+            let mut missing = applied.iter()
+                .filter(|v| !available.iter().any(|m| m.version() == &v[..]))
+                .collect::<Vec<_>>();
+            missing.sort();
+            for version in missing {
+                println!("[X] {} (not in the migrations directory)", version);
+            }
+        }
+        Command::Redo => {
+            let migration = latest_applied(conn, &available, 1)?.pop()
+                .ok_or(MigrationError::NoMigrationRun)?;
+            revert(conn, migration)?;
+            apply(conn, migration)?;
+        }
+        Command::Down(n) => {
+            for migration in latest_applied(conn, &available, n)? {
+                revert(conn, migration)?;
+            }
+        }
+    }
+    Ok(())
+}
 
-    if table_exists("schema_migrations", &conn) {
+/// Creates the table of applied migrations, and carries over the ones
+/// applied by the `schema_migrations` era migration runner.
+fn setup(conn: &PgConnection) -> Result<(), RunMigrationsError> {
+    if !table_exists("__diesel_schema_migrations", conn)? {
+        migrations::setup_database(conn)?;
+    }
+
+    if table_exists("schema_migrations", conn)? {
         conn.execute("INSERT INTO __diesel_schema_migrations (
             SELECT version::text AS version, CURRENT_TIMESTAMP as run_on
                 FROM schema_migrations
-        ) ON CONFLICT DO NOTHING").unwrap();
+        ) ON CONFLICT DO NOTHING")?;
     }
+    Ok(())
+}
+
+/// The migrations in the `migrations` directory, oldest first.
+fn available_migrations() -> Result<Vec<Box<Migration>>, RunMigrationsError> {
+    let dir = migrations::find_migrations_directory()?;
+    let mut available = migrations::migration_paths_in_directory(&dir)?
+        .into_iter()
+        .map(|entry| migrations::migration_from(entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    available.sort_by(|a, b| a.version().cmp(b.version()));
+    Ok(available)
+}
+
+/// The `n` migrations which were applied last, newest first.
+fn latest_applied<'a>(conn: &PgConnection, available: &'a [Box<Migration>], n: usize)
+                      -> Result<Vec<&'a Migration>, RunMigrationsError> {
+    let applied = conn.previously_run_migration_versions()?;
+    let mut versions = applied.iter().collect::<Vec<_>>();
+    versions.sort();
+    versions.reverse();
+    versions.into_iter().take(n).map(|version| {
+        available.iter()
+            .find(|m| m.version() == &version[..])
+            .map(|m| &**m)
+            .ok_or_else(|| {
+                let version = version.clone();
+                RunMigrationsError::from(MigrationError::UnknownMigrationVersion(version))
+            })
+    }).collect()
+}
+
+fn apply(conn: &PgConnection, migration: &Migration) -> Result<(), RunMigrationsError> {
+    println!("Running migration {}", migration.version());
+    conn.transaction(|| {
+        migration.run(conn)?;
+        conn.insert_new_migration(migration.version())?;
+        Ok(())
+    })
+}
 
-    println!("The `migrate` binary is no longer used. Use `diesel migration run` \
-              and `diesel migration revert` instead.");
+fn revert(conn: &PgConnection, migration: &Migration) -> Result<(), RunMigrationsError> {
+    println!("Rolling back migration {}", migration.version());
+    conn.transaction(|| {
+        migration.revert(conn)?;
+        let target = __diesel_schema_migrations::table.find(migration.version());
+        diesel::delete(target).execute(conn)?;
+        Ok(())
+    })
 }
 
-fn table_exists(target: &str, conn: &PgConnection) -> bool {
+fn table_exists(target: &str, conn: &PgConnection) -> QueryResult<bool> {
     use self::tables::dsl::*;
     use diesel::expression::dsl::exists;
 
     let table_query = tables.filter(table_name.eq(target));
-    select(exists(table_query)).get_result(conn).unwrap()
+    select(exists(table_query)).get_result(conn)
 }
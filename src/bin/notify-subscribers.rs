@@ -0,0 +1,32 @@
+// Ping the callbacks mirrors subscribed with `PUT /api/v1/index/subscriptions`
+// about the events which happened since they were last pinged. However many
+// events there were, each callback is pinged at most once per run.
+//
+// Usage:
+//      cargo run --bin notify-subscribers [daemon <seconds between runs>]
+
+#![deny(warnings)]
+
+extern crate cargo_registry;
+
+use std::env;
+use std::time::Duration;
+
+use cargo_registry::hub;
+
+#[allow(dead_code)]
+fn main() {
+    let daemon = env::args().nth(1).as_ref().map(|s| &s[..]) == Some("daemon");
+    let sleep = env::args().nth(2).map(|s| s.parse().unwrap());
+    loop {
+        let conn = cargo_registry::db::connect_now();
+        let pinged = hub::notify_all(&conn).unwrap();
+        println!("pinged {} subscribers", pinged);
+        drop(conn);
+        if daemon {
+            std::thread::sleep(Duration::new(sleep.unwrap(), 0));
+        } else {
+            break
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// Render the READMEs of existing versions for the backfill an admin started
+// with `PUT /api/v1/admin/readme_backfill`.
+//
+// Crate files are read from the S3 bucket named by `S3_BUCKET`, or from
+// `dist/local_uploads` if it isn't set. Progress is saved after every
+// version, so the backfill picks up where it stopped when this is restarted.
+// Only one instance works through the backfill at a time, others wait for
+// their next run.
+//
+// Usage:
+//      cargo run --bin render-readmes [daemon <seconds between runs>]
+
+#![deny(warnings)]
+
+extern crate cargo_registry;
+extern crate curl;
+extern crate diesel;
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use cargo_registry::{readme, util, Uploader};
+use cargo_registry::db::InstrumentedConnection;
+use cargo_registry::util::{CargoResult, TarballFile};
+use curl::easy::Easy;
+use diesel::Connection;
+
+#[allow(dead_code)]
+fn main() {
+    let daemon = env::args().nth(1).as_ref().map(|s| &s[..]) == Some("daemon");
+    let sleep = env::args().nth(2).map(|s| s.parse().unwrap());
+    let uploader = Uploader::from_env();
+
+    loop {
+        let conn = InstrumentedConnection::establish(&env::var("DATABASE_URL").unwrap()).unwrap();
+        let fetch = |name: &str, num: &str| -> CargoResult<Vec<TarballFile>> {
+            let tarball = uploader.fetch(&mut Easy::new(), name, num)?;
+            util::unpack(&tarball)
+        };
+        let pause = |delay: Duration| thread::sleep(delay);
+        match readme::try_lock_backfill(&conn) {
+            Ok(true) => render(&conn, &fetch, &pause),
+            Ok(false) => println!("another instance is working through the backfill"),
+            Err(e) => println!("unable to lock the backfill: {}", e),
+        }
+        drop(conn);
+        if daemon {
+            thread::sleep(Duration::new(sleep.unwrap(), 0));
+        } else {
+            break
+        }
+    }
+}
+
+/// Works through the active backfill until it's finished or a batch fails.
+#[allow(dead_code)]
+fn render(conn: &InstrumentedConnection,
+          fetch: &Fn(&str, &str) -> CargoResult<Vec<TarballFile>>,
+          pause: &Fn(Duration)) {
+    loop {
+        match readme::run_batch(conn, fetch, pause) {
+            Ok(Some(backfill)) => {
+                println!("backfill {}: rendered {}, failed {}, up to version {}",
+                         backfill.id, backfill.rendered, backfill.failed,
+                         backfill.last_version_id);
+                if backfill.finished_at.is_some() {
+                    break
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                println!("rendering a batch of READMEs failed: {}", e);
+                break
+            }
+        }
+    }
+}
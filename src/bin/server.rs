@@ -5,15 +5,26 @@ extern crate conduit_middleware;
 extern crate civet;
 extern crate git2;
 extern crate env_logger;
+extern crate libc;
 extern crate s3;
 
-use cargo_registry::{env, Env, Uploader, Replica};
+use cargo_registry::{env, download, token, App, Env, Uploader, Replica};
+use cargo_registry::config::{Rollout, SearchWeights};
+use cargo_registry::scanner::{self, ScanPolicy};
+use cargo_registry::signature::SigningKey;
+use cargo_registry::util::Cidr;
 use civet::Server;
+use std::collections::HashSet;
 use std::env;
 use std::fs::{self, File};
+use std::io::Read;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static SHUTTING_DOWN: AtomicBool = ATOMIC_BOOL_INIT;
 
 #[allow(dead_code)]
 fn main() {
@@ -95,6 +106,80 @@ fn main() {
         },
     };
 
+    let admin_gh_ids = env::var("ADMIN_GH_IDS").unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect();
+
+    let search_weights = match env::var("SEARCH_WEIGHTS") {
+        Ok(s) => SearchWeights::parse(&s).expect("SEARCH_WEIGHTS should be four \
+            comma separated numbers between 0 and 1"),
+        Err(..) => SearchWeights::default(),
+    };
+
+    let stale_token_days = env::var("STALE_TOKEN_DAYS").ok().map(|s| {
+        s.parse().expect("STALE_TOKEN_DAYS should be a number of days")
+    });
+
+    let slow_query_ms = env::var("SLOW_QUERY_MS").ok().map(|s| {
+        s.parse().expect("SLOW_QUERY_MS should be a number of milliseconds")
+    });
+
+    let max_per_page = env::var("MAX_PER_PAGE").ok().map(|s| {
+        s.parse().expect("MAX_PER_PAGE should be a number of items")
+    }).unwrap_or(100);
+
+    let trusted_proxies = env::var("TRUSTED_PROXIES").unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| Cidr::parse(s).expect("TRUSTED_PROXIES should be a comma separated \
+            list of IP ranges"))
+        .collect();
+
+    let known_malware = match env::var("KNOWN_MALWARE_HASHES") {
+        Ok(path) => {
+            let mut hashes = String::new();
+            File::open(&path).and_then(|mut f| f.read_to_string(&mut hashes))
+                .expect("KNOWN_MALWARE_HASHES should be a readable file");
+            hashes.lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect()
+        }
+        Err(..) => HashSet::new(),
+    };
+
+    let scan_policy = match env::var("SCAN_POLICY") {
+        Ok(s) => ScanPolicy::parse(&s).expect("SCAN_POLICY should look like \
+            `warning=warn,critical=reject`"),
+        Err(..) => ScanPolicy::default(),
+    };
+
+    let async_downloads = match env::var("FLAG_ASYNC_DOWNLOADS") {
+        Ok(s) => Rollout::parse(&s).expect("FLAG_ASYNC_DOWNLOADS should be `off`, \
+            `shadow` or `on`"),
+        Err(..) => Rollout::Off,
+    };
+
+    let download_dedupe_secs = env::var("DOWNLOAD_DEDUPE_SECS").ok().map(|s| {
+        s.parse().expect("DOWNLOAD_DEDUPE_SECS should be a number of seconds")
+    }).unwrap_or(60);
+
+    let max_body_size = env::var("MAX_BODY_SIZE").ok().map(|s| {
+        s.parse().expect("MAX_BODY_SIZE should be a number of bytes")
+    }).unwrap_or(1024 * 1024);
+
+    let body_timeout_secs = env::var("BODY_TIMEOUT_SECS").ok().map(|s| {
+        s.parse().expect("BODY_TIMEOUT_SECS should be a number of seconds")
+    }).unwrap_or(30);
+
+    let signing_key = env::var("SIGNING_KEY").ok().map(|path| {
+        let mut pem = Vec::new();
+        File::open(&path).and_then(|mut f| f.read_to_end(&mut pem))
+            .expect("SIGNING_KEY should be a readable file");
+        Arc::new(SigningKey::from_pem(&pem).unwrap())
+    });
+
     let config = cargo_registry::Config {
         uploader: uploader,
         session_key: env("SESSION_KEY"),
@@ -106,9 +191,32 @@ fn main() {
         max_upload_size: 10 * 1024 * 1024,
         mirror: mirror,
         api_protocol: api_protocol,
+        download_stats: env::var("DOWNLOAD_STATS").is_ok(),
+        admin_gh_ids: admin_gh_ids,
+        search_weights: search_weights,
+        private_crates: env::var("PRIVATE_CRATES").is_ok(),
+        stale_token_days: stale_token_days,
+        trusted_proxies: trusted_proxies,
+        scanners: scanner::default_scanners(known_malware),
+        scan_policy: scan_policy,
+        signing_key: signing_key,
+        index_v2: env::var("INDEX_V2").is_ok(),
+        slow_query_ms: slow_query_ms,
+        max_per_page: max_per_page,
+        download_url_template: env::var("DOWNLOAD_URL_TEMPLATE").ok(),
+        serve_downloads: env::var("SERVE_DOWNLOADS").is_ok(),
+        async_downloads: async_downloads,
+        download_dedupe_secs: download_dedupe_secs,
+        max_body_size: max_body_size,
+        body_timeout_secs: body_timeout_secs,
     };
-    let app = cargo_registry::App::new(&config);
-    let app = cargo_registry::middleware(Arc::new(app));
+    let app = Arc::new(App::new(&config));
+    if let Some(ref key) = config.signing_key {
+        if mirror == Replica::Primary {
+            cargo_registry::git::set_config(&app, "signing-key", key.fingerprint()).unwrap();
+        }
+    }
+    let handler = cargo_registry::middleware(app.clone());
 
     cargo_registry::categories::sync().unwrap();
 
@@ -120,13 +228,42 @@ fn main() {
     let threads = if cargo_env == Env::Development {1} else {50};
     let mut cfg = civet::Config::new();
     cfg.port(port).threads(threads).keep_alive(true);
-    let _a = Server::start(cfg, app);
+    let server = Server::start(cfg, handler);
     println!("listening on port {}", port);
     if heroku {
         File::create("/tmp/app-initialized").unwrap();
     }
 
-    // TODO: handle a graceful shutdown by just waiting for a SIG{INT,TERM}
-    let (_tx, rx) = channel::<()>();
-    rx.recv().unwrap();
+    // Batched downloads and token uses are written by the requests which
+    // find them due, so write them every interval in case no requests come
+    // in, and once more on SIG{INT,TERM} after the server stopped taking
+    // requests, so that a restart doesn't lose them.
+    unsafe {
+        libc::signal(libc::SIGINT, shut_down as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, shut_down as libc::sighandler_t);
+    }
+    let interval = Duration::from_secs(download::FLUSH_INTERVAL_SECS);
+    let mut last_flush = Instant::now();
+    while !SHUTTING_DOWN.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(200));
+        if last_flush.elapsed() >= interval {
+            flush(&app);
+            last_flush = Instant::now();
+        }
+    }
+    drop(server);
+    flush(&app);
+}
+
+extern "C" fn shut_down(_signal: libc::c_int) {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+fn flush(app: &App) {
+    if let Err(e) = download::flush_all(app) {
+        println!("failed to write downloads: {}", e);
+    }
+    if let Err(e) = token::flush_all(app) {
+        println!("failed to write token uses: {}", e);
+    }
 }
@@ -0,0 +1,37 @@
+// Store a snapshot of the registry's totals in `registry_stats`, which is
+// what `GET /api/v1/stats` serves, and recount the recent months of
+// `category_stats` for `GET /api/v1/categories/:category_id/stats`. Meant to
+// be run once a week.
+//
+// Usage:
+//      cargo run --bin snapshot-stats [daemon <seconds between runs>]
+
+#![deny(warnings)]
+
+extern crate cargo_registry;
+
+use std::env;
+use std::time::Duration;
+
+use cargo_registry::stats::{CategoryStats, RegistryStats};
+
+#[allow(dead_code)]
+fn main() {
+    let daemon = env::args().nth(1).as_ref().map(|s| &s[..]) == Some("daemon");
+    let sleep = env::args().nth(2).map(|s| s.parse().unwrap());
+    loop {
+        let conn = cargo_registry::db::connect_now();
+        let stats = RegistryStats::snapshot(&conn).unwrap();
+        println!("{} crates, {} versions, {} downloads, {} users ({} new)",
+                 stats.total_crates, stats.total_versions, stats.total_downloads,
+                 stats.total_users, stats.new_users);
+        let months = CategoryStats::snapshot(&conn).unwrap();
+        println!("counted {} months of categories", months);
+        drop(conn);
+        if daemon {
+            std::thread::sleep(Duration::new(sleep.unwrap(), 0));
+        } else {
+            break
+        }
+    }
+}
@@ -9,29 +9,108 @@ extern crate time;
 
 use std::collections::HashMap;
 use std::env;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use cargo_registry::{VersionDownload, Version, Model};
+use cargo_registry::{VersionDownload, Model};
+use cargo_registry::{alert, cleanup, dependency};
 
 static LIMIT: i64 = 1000;
 
 #[allow(dead_code)] // dead in tests
 fn main() {
-    let daemon = env::args().nth(1).as_ref().map(|s| &s[..])
-                    == Some("daemon");
+    let mode = env::args().nth(1);
+    let daemon = mode.as_ref().map(|s| &s[..]) == Some("daemon");
+    if mode.as_ref().map(|s| &s[..]) == Some("reconcile") {
+        let conn = cargo_registry::db::connect_now();
+        reconcile(&conn).unwrap();
+        return
+    }
     let sleep = env::args().nth(2).map(|s| s.parse().unwrap());
+    let retention = cleanup::Retention::from_env().unwrap();
+    let mut last_cleanup = None::<Instant>;
+    let mut last_recount = None::<Instant>;
+    let mailer = Arc::new(alert::Mailer::from_env());
+    let delivering = Arc::new(AtomicBool::new(false));
     loop {
         let conn = cargo_registry::db::connect_now();
         update(&conn).unwrap();
+        match alert::evaluate(&conn) {
+            Ok(alerts) => println!("raising {} download alerts", alerts.len()),
+            Err(e) => println!("raising download alerts failed: {}", e),
+        }
+        let delivery = deliver_alerts(&mailer, &delivering);
+
+        // Purging isn't needed as often as downloads are counted
+        let cleanup_due = last_cleanup.map(|at| {
+            at.elapsed() >= Duration::from_secs(cleanup::INTERVAL_SECS)
+        }).unwrap_or(true);
+        if cleanup_due {
+            match cleanup::run(&conn, &retention) {
+                Ok(purged) => for p in purged {
+                    println!("purged {} rows of {} older than {} days in {}ms",
+                             p.rows, p.table, p.days, p.duration_ms);
+                },
+                Err(e) => println!("cleanup failed: {}", e),
+            }
+            last_cleanup = Some(Instant::now());
+        }
+
+        // Dependents are counted as crates are published and yanked, the
+        // recount only catches what that missed
+        let recount_due = last_recount.map(|at| {
+            at.elapsed() >= Duration::from_secs(dependency::RECOUNT_INTERVAL_SECS)
+        }).unwrap_or(true);
+        if recount_due {
+            match dependency::recount_dependents(&conn) {
+                Ok(n) => println!("recounted dependents, fixing {} crates", n),
+                Err(e) => println!("recounting dependents failed: {}", e),
+            }
+            last_recount = Some(Instant::now());
+        }
         drop(conn);
         if daemon {
-            std::thread::sleep(Duration::new(sleep.unwrap(), 0));
+            thread::sleep(Duration::new(sleep.unwrap(), 0));
         } else {
+            if let Some(delivery) = delivery {
+                delivery.join().unwrap();
+            }
             break
         }
     }
 }
 
+/// Delivers the pending download alerts on their own thread and connection,
+/// so that slow webhooks don't hold up counting downloads. Nothing is started
+/// while the previous delivery is still running.
+#[allow(dead_code)] // dead in tests
+fn deliver_alerts(mailer: &Arc<Option<alert::Mailer>>,
+                  delivering: &Arc<AtomicBool>) -> Option<thread::JoinHandle<()>> {
+    struct Done(Arc<AtomicBool>);
+
+    impl Drop for Done {
+        fn drop(&mut self) {
+            self.0.store(false, Ordering::SeqCst);
+        }
+    }
+
+    if delivering.swap(true, Ordering::SeqCst) {
+        return None
+    }
+    let mailer = mailer.clone();
+    let done = Done(delivering.clone());
+    Some(thread::spawn(move || {
+        let _done = done;
+        let conn = cargo_registry::db::connect_now();
+        match alert::deliver_pending(&conn, (*mailer).as_ref()) {
+            Ok(n) => println!("delivered {} download alerts", n),
+            Err(e) => println!("delivering download alerts failed: {}", e),
+        }
+    }))
+}
+
 fn update(conn: &postgres::GenericConnection) -> postgres::Result<()> {
     let mut max = 0;
     loop {
@@ -74,6 +153,11 @@ fn collect(tx: &postgres::transaction::Transaction,
 
     let mut max = 0;
     let mut total = 0;
+    let mut ids = Vec::new();
+    let mut amts = Vec::new();
+    let mut processed = Vec::new();
+    let mut version_amts = HashMap::new();
+    let mut version_dates = Vec::new();
     for (id, download) in map.iter() {
         if *id > max {
             max = *id;
@@ -85,38 +169,73 @@ fn collect(tx: &postgres::transaction::Transaction,
 
         // Flag this row as having been processed if we're passed the cutoff,
         // and unconditionally increment the number of counted downloads.
-        tx.execute("UPDATE version_downloads
-                         SET processed = $2, counted = counted + $3
-                         WHERE id = $1",
-                   &[id, &(download.date < cutoff), &amt])?;
+        ids.push(*id);
+        amts.push(amt);
+        processed.push(download.date < cutoff);
         total += amt as i64;
 
         if amt == 0 {
             continue
         }
+        *version_amts.entry(download.version_id).or_insert(0) += amt;
+        version_dates.push((download.version_id, download.date, amt));
+    }
+
+    if !ids.is_empty() {
+        tx.execute("UPDATE version_downloads
+                       SET processed = u.processed, counted = counted + u.amt
+                      FROM UNNEST($1::int4[], $2::int4[], $3::bool[])
+                        AS u (id, amt, processed)
+                     WHERE version_downloads.id = u.id",
+                   &[&ids, &amts, &processed])?;
+    }
 
-        let crate_id = Version::find(tx, download.version_id).unwrap().crate_id;
+    if !version_amts.is_empty() {
+        let version_ids = version_amts.keys().cloned().collect::<Vec<i32>>();
+        let stmt = tx.prepare("SELECT id, crate_id FROM versions
+                                WHERE id = ANY($1)")?;
+        let crate_ids = stmt.query(&[&version_ids])?.iter().map(|row| {
+            (row.get::<_, i32>("id"), row.get::<_, i32>("crate_id"))
+        }).collect::<HashMap<i32, i32>>();
+
+        let mut crate_amts = HashMap::new();
+        let mut crate_day_amts = HashMap::new();
+        for &(version_id, date, amt) in &version_dates {
+            let crate_id = crate_ids[&version_id];
+            *crate_amts.entry(crate_id).or_insert(0) += amt;
+            *crate_day_amts.entry((crate_id, date)).or_insert(0) += amt;
+        }
 
         // Update the total number of version downloads
+        let amts = version_ids.iter().map(|id| version_amts[id]).collect::<Vec<i32>>();
         tx.execute("UPDATE versions
-                         SET downloads = downloads + $1
-                         WHERE id = $2",
-                   &[&amt, &download.version_id])?;
+                       SET downloads = versions.downloads + u.amt
+                      FROM UNNEST($1::int4[], $2::int4[]) AS u (id, amt)
+                     WHERE versions.id = u.id",
+                   &[&version_ids, &amts])?;
+
         // Update the total number of crate downloads
-        tx.execute("UPDATE crates SET downloads = downloads + $1
-                         WHERE id = $2", &[&amt, &crate_id])?;
-
-        // Update the total number of crate downloads for today
-        let cnt = tx.execute("UPDATE crate_downloads
-                                   SET downloads = downloads + $2
-                                   WHERE crate_id = $1 AND date = $3",
-                             &[&crate_id, &amt, &download.date])?;
-        if cnt == 0 {
-            tx.execute("INSERT INTO crate_downloads
-                             (crate_id, downloads, date)
-                             VALUES ($1, $2, $3)",
-                       &[&crate_id, &amt, &download.date])?;
+        let (crate_ids, amts): (Vec<i32>, Vec<i32>) = crate_amts.into_iter().unzip();
+        tx.execute("UPDATE crates
+                       SET downloads = crates.downloads + u.amt
+                      FROM UNNEST($1::int4[], $2::int4[]) AS u (id, amt)
+                     WHERE crates.id = u.id",
+                   &[&crate_ids, &amts])?;
+
+        // Roll the downloads up into the daily totals for each crate
+        let mut crate_ids = Vec::new();
+        let mut dates = Vec::new();
+        let mut amts = Vec::new();
+        for ((crate_id, date), amt) in crate_day_amts {
+            crate_ids.push(crate_id);
+            dates.push(date);
+            amts.push(amt);
         }
+        tx.execute("INSERT INTO crate_downloads (crate_id, downloads, date)
+                    SELECT * FROM UNNEST($1::int4[], $2::int4[], $3::date[])
+                    ON CONFLICT (crate_id, date) DO UPDATE
+                       SET downloads = crate_downloads.downloads + EXCLUDED.downloads",
+                   &[&crate_ids, &amts, &dates])?;
     }
 
     // After everything else is done, update the global counter of total
@@ -127,6 +246,39 @@ fn collect(tx: &postgres::transaction::Transaction,
     Ok(Some(max))
 }
 
+/// Recomputes every denormalized download total from the counted
+/// `version_downloads` rows, fixing up any drift that has accumulated.
+/// Versions without any counted downloads and crates without any versions
+/// are reset to zero.
+fn reconcile(conn: &postgres::GenericConnection) -> postgres::Result<()> {
+    let tx = conn.transaction()?;
+    let n = tx.execute("UPDATE versions SET downloads = totals.downloads
+                          FROM (SELECT versions.id,
+                                       COALESCE(SUM(version_downloads.counted), 0)::int4
+                                           AS downloads
+                                  FROM versions
+                                  LEFT JOIN version_downloads
+                                    ON version_downloads.version_id = versions.id
+                                 GROUP BY versions.id) AS totals
+                         WHERE versions.id = totals.id
+                           AND versions.downloads != totals.downloads", &[])?;
+    println!("reconciled downloads of {} versions", n);
+    let n = tx.execute("UPDATE crates SET downloads = totals.downloads
+                          FROM (SELECT crates.id,
+                                       COALESCE(SUM(versions.downloads), 0)::int4
+                                           AS downloads
+                                  FROM crates
+                                  LEFT JOIN versions ON versions.crate_id = crates.id
+                                 GROUP BY crates.id) AS totals
+                         WHERE crates.id = totals.id
+                           AND crates.downloads != totals.downloads", &[])?;
+    println!("reconciled downloads of {} crates", n);
+    tx.execute("UPDATE metadata SET total_downloads =
+                    (SELECT COALESCE(SUM(downloads), 0) FROM crates)", &[])?;
+    tx.set_commit();
+    tx.finish()
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
@@ -138,6 +290,7 @@ mod test {
     use semver;
 
     use cargo_registry::{Version, Crate, User, Model, env};
+    use cargo_registry::alert;
 
     fn conn() -> postgres::Connection {
         postgres::Connection::connect(&env("TEST_DATABASE_URL")[..],
@@ -301,4 +454,97 @@ mod test {
         let krate2 = Crate::find(&tx, krate.id).unwrap();
         assert_eq!(krate2.updated_at, krate_before.updated_at);
     }
+
+    #[test]
+    fn reconcile_fixes_drifted_totals() {
+        let conn = conn();
+        let tx = conn.transaction().unwrap();
+        let user = user(&tx);
+        let krate = Crate::find_or_insert(&tx, "foo", user.id, &None,
+                                          &None, &None, &None, &None,
+                                          &None, &None, None).unwrap();
+        let version = Version::insert(&tx, krate.id,
+                                      &semver::Version::parse("1.0.0").unwrap(),
+                                      &HashMap::new(), &[]).unwrap();
+        tx.execute("INSERT INTO version_downloads \
+                    (version_id, downloads, counted, date, processed)
+                    VALUES ($1, 5, 5, current_date - interval '2 days', true)",
+                   &[&version.id]).unwrap();
+        tx.execute("UPDATE versions SET downloads = 42 WHERE id = $1",
+                   &[&version.id]).unwrap();
+        tx.execute("UPDATE crates SET downloads = 42 WHERE id = $1",
+                   &[&krate.id]).unwrap();
+        // Nothing was ever counted for these, so they shouldn't have any
+        let uncounted = Version::insert(&tx, krate.id,
+                                        &semver::Version::parse("1.1.0").unwrap(),
+                                        &HashMap::new(), &[]).unwrap();
+        tx.execute("UPDATE versions SET downloads = 7 WHERE id = $1",
+                   &[&uncounted.id]).unwrap();
+        let empty = Crate::find_or_insert(&tx, "bar", user.id, &None,
+                                          &None, &None, &None, &None,
+                                          &None, &None, None).unwrap();
+        tx.execute("UPDATE crates SET downloads = 3 WHERE id = $1",
+                   &[&empty.id]).unwrap();
+
+        ::reconcile(&tx).unwrap();
+        assert_eq!(Version::find(&tx, version.id).unwrap().downloads, 5);
+        assert_eq!(Version::find(&tx, uncounted.id).unwrap().downloads, 0);
+        assert_eq!(Crate::find(&tx, krate.id).unwrap().downloads, 5);
+        assert_eq!(Crate::find(&tx, empty.id).unwrap().downloads, 0);
+    }
+
+    #[test]
+    fn alerts_are_raised_once() {
+        let conn = conn();
+        let tx = conn.transaction().unwrap();
+        let user = user(&tx);
+        let krate = Crate::find_or_insert(&tx, "foo", user.id, &None,
+                                          &None, &None, &None, &None,
+                                          &None, &None, None).unwrap();
+        let version = Version::insert(&tx, krate.id,
+                                      &semver::Version::parse("1.0.0").unwrap(),
+                                      &HashMap::new(), &[]).unwrap();
+        tx.execute("INSERT INTO crate_settings
+                    (crate_id, download_thresholds, spike_alerts)
+                    VALUES ($1, '{100, 1000}', true)",
+                   &[&krate.id]).unwrap();
+        tx.execute("UPDATE crates SET downloads = 500 WHERE id = $1",
+                   &[&krate.id]).unwrap();
+        tx.execute("INSERT INTO version_downloads \
+                    (version_id, downloads, counted, date, processed)
+                    VALUES ($1, 10, 10, current_date - interval '3 days', true),
+                           ($1, 490, 490, current_date - interval '1 day', true)",
+                   &[&version.id]).unwrap();
+
+        let alerts = alert::evaluate(&tx).unwrap();
+        let mut kinds = alerts.iter()
+            .map(|alert| (&alert.kind[..], alert.threshold))
+            .collect::<Vec<_>>();
+        kinds.sort();
+        assert_eq!(kinds, [("milestone", Some(100)), ("spike", None)]);
+        assert!(alert::evaluate(&tx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn alerts_are_delivered_once() {
+        let conn = conn();
+        let tx = conn.transaction().unwrap();
+        let user = user(&tx);
+        let krate = Crate::find_or_insert(&tx, "foo", user.id, &None,
+                                          &None, &None, &None, &None,
+                                          &None, &None, None).unwrap();
+        tx.execute("INSERT INTO crate_settings
+                    (crate_id, download_thresholds, email_alerts)
+                    VALUES ($1, '{100}', true)",
+                   &[&krate.id]).unwrap();
+        tx.execute("UPDATE crates SET downloads = 500 WHERE id = $1",
+                   &[&krate.id]).unwrap();
+
+        let alerts = alert::evaluate(&tx).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert!(!alerts[0].delivered);
+        // Without webhooks or a mailer there's nothing to wait for
+        assert_eq!(alert::deliver_pending(&tx, None).unwrap(), 1);
+        assert_eq!(alert::deliver_pending(&tx, None).unwrap(), 0);
+    }
 }
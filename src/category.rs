@@ -4,14 +4,13 @@ use time::Timespec;
 use conduit::{Request, Response};
 use conduit_router::RequestParams;
 use diesel::*;
-use diesel::pg::PgConnection;
 use pg::GenericConnection;
 use pg::rows::Row;
 
-use db::RequestTransaction;
+use db::{InstrumentedConnection, RequestTransaction};
 use schema::*;
 use util::errors::NotFound;
-use util::{RequestUtils, CargoResult, ChainError};
+use util::{RequestUtils, CargoResult, ChainError, PaginationMeta};
 use {Model, Crate};
 
 #[derive(Clone, Identifiable, Queryable)]
@@ -59,9 +58,8 @@ pub struct EncodableCategoryWithSubcategories {
 impl Category {
     pub fn find_by_category(conn: &GenericConnection, name: &str)
                             -> CargoResult<Category> {
-        let stmt = conn.prepare("SELECT * FROM categories \
-                                      WHERE category = $1")?;
-        let rows = stmt.query(&[&name])?;
+        let rows = conn.query("SELECT * FROM categories \
+                                    WHERE category = $1", &[&name])?;
         rows.iter().next()
                    .chain_error(|| NotFound)
                    .map(|row| Model::from_row(&row))
@@ -69,9 +67,8 @@ impl Category {
 
     pub fn find_by_slug(conn: &GenericConnection, slug: &str)
                             -> CargoResult<Category> {
-        let stmt = conn.prepare("SELECT * FROM categories \
-                                      WHERE slug = LOWER($1)")?;
-        let rows = stmt.query(&[&slug])?;
+        let rows = conn.query("SELECT * FROM categories \
+                                    WHERE slug = LOWER($1)", &[&slug])?;
         rows.iter().next()
                    .chain_error(|| NotFound)
                    .map(|row| Model::from_row(&row))
@@ -91,7 +88,7 @@ impl Category {
         }
     }
 
-    pub fn update_crate<'a>(conn: &PgConnection,
+    pub fn update_crate<'a>(conn: &InstrumentedConnection,
                             krate: &Crate,
                             slugs: &[&'a str]) -> QueryResult<Vec<&'a str>> {
         use diesel::expression::dsl::any;
@@ -175,12 +172,11 @@ impl Category {
             WHERE category NOT LIKE '%::%'",
             Model::table_name(None::<Self>
         ));
-        let stmt = conn.prepare(&sql)?;
-        let rows = stmt.query(&[])?;
+        let rows = conn.query(&sql, &[])?;
         Ok(rows.iter().next().unwrap().get("count"))
     }
 
-    pub fn toplevel(conn: &PgConnection,
+    pub fn toplevel(conn: &InstrumentedConnection,
                     sort: &str,
                     limit: i64,
                     offset: i64) -> QueryResult<Vec<Category>> {
@@ -218,7 +214,7 @@ impl Category {
 
         // Collect all the top-level categories and sum up the crates_cnt of
         // the crates in all subcategories
-        let stmt = conn.prepare(&format!(
+        let rows = conn.query(&format!(
             "SELECT c.id, c.category, c.slug, c.description, c.created_at,
                 sum(c2.crates_cnt)::int as crates_cnt
              FROM categories as c
@@ -227,9 +223,9 @@ impl Category {
              GROUP BY c.id
              {} LIMIT $1 OFFSET $2",
             sort_sql
-        ))?;
+        ), &[&limit, &offset])?;
 
-        let categories: Vec<_> = stmt.query(&[&limit, &offset])?
+        let categories: Vec<_> = rows
             .iter()
             .map(|row| Model::from_row(&row))
             .collect();
@@ -239,7 +235,7 @@ impl Category {
 
     pub fn subcategories(&self, conn: &GenericConnection)
                                 -> CargoResult<Vec<Category>> {
-        let stmt = conn.prepare("\
+        let rows = conn.query("\
             SELECT c.id, c.category, c.slug, c.description, c.created_at, \
             COALESCE (( \
                 SELECT sum(c2.crates_cnt)::int \
@@ -249,11 +245,61 @@ impl Category {
             ), 0) as crates_cnt \
             FROM categories as c \
             WHERE c.category ILIKE $1 || '::%' \
-            AND c.category NOT ILIKE $1 || '::%::%'")?;
-
-        let rows = stmt.query(&[&self.category])?;
+            AND c.category NOT ILIKE $1 || '::%::%'", &[&self.category])?;
         Ok(rows.iter().map(|r| Model::from_row(&r)).collect())
     }
+
+    /// Suggests up to three categories for a version of a crate which was
+    /// published without any, from the categories of its dependencies and
+    /// the words its description shares with the names of categories.
+    pub fn suggest(conn: &InstrumentedConnection,
+                   version_id: i32,
+                   description: Option<&str>) -> QueryResult<Vec<String>> {
+        use diesel::expression::dsl::any;
+
+        let dependency_ids = dependencies::table
+            .filter(dependencies::version_id.eq(version_id))
+            .select(dependencies::crate_id)
+            .load::<i32>(conn)?;
+        let dependency_categories = crates_categories::table
+            .filter(crates_categories::crate_id.eq(any(dependency_ids)))
+            .select(crates_categories::category_id)
+            .load::<i32>(conn)?;
+        let description = description.map(stems).unwrap_or_else(HashSet::new);
+
+        let mut scored = categories::table.load::<Category>(conn)?.into_iter().filter_map(|c| {
+            // A dependency says more about a crate than a word does
+            let score = 2 * dependency_categories.iter().filter(|&&id| id == c.id).count() +
+                stems(&c.category).intersection(&description).count();
+            if score > 0 { Some((score, c)) } else { None }
+        }).collect::<Vec<_>>();
+        scored.sort_by(|&(a, ref c), &(b, ref d)| {
+            (b, d.crates_cnt, &c.slug).cmp(&(a, c.crates_cnt, &d.slug))
+        });
+        Ok(scored.into_iter().take(3).map(|(_, c)| c.slug).collect())
+    }
+}
+
+/// The distinct words of `text` worth comparing, lowercased and without the
+/// most common suffixes so that e.g. "parser" and "parsing" are the same.
+fn stems(text: &str) -> HashSet<String> {
+    const IGNORED: &'static [&'static str] = &["crate", "library", "rust", "that", "this",
+                                               "with", "from", "your"];
+    const SUFFIXES: &'static [&'static str] = &["ations", "ation", "ings", "ing", "ers",
+                                                "er", "es", "s"];
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.len() >= 4 && !IGNORED.iter().any(|w| *w == word))
+        .map(|mut word| {
+            let stem = SUFFIXES.iter()
+                .find(|s| word.len() >= s.len() + 4 && word.ends_with(**s))
+                .map(|s| word.len() - s.len());
+            if let Some(len) = stem {
+                word.truncate(len);
+            }
+            word
+        })
+        .collect()
 }
 
 #[derive(Insertable, Default)]
@@ -264,7 +310,7 @@ pub struct NewCategory<'a> {
 }
 
 impl<'a> NewCategory<'a> {
-    pub fn find_or_create(&self, conn: &PgConnection) -> QueryResult<Category> {
+    pub fn find_or_create(&self, conn: &InstrumentedConnection) -> QueryResult<Category> {
         use schema::categories::dsl::*;
         use diesel::pg::upsert::*;
 
@@ -310,13 +356,11 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
     let total = Category::count_toplevel(conn)?;
 
     #[derive(RustcEncodable)]
-    struct R { categories: Vec<EncodableCategory>, meta: Meta }
-    #[derive(RustcEncodable)]
-    struct Meta { total: i64 }
+    struct R { categories: Vec<EncodableCategory>, meta: PaginationMeta }
 
     Ok(req.json(&R {
         categories: categories,
-        meta: Meta { total: total },
+        meta: req.pagination_meta(offset, limit, total),
     }))
 }
 
@@ -347,9 +391,8 @@ pub fn show(req: &mut Request) -> CargoResult<Response> {
 /// Handles the `GET /category_slugs` route.
 pub fn slugs(req: &mut Request) -> CargoResult<Response> {
     let conn = req.tx()?;
-    let stmt = conn.prepare("SELECT slug FROM categories \
-                                  ORDER BY slug")?;
-    let rows = stmt.query(&[])?;
+    let rows = conn.query("SELECT slug FROM categories \
+                                ORDER BY slug", &[])?;
 
     #[derive(RustcEncodable)]
     struct Slug { id: String, slug: String }
@@ -371,11 +414,11 @@ mod tests {
     use dotenv::dotenv;
     use std::env;
 
-    fn pg_connection() -> PgConnection {
+    fn pg_connection() -> InstrumentedConnection {
         let _ = dotenv();
         let database_url = env::var("TEST_DATABASE_URL")
             .expect("TEST_DATABASE_URL must be set to run tests");
-        let conn = PgConnection::establish(&database_url).unwrap();
+        let conn = InstrumentedConnection::establish(&database_url).unwrap();
         // These tests deadlock if run concurrently
         conn.batch_execute("BEGIN; LOCK categories IN ACCESS EXCLUSIVE MODE").unwrap();
         conn
@@ -0,0 +1,167 @@
+//! Purging rows of pending or finished state which nothing needs any more.
+//!
+//! Each `Task` purges one table, keeping the rows of the last `days` days.
+//! Every task has a default retention, which `CLEANUP_RETENTION_DAYS` can
+//! change, e.g. `CLEANUP_RETENTION_DAYS=api_tokens=90,owner_approvals=30`.
+//! The background worker runs the tasks every `INTERVAL_SECS`, and logs how
+//! many rows each of them purged and how long that took.
+//!
+//! Held publishes aren't purged however old they are: dropping the hold
+//! would lose the version's index entry without releasing or rejecting it,
+//! so they stay until an admin decides. OAuth states only live in the
+//! session cookie, and there are no email confirmations to expire.
+
+use std::collections::HashMap;
+use std::env;
+use std::time::Instant;
+
+use pg::GenericConnection;
+
+use owner_approval;
+use token;
+use util::{CargoResult, human};
+
+/// How often the background worker purges, in seconds.
+pub const INTERVAL_SECS: u64 = 60 * 60;
+
+pub struct Task {
+    /// The table purged, which is also the task's name in
+    /// `CLEANUP_RETENTION_DAYS`
+    pub table: &'static str,
+    /// How many days of rows are kept unless configured otherwise
+    pub default_days: u32,
+    purge: fn(&GenericConnection, u32) -> CargoResult<u64>,
+}
+
+pub const TASKS: &'static [Task] = &[
+    Task { table: "api_tokens", default_days: 30, purge: purge_api_tokens },
+    Task { table: "api_token_usage", default_days: 90, purge: purge_api_token_usage },
+    Task { table: "owner_approvals", default_days: 90, purge: purge_owner_approvals },
+    Task { table: "publish_idempotency_keys", default_days: 2, purge: purge_idempotency_keys },
+];
+
+/// How many days of rows each task keeps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retention {
+    days: HashMap<&'static str, u32>,
+}
+
+/// What one task purged.
+#[derive(Debug)]
+pub struct Purged {
+    pub table: &'static str,
+    pub days: u32,
+    pub rows: u64,
+    pub duration_ms: u64,
+}
+
+impl Retention {
+    /// Parses `CLEANUP_RETENTION_DAYS`, every task it doesn't mention keeping
+    /// its default.
+    pub fn from_env() -> CargoResult<Retention> {
+        match env::var("CLEANUP_RETENTION_DAYS") {
+            Ok(s) => Retention::parse(&s),
+            Err(..) => Ok(Retention::default()),
+        }
+    }
+
+    /// Parses retentions written like `api_tokens=90,owner_approvals=30`.
+    pub fn parse(s: &str) -> CargoResult<Retention> {
+        let mut retention = Retention::default();
+        for part in s.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let mut kv = part.splitn(2, '=');
+            let table = kv.next().unwrap().trim();
+            let task = TASKS.iter().find(|task| task.table == table).ok_or_else(|| {
+                human(&format_args!("there is no cleanup of `{}`", table))
+            })?;
+            let days = kv.next().and_then(|d| d.trim().parse().ok()).ok_or_else(|| {
+                human(&format_args!("the retention of `{}` should be a number of days",
+                                    table))
+            })?;
+            retention.days.insert(task.table, days);
+        }
+        Ok(retention)
+    }
+
+    pub fn days(&self, table: &str) -> Option<u32> {
+        self.days.get(table).cloned()
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Retention {
+        Retention {
+            days: TASKS.iter().map(|task| (task.table, task.default_days)).collect(),
+        }
+    }
+}
+
+/// Runs every task, each in its own transaction so that one failing doesn't
+/// undo the others.
+pub fn run(conn: &GenericConnection, retention: &Retention) -> CargoResult<Vec<Purged>> {
+    let mut purged = Vec::new();
+    for task in TASKS {
+        let days = retention.days(task.table).unwrap_or(task.default_days);
+        let start = Instant::now();
+        let tx = conn.transaction()?;
+        let rows = (task.purge)(&tx, days)?;
+        tx.commit()?;
+
+        let elapsed = start.elapsed();
+        let millis = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        info!("cleanup table={} retention_days={} purged={} duration_ms={}",
+              task.table, days, rows, millis);
+        purged.push(Purged { table: task.table, days: days, rows: rows, duration_ms: millis });
+    }
+    Ok(purged)
+}
+
+fn purge_api_tokens(conn: &GenericConnection, days: u32) -> CargoResult<u64> {
+    token::purge_expired(conn, days)
+}
+
+/// Daily request counts of tokens, which are shown for 90 days, see
+/// `token::usage`.
+fn purge_api_token_usage(conn: &GenericConnection, days: u32) -> CargoResult<u64> {
+    let n = conn.execute("\
+        DELETE FROM api_token_usage
+         WHERE date <= CURRENT_DATE - $1::int4",
+        &[&(days as i32)])?;
+    Ok(n)
+}
+
+/// Approvals which were approved, rejected or expired more than `days` ago.
+fn purge_owner_approvals(conn: &GenericConnection, days: u32) -> CargoResult<u64> {
+    let n = conn.execute("\
+        DELETE FROM owner_approvals
+         WHERE COALESCE(resolved_at, created_at + $2::int4 * INTERVAL '1 day')
+               < now() - $1::int4 * INTERVAL '1 day'",
+        &[&(days as i32), &(owner_approval::EXPIRY_DAYS as i32)])?;
+    Ok(n)
+}
+
+/// Idempotency keys, which can only be replayed for a day anyway.
+fn purge_idempotency_keys(conn: &GenericConnection, days: u32) -> CargoResult<u64> {
+    let n = conn.execute("\
+        DELETE FROM publish_idempotency_keys
+         WHERE created_at < now() - $1::int4 * INTERVAL '1 day'",
+        &[&(days as i32)])?;
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Retention;
+
+    #[test]
+    fn parse_retention() {
+        let retention = Retention::parse("api_tokens=90, owner_approvals = 7").unwrap();
+        assert_eq!(retention.days("api_tokens"), Some(90));
+        assert_eq!(retention.days("owner_approvals"), Some(7));
+        assert_eq!(retention.days("publish_idempotency_keys"), Some(2));
+        assert_eq!(retention.days("api_token_usage"), Some(90));
+        assert_eq!(Retention::parse("").unwrap(), Retention::default());
+        assert!(Retention::parse("sessions=1").is_err());
+        assert!(Retention::parse("api_tokens=soon").is_err());
+    }
+}
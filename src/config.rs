@@ -1,4 +1,9 @@
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use scanner::{Scanner, ScanPolicy};
+use signature::SigningKey;
+use util::Cidr;
 use {Uploader, Replica};
 
 #[derive(Clone)]
@@ -13,4 +18,163 @@ pub struct Config {
     pub max_upload_size: u64,
     pub mirror: Replica,
     pub api_protocol: String,
+    pub download_stats: bool,
+    pub admin_gh_ids: Vec<i32>,
+    pub search_weights: SearchWeights,
+    /// Whether owners can make crates private, so that only the users and
+    /// teams they grant access to can see and download them. Meant for
+    /// self-hosted registries, crates.io leaves it off.
+    pub private_crates: bool,
+    /// API tokens which haven't been used for this many days stop working.
+    /// Tokens are never considered stale if this isn't set.
+    pub stale_token_days: Option<u32>,
+    /// The proxies and load balancers in front of the app, whose
+    /// `X-Forwarded-For` headers are believed when working out the client's
+    /// address.
+    pub trusted_proxies: Vec<Cidr>,
+    /// What published crates are scanned for, see the `scanner` module.
+    pub scanners: Vec<Arc<Scanner>>,
+    /// What happens to publishes the scanners find something in.
+    pub scan_policy: ScanPolicy,
+    /// The key published versions are signed with, see the `signature`
+    /// module. Versions aren't signed if this isn't set.
+    pub signing_key: Option<Arc<SigningKey>>,
+    /// Whether index entries get the fields older cargos don't know about,
+    /// see `git::Crate`. Crates whose features need them are rejected when
+    /// this is off.
+    pub index_v2: bool,
+    /// Queries which take at least this many milliseconds are logged along
+    /// with the route they were made for. Nothing is logged if this isn't set.
+    pub slow_query_ms: Option<u64>,
+    /// The most items any paginated endpoint returns at once, whatever its
+    /// own limit is.
+    pub max_per_page: usize,
+    /// Where downloads are redirected to, with the `{crate}`, `{version}` and
+    /// `{prefix}` placeholders filled in, e.g. for a CDN in front of the
+    /// bucket. Downloads are redirected to the uploader's location of the
+    /// crate file if this isn't set.
+    pub download_url_template: Option<String>,
+    /// Whether downloads are answered with the crate file itself rather than
+    /// redirected. Meant for development with the local uploader, when
+    /// nothing else serves the uploaded files.
+    pub serve_downloads: bool,
+    /// Whether downloads are counted in memory and written in batches, see
+    /// `download::PendingDownloads`, rather than written one by one as they
+    /// happen. While this is `Shadow` the batches go to
+    /// `version_downloads_shadow` instead, for `compare-download-counts` to
+    /// check against the counts written one by one.
+    pub async_downloads: Rollout,
+    /// How many seconds a client's repeated downloads of the same version are
+    /// counted once for, zero counting every download.
+    pub download_dedupe_secs: u64,
+    /// The largest request body most routes accept, in bytes. Publishes
+    /// check their own limit, see `util::body_limit`.
+    pub max_body_size: u64,
+    /// How long a request body can take to arrive, in seconds. Routes taking
+    /// larger bodies get a multiple of this.
+    pub body_timeout_secs: u64,
+}
+
+/// How far a change to the way something is done has been rolled out, set
+/// with a `FLAG_*` environment variable, e.g. `FLAG_ASYNC_DOWNLOADS=shadow`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rollout {
+    /// Only the old way is used.
+    Off,
+    /// The old way is still the one that counts, and the new way runs next
+    /// to it writing to a table of its own, so that the two can be compared
+    /// before the cutover.
+    Shadow,
+    /// Only the new way is used.
+    On,
+}
+
+impl Rollout {
+    /// Parses `off`, `shadow` or `on`.
+    pub fn parse(s: &str) -> Option<Rollout> {
+        match &s.trim().to_lowercase()[..] {
+            "off" => Some(Rollout::Off),
+            "shadow" => Some(Rollout::Shadow),
+            "on" => Some(Rollout::On),
+            _ => None,
+        }
+    }
+
+    /// Whether the old way still has to run.
+    pub fn runs_old(self) -> bool {
+        self != Rollout::On
+    }
+
+    /// Whether the new way has to run, in earnest or as a shadow.
+    pub fn runs_new(self) -> bool {
+        self != Rollout::Off
+    }
+}
+
+/// How much a match in each part of a crate's search document counts towards
+/// its rank. Only the ratios between the weights matter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchWeights {
+    pub name: f32,
+    pub keywords: f32,
+    pub description: f32,
+    pub readme: f32,
+}
+
+impl SearchWeights {
+    /// Parses weights given as `name,keywords,description,readme`, e.g.
+    /// `1.0,0.4,0.2,0.1`.
+    pub fn parse(s: &str) -> Option<SearchWeights> {
+        let weights = s.split(',')
+            .map(|w| w.trim().parse::<f32>().ok().and_then(|w| {
+                if w >= 0.0 && w <= 1.0 { Some(w) } else { None }
+            }))
+            .collect::<Option<Vec<f32>>>();
+        match weights {
+            Some(ref w) if w.len() == 4 => Some(SearchWeights {
+                name: w[0],
+                keywords: w[1],
+                description: w[2],
+                readme: w[3],
+            }),
+            _ => None,
+        }
+    }
+
+    /// The weights in the order postgres' ranking functions expect them,
+    /// which is from the `D` label (the README) up to `A` (the name).
+    pub fn as_pg_array(&self) -> Vec<f32> {
+        vec![self.readme, self.description, self.keywords, self.name]
+    }
+}
+
+impl Default for SearchWeights {
+    /// The weights postgres uses when none are given.
+    fn default() -> SearchWeights {
+        SearchWeights { name: 1.0, keywords: 0.4, description: 0.2, readme: 0.1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rollout, SearchWeights};
+
+    #[test]
+    fn parse_search_weights() {
+        assert_eq!(SearchWeights::parse("1.0, 0.5,0.25,0"),
+                   Some(SearchWeights { name: 1.0, keywords: 0.5, description: 0.25, readme: 0.0 }));
+        assert_eq!(SearchWeights::parse("1.0,0.5,0.25"), None);
+        assert_eq!(SearchWeights::parse("1.0,0.5,0.25,2"), None);
+        assert_eq!(SearchWeights::parse("a,b,c,d"), None);
+    }
+
+    #[test]
+    fn parse_rollout() {
+        assert_eq!(Rollout::parse("off"), Some(Rollout::Off));
+        assert_eq!(Rollout::parse(" Shadow"), Some(Rollout::Shadow));
+        assert_eq!(Rollout::parse("on"), Some(Rollout::On));
+        assert_eq!(Rollout::parse("yes"), None);
+        assert!(Rollout::Shadow.runs_old() && Rollout::Shadow.runs_new());
+        assert!(!Rollout::On.runs_old() && !Rollout::Off.runs_new());
+    }
 }
@@ -1,15 +1,25 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::env;
 use std::error::Error;
 use std::fmt;
 use std::mem;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use conduit::{Request, Response};
 use conduit_middleware::Middleware;
-use diesel::pg::PgConnection;
+use diesel::connection::{AnsiTransactionManager, SimpleConnection};
+use diesel::pg::{Pg, PgConnection, PgQueryBuilder};
+use diesel::query_builder::{AsQuery, QueryBuilder, QueryFragment, QueryId};
+use diesel::query_source::Queryable;
+use diesel::result::{ConnectionResult, QueryResult};
+use diesel::types::HasSqlType;
+use diesel::Connection;
 use openssl::ssl::{SslConnector, SslConnectorBuilder, SslMethod, SSL_VERIFY_NONE};
 use pg::GenericConnection;
+use pg::rows::Rows;
+use pg::stmt::Statement;
+use pg::types::ToSql;
 use pg::tls::{TlsHandshake, Stream, TlsStream};
 use pg;
 use r2d2;
@@ -21,13 +31,13 @@ use r2d2_diesel::{self, ConnectionManager};
 use url::Url;
 
 use app::{App, RequestApp};
-use util::{CargoResult, LazyCell, internal};
+use util::{CargoResult, LazyCell, RouteName, internal};
 
 pub type Pool = r2d2::Pool<PCM>;
 pub type Config = r2d2::Config<pg::Connection, r2d2_postgres::Error>;
 type PooledConnnection = r2d2::PooledConnection<PCM>;
-pub type DieselPool = r2d2::Pool<ConnectionManager<PgConnection>>;
-type DieselPooledConn = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
+pub type DieselPool = r2d2::Pool<ConnectionManager<InstrumentedConnection>>;
+type DieselPooledConn = r2d2::PooledConnection<ConnectionManager<InstrumentedConnection>>;
 
 /// Creates a TLS handshake mechanism used by our postgres driver to negotiate
 /// the TLS connection.
@@ -103,7 +113,9 @@ pub fn pool(url: &str, config: r2d2::Config<postgres::Connection, r2d2_postgres:
     r2d2::Pool::new(config, mgr).unwrap()
 }
 
-pub fn diesel_pool(url: &str, config: r2d2::Config<PgConnection, r2d2_diesel::Error>) -> DieselPool {
+pub fn diesel_pool(url: &str,
+                   config: r2d2::Config<InstrumentedConnection, r2d2_diesel::Error>)
+                   -> DieselPool {
     let mut url = Url::parse(url).expect("Invalid database URL");
     if env::var("HEROKU").is_ok() && !url.query_pairs().any(|(k, _)| k == "sslmode") {
         url.query_pairs_mut().append_pair("sslmode", "require");
@@ -112,6 +124,206 @@ pub fn diesel_pool(url: &str, config: r2d2::Config<PgConnection, r2d2_diesel::Er
     r2d2::Pool::new(config, manager).unwrap()
 }
 
+/// How long the queries of a request took in total, for the request log.
+#[derive(Default)]
+pub struct QueryTimer {
+    totals: Mutex<(Duration, u32)>,
+}
+
+impl QueryTimer {
+    fn record(&self, elapsed: Duration) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.0 += elapsed;
+        totals.1 += 1;
+    }
+
+    /// The time spent running queries and how many there were.
+    pub fn totals(&self) -> (Duration, u32) {
+        *self.totals.lock().unwrap()
+    }
+}
+
+/// What a connection is currently running queries for, see
+/// `InstrumentedConnection` and `InstrumentedTransaction`.
+struct Instrumentation {
+    route: &'static str,
+    slow_query_threshold: Option<Duration>,
+    timer: Weak<QueryTimer>,
+}
+
+impl Instrumentation {
+    fn for_request<T: Request + ?Sized>(req: &T) -> Instrumentation {
+        Instrumentation {
+            route: req.extensions().find::<RouteName>().map(|r| r.0).unwrap_or("-"),
+            slow_query_threshold: req.app().config.slow_query_ms.map(Duration::from_millis),
+            timer: req.extensions().find::<Arc<QueryTimer>>()
+                .map(Arc::downgrade)
+                .unwrap_or_else(Weak::new),
+        }
+    }
+
+    fn logs_slow_queries(&self) -> bool {
+        self.slow_query_threshold.is_some()
+    }
+
+    fn time<T, F, S>(&self, run: F, sql: S) -> T
+        where F: FnOnce() -> T, S: FnOnce() -> String,
+    {
+        let start = Instant::now();
+        let result = run();
+        let elapsed = start.elapsed();
+        if let Some(timer) = self.timer.upgrade() {
+            timer.record(elapsed);
+        }
+        match self.slow_query_threshold {
+            Some(threshold) if elapsed >= threshold => {
+                warn!("slow query route={} duration={}ms sql={:?}",
+                      self.route, millis(elapsed), sql());
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+/// A diesel connection which times the queries run through it.
+///
+/// `RequestTransaction::db_conn` points each connection it hands out at the
+/// request it's for. The time spent in queries is then added to the request's
+/// `QueryTimer`, and queries slower than `Config::slow_query_ms` are logged
+/// along with the route they were made for. The connection underneath isn't
+/// reachable, so that no query can get past the timing.
+pub struct InstrumentedConnection {
+    conn: PgConnection,
+    instrumentation: RefCell<Option<Instrumentation>>,
+}
+
+impl InstrumentedConnection {
+    fn instrument(&self, instrumentation: Option<Instrumentation>) {
+        *self.instrumentation.borrow_mut() = instrumentation;
+    }
+
+    fn logs_slow_queries(&self) -> bool {
+        self.instrumentation.borrow().as_ref()
+            .map(Instrumentation::logs_slow_queries)
+            .unwrap_or(false)
+    }
+
+    fn timed<T, F, S>(&self, run: F, sql: S) -> T
+        where F: FnOnce() -> T, S: FnOnce() -> String,
+    {
+        match *self.instrumentation.borrow() {
+            Some(ref instrumentation) => instrumentation.time(run, sql),
+            None => run(),
+        }
+    }
+}
+
+fn to_sql<T: QueryFragment<Pg>>(query: &T) -> String {
+    let mut query_builder = PgQueryBuilder::new();
+    match query.to_sql(&mut query_builder) {
+        Ok(()) => query_builder.finish(),
+        Err(_) => String::from("<unprintable query>"),
+    }
+}
+
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+impl SimpleConnection for InstrumentedConnection {
+    fn batch_execute(&self, query: &str) -> QueryResult<()> {
+        self.timed(|| self.conn.batch_execute(query), || query.to_string())
+    }
+}
+
+impl Connection for InstrumentedConnection {
+    type Backend = Pg;
+    type TransactionManager = AnsiTransactionManager;
+
+    fn establish(database_url: &str) -> ConnectionResult<InstrumentedConnection> {
+        PgConnection::establish(database_url).map(|conn| {
+            InstrumentedConnection { conn: conn, instrumentation: RefCell::new(None) }
+        })
+    }
+
+    fn execute(&self, query: &str) -> QueryResult<usize> {
+        self.timed(|| self.conn.execute(query), || query.to_string())
+    }
+
+    fn query_all<T, U>(&self, source: T) -> QueryResult<Vec<U>> where
+        T: AsQuery,
+        T::Query: QueryFragment<Pg> + QueryId,
+        Pg: HasSqlType<T::SqlType>,
+        U: Queryable<T::SqlType, Pg>,
+    {
+        // The query is moved into the connection underneath, so its SQL has
+        // to be built up front in case it turns out to be slow
+        let query = source.as_query();
+        let sql = if self.logs_slow_queries() { to_sql(&query) } else { String::new() };
+        self.timed(|| self.conn.query_all(query), || sql)
+    }
+
+    fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize> where
+        T: QueryFragment<Pg> + QueryId,
+    {
+        self.timed(|| self.conn.execute_returning_count(source), || to_sql(source))
+    }
+
+    fn silence_notices<F: FnOnce() -> T, T>(&self, f: F) -> T {
+        self.conn.silence_notices(f)
+    }
+
+    fn transaction_manager(&self) -> &AnsiTransactionManager {
+        self.conn.transaction_manager()
+    }
+
+    fn setup_helper_functions(&self) {
+        self.conn.setup_helper_functions()
+    }
+}
+
+/// The transaction of a request, which times the queries run through it
+/// like `InstrumentedConnection` does.
+///
+/// Statements handed out by `prepare` are run without it knowing, so queries
+/// are run with `query` and `execute` instead, which prepare and run them in
+/// one go. Queries run in savepoints aren't timed either.
+struct InstrumentedTransaction {
+    tx: pg::transaction::Transaction<'static>,
+    instrumentation: Instrumentation,
+}
+
+impl GenericConnection for InstrumentedTransaction {
+    fn execute(&self, query: &str, params: &[&ToSql]) -> pg::Result<u64> {
+        self.instrumentation.time(|| self.tx.execute(query, params), || query.to_string())
+    }
+
+    fn query<'a>(&'a self, query: &str, params: &[&ToSql]) -> pg::Result<Rows<'a>> {
+        self.instrumentation.time(|| self.tx.query(query, params), || query.to_string())
+    }
+
+    fn prepare<'a>(&'a self, query: &str) -> pg::Result<Statement<'a>> {
+        self.tx.prepare(query)
+    }
+
+    fn prepare_cached<'a>(&'a self, query: &str) -> pg::Result<Statement<'a>> {
+        self.tx.prepare_cached(query)
+    }
+
+    fn transaction<'a>(&'a self) -> pg::Result<pg::transaction::Transaction<'a>> {
+        self.tx.transaction()
+    }
+
+    fn batch_execute(&self, query: &str) -> pg::Result<()> {
+        self.instrumentation.time(|| self.tx.batch_execute(query), || query.to_string())
+    }
+
+    fn is_active(&self) -> bool {
+        self.tx.is_active()
+    }
+}
+
 pub struct TransactionMiddleware;
 
 pub struct Transaction {
@@ -123,9 +335,10 @@ pub struct Transaction {
     // into `PooledConnnection`, but this `Transaction` can be moved around in
     // memory, so we need the borrow to be from a stable address. The `Box` will
     // provide this stable address.
-    tx: LazyCell<pg::transaction::Transaction<'static>>,
+    tx: LazyCell<InstrumentedTransaction>,
     slot: LazyCell<Box<PooledConnnection>>,
     commit: Cell<Option<bool>>,
+    after_commit: RefCell<Vec<Box<Fn() + Send>>>,
 
     // Keep a handle to the app which keeps a handle to the database to ensure
     // that this `'static` is indeed at least a little more accurate (in that
@@ -140,6 +353,7 @@ impl Transaction {
             slot: LazyCell::new(),
             tx: LazyCell::new(),
             commit: Cell::new(None),
+            after_commit: RefCell::new(Vec::new()),
         }
     }
 
@@ -153,7 +367,9 @@ impl Transaction {
         Ok(&**self.slot.borrow().unwrap())
     }
 
-    fn tx(&self) -> CargoResult<&GenericConnection> {
+    fn tx<F>(&self, instrumentation: F) -> CargoResult<&GenericConnection>
+        where F: FnOnce() -> Instrumentation,
+    {
         // Similar to above, the transaction for this request is actually tied
         // to the connection in the request itself, not 'static. We transmute it
         // to static as its paired with the inner connection to achieve the
@@ -163,11 +379,14 @@ impl Transaction {
                 let conn = self.conn()?;
                 let t = conn.transaction()?;
                 let t = mem::transmute::<_, pg::transaction::Transaction<'static>>(t);
-                self.tx.fill(t);
+                self.tx.fill(InstrumentedTransaction {
+                    tx: t,
+                    instrumentation: instrumentation(),
+                });
             }
         }
         let tx = self.tx.borrow();
-        let tx: &pg::transaction::Transaction<'static> = tx.unwrap();
+        let tx: &InstrumentedTransaction = tx.unwrap();
         Ok(tx)
     }
 
@@ -180,6 +399,12 @@ impl Transaction {
             self.commit.set(Some(true));
         }
     }
+
+    /// Runs `f` once the transaction has been committed, which never happens
+    /// if it is rolled back.
+    pub fn after_commit(&self, f: Box<Fn() + Send>) {
+        self.after_commit.borrow_mut().push(f);
+    }
 }
 
 impl Middleware for TransactionMiddleware {
@@ -193,13 +418,19 @@ impl Middleware for TransactionMiddleware {
              -> Result<Response, Box<Error+Send>> {
         let tx = req.mut_extensions().pop::<Transaction>()
                     .expect("Transaction not present in request");
-        if let Some(transaction) = tx.tx.into_inner() {
-            if res.is_ok() && tx.commit.get() == Some(true) {
+        if let Some(InstrumentedTransaction { tx: transaction, .. }) = tx.tx.into_inner() {
+            let commit = res.is_ok() && tx.commit.get() == Some(true);
+            if commit {
                 transaction.set_commit();
             }
             transaction.finish().map_err(|e| {
                 Box::new(e) as Box<Error + Send>
             })?;
+            if commit {
+                for f in tx.after_commit.into_inner() {
+                    f();
+                }
+            }
         }
         res
     }
@@ -221,18 +452,21 @@ pub trait RequestTransaction {
     fn rollback(&self);
     /// Flag this transaction to be committed. Does not affect Diesel connections.
     fn commit(&self);
+    /// Run `f` once this transaction has been committed, if it ever is.
+    fn after_commit(&self, f: Box<Fn() + Send>);
 }
 
 impl<T: Request + ?Sized> RequestTransaction for T {
     fn db_conn(&self) -> CargoResult<DieselPooledConn> {
-        self.app().diesel_database.get()
-            .map_err(Into::into)
+        let conn = self.app().diesel_database.get()?;
+        conn.instrument(Some(Instrumentation::for_request(self)));
+        Ok(conn)
     }
 
     fn tx(&self) -> CargoResult<&GenericConnection> {
         self.extensions().find::<Transaction>()
             .expect("Transaction not present in request")
-            .tx()
+            .tx(|| Instrumentation::for_request(self))
     }
 
     fn rollback(&self) {
@@ -246,4 +480,10 @@ impl<T: Request + ?Sized> RequestTransaction for T {
             .expect("Transaction not present in request")
             .commit()
     }
+
+    fn after_commit(&self, f: Box<Fn() + Send>) {
+        self.extensions().find::<Transaction>()
+            .expect("Transaction not present in request")
+            .after_commit(f)
+    }
 }
@@ -1,10 +1,11 @@
 use diesel::prelude::*;
-use diesel::pg::{Pg, PgConnection};
+use diesel::pg::Pg;
 use pg::GenericConnection;
 use pg::rows::Row;
 use semver;
 
 use Model;
+use db::InstrumentedConnection;
 use git;
 use krate::{Crate, canon_crate_name};
 use schema::*;
@@ -73,14 +74,14 @@ impl Dependency {
                   features: &[String], target: &Option<String>)
                   -> CargoResult<Dependency> {
         let req = req.to_string();
-        let stmt = conn.prepare("INSERT INTO dependencies
-                                      (version_id, crate_id, req, optional,
-                                       default_features, features, target, kind)
-                                      VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&version_id, &crate_id, &req,
-            &optional, &default_features,
-            &features, target, &(kind as i32)])?;
+        let rows = conn.query("INSERT INTO dependencies
+                                    (version_id, crate_id, req, optional,
+                                     default_features, features, target, kind)
+                                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                                    RETURNING *",
+                              &[&version_id, &crate_id, &req,
+                                &optional, &default_features,
+                                &features, target, &(kind as i32)])?;
         Ok(Model::from_row(&rows.iter().next().unwrap()))
     }
 
@@ -120,7 +121,7 @@ impl ReverseDependency {
 }
 
 pub fn add_dependencies(
-    conn: &PgConnection,
+    conn: &InstrumentedConnection,
     deps: &[::upload::CrateDependency],
     version_id: i32,
 ) -> CargoResult<Vec<Dependency>> {
@@ -162,6 +163,84 @@ pub fn add_dependencies(
         .map_err(Into::into)
 }
 
+/// How often the background worker recounts the dependents of every crate,
+/// in seconds.
+pub const RECOUNT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+
+/// The crates depended on by the newest version of a crate which isn't
+/// yanked, which are the ones counting it among their dependents.
+pub fn newest_dependencies(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<Vec<i32>> {
+    use diesel::expression::dsl::sql;
+    use diesel::types::Integer;
+
+    let newest = versions::table
+        .filter(versions::crate_id.eq(crate_id))
+        .filter(versions::yanked.eq(false))
+        .order(sql::<Integer>("to_semver_no_prerelease(num) DESC NULLS LAST, id DESC"))
+        .select(versions::id)
+        .first::<i32>(conn)
+        .optional()?;
+    let newest = match newest {
+        Some(version_id) => version_id,
+        None => return Ok(Vec::new()),
+    };
+    let ids = dependencies::table
+        .filter(dependencies::version_id.eq(newest))
+        .select(dependencies::crate_id)
+        .distinct()
+        .load(conn)?;
+    Ok(ids)
+}
+
+/// Updates `dependents_cnt` after a publish or a yank changed which crates a
+/// crate depends on, from the `newest_dependencies` before to the ones after.
+pub fn update_dependents_cnt(conn: &InstrumentedConnection, before: &[i32], after: &[i32])
+                             -> CargoResult<()> {
+    use diesel::update;
+    use diesel::expression::dsl::any;
+
+    let added = after.iter().filter(|id| !before.contains(id)).cloned().collect::<Vec<_>>();
+    let removed = before.iter().filter(|id| !after.contains(id)).cloned().collect::<Vec<_>>();
+    if !added.is_empty() {
+        update(crates::table.filter(crates::id.eq(any(added))))
+            .set(crates::dependents_cnt.eq(crates::dependents_cnt + 1))
+            .execute(conn)?;
+    }
+    if !removed.is_empty() {
+        update(crates::table.filter(crates::id.eq(any(removed))))
+            .set(crates::dependents_cnt.eq(crates::dependents_cnt - 1))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Counts the dependents of every crate from scratch, fixing the counts which
+/// drifted from the incremental updates. Returns how many crates were fixed.
+pub fn recount_dependents(conn: &GenericConnection) -> CargoResult<u64> {
+    let tx = conn.transaction()?;
+    let n = tx.execute("\
+        UPDATE crates SET dependents_cnt = COALESCE(counts.cnt, 0)
+          FROM crates c
+          LEFT JOIN (
+            SELECT dependencies.crate_id, COUNT(DISTINCT versions.crate_id)::int4 cnt
+              FROM dependencies
+             INNER JOIN (
+                SELECT id, crate_id, row_number() OVER (
+                    PARTITION BY crate_id
+                    ORDER BY to_semver_no_prerelease(num) DESC NULLS LAST, id DESC
+                ) rn
+                  FROM versions
+                 WHERE NOT yanked
+            ) versions ON versions.id = dependencies.version_id
+             WHERE rn = 1
+             GROUP BY dependencies.crate_id
+          ) counts ON counts.crate_id = c.id
+         WHERE crates.id = c.id
+           AND crates.dependents_cnt != COALESCE(counts.cnt, 0)", &[])?;
+    tx.commit()?;
+    Ok(n)
+}
+
 impl Queryable<dependencies::SqlType, Pg> for Dependency {
     type Row = (i32, i32, i32, String, bool, bool, Vec<String>, Option<String>,
                 i32);
@@ -0,0 +1,278 @@
+//! Comparisons between two versions of a crate.
+//!
+//! Which files were added, removed or changed is worked out from the file
+//! listings recorded at publish time (see the `files` module), so it's cheap
+//! enough to do on every request. The patches of the text files need both
+//! crate files though, so they're only computed when someone asks for them:
+//! the request is queued in `version_diffs`, and the `compute-diffs` job
+//! fills in the patches, which are then served from there.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use pg::GenericConnection;
+use rustc_serialize::json;
+use semver;
+
+use db::RequestTransaction;
+use files::package_path;
+use permission;
+use util::{RequestUtils, CargoResult, TarballFile, human, unified_diff};
+use {Crate, Version};
+
+/// Files bigger than this on either side don't get a patch.
+pub const MAX_PATCH_FILE_SIZE: u64 = 64 * 1024;
+/// Lines of context around each change in the patches.
+const CONTEXT_LINES: usize = 3;
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableFileChange {
+    pub path: String,
+    pub old_size: Option<i64>,
+    pub new_size: Option<i64>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodablePatch {
+    pub path: String,
+    /// A unified diff, or `None` for binary files and files which are too
+    /// big to be diffed.
+    pub patch: Option<String>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableDiff {
+    pub from: String,
+    pub to: String,
+    pub added: Vec<EncodableFileChange>,
+    pub removed: Vec<EncodableFileChange>,
+    pub changed: Vec<EncodableFileChange>,
+    pub patches: Option<Vec<EncodablePatch>>,
+    /// Whether the patches were asked for but haven't been computed yet
+    pub patches_pending: bool,
+}
+
+/// The recorded files of a version, mapped from path to size and checksum.
+fn listing(conn: &GenericConnection, version: &Version)
+           -> CargoResult<BTreeMap<String, (i64, String)>> {
+    let rows = conn.query("SELECT path, size, checksum FROM version_files \
+                           WHERE version_id = $1", &[&version.id])?;
+    let files: BTreeMap<String, (i64, String)> = rows.iter()
+        .map(|row| (row.get("path"), (row.get("size"), row.get("checksum"))))
+        .collect();
+    if files.is_empty() {
+        return Err(human(&format_args!("the files of version `{}` were not recorded \
+                                        when it was published", version.num)))
+    }
+    Ok(files)
+}
+
+fn find_version(conn: &GenericConnection, krate: &Crate, num: &str) -> CargoResult<Version> {
+    let missing = || {
+        human(&format_args!("crate `{}` does not have a version `{}`", krate.name, num))
+    };
+    let vers = semver::Version::parse(num).map_err(|_| missing())?;
+    Version::find_by_num(conn, krate.id, &vers)?.ok_or_else(missing)
+}
+
+/// The patches between two versions, if they've been computed. Asking for
+/// patches which haven't been computed yet queues them up.
+fn cached_patches(conn: &GenericConnection, from: &Version, to: &Version)
+           -> CargoResult<Option<Vec<EncodablePatch>>> {
+    let rows = conn.query("SELECT patches FROM version_diffs \
+                           WHERE from_version_id = $1 AND to_version_id = $2",
+                          &[&from.id, &to.id])?;
+    let patches = match rows.iter().next() {
+        Some(row) => row.get::<_, Option<String>>("patches"),
+        None => {
+            conn.execute("INSERT INTO version_diffs (from_version_id, to_version_id) \
+                          VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                         &[&from.id, &to.id])?;
+            None
+        }
+    };
+    match patches {
+        Some(patches) => Ok(Some(json::decode(&patches).map_err(|_| {
+            human("the cached patches are corrupt")
+        })?)),
+        None => Ok(None),
+    }
+}
+
+/// Handles the `GET /crates/:crate_id/:version/diff/:other_version` route.
+///
+/// The patches are only included with `?patches=1`. Until they've been
+/// computed, `patches` is `null` and `patches_pending` is `true`.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = &req.params()["crate_id"];
+    let from_num = &req.params()["version"];
+    let to_num = &req.params()["other_version"];
+    let with_patches = req.query().get("patches").map(|s| s == "1" || s == "true")
+        .unwrap_or(false);
+    permission::ensure_readable(req, crate_name)?;
+
+    let tx = req.tx()?;
+    let krate = Crate::find_by_name(tx, crate_name)?;
+    let from = find_version(tx, &krate, from_num)?;
+    let to = find_version(tx, &krate, to_num)?;
+    let old = listing(tx, &from)?;
+    let new = listing(tx, &to)?;
+
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    for (path, &(new_size, ref new_checksum)) in &new {
+        match old.get(path) {
+            None => added.push(EncodableFileChange {
+                path: path.clone(),
+                old_size: None,
+                new_size: Some(new_size),
+            }),
+            Some(&(old_size, ref old_checksum)) if old_checksum != new_checksum => {
+                changed.push(EncodableFileChange {
+                    path: path.clone(),
+                    old_size: Some(old_size),
+                    new_size: Some(new_size),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    let removed = old.iter().filter(|&(path, _)| !new.contains_key(path)).map(|(path, file)| {
+        EncodableFileChange { path: path.clone(), old_size: Some(file.0), new_size: None }
+    }).collect();
+
+    let patches = if with_patches { cached_patches(tx, &from, &to)? } else { None };
+    let diff = EncodableDiff {
+        from: from.num.to_string(),
+        to: to.num.to_string(),
+        added: added,
+        removed: removed,
+        changed: changed,
+        patches_pending: with_patches && patches.is_none(),
+        patches: patches,
+    };
+
+    #[derive(RustcEncodable)]
+    struct R { diff: EncodableDiff }
+    Ok(req.json(&R { diff: diff }))
+}
+
+/// Computes the patches between the files of two versions of a crate, as
+/// unpacked from their crate files. Only files which differ get a patch.
+pub fn compute_patches(old: &[TarballFile], new: &[TarballFile]) -> Vec<EncodablePatch> {
+    let old = old.iter().map(|f| (package_path(&f.path), f)).collect::<HashMap<_, _>>();
+    let new = new.iter().map(|f| (package_path(&f.path), f)).collect::<HashMap<_, _>>();
+    let paths = old.keys().chain(new.keys()).cloned().collect::<BTreeSet<_>>();
+
+    paths.into_iter().filter_map(|path| {
+        let old_file = old.get(path).map(|f| *f);
+        let new_file = new.get(path).map(|f| *f);
+        if old_file.map(|f| &f.contents) == new_file.map(|f| &f.contents) {
+            return None
+        }
+        let patch = match (diffable_text(old_file), diffable_text(new_file)) {
+            (Some(old_text), Some(new_text)) => {
+                let old_name = old_file.map(|_| format!("a/{}", path));
+                let new_name = new_file.map(|_| format!("b/{}", path));
+                Some(format!("--- {}\n+++ {}\n{}",
+                             old_name.unwrap_or_else(|| "/dev/null".to_string()),
+                             new_name.unwrap_or_else(|| "/dev/null".to_string()),
+                             unified_diff(old_text, new_text, CONTEXT_LINES)))
+            }
+            _ => None,
+        };
+        Some(EncodablePatch { path: path.to_string(), patch: patch })
+    }).collect()
+}
+
+/// The contents of a file as text, with missing files being empty. `None` if
+/// the file is binary or too big.
+fn diffable_text(file: Option<&TarballFile>) -> Option<&str> {
+    match file {
+        None => Some(""),
+        Some(file) if file.size > MAX_PATCH_FILE_SIZE => None,
+        Some(file) => ::std::str::from_utf8(&file.contents).ok(),
+    }
+}
+
+/// Computes the patches which were asked for but haven't been computed yet.
+/// `fetch` returns the unpacked crate file of a crate's version. Returns how
+/// many diffs were computed.
+pub fn compute_pending(conn: &GenericConnection,
+                       fetch: &Fn(&str, &str) -> CargoResult<Vec<TarballFile>>)
+                       -> CargoResult<usize> {
+    let rows = conn.query("\
+        SELECT version_diffs.from_version_id, version_diffs.to_version_id,
+               crates.name, from_versions.num AS from_num, to_versions.num AS to_num
+          FROM version_diffs
+         INNER JOIN versions from_versions
+                 ON from_versions.id = version_diffs.from_version_id
+         INNER JOIN versions to_versions ON to_versions.id = version_diffs.to_version_id
+         INNER JOIN crates ON crates.id = from_versions.crate_id
+         WHERE version_diffs.patches IS NULL
+         ORDER BY version_diffs.requested_at", &[])?;
+    let mut computed = 0;
+    for row in rows.iter() {
+        let from_id: i32 = row.get("from_version_id");
+        let to_id: i32 = row.get("to_version_id");
+        let name: String = row.get("name");
+        let from_num: String = row.get("from_num");
+        let to_num: String = row.get("to_num");
+
+        let files = fetch(&name, &from_num).and_then(|old| {
+            fetch(&name, &to_num).map(|new| (old, new))
+        });
+        let (old, new) = match files {
+            Ok(files) => files,
+            Err(e) => {
+                println!("unable to diff {} {} and {}: {:?}", name, from_num, to_num, e);
+                continue
+            }
+        };
+        let patches = json::encode(&compute_patches(&old, &new)).unwrap();
+        conn.execute("UPDATE version_diffs SET patches = $3, computed_at = now() \
+                      WHERE from_version_id = $1 AND to_version_id = $2",
+                     &[&from_id, &to_id, &patches])?;
+        computed += 1;
+    }
+    Ok(computed)
+}
+
+#[cfg(test)]
+mod tests {
+    use util::TarballFile;
+    use super::compute_patches;
+
+    fn file(path: &str, contents: &[u8]) -> TarballFile {
+        TarballFile {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            contents: contents.to_vec(),
+        }
+    }
+
+    #[test]
+    fn only_differing_files_get_patches() {
+        let old = [
+            file("foo-0.1.0/Cargo.toml", b"[package]\nversion = \"0.1.0\"\n"),
+            file("foo-0.1.0/src/lib.rs", b"pub fn foo() {}\n"),
+            file("foo-0.1.0/src/old.rs", b"old\n"),
+        ];
+        let new = [
+            file("foo-0.1.1/Cargo.toml", b"[package]\nversion = \"0.1.1\"\n"),
+            file("foo-0.1.1/src/lib.rs", b"pub fn foo() {}\n"),
+            file("foo-0.1.1/logo.png", b"\x89PNG\r\n\x1a\n\xff"),
+        ];
+        let patches = compute_patches(&old, &new);
+        let paths = patches.iter().map(|p| &p.path[..]).collect::<Vec<_>>();
+        assert_eq!(paths, ["Cargo.toml", "logo.png", "src/old.rs"]);
+
+        assert_eq!(patches[0].patch.as_ref().unwrap(),
+                   "--- a/Cargo.toml\n+++ b/Cargo.toml\n\
+                    @@ -1,2 +1,2 @@\n [package]\n-version = \"0.1.0\"\n+version = \"0.1.1\"\n");
+        assert!(patches[1].patch.is_none());
+        assert_eq!(patches[2].patch.as_ref().unwrap(),
+                   "--- a/src/old.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-old\n");
+    }
+}
@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::path::PathBuf;
 
 use conduit::{Request, Response, Handler};
 use conduit_static::Static;
@@ -6,8 +7,17 @@ use conduit_middleware::AroundMiddleware;
 
 use util::RequestProxy;
 
+/// How long browsers can keep fingerprinted assets, which never change: a
+/// new build gives changed assets a new name.
+const IMMUTABLE_CACHE_CONTROL: &'static str = "public, max-age=31536000, immutable";
+
+/// `index.html` and the assets which aren't fingerprinted have to be checked
+/// for changes every time, so that a deploy takes effect right away.
+const REVALIDATE_CACHE_CONTROL: &'static str = "no-cache";
+
 pub struct Middleware {
     handler: Option<Box<Handler>>,
+    root: PathBuf,
     dist: Static,
 }
 
@@ -15,6 +25,7 @@ impl Default for  Middleware {
     fn default() -> Middleware {
         Middleware {
             handler: None,
+            root: PathBuf::from("dist"),
             dist: Static::new("dist"),
         }
     }
@@ -32,22 +43,106 @@ impl Handler for Middleware {
         // file, then keep going.
         match self.dist.call(req) {
             Ok(ref resp) if resp.status.0 == 404 => {}
+            Ok(resp) => return self.serve_asset(req, resp),
             ret => return ret,
         }
 
         // Second, if we're requesting html, then we've only got one page so
-        // serve up that page. Otherwise proxy on to the rest of the app.
+        // serve up that page, whichever client-side route is asked for. API
+        // routes are left alone so that they still 404 as json. Otherwise
+        // proxy on to the rest of the app.
         let wants_html = req.headers().find("Accept").map(|accept| {
             accept.iter().any(|s| s.contains("html"))
         }).unwrap_or(false);
-        if wants_html {
-            self.dist.call(&mut RequestProxy {
+        if wants_html && !req.path().starts_with("/api/") {
+            let mut resp = self.dist.call(&mut RequestProxy {
                 other: req,
                 path: Some("/index.html"),
                 method: None,
-            })
+            })?;
+            set_header(&mut resp, "Cache-Control", REVALIDATE_CACHE_CONTROL);
+            Ok(resp)
         } else {
             self.handler.as_ref().unwrap().call(req)
         }
     }
 }
+
+impl Middleware {
+    /// Adds cache headers to a static file, and swaps it for its gzipped
+    /// copy if the build has one next to it and the client takes gzip.
+    fn serve_asset(&self, req: &mut Request, mut resp: Response)
+                   -> Result<Response, Box<Error+Send>> {
+        let path = req.path().to_string();
+        let cache_control = if is_fingerprinted(&path) {
+            IMMUTABLE_CACHE_CONTROL
+        } else {
+            REVALIDATE_CACHE_CONTROL
+        };
+        set_header(&mut resp, "Cache-Control", cache_control);
+
+        let accepts_gzip = req.headers().find("Accept-Encoding").map(|encodings| {
+            encodings.iter().any(|s| s.split(',').any(|e| e.trim().starts_with("gzip")))
+        }).unwrap_or(false);
+        let gzip_path = format!("{}.gz", path);
+        if path.contains("..") || !self.root.join(&gzip_path[1..]).is_file() {
+            return Ok(resp)
+        }
+        set_header(&mut resp, "Vary", "Accept-Encoding");
+        if !accepts_gzip {
+            return Ok(resp)
+        }
+
+        let gzipped = self.dist.call(&mut RequestProxy {
+            other: req,
+            path: Some(&gzip_path[..]),
+            method: None,
+        })?;
+        if gzipped.status.0 != 200 {
+            return Ok(resp)
+        }
+        // The gzipped copy has the type of a `.gz` file, the original's type
+        // is kept
+        if let Some(length) = gzipped.headers.get("Content-Length") {
+            resp.headers.insert("Content-Length".to_string(), length.clone());
+        }
+        set_header(&mut resp, "Content-Encoding", "gzip");
+        resp.body = gzipped.body;
+        Ok(resp)
+    }
+}
+
+fn set_header(resp: &mut Response, name: &str, value: &str) {
+    resp.headers.insert(name.to_string(), vec![value.to_string()]);
+}
+
+/// Whether the file name has the `-<md5 in hex>` fingerprint ember-cli
+/// gives assets, e.g. `/assets/vendor-d41d8cd98f00b204e9800998ecf8427e.js`.
+fn is_fingerprinted(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let stem = match name.find('.') {
+        Some(i) => &name[..i],
+        None => name,
+    };
+    match stem.rfind('-') {
+        Some(i) => {
+            let fingerprint = &stem[i + 1..];
+            fingerprint.len() == 32 && fingerprint.chars().all(|c| c.is_digit(16))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_fingerprinted;
+
+    #[test]
+    fn fingerprinted_assets() {
+        assert!(is_fingerprinted("/assets/vendor-d41d8cd98f00b204e9800998ecf8427e.js"));
+        assert!(is_fingerprinted("/assets/cargo-d41d8cd98f00b204e9800998ecf8427e.css.map"));
+        assert!(!is_fingerprinted("/index.html"));
+        assert!(!is_fingerprinted("/assets/cargo.js"));
+        assert!(!is_fingerprinted("/assets/foo-bar.js"));
+    }
+}
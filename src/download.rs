@@ -1,7 +1,23 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
 use chrono::NaiveDate;
+use conduit::{Request, Response};
+use conduit_middleware::Middleware;
+use pg::GenericConnection;
 use pg::rows::Row;
 
+use admin;
+use app::{App, RequestApp};
+use db::{self, RequestTransaction};
 use Model;
+use util::{RequestUtils, CargoResult};
 
 pub struct VersionDownload {
     pub id: i32,
@@ -43,3 +59,630 @@ impl Model for VersionDownload {
 
     fn table_name(_: Option<VersionDownload>) -> &'static str { "version_downloads" }
 }
+
+/// How long downloads and token uses are held in memory before they're
+/// written.
+pub const FLUSH_INTERVAL_SECS: u64 = 10;
+
+/// Download increments which have been counted in memory but not yet written
+/// to `version_downloads`.
+///
+/// Every download request records its increment here, and once enough time
+/// has passed (or enough distinct rows are dirty) the whole batch is written
+/// with a single `INSERT ... ON CONFLICT DO UPDATE`, see `FlushMiddleware`.
+/// The server also writes the batch every `FLUSH_INTERVAL_SECS` and when it
+/// shuts down, see `flush_all`. Anything still pending when the process dies
+/// is lost, which is fine for counters like these.
+pub struct PendingDownloads {
+    pending: Mutex<Pending>,
+    flush_interval: Duration,
+    max_batch_size: usize,
+    table: &'static str,
+}
+
+struct Pending {
+    counts: HashMap<(i32, NaiveDate), i32>,
+    last_flush: Instant,
+    metrics: FlushMetrics,
+}
+
+/// How the flushes of a `PendingDownloads` went since the process started.
+#[derive(Clone, Copy, Debug, Default)]
+struct FlushMetrics {
+    flushes: u64,
+    failed_flushes: u64,
+    /// Rows of the table inserted or updated
+    rows: u64,
+    downloads: u64,
+    last_flush_ms: u64,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableFlushMetrics {
+    pub table: String,
+    /// Rows waiting for the next flush
+    pub pending_rows: u64,
+    pub flushes: u64,
+    pub failed_flushes: u64,
+    pub rows: u64,
+    pub downloads: u64,
+    pub last_flush_ms: u64,
+}
+
+impl PendingDownloads {
+    pub fn new(flush_interval: Duration, max_batch_size: usize) -> PendingDownloads {
+        PendingDownloads {
+            pending: Mutex::new(Pending {
+                counts: HashMap::new(),
+                last_flush: Instant::now(),
+                metrics: FlushMetrics::default(),
+            }),
+            flush_interval: flush_interval,
+            max_batch_size: max_batch_size,
+            table: "version_downloads",
+        }
+    }
+
+    /// Pending downloads which are written to `version_downloads_shadow`
+    /// rather than `version_downloads`, for the `Shadow` rollout of
+    /// `Config::async_downloads`.
+    pub fn shadow(flush_interval: Duration, max_batch_size: usize) -> PendingDownloads {
+        PendingDownloads {
+            table: "version_downloads_shadow",
+            ..PendingDownloads::new(flush_interval, max_batch_size)
+        }
+    }
+
+    /// Records a single download of `version_id` on `date`.
+    pub fn increment(&self, version_id: i32, date: NaiveDate) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.counts.entry((version_id, date)).or_insert(0) += 1;
+    }
+
+    /// The number of distinct `(version_id, date)` pairs waiting to be written.
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the flush interval has elapsed or the batch has grown past its
+    /// maximum size.
+    pub fn is_due(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.last_flush.elapsed() >= self.flush_interval ||
+            pending.counts.len() >= self.max_batch_size
+    }
+
+    /// Writes the pending batch if it is due.
+    pub fn flush_if_needed(&self, conn: &GenericConnection) -> CargoResult<()> {
+        if self.is_due() {
+            self.flush(conn)?;
+        }
+        Ok(())
+    }
+
+    /// How the flushes went so far.
+    pub fn metrics(&self) -> EncodableFlushMetrics {
+        let pending = self.pending.lock().unwrap();
+        let metrics = pending.metrics;
+        EncodableFlushMetrics {
+            table: self.table.to_string(),
+            pending_rows: pending.counts.len() as u64,
+            flushes: metrics.flushes,
+            failed_flushes: metrics.failed_flushes,
+            rows: metrics.rows,
+            downloads: metrics.downloads,
+            last_flush_ms: metrics.last_flush_ms,
+        }
+    }
+
+    /// Writes every pending increment to the table in one statement.
+    ///
+    /// If the write fails the batch is merged back into the pending counts so
+    /// that it will be retried on the next flush.
+    pub fn flush(&self, conn: &GenericConnection) -> CargoResult<()> {
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.last_flush = Instant::now();
+            mem::replace(&mut pending.counts, HashMap::new())
+        };
+        if batch.is_empty() {
+            return Ok(())
+        }
+
+        let start = Instant::now();
+        let mut version_ids = Vec::with_capacity(batch.len());
+        let mut downloads = Vec::with_capacity(batch.len());
+        let mut dates = Vec::with_capacity(batch.len());
+        for (&(version_id, date), &amt) in &batch {
+            version_ids.push(version_id);
+            downloads.push(amt);
+            dates.push(date);
+        }
+
+        let res = conn.execute(&format!("\
+            INSERT INTO {table} (version_id, downloads, date)
+            SELECT * FROM UNNEST($1::int4[], $2::int4[], $3::date[])
+            ON CONFLICT (version_id, date) DO UPDATE
+               SET downloads = {table}.downloads + EXCLUDED.downloads",
+            table = self.table),
+            &[&version_ids, &downloads, &dates]);
+
+        let mut pending = self.pending.lock().unwrap();
+        if let Err(e) = res {
+            pending.metrics.failed_flushes += 1;
+            for (key, amt) in batch {
+                *pending.counts.entry(key).or_insert(0) += amt;
+            }
+            return Err(e.into())
+        }
+
+        pending.metrics.flushes += 1;
+        pending.metrics.rows += batch.len() as u64;
+        pending.metrics.downloads += downloads.iter().map(|&amt| amt as u64).sum::<u64>();
+        pending.metrics.last_flush_ms = db::millis(start.elapsed());
+        Ok(())
+    }
+}
+
+/// Download statistics which have been counted in memory but not yet written
+/// to `version_download_stats`, batched like `PendingDownloads` so that a
+/// download doesn't wait for a write of its own.
+pub struct PendingDownloadStats {
+    pending: Mutex<PendingStats>,
+    flush_interval: Duration,
+    max_batch_size: usize,
+}
+
+struct PendingStats {
+    /// Keyed by version, date, user agent family and country
+    counts: HashMap<(i32, NaiveDate, String, String), i32>,
+    last_flush: Instant,
+}
+
+impl PendingDownloadStats {
+    pub fn new(flush_interval: Duration, max_batch_size: usize) -> PendingDownloadStats {
+        PendingDownloadStats {
+            pending: Mutex::new(PendingStats {
+                counts: HashMap::new(),
+                last_flush: Instant::now(),
+            }),
+            flush_interval: flush_interval,
+            max_batch_size: max_batch_size,
+        }
+    }
+
+    /// Records a single download of `version_id` on `date`.
+    pub fn increment(&self, version_id: i32, date: NaiveDate, user_agent: String,
+                     country: String) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.counts.entry((version_id, date, user_agent, country)).or_insert(0) += 1;
+    }
+
+    /// The number of distinct rows waiting to be written.
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().counts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the flush interval has elapsed or the batch has grown past its
+    /// maximum size.
+    pub fn is_due(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.last_flush.elapsed() >= self.flush_interval ||
+            pending.counts.len() >= self.max_batch_size
+    }
+
+    /// Writes every pending row in one statement, merging the batch back
+    /// into the pending counts if that fails.
+    pub fn flush(&self, conn: &GenericConnection) -> CargoResult<()> {
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.last_flush = Instant::now();
+            mem::replace(&mut pending.counts, HashMap::new())
+        };
+        if batch.is_empty() {
+            return Ok(())
+        }
+
+        let mut version_ids = Vec::with_capacity(batch.len());
+        let mut dates = Vec::with_capacity(batch.len());
+        let mut user_agents = Vec::with_capacity(batch.len());
+        let mut countries = Vec::with_capacity(batch.len());
+        let mut downloads = Vec::with_capacity(batch.len());
+        for (&(version_id, date, ref user_agent, ref country), &amt) in &batch {
+            version_ids.push(version_id);
+            dates.push(date);
+            user_agents.push(user_agent.clone());
+            countries.push(country.clone());
+            downloads.push(amt);
+        }
+
+        let res = conn.execute("\
+            INSERT INTO version_download_stats
+                        (version_id, date, user_agent, country, downloads)
+            SELECT * FROM UNNEST($1::int4[], $2::date[], $3::varchar[], $4::varchar[],
+                                 $5::int4[])
+            ON CONFLICT (version_id, date, user_agent, country) DO UPDATE
+               SET downloads = version_download_stats.downloads + EXCLUDED.downloads",
+            &[&version_ids, &dates, &user_agents, &countries, &downloads]);
+        if let Err(e) = res {
+            let mut pending = self.pending.lock().unwrap();
+            for (key, amt) in batch {
+                *pending.counts.entry(key).or_insert(0) += amt;
+            }
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
+
+/// Writes the pending downloads once a request is done.
+///
+/// The batches hold the downloads of every request since the last flush, so
+/// they aren't written in the transaction of whichever request they became
+/// due in, which would lose them all if that request rolled back. They're
+/// written on a connection and in a transaction of their own instead, except
+/// for requests made by tests, which all share the transaction of the test.
+/// Batches which fail to be written are kept for the next flush.
+///
+/// This has to be added before `db::TransactionMiddleware`, so that the
+/// request's connection is back in the pool by the time the batches are
+/// written.
+pub struct FlushMiddleware;
+
+impl Middleware for FlushMiddleware {
+    fn after(&self, req: &mut Request, res: Result<Response, Box<Error+Send>>)
+             -> Result<Response, Box<Error+Send>> {
+        let app = req.app().clone();
+        if !app.pending_downloads.is_due() && !app.shadow_downloads.is_due() &&
+           !app.pending_download_stats.is_due() {
+            return res
+        }
+
+        let flushed = if req.extensions().find::<db::Transaction>().is_some() {
+            req.tx().and_then(|tx| flush_batches(&app, tx, true))
+        } else {
+            flush_on_own_connection(&app, true)
+        };
+        if let Err(e) = flushed {
+            println!("failed to write downloads: {}", e);
+        }
+        res
+    }
+}
+
+/// Writes the batches whether they're due or not. The server does this
+/// every flush interval, so that downloads don't wait for the next request
+/// to be written, and once more when it shuts down.
+pub fn flush_all(app: &App) -> CargoResult<()> {
+    flush_on_own_connection(app, false)
+}
+
+fn flush_on_own_connection(app: &App, only_due: bool) -> CargoResult<()> {
+    let conn = app.database.get()?;
+    let tx = conn.transaction()?;
+    flush_batches(app, &tx, only_due)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Writes the batches, or only those which are due, each in a savepoint so
+/// that one failing can't take the other down with it.
+fn flush_batches(app: &App, conn: &GenericConnection, only_due: bool) -> CargoResult<()> {
+    for pending in &[&app.pending_downloads, &app.shadow_downloads] {
+        if only_due && !pending.is_due() {
+            continue
+        }
+        let savepoint = conn.transaction()?;
+        match pending.flush(&savepoint) {
+            Ok(()) => savepoint.commit()?,
+            Err(e) => println!("failed to write downloads to {}: {}", pending.table, e),
+        }
+    }
+    if !only_due || app.pending_download_stats.is_due() {
+        let savepoint = conn.transaction()?;
+        match app.pending_download_stats.flush(&savepoint) {
+            Ok(()) => savepoint.commit()?,
+            Err(e) => println!("failed to write download statistics: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Handles the `GET /admin/download_flushes` route.
+///
+/// Shows how the batched downloads have been written since the process
+/// started, for `version_downloads` and `version_downloads_shadow`.
+pub fn flushes(req: &mut Request) -> CargoResult<Response> {
+    admin::require_admin(req)?;
+    let app = req.app();
+
+    #[derive(RustcEncodable)]
+    struct R { flushes: Vec<EncodableFlushMetrics> }
+    Ok(req.json(&R {
+        flushes: vec![app.pending_downloads.metrics(), app.shadow_downloads.metrics()],
+    }))
+}
+
+/// The clients whose download of a version was counted recently.
+///
+/// A client downloading the same version again within the dedupe window of
+/// its last counted download isn't counted again, so that retries and CI
+/// loops don't inflate the counts, however `Config::async_downloads` writes
+/// them. Clients are told apart by a hash of their IP address, salted per
+/// process, so that the addresses themselves are never kept. Fingerprints
+/// are dropped once their window is over, and no more than `max_entries`
+/// are kept at once.
+pub struct RecentDownloads {
+    recent: Mutex<Recent>,
+    window: Option<Duration>,
+    max_entries: usize,
+    salt: RandomState,
+}
+
+struct Recent {
+    /// When each `(client hash, version_id)` was last counted
+    counted: HashMap<(u64, i32), Instant>,
+    last_pruned: Instant,
+}
+
+impl RecentDownloads {
+    /// Counts a client's downloads of a version only once per `window`,
+    /// remembering at most `max_entries` of them. A zero window counts every
+    /// download.
+    pub fn new(window: Duration, max_entries: usize) -> RecentDownloads {
+        RecentDownloads {
+            recent: Mutex::new(Recent {
+                counted: HashMap::new(),
+                last_pruned: Instant::now(),
+            }),
+            window: if window == Duration::from_secs(0) { None } else { Some(window) },
+            max_entries: max_entries,
+            salt: RandomState::new(),
+        }
+    }
+
+    /// Whether a download of `version_id` by `client` is counted, which it
+    /// isn't if the client's last counted download of it is still within the
+    /// window.
+    pub fn count(&self, client: IpAddr, version_id: i32) -> bool {
+        let window = match self.window {
+            Some(window) => window,
+            None => return true,
+        };
+        let mut hasher = self.salt.build_hasher();
+        client.hash(&mut hasher);
+        let key = (hasher.finish(), version_id);
+
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        if let Some(&counted_at) = recent.counted.get(&key) {
+            if now.duration_since(counted_at) < window {
+                return false
+            }
+        }
+        // Sweeping once per window keeps at most two windows' worth of
+        // fingerprints around without going through them on every download,
+        // unless there are too many of them already
+        if now.duration_since(recent.last_pruned) >= window ||
+           recent.counted.len() >= self.max_entries {
+            let counted = mem::replace(&mut recent.counted, HashMap::new());
+            recent.counted = counted.into_iter()
+                .filter(|&(_, counted_at)| now.duration_since(counted_at) < window)
+                .collect();
+            recent.last_pruned = now;
+        }
+        // Once full, downloads are counted without being remembered, it's
+        // better to count a few repeated downloads than to run out of memory
+        if recent.counted.len() < self.max_entries {
+            recent.counted.insert(key, now);
+        }
+        true
+    }
+
+    /// The number of fingerprints currently kept.
+    pub fn len(&self) -> usize {
+        self.recent.lock().unwrap().counted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Writes a single download of `version_id` on `date` straight to
+/// `version_downloads`, which is how downloads are counted when
+/// `Config::async_downloads` isn't `On`.
+pub fn record(conn: &GenericConnection, version_id: i32, date: NaiveDate) -> CargoResult<()> {
+    conn.execute("\
+        INSERT INTO version_downloads (version_id, downloads, date)
+        VALUES ($1, 1, $2)
+        ON CONFLICT (version_id, date) DO UPDATE
+           SET downloads = version_downloads.downloads + 1",
+        &[&version_id, &date])?;
+    Ok(())
+}
+
+/// A day on which `version_downloads` and `version_downloads_shadow` don't
+/// have the same count for a version.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ShadowMismatch {
+    pub version_id: i32,
+    pub date: NaiveDate,
+    pub downloads: i32,
+    pub shadow_downloads: i32,
+}
+
+/// The days from `since` to `until`, both included, on which the batched
+/// downloads written while `Config::async_downloads` was `Shadow` don't add
+/// up to the downloads written one by one. Rows missing from either table
+/// count as no downloads.
+///
+/// Only days which were entirely in the shadow rollout can be compared:
+/// the day the shadow started has downloads counted before it, and today's
+/// batches may still be pending.
+pub fn shadow_mismatches(conn: &GenericConnection,
+                         since: NaiveDate,
+                         until: NaiveDate) -> CargoResult<Vec<ShadowMismatch>> {
+    let rows = conn.query("\
+        SELECT version_id, date,
+               COALESCE(d.downloads, 0) AS downloads,
+               COALESCE(s.downloads, 0) AS shadow_downloads
+          FROM (SELECT version_id, date, downloads FROM version_downloads
+                 WHERE date BETWEEN $1 AND $2) d
+          FULL OUTER JOIN
+               (SELECT version_id, date, downloads FROM version_downloads_shadow
+                 WHERE date BETWEEN $1 AND $2) s
+         USING (version_id, date)
+         WHERE COALESCE(d.downloads, 0) != COALESCE(s.downloads, 0)
+         ORDER BY date, version_id", &[&since, &until])?;
+    Ok(rows.iter().map(|row| ShadowMismatch {
+        version_id: row.get("version_id"),
+        date: row.get("date"),
+        downloads: row.get("downloads"),
+        shadow_downloads: row.get("shadow_downloads"),
+    }).collect())
+}
+
+/// The first day there are shadow downloads for, if there are any.
+pub fn shadow_started(conn: &GenericConnection) -> CargoResult<Option<NaiveDate>> {
+    let rows = conn.query("SELECT MIN(date) FROM version_downloads_shadow", &[])?;
+    Ok(rows.get(0).get(0))
+}
+
+/// Headers which CDNs in front of the app use to pass along the country the
+/// request came from, in order of preference.
+const COUNTRY_HEADERS: &'static [&'static str] = &[
+    "CloudFront-Viewer-Country",
+    "CF-IPCountry",
+];
+
+/// Reduces a `User-Agent` header to the family it is counted under in the
+/// download statistics. Cargo identifies itself as e.g.
+/// `cargo 0.18.0 (5db6d64 2017-03-03)`, which is counted as `cargo 0.18`.
+/// Everything else is lumped together as `other`.
+pub fn user_agent_family(user_agent: Option<&str>) -> String {
+    let mut parts = user_agent.unwrap_or("").split_whitespace();
+    if parts.next() != Some("cargo") {
+        return "other".to_string()
+    }
+    let version = parts.next().unwrap_or("");
+    let mut nums = version.split('.');
+    match (nums.next(), nums.next()) {
+        (Some(major), Some(minor)) if !major.is_empty() &&
+                                      major.chars().all(|c| c.is_digit(10)) &&
+                                      minor.chars().all(|c| c.is_digit(10)) &&
+                                      !minor.is_empty() => {
+            format!("cargo {}.{}", major, minor)
+        }
+        _ => "cargo".to_string(),
+    }
+}
+
+/// The two letter country code the CDN reported for this request, or
+/// `unknown` if there isn't one.
+pub fn request_country(req: &Request) -> String {
+    COUNTRY_HEADERS.iter()
+        .filter_map(|name| req.headers().find(name))
+        .filter_map(|values| values.first().map(|s| s.trim().to_uppercase()))
+        .find(|code| code.len() == 2 && code.chars().all(|c| c >= 'A' && c <= 'Z'))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Counts a download of `version_id` in the aggregated statistics, which are
+/// written with the next batch. Only the user agent family and the country
+/// are recorded, never anything that could identify the client.
+pub fn record_stats(req: &Request, version_id: i32, date: NaiveDate) {
+    let user_agent = user_agent_family(req.headers().find("User-Agent")
+                                          .and_then(|v| v.first().cloned()));
+    let country = request_country(req);
+    req.app().pending_download_stats.increment(version_id, date, user_agent, country);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increments_for_the_same_row_are_coalesced() {
+        let pending = PendingDownloads::new(Duration::from_secs(60), 100);
+        let today = NaiveDate::from_ymd(2017, 3, 1);
+        let yesterday = NaiveDate::from_ymd(2017, 2, 28);
+        pending.increment(1, today);
+        pending.increment(1, today);
+        pending.increment(1, yesterday);
+        pending.increment(2, today);
+        assert_eq!(pending.len(), 3);
+    }
+
+    #[test]
+    fn stats_for_the_same_row_are_coalesced() {
+        let pending = PendingDownloadStats::new(Duration::from_secs(60), 100);
+        let today = NaiveDate::from_ymd(2017, 3, 1);
+        let cargo = || "cargo 0.18".to_string();
+        pending.increment(1, today, cargo(), "DE".to_string());
+        pending.increment(1, today, cargo(), "DE".to_string());
+        pending.increment(1, today, cargo(), "FR".to_string());
+        pending.increment(1, today, "other".to_string(), "DE".to_string());
+        assert_eq!(pending.len(), 3);
+        assert!(!pending.is_due());
+    }
+
+    #[test]
+    fn repeated_downloads_within_the_window_are_counted_once() {
+        let recent = RecentDownloads::new(Duration::from_secs(60), 100);
+        let client = "10.0.0.1".parse().unwrap();
+        let other = "10.0.0.2".parse().unwrap();
+        assert!(recent.count(client, 1));
+        assert!(!recent.count(client, 1));
+        assert!(recent.count(client, 2));
+        assert!(recent.count(other, 1));
+        assert_eq!(recent.len(), 3);
+
+        let every = RecentDownloads::new(Duration::from_secs(0), 100);
+        assert!(every.count(client, 1));
+        assert!(every.count(client, 1));
+        assert!(every.is_empty());
+    }
+
+    #[test]
+    fn fingerprints_are_dropped_once_their_window_is_over() {
+        let recent = RecentDownloads::new(Duration::from_millis(20), 100);
+        let client = "10.0.0.1".parse().unwrap();
+        assert!(recent.count(client, 1));
+        assert!(recent.count(client, 2));
+        ::std::thread::sleep(Duration::from_millis(30));
+        assert!(recent.count(client, 1));
+        assert_eq!(recent.len(), 1);
+    }
+
+    #[test]
+    fn at_most_max_entries_fingerprints_are_kept() {
+        let recent = RecentDownloads::new(Duration::from_secs(60), 2);
+        let client = "10.0.0.1".parse().unwrap();
+        assert!(recent.count(client, 1));
+        assert!(recent.count(client, 2));
+        assert!(recent.count(client, 3));
+        assert!(recent.count(client, 3));
+        assert!(!recent.count(client, 1));
+        assert_eq!(recent.len(), 2);
+    }
+
+    #[test]
+    fn user_agent_families() {
+        assert_eq!(user_agent_family(Some("cargo 0.18.0 (5db6d64 2017-03-03)")),
+                   "cargo 0.18");
+        assert_eq!(user_agent_family(Some("cargo 1.2.3-nightly")), "cargo 1.2");
+        assert_eq!(user_agent_family(Some("cargo")), "cargo");
+        assert_eq!(user_agent_family(Some("cargo-edit/0.2")), "other");
+        assert_eq!(user_agent_family(Some("curl/7.52.1")), "other");
+        assert_eq!(user_agent_family(None), "other");
+    }
+}
@@ -0,0 +1,118 @@
+//! The files each version ships, as recorded when it was published.
+//!
+//! Paths are stored relative to the package's root directory, so that the
+//! same file has the same path in every version of a crate.
+
+use conduit::{Request, Response};
+use diesel;
+use diesel::prelude::*;
+use openssl::hash::{hash, MessageDigest};
+use rustc_serialize::hex::ToHex;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use schema::*;
+use util::{RequestUtils, CargoResult, TarballFile};
+use version::version_and_crate;
+
+/// How many files are inserted with a single statement, to stay well below
+/// the number of parameters postgres accepts.
+const INSERT_BATCH_SIZE: usize = 1000;
+
+#[derive(Clone, Debug, Queryable)]
+pub struct VersionFile {
+    pub version_id: i32,
+    pub path: String,
+    pub size: i64,
+    /// The hex encoded SHA-256 of the contents
+    pub checksum: String,
+}
+
+#[derive(Insertable)]
+#[table_name="version_files"]
+struct NewVersionFile<'a> {
+    version_id: i32,
+    path: &'a str,
+    size: i64,
+    checksum: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableVersionFile {
+    pub path: String,
+    pub size: i64,
+    pub checksum: String,
+}
+
+impl VersionFile {
+    pub fn encodable(self) -> EncodableVersionFile {
+        EncodableVersionFile {
+            path: self.path,
+            size: self.size,
+            checksum: self.checksum,
+        }
+    }
+
+    /// The files of the version, ordered by path.
+    pub fn all(conn: &InstrumentedConnection, version_id: i32) -> CargoResult<Vec<VersionFile>> {
+        let files = version_files::table
+            .filter(version_files::version_id.eq(version_id))
+            .order(version_files::path)
+            .load(conn)?;
+        Ok(files)
+    }
+}
+
+/// The path of a file in a crate's tarball relative to the package's root
+/// directory, e.g. `src/lib.rs` for `foo-0.1.0/src/lib.rs`.
+pub fn package_path(path: &str) -> &str {
+    match path.find('/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+/// Records the files of a newly published version.
+pub fn record(conn: &InstrumentedConnection,
+              version_id: i32, files: &[TarballFile]) -> CargoResult<()> {
+    for batch in files.chunks(INSERT_BATCH_SIZE) {
+        let batch = batch.iter().map(|file| NewVersionFile {
+            version_id: version_id,
+            path: package_path(&file.path),
+            size: file.size as i64,
+            checksum: hash(MessageDigest::sha256(), &file.contents).unwrap().to_hex(),
+        }).collect::<Vec<_>>();
+        diesel::insert(&batch).into(version_files::table).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/:version/files` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+
+    let files = VersionFile::all(&conn, version.id)?;
+    let total_size = files.iter().map(|file| file.size).sum();
+    let files = files.into_iter().map(VersionFile::encodable).collect::<Vec<_>>();
+
+    #[derive(RustcEncodable)]
+    struct R { files: Vec<EncodableVersionFile>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta { total: usize, total_size: i64 }
+    Ok(req.json(&R {
+        meta: Meta { total: files.len(), total_size: total_size },
+        files: files,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::package_path;
+
+    #[test]
+    fn package_paths() {
+        assert_eq!(package_path("foo-0.1.0/src/lib.rs"), "src/lib.rs");
+        assert_eq!(package_path("foo-0.1.0/Cargo.toml"), "Cargo.toml");
+        assert_eq!(package_path("Cargo.toml"), "Cargo.toml");
+    }
+}
@@ -6,13 +6,20 @@ use std::path::{Path, PathBuf};
 
 use semver;
 use git2;
-use rustc_serialize::json;
+use rustc_serialize::json::{self, Json};
+use rustc_serialize::{Encodable, Encoder};
 
 use app::App;
 use dependency::Kind;
 use util::{CargoResult, internal};
 
-#[derive(RustcEncodable, RustcDecodable)]
+/// A line of the index.
+///
+/// The fields after `yanked` are only written by registries with
+/// `Config::index_v2` set, and are left out of the line entirely when they're
+/// not set so that entries written without them stay byte for byte the same
+/// when they're rewritten, e.g. on yank.
+#[derive(RustcDecodable)]
 pub struct Crate {
     pub name: String,
     pub vers: String,
@@ -20,6 +27,67 @@ pub struct Crate {
     pub cksum: String,
     pub features: HashMap<String, Vec<String>>,
     pub yanked: Option<bool>,
+    /// The `links` key of the crate's manifest
+    pub links: Option<String>,
+    /// The minimum Rust version the crate supports, e.g. `1.15`
+    pub rust_version: Option<String>,
+    /// Features using syntax which older cargos don't understand, i.e.
+    /// `dep:name` and `name?/feature`
+    pub features2: Option<HashMap<String, Vec<String>>>,
+    /// The version of the entry's format, `2` when `features2` is set
+    pub v: Option<u32>,
+}
+
+impl Encodable for Crate {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let extra = [self.links.is_some(), self.rust_version.is_some(),
+                     self.features2.is_some(), self.v.is_some()];
+        let len = 6 + extra.iter().filter(|&&set| set).count();
+        s.emit_struct("Crate", len, |s| {
+            s.emit_struct_field("name", 0, |s| self.name.encode(s))?;
+            s.emit_struct_field("vers", 1, |s| self.vers.encode(s))?;
+            s.emit_struct_field("deps", 2, |s| self.deps.encode(s))?;
+            s.emit_struct_field("cksum", 3, |s| self.cksum.encode(s))?;
+            s.emit_struct_field("features", 4, |s| self.features.encode(s))?;
+            s.emit_struct_field("yanked", 5, |s| self.yanked.encode(s))?;
+            let mut idx = 6;
+            if let Some(ref links) = self.links {
+                s.emit_struct_field("links", idx, |s| links.encode(s))?;
+                idx += 1;
+            }
+            if let Some(ref rust_version) = self.rust_version {
+                s.emit_struct_field("rust_version", idx, |s| rust_version.encode(s))?;
+                idx += 1;
+            }
+            if let Some(ref features2) = self.features2 {
+                s.emit_struct_field("features2", idx, |s| features2.encode(s))?;
+                idx += 1;
+            }
+            if let Some(v) = self.v {
+                s.emit_struct_field("v", idx, |s| v.encode(s))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Whether a feature's value uses syntax which only cargos reading
+/// `features2` understand.
+pub fn is_features2_value(value: &str) -> bool {
+    value.starts_with("dep:") || value.contains("?/")
+}
+
+/// Splits features into the ones every cargo understands and, if there are
+/// any, the ones which have to go into `features2`.
+pub fn split_features(features: HashMap<String, Vec<String>>)
+                      -> (HashMap<String, Vec<String>>, Option<HashMap<String, Vec<String>>>) {
+    let (features2, features): (HashMap<_, _>, HashMap<_, _>) = features.into_iter()
+        .partition(|&(_, ref values)| values.iter().any(|v| is_features2_value(v)));
+    if features2.is_empty() {
+        (features, None)
+    } else {
+        (features, Some(features2))
+    }
 }
 
 #[derive(RustcEncodable, RustcDecodable)]
@@ -34,14 +102,19 @@ pub struct Dependency {
 }
 
 fn index_file(base: &Path, name: &str) -> PathBuf {
+    let name = name.chars().flat_map(|c| c.to_lowercase()).collect::<String>();
+    base.join(index_prefix(&name)).join(&name)
+}
+
+/// The directory of the index a crate's file is in, e.g. `3/s` for `syn` or
+/// `se/rd` for `serde`.
+pub fn index_prefix(name: &str) -> String {
     let name = name.chars().flat_map(|c| c.to_lowercase()).collect::<String>();
     match name.len() {
-        1 => base.join("1").join(&name),
-        2 => base.join("2").join(&name),
-        3 => base.join("3").join(&name[..1]).join(&name),
-        _ => base.join(&name[0..2])
-                 .join(&name[2..4])
-                 .join(&name),
+        1 => String::from("1"),
+        2 => String::from("2"),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[0..2], &name[2..4]),
     }
 }
 
@@ -71,6 +144,23 @@ pub fn add_crate(app: &App, krate: &Crate) -> CargoResult<()> {
 
 pub fn yank(app: &App, krate: &str, version: &semver::Version,
             yanked: bool) -> CargoResult<()> {
+    let message = format!("{} crate `{}#{}`",
+                          if yanked {"Yanking"} else {"Unyanking"},
+                          krate, version);
+    set_yanked(app, krate, &[version.to_string()], yanked, message)
+}
+
+/// Yanks every one of `versions` of the crate in a single commit, the
+/// versions being those matching `range`.
+pub fn yank_all(app: &App, krate: &str, versions: &[String],
+                range: &str) -> CargoResult<()> {
+    let message = format!("Yanking {} versions of crate `{}` matching `{}`",
+                          versions.len(), krate, range);
+    set_yanked(app, krate, versions, true, message)
+}
+
+fn set_yanked(app: &App, krate: &str, versions: &[String], yanked: bool,
+              message: String) -> CargoResult<()> {
     let repo = app.git_repo.lock().unwrap();
     let repo_path = repo.workdir().unwrap();
     let dst = index_file(repo_path, krate);
@@ -82,8 +172,7 @@ pub fn yank(app: &App, krate: &str, version: &semver::Version,
             let mut git_crate = json::decode::<Crate>(line).map_err(|_| {
                 internal(&format_args!("couldn't decode: `{}`", line))
             })?;
-            if git_crate.name != krate ||
-               git_crate.vers != version.to_string() {
+            if git_crate.name != krate || !versions.contains(&git_crate.vers) {
                 return Ok(line.to_string())
             }
             git_crate.yanked = Some(yanked);
@@ -94,10 +183,34 @@ pub fn yank(app: &App, krate: &str, version: &semver::Version,
         f.write_all(new.as_bytes())?;
         f.write_all(b"\n")?;
 
-        Ok((format!("{} crate `{}#{}`",
-                    if yanked {"Yanking"} else {"Unyanking"},
-                    krate, version),
-            dst.clone()))
+        Ok((message.clone(), dst.clone()))
+    })
+}
+
+/// Sets `key` in the index's `config.json` to `value`, unless it's already
+/// set to it.
+pub fn set_config(app: &App, key: &str, value: &str) -> CargoResult<()> {
+    let repo = app.git_repo.lock().unwrap();
+    let dst = repo.workdir().unwrap().join("config.json");
+    let read_config = || -> CargoResult<json::Object> {
+        let mut config = String::new();
+        File::open(&dst).and_then(|mut f| f.read_to_string(&mut config))?;
+        Json::from_str(&config).ok().and_then(Json::into_object).ok_or_else(|| {
+            internal("the index's config.json isn't a JSON object")
+        })
+    };
+    if read_config()?.get(key).and_then(Json::as_string) == Some(value) {
+        return Ok(())
+    }
+
+    commit_and_push(&repo, || {
+        let mut config = read_config()?;
+        config.insert(key.to_string(), Json::String(value.to_string()));
+        let mut f = File::create(&dst)?;
+        write!(f, "{}", json::as_pretty_json(&Json::Object(config)))?;
+        f.write_all(b"\n")?;
+
+        Ok((format!("Updating `{}` in config.json", key), dst.clone()))
     })
 }
 
@@ -176,3 +289,67 @@ pub fn credentials(_user: &str, _user_from_url: Option<&str>,
         _ => Err(git2::Error::from_str("no authentication set"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rustc_serialize::json;
+
+    use super::{split_features, Crate};
+
+    fn entry() -> Crate {
+        Crate {
+            name: "foo".to_string(),
+            vers: "1.0.0".to_string(),
+            deps: Vec::new(),
+            cksum: "abcd".to_string(),
+            features: HashMap::new(),
+            yanked: Some(false),
+            links: None,
+            rust_version: None,
+            features2: None,
+            v: None,
+        }
+    }
+
+    #[test]
+    fn entries_without_v2_fields_are_unchanged() {
+        assert_eq!(json::encode(&entry()).unwrap(),
+                   r#"{"name":"foo","vers":"1.0.0","deps":[],"cksum":"abcd","features":{},"yanked":false}"#);
+    }
+
+    #[test]
+    fn v2_fields_are_written_when_set() {
+        let mut features2 = HashMap::new();
+        features2.insert("json".to_string(), vec!["dep:serde_json".to_string()]);
+        let mut krate = entry();
+        krate.links = Some("git2".to_string());
+        krate.rust_version = Some("1.15".to_string());
+        krate.features2 = Some(features2);
+        krate.v = Some(2);
+        assert_eq!(json::encode(&krate).unwrap(),
+                   r#"{"name":"foo","vers":"1.0.0","deps":[],"cksum":"abcd","features":{},"yanked":false,"links":"git2","rust_version":"1.15","features2":{"json":["dep:serde_json"]},"v":2}"#);
+
+        let decoded: Crate = json::decode(&json::encode(&krate).unwrap()).unwrap();
+        assert_eq!(decoded.v, Some(2));
+        let decoded: Crate = json::decode(&json::encode(&entry()).unwrap()).unwrap();
+        assert!(decoded.features2.is_none());
+    }
+
+    #[test]
+    fn features_are_split() {
+        let mut features = HashMap::new();
+        features.insert("default".to_string(), vec!["std".to_string()]);
+        features.insert("std".to_string(), vec!["serde?/std".to_string()]);
+        let (features, features2) = split_features(features);
+        assert_eq!(features.keys().collect::<Vec<_>>(), vec!["default"]);
+        assert_eq!(features2.unwrap().keys().collect::<Vec<_>>(), vec!["std"]);
+
+        let mut features = HashMap::new();
+        features.insert("default".to_string(), vec!["std".to_string()]);
+        let (features, features2) = split_features(features);
+        assert_eq!(features.len(), 1);
+        assert!(features2.is_none());
+    }
+}
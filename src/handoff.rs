@@ -0,0 +1,294 @@
+//! Handing a crate's settings over when its ownership changes hands.
+//!
+//! Webhooks, download thresholds and deprecation notices are set up by
+//! whoever owned the crate at the time, and would silently keep pointing at
+//! their endpoints after they're gone. So when an admin transfers a crate or
+//! an owner is removed, the settings which are set are put up for handoff.
+//! Until an owner has confirmed, disabled or replaced each of them, the
+//! crate's webhooks aren't called.
+//!
+//! Owners see what is left to hand off with
+//! `GET /crates/:crate_id/settings_handoff`, and confirm or disable settings
+//! with `PUT` on the same route. Changing a setting through
+//! `PUT /crates/:crate_id/settings` replaces it.
+
+use std::io::Read;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::pg::upsert::*;
+use diesel::prelude::*;
+use rustc_serialize::json;
+use time::Timespec;
+
+use audit;
+use db::{InstrumentedConnection, RequestTransaction};
+use owner::{request_rights, Rights};
+use schema::*;
+use settings::{CrateSettings, EncodableCrateSettings};
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, human};
+use Crate;
+
+/// The settings which are handed off.
+pub const SETTINGS: &'static [&'static str] = &[
+    "webhook_urls",
+    "download_thresholds",
+    "deprecation_notice",
+];
+
+#[derive(Clone, Debug, Queryable)]
+pub struct SettingsHandoff {
+    pub crate_id: i32,
+    /// The settings still waiting for an owner's decision
+    pub settings: Vec<String>,
+    pub reason: String,
+    pub started_by: i32,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name="settings_handoffs"]
+struct NewSettingsHandoff<'a> {
+    crate_id: i32,
+    settings: Vec<String>,
+    reason: &'a str,
+    started_by: i32,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableSettingsHandoff {
+    /// The settings still waiting for an owner's decision
+    pub pending: Vec<String>,
+    /// Who changed hands and why
+    pub reason: String,
+    pub started_by: String,
+    pub created_at: String,
+}
+
+/// What an owner decided to do with a handed off setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Keep it as it is
+    Confirm,
+    /// Clear it
+    Disable,
+    /// It was changed through the crate settings
+    Replace,
+}
+
+impl Decision {
+    pub fn parse(decision: &str) -> Option<Decision> {
+        match decision {
+            "confirm" => Some(Decision::Confirm),
+            "disable" => Some(Decision::Disable),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Decision::Confirm => "confirm",
+            Decision::Disable => "disable",
+            Decision::Replace => "replace",
+        }
+    }
+}
+
+impl SettingsHandoff {
+    pub fn find(conn: &InstrumentedConnection,
+                crate_id: i32) -> CargoResult<Option<SettingsHandoff>> {
+        let handoff = settings_handoffs::table.find(crate_id)
+            .first(conn)
+            .optional()?;
+        Ok(handoff)
+    }
+
+    pub fn encodable(self, conn: &InstrumentedConnection) -> CargoResult<EncodableSettingsHandoff> {
+        let started_by = users::table.find(self.started_by)
+            .select(users::gh_login)
+            .first::<String>(conn)?;
+        Ok(EncodableSettingsHandoff {
+            pending: self.settings,
+            reason: self.reason,
+            started_by: started_by,
+            created_at: ::encode_time(self.created_at),
+        })
+    }
+}
+
+/// The handed off settings which `settings` has set.
+fn set_settings(settings: &CrateSettings) -> Vec<String> {
+    let mut set = Vec::new();
+    if !settings.webhook_urls.is_empty() {
+        set.push("webhook_urls".to_string());
+    }
+    if !settings.download_thresholds.is_empty() {
+        set.push("download_thresholds".to_string());
+    }
+    if settings.deprecation_notice.is_some() {
+        set.push("deprecation_notice".to_string());
+    }
+    set
+}
+
+/// Puts the crate's settings up for handoff after its ownership changed for
+/// `reason`. Crates without any of the settings set have nothing to hand
+/// off, and a handoff which is already pending is started over.
+pub fn start(conn: &InstrumentedConnection,
+             krate: &Crate, user_id: i32, reason: &str) -> CargoResult<()> {
+    let settings = set_settings(&CrateSettings::find(conn, krate.id)?);
+    if settings.is_empty() {
+        return Ok(())
+    }
+
+    let details = format!("{}: {}", settings.join(", "), reason);
+    let handoff = NewSettingsHandoff {
+        crate_id: krate.id,
+        settings: settings,
+        reason: reason,
+        started_by: user_id,
+    };
+    diesel::insert(&handoff.on_conflict(settings_handoffs::crate_id, do_update().set(&handoff)))
+        .into(settings_handoffs::table)
+        .execute(conn)?;
+    audit::record(conn, user_id, "start_settings_handoff", Some(&krate.name), &details)
+}
+
+/// Records the decisions about handed off settings, finishing the handoff
+/// once there are none left. Settings which aren't waiting for a decision
+/// are ignored.
+pub fn decide(conn: &InstrumentedConnection,
+              krate: &Crate,
+              user_id: i32,
+              decisions: &[(&str, Decision)]) -> CargoResult<()> {
+    let mut handoff = match SettingsHandoff::find(conn, krate.id)? {
+        Some(handoff) => handoff,
+        None => return Ok(()),
+    };
+    let decided = decisions.iter()
+        .filter(|&&(setting, _)| handoff.settings.iter().any(|s| s == setting))
+        .map(|&(setting, decision)| format!("{} {}", decision.as_str(), setting))
+        .collect::<Vec<_>>();
+    if decided.is_empty() {
+        return Ok(())
+    }
+
+    handoff.settings.retain(|s| !decisions.iter().any(|&(setting, _)| s == setting));
+    let target = settings_handoffs::table.find(krate.id);
+    if handoff.settings.is_empty() {
+        diesel::delete(target).execute(conn)?;
+    } else {
+        diesel::update(target)
+            .set(settings_handoffs::settings.eq(handoff.settings.clone()))
+            .execute(conn)?;
+    }
+    audit::record(conn, user_id, "settings_handoff", Some(&krate.name), &decided.join(", "))
+}
+
+/// Loads the crate named in the request, failing unless the current user
+/// fully owns it.
+fn owned_crate(req: &Request, conn: &InstrumentedConnection) -> CargoResult<Crate> {
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(conn)?;
+    let owners = krate.owners(conn)?;
+    if request_rights(req, conn, &owners)? < Rights::Full {
+        return Err(human("only owners can see and hand off the settings of a crate"))
+    }
+    Ok(krate)
+}
+
+fn respond(req: &Request, conn: &InstrumentedConnection, krate: &Crate) -> CargoResult<Response> {
+    let handoff = match SettingsHandoff::find(conn, krate.id)? {
+        Some(handoff) => Some(handoff.encodable(conn)?),
+        None => None,
+    };
+    let settings = CrateSettings::find(conn, krate.id)?.encodable(krate, conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { handoff: Option<EncodableSettingsHandoff>, settings: EncodableCrateSettings }
+    Ok(req.json(&R { handoff: handoff, settings: settings }))
+}
+
+/// Handles the `GET /crates/:crate_id/settings_handoff` route.
+///
+/// The handoff is `null` when there's nothing to hand off.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let krate = owned_crate(req, &conn)?;
+    respond(req, &conn, &krate)
+}
+
+/// Handles the `PUT /crates/:crate_id/settings_handoff` route.
+///
+/// Each setting named in the body is either confirmed as it is or disabled.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "webhook_urls": "disable", "deprecation_notice": "confirm" }
+/// ```
+pub fn update(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request {
+        webhook_urls: Option<String>,
+        download_thresholds: Option<String>,
+        deprecation_notice: Option<String>,
+    }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = owned_crate(req, &conn)?;
+    let handoff = SettingsHandoff::find(&conn, krate.id)?.ok_or_else(|| {
+        human("this crate has no settings waiting to be handed off")
+    })?;
+
+    let mut decisions = Vec::new();
+    for (setting, decision) in SETTINGS.iter().zip(vec![request.webhook_urls,
+                                                         request.download_thresholds,
+                                                         request.deprecation_notice]) {
+        let decision = match decision {
+            Some(decision) => decision,
+            None => continue,
+        };
+        let decision = Decision::parse(&decision).ok_or_else(|| {
+            human(&format_args!("`{}` should be either `confirm` or `disable`", setting))
+        })?;
+        if !handoff.settings.iter().any(|s| s == setting) {
+            return Err(human(&format_args!("`{}` isn't waiting to be handed off", setting)))
+        }
+        decisions.push((*setting, decision));
+    }
+
+    conn.transaction(|| -> CargoResult<()> {
+        let disabled = decisions.iter()
+            .filter(|&&(_, decision)| decision == Decision::Disable)
+            .map(|&(setting, _)| setting)
+            .collect::<Vec<_>>();
+        if !disabled.is_empty() {
+            let mut settings = CrateSettings::find(&conn, krate.id)?;
+            for setting in &disabled {
+                match *setting {
+                    "webhook_urls" => settings.webhook_urls = Vec::new(),
+                    "download_thresholds" => settings.download_thresholds = Vec::new(),
+                    _ => settings.deprecation_notice = None,
+                }
+            }
+            settings.save(&conn)?;
+            if disabled.contains(&"deprecation_notice") {
+                audit::record(&conn, user.id, "update_deprecation_notice", Some(&krate.name),
+                              "")?;
+            }
+            let details = format!("changed {}", disabled.join(", "));
+            audit::record(&conn, user.id, "update_settings", Some(&krate.name), &details)?;
+        }
+        decide(&conn, &krate, user.id, &decisions)
+    })?;
+
+    respond(req, &conn, &krate)
+}
@@ -5,6 +5,7 @@ use app::App;
 use util::{CargoResult, internal, ChainError, human};
 use rustc_serialize::{json, Decodable};
 use std::str;
+use std::time::Duration;
 
 
 /// Does all the nonsense for sending a GET to Github. Doesn't handle parsing
@@ -76,3 +77,33 @@ pub fn token(token: String) -> Token {
         token_type: String::new(),
     }
 }
+
+/// POSTs a JSON `body` to `url` along with the extra `headers`, such as a
+/// `User-Agent`, and returns the response code. The response itself is
+/// thrown away.
+pub fn post_json(url: &str, body: &[u8], headers: &[&str]) -> Result<u32, curl::Error> {
+    let mut list = List::new();
+    list.append("Content-Type: application/json")?;
+    for header in headers {
+        list.append(header)?;
+    }
+
+    let mut handle = Easy::new();
+    handle.url(url)?;
+    handle.post(true)?;
+    handle.post_fields_copy(body)?;
+    handle.http_headers(list)?;
+    handle.timeout(Duration::from_secs(10))?;
+    perform(handle)
+}
+
+/// Performs the request set up on `handle` and returns the response code,
+/// throwing the response itself away.
+pub fn perform(mut handle: Easy) -> Result<u32, curl::Error> {
+    {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|buf| Ok(buf.len()))?;
+        transfer.perform()?;
+    }
+    handle.response_code()
+}
@@ -0,0 +1,290 @@
+//! WebSub-style push notifications of index changes.
+//!
+//! Rather than polling `GET /events`, registered mirrors can subscribe a
+//! callback URL with `PUT /index/subscriptions`, sending their token in the
+//! `X-Mirror-Token` header. The registry first checks that the callback
+//! wants the subscription by GETting it with `hub.mode`, `hub.topic` and a
+//! `hub.challenge` it has to echo back, and does the same before
+//! unsubscribing.
+//!
+//! The `notify-subscribers` job then POSTs a ping to every callback which
+//! hasn't been told about the latest event yet, so that however many events
+//! happen between two runs, a callback is pinged at most once per run. Pings
+//! are signed with the subscription's secret: the `X-Hub-Signature` header
+//! is `sha256=<hex HMAC-SHA256 of the body>`.
+
+use std::time::Duration;
+
+use conduit::{Host, Request, Response};
+use curl::easy::Easy;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use pg::GenericConnection;
+use pg::rows::Row;
+use rand::{thread_rng, Rng};
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json;
+use time::Timespec;
+use url::Url;
+
+use Model;
+use app::RequestApp;
+use audit::REGISTRY_EVENTS;
+use db::RequestTransaction;
+use http;
+use mirror::{self, Mirror};
+use util::{RequestUtils, CargoResult, ChainError, decode_body, human};
+
+/// Secrets longer than this are rejected, as WebSub hubs do.
+const MAX_SECRET_LEN: usize = 200;
+
+/// Only this much of a callback's answer to a verification is read.
+const MAX_CHALLENGE_RESPONSE: usize = 1024;
+
+pub struct IndexSubscription {
+    pub id: i32,
+    pub mirror_id: i32,
+    pub callback: String,
+    pub secret: String,
+    /// The `seq` of the last event the callback was pinged about
+    pub last_notified_seq: i32,
+    pub created_at: Timespec,
+}
+
+#[derive(RustcDecodable)]
+struct SubscriptionRequest {
+    callback: String,
+    secret: Option<String>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Ping {
+    /// The `seq` of the newest event, see `GET /events`
+    pub seq: i32,
+    /// The `seq` of the newest event the callback was pinged about before,
+    /// i.e. what to pass as `since` to catch up
+    pub since: i32,
+}
+
+impl IndexSubscription {
+    /// Subscribes `callback` for the mirror, or updates its secret if it's
+    /// already subscribed. It's only pinged about events from now on.
+    pub fn subscribe(conn: &GenericConnection,
+                     mirror_id: i32,
+                     callback: &str,
+                     secret: &str) -> CargoResult<IndexSubscription> {
+        let rows = conn.query("\
+            INSERT INTO index_subscriptions (mirror_id, callback, secret, last_notified_seq)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (mirror_id, callback) DO UPDATE SET secret = EXCLUDED.secret
+            RETURNING *", &[&mirror_id, &callback, &secret, &latest_seq(conn)?])?;
+        Ok(Model::from_row(&rows.get(0)))
+    }
+
+    /// Removes the subscription, returning whether there was one.
+    pub fn unsubscribe(conn: &GenericConnection,
+                       mirror_id: i32,
+                       callback: &str) -> CargoResult<bool> {
+        let n = conn.execute("DELETE FROM index_subscriptions \
+                              WHERE mirror_id = $1 AND callback = $2",
+                             &[&mirror_id, &callback])?;
+        Ok(n > 0)
+    }
+
+    /// The subscriptions of mirrors which weren't revoked and haven't been
+    /// pinged about event `seq` yet.
+    pub fn behind(conn: &GenericConnection, seq: i32) -> CargoResult<Vec<IndexSubscription>> {
+        let rows = conn.query("\
+            SELECT index_subscriptions.* FROM index_subscriptions
+             INNER JOIN mirrors ON mirrors.id = index_subscriptions.mirror_id
+             WHERE NOT mirrors.revoked
+               AND index_subscriptions.last_notified_seq < $1
+             ORDER BY index_subscriptions.id", &[&seq])?;
+        Ok(rows.iter().map(|row| Model::from_row(&row)).collect())
+    }
+
+    fn notified(&self, conn: &GenericConnection, seq: i32) -> CargoResult<()> {
+        conn.execute("UPDATE index_subscriptions SET last_notified_seq = $2 WHERE id = $1",
+                     &[&self.id, &seq])?;
+        Ok(())
+    }
+}
+
+impl Model for IndexSubscription {
+    fn from_row(row: &Row) -> IndexSubscription {
+        IndexSubscription {
+            id: row.get("id"),
+            mirror_id: row.get("mirror_id"),
+            callback: row.get("callback"),
+            secret: row.get("secret"),
+            last_notified_seq: row.get("last_notified_seq"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    fn table_name(_: Option<IndexSubscription>) -> &'static str { "index_subscriptions" }
+}
+
+/// The `seq` of the newest event on the stream, or 0 if there are none.
+pub fn latest_seq(conn: &GenericConnection) -> CargoResult<i32> {
+    let actions = REGISTRY_EVENTS.to_vec();
+    let rows = conn.query("SELECT COALESCE(MAX(id), 0) AS seq FROM audit_log \
+                           WHERE action = ANY($1)", &[&actions])?;
+    Ok(rows.get(0).get("seq"))
+}
+
+/// Pings every subscription which is behind, returning how many were
+/// pinged. Callbacks which can't be reached are tried again on the next run.
+pub fn notify_all(conn: &GenericConnection) -> CargoResult<usize> {
+    let seq = latest_seq(conn)?;
+    let mut pinged = 0;
+    for subscription in IndexSubscription::behind(conn, seq)? {
+        let body = json::encode(&Ping { seq: seq, since: subscription.last_notified_seq })
+            .unwrap();
+        let signature = sign(&subscription.secret, body.as_bytes())?;
+        let signature = format!("X-Hub-Signature: {}", signature);
+        let headers = ["User-Agent: crates.io index hub", &signature[..]];
+        match http::post_json(&subscription.callback, body.as_bytes(), &headers) {
+            Ok(200...299) => {
+                subscription.notified(conn, seq)?;
+                pinged += 1;
+            }
+            Ok(code) => warn!("index subscriber {} responded with {}",
+                              subscription.callback, code),
+            Err(e) => warn!("failed to ping index subscriber {}: {}",
+                            subscription.callback, e),
+        }
+    }
+    Ok(pinged)
+}
+
+/// The `X-Hub-Signature` of a ping with `body`.
+pub fn sign(secret: &str, body: &[u8]) -> CargoResult<String> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(body)?;
+    Ok(format!("sha256={}", signer.finish()?.to_hex()))
+}
+
+/// Handles the `PUT /index/subscriptions` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "callback": "https://mirror.example.com/hub", "secret": "..." }
+/// ```
+pub fn subscribe(req: &mut Request) -> CargoResult<Response> {
+    let mirror = request_mirror(req)?;
+    let new: SubscriptionRequest = decode_body(req)?;
+    let callback = parse_callback(&new.callback)?;
+    let secret = new.secret.unwrap_or_else(String::new);
+    if secret.is_empty() || secret.len() > MAX_SECRET_LEN {
+        return Err(human(&format_args!("the secret must be between 1 and {} bytes long",
+                                       MAX_SECRET_LEN)))
+    }
+    verify_intent(&callback, "subscribe", &topic(req))?;
+    IndexSubscription::subscribe(req.tx()?, mirror.id, callback.as_str(), &secret)?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `DELETE /index/subscriptions` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "callback": "https://mirror.example.com/hub" }
+/// ```
+pub fn unsubscribe(req: &mut Request) -> CargoResult<Response> {
+    let mirror = request_mirror(req)?;
+    let request: SubscriptionRequest = decode_body(req)?;
+    let callback = parse_callback(&request.callback)?;
+    verify_intent(&callback, "unsubscribe", &topic(req))?;
+    if !IndexSubscription::unsubscribe(req.tx()?, mirror.id, callback.as_str())? {
+        return Err(human("this callback isn't subscribed"))
+    }
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+fn request_mirror(req: &Request) -> CargoResult<Mirror> {
+    let token = req.headers().find(mirror::TOKEN_HEADER).map(|t| t[0].to_string());
+    let token = token.chain_error(|| {
+        human(&format_args!("only registered mirrors can subscribe, send the \
+                             mirror's token in the {} header", mirror::TOKEN_HEADER))
+    })?;
+    Mirror::find_active(req.tx()?, &token)?.chain_error(|| {
+        human("unknown or revoked mirror token")
+    })
+}
+
+fn parse_callback(callback: &str) -> CargoResult<Url> {
+    let url = Url::parse(callback).map_err(|_| {
+        human(&format_args!("`{}` is not a valid callback url", callback))
+    })?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(human(&format_args!("callback url `{}` must use http or https", callback)))
+    }
+    Ok(url)
+}
+
+/// What's subscribed to, sent as `hub.topic`.
+fn topic(req: &Request) -> String {
+    let host = match req.host() {
+        Host::Name(name) => name.to_string(),
+        Host::Socket(addr) => addr.to_string(),
+    };
+    format!("{}://{}/api/v1/events", req.app().config.api_protocol, host)
+}
+
+/// Makes sure the callback wants to be (un)subscribed, so that mirrors can't
+/// have pings sent to URLs which aren't theirs.
+fn verify_intent(callback: &Url, mode: &str, topic: &str) -> CargoResult<()> {
+    let challenge: String = thread_rng().gen_ascii_chars().take(32).collect();
+    let mut url = callback.clone();
+    url.query_pairs_mut()
+        .append_pair("hub.mode", mode)
+        .append_pair("hub.topic", topic)
+        .append_pair("hub.challenge", &challenge);
+
+    let mut handle = Easy::new();
+    let mut body = Vec::new();
+    let result = (|| {
+        handle.url(url.as_str())?;
+        handle.timeout(Duration::from_secs(10))?;
+        let mut transfer = handle.transfer();
+        transfer.write_function(|buf| {
+            let n = ::std::cmp::min(buf.len(), MAX_CHALLENGE_RESPONSE - body.len());
+            body.extend_from_slice(&buf[..n]);
+            Ok(buf.len())
+        })?;
+        transfer.perform()
+    })();
+    result.chain_error(|| {
+        human(&format_args!("could not reach callback `{}` to verify it", callback))
+    })?;
+    let code = handle.response_code()?;
+    if code < 200 || code >= 300 || String::from_utf8_lossy(&body).trim() != challenge {
+        return Err(human(&format_args!("callback `{}` did not confirm the {}, it must \
+                                        respond with the `hub.challenge` it's sent",
+                                       callback, mode)))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sign;
+
+    #[test]
+    fn pings_are_signed_with_hmac_sha256() {
+        // From RFC 4231, test case 2
+        assert_eq!(sign("Jefe", b"what do ya want for nothing?").unwrap(),
+                   "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+}
@@ -0,0 +1,122 @@
+//! Idempotency keys for `PUT /crates/new`.
+//!
+//! A publish can send an `Idempotency-Key` header, any string of up to
+//! `MAX_KEY_LEN` printable characters the client picks for it. Once the
+//! publish succeeded, its response is kept with a fingerprint of the request
+//! for a day, and a retry with the same key and the same request gets that
+//! response back without being published again. Reusing a key for a
+//! different request is an error. Failed publishes aren't kept since nothing
+//! of them was stored, so retrying them runs them again.
+
+use diesel;
+use diesel::prelude::*;
+use openssl::hash::{Hasher, MessageDigest};
+use rustc_serialize::hex::ToHex;
+use time::{self, Duration, Timespec};
+
+use db::InstrumentedConnection;
+use schema::publish_idempotency_keys;
+use util::{CargoResult, human};
+
+pub const HEADER: &'static str = "Idempotency-Key";
+
+pub const MAX_KEY_LEN: usize = 255;
+
+#[derive(Queryable)]
+pub struct IdempotentPublish {
+    pub user_id: i32,
+    pub key: String,
+    /// The hex SHA-256 of the publish's metadata and crate file
+    pub fingerprint: String,
+    /// The body of the publish's response
+    pub response: String,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="publish_idempotency_keys"]
+struct NewIdempotentPublish<'a> {
+    user_id: i32,
+    key: &'a str,
+    fingerprint: &'a str,
+    response: &'a str,
+}
+
+/// Checks that a key sent in the `Idempotency-Key` header can be used.
+pub fn validate(key: &str) -> CargoResult<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN ||
+        !key.chars().all(|c| c >= ' ' && c <= '~') {
+        return Err(human(&format_args!("the {} header must be between 1 and {} printable \
+                                        ascii characters", HEADER, MAX_KEY_LEN)))
+    }
+    Ok(())
+}
+
+/// The fingerprint of a publish with the json `metadata` and `tarball`.
+pub fn fingerprint(metadata: &[u8], tarball: &[u8]) -> String {
+    let mut hasher = Hasher::new(MessageDigest::sha256()).unwrap();
+    hasher.update(metadata.len().to_string().as_bytes()).unwrap();
+    hasher.update(b":").unwrap();
+    hasher.update(metadata).unwrap();
+    hasher.update(tarball).unwrap();
+    hasher.finish().unwrap().to_hex()
+}
+
+impl IdempotentPublish {
+    /// The publish `user_id` made with `key` in the last day, if any.
+    pub fn find(conn: &InstrumentedConnection, user_id: i32, key: &str)
+                -> CargoResult<Option<IdempotentPublish>> {
+        let publish = publish_idempotency_keys::table.find((user_id, key))
+            .first::<IdempotentPublish>(conn)
+            .optional()?;
+        let oldest = time::now_utc().to_timespec() - Duration::days(1);
+        Ok(publish.and_then(|p| if p.created_at > oldest { Some(p) } else { None }))
+    }
+
+    /// Keeps the response of a successful publish, replacing an expired
+    /// publish with the same key.
+    pub fn save(conn: &InstrumentedConnection,
+                user_id: i32,
+                key: &str,
+                fingerprint: &str,
+                response: &str) -> CargoResult<()> {
+        let new = NewIdempotentPublish {
+            user_id: user_id,
+            key: key,
+            fingerprint: fingerprint,
+            response: response,
+        };
+        diesel::delete(publish_idempotency_keys::table.find((user_id, key))).execute(conn)?;
+        diesel::insert(&new).into(publish_idempotency_keys::table).execute(conn)?;
+        Ok(())
+    }
+
+    /// The response to send back to a retry with `fingerprint`.
+    pub fn replay(&self, fingerprint: &str) -> CargoResult<&str> {
+        if self.fingerprint != fingerprint {
+            return Err(human(&format_args!("the {} `{}` was already used for a different \
+                                            publish", HEADER, self.key)))
+        }
+        Ok(&self.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint, validate};
+
+    #[test]
+    fn keys_must_be_printable() {
+        assert!(validate("0b4c9c5e-4b2a-4f8e-a3c5-1d2e3f4a5b6c").is_ok());
+        assert!(validate("").is_err());
+        assert!(validate("new\nline").is_err());
+        let long = ::std::iter::repeat('a').take(256).collect::<String>();
+        assert!(validate(&long).is_err());
+    }
+
+    #[test]
+    fn fingerprints_tell_metadata_and_tarball_apart() {
+        assert_eq!(fingerprint(b"{}", b"tar"), fingerprint(b"{}", b"tar"));
+        assert!(fingerprint(b"{}", b"tar") != fingerprint(b"{}t", b"ar"));
+    }
+}
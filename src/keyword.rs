@@ -4,16 +4,15 @@ use time::Timespec;
 
 use conduit::{Request, Response};
 use conduit_router::RequestParams;
-use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel;
 use pg::GenericConnection;
 use pg::rows::Row;
 
 use {Model, Crate};
-use db::RequestTransaction;
+use db::{InstrumentedConnection, RequestTransaction};
 use schema::*;
-use util::{RequestUtils, CargoResult, ChainError, internal};
+use util::{RequestUtils, CargoResult, ChainError, internal, PaginationMeta};
 use util::errors::NotFound;
 
 #[derive(Clone, Identifiable, Queryable)]
@@ -45,13 +44,13 @@ pub struct EncodableKeyword {
 impl Keyword {
     pub fn find_by_keyword(conn: &GenericConnection, name: &str)
                            -> CargoResult<Option<Keyword>> {
-        let stmt = conn.prepare("SELECT * FROM keywords \
-                                      WHERE keyword = LOWER($1)")?;
-        let rows = stmt.query(&[&name])?;
+        let rows = conn.query("SELECT * FROM keywords \
+                                    WHERE keyword = LOWER($1)", &[&name])?;
         Ok(rows.iter().next().map(|r| Model::from_row(&r)))
     }
 
-    pub fn find_or_create_all(conn: &PgConnection, names: &[&str]) -> QueryResult<Vec<Keyword>> {
+    pub fn find_or_create_all(conn: &InstrumentedConnection,
+                              names: &[&str]) -> QueryResult<Vec<Keyword>> {
         use diesel::pg::upsert::*;
         use diesel::expression::dsl::any;
 
@@ -78,15 +77,14 @@ impl Keyword {
     pub fn find_or_insert(conn: &GenericConnection, name: &str)
                           -> CargoResult<Keyword> {
         // TODO: racy (the select then insert is not atomic)
-        let stmt = conn.prepare("SELECT * FROM keywords
-                                      WHERE keyword = LOWER($1)")?;
-        for row in stmt.query(&[&name])?.iter() {
+        let rows = conn.query("SELECT * FROM keywords
+                                    WHERE keyword = LOWER($1)", &[&name])?;
+        for row in rows.iter() {
             return Ok(Model::from_row(&row))
         }
 
-        let stmt = conn.prepare("INSERT INTO keywords (keyword) VALUES (LOWER($1))
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&name])?;
+        let rows = conn.query("INSERT INTO keywords (keyword) VALUES (LOWER($1))
+                                    RETURNING *", &[&name])?;
         Ok(Model::from_row(&rows.iter().next().chain_error(|| {
             internal("no version returned")
         })?))
@@ -100,11 +98,12 @@ impl Keyword {
            _ => "ORDER BY keyword ASC",
         };
 
-        let stmt = conn.prepare(&format!("SELECT * FROM keywords {}
-                                               LIMIT $1 OFFSET $2",
-                                         sort_sql))?;
+        let rows = conn.query(&format!("SELECT * FROM keywords {}
+                                             LIMIT $1 OFFSET $2",
+                                       sort_sql),
+                              &[&limit, &offset])?;
 
-        let keywords: Vec<_> = stmt.query(&[&limit, &offset])?
+        let keywords: Vec<_> = rows
             .iter()
             .map(|row| Model::from_row(&row))
             .collect();
@@ -129,7 +128,7 @@ impl Keyword {
         }
     }
 
-    pub fn update_crate(conn: &PgConnection,
+    pub fn update_crate(conn: &InstrumentedConnection,
                         krate: &Crate,
                         keywords: &[&str]) -> QueryResult<()> {
         conn.transaction(|| {
@@ -213,13 +212,11 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
     let total = Keyword::count(conn)?;
 
     #[derive(RustcEncodable)]
-    struct R { keywords: Vec<EncodableKeyword>, meta: Meta }
-    #[derive(RustcEncodable)]
-    struct Meta { total: i64 }
+    struct R { keywords: Vec<EncodableKeyword>, meta: PaginationMeta }
 
     Ok(req.json(&R {
         keywords: keywords,
-        meta: Meta { total: total },
+        meta: req.pagination_meta(offset, limit, total),
     }))
 }
 
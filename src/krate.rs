@@ -1,13 +1,15 @@
 use std::ascii::AsciiExt;
 use std::cmp;
 use std::collections::HashMap;
+use std::io::{self, Read};
 
+use chrono::UTC;
 use conduit::{Request, Response};
 use conduit_router::RequestParams;
 use diesel::associations::Identifiable;
 use diesel::helper_types::Select;
 use diesel::pg::upsert::*;
-use diesel::pg::{Pg, PgConnection};
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel;
 use diesel_full_text_search::*;
@@ -17,24 +19,45 @@ use pg::rows::Row;
 use rustc_serialize::hex::ToHex;
 use rustc_serialize::json;
 use semver;
-use time::{Timespec, Duration};
+use time::{self, Timespec, Duration};
 use url::Url;
 
+use admin::{EncodableTombstone, Tombstone};
 use app::{App, RequestApp};
+use audit;
 use badge::EncodableBadge;
 use category::{EncodableCategory, CrateCategory};
-use db::RequestTransaction;
+use config::Rollout;
+use db::{InstrumentedConnection, RequestTransaction};
 use dependency::{self, ReverseDependency, EncodableDependency};
-use download::{VersionDownload, EncodableVersionDownload};
+use download::{self, VersionDownload, EncodableVersionDownload};
+use files;
 use git;
+use handoff;
+use http;
+use idempotency::{self, IdempotentPublish};
 use keyword::{EncodableKeyword, CrateKeyword};
-use owner::{EncodableOwner, Owner, Rights, OwnerKind, Team, rights, CrateOwner};
+use mirror::{self, Mirror};
+use notification;
+use org;
+use owner::{EncodableOwner, EncodableOwnerChange, Owner, OwnerKind, OwnerRole, Rights, Team,
+            CrateOwner, request_rights};
+use owner_approval::{self, ApprovalAction};
+use permission;
+use provenance;
+use quarantine;
+use readme;
+use scanner::{self, Finding, ScanAction, Severity};
 use schema::*;
-use upload;
-use user::RequestUser;
+use settings::CrateSettings;
+use signature;
+use stats::RegistryStats;
+use tag_history;
+use upload::{self, FirstPublishReport, PublishWarning};
+use user::{EncodableUser, RequestUser};
 use util::errors::NotFound;
-use util::{read_le_u32, read_fill};
-use util::{RequestUtils, CargoResult, internal, ChainError, human};
+use util::{self, read_le_u32, read_fill, LimitErrorReader};
+use util::{RequestUtils, CargoResult, internal, ChainError, human, PaginationMeta};
 use version::{EncodableVersion, NewVersion};
 use {Model, User, Keyword, Version, Category, Badge, Replica};
 
@@ -52,6 +75,15 @@ pub struct Crate {
     pub license: Option<String>,
     pub repository: Option<String>,
     pub max_upload_size: Option<i32>,
+    /// Whether an owner showed they can push to `repository`
+    pub repository_verified: bool,
+    /// When the crate's first version was published, which unlike
+    /// `created_at` isn't changed by renames
+    pub first_published_at: Option<Timespec>,
+    /// Who published the crate's first version
+    pub first_published_by: Option<i32>,
+    /// How many crates depend on this one in their newest available version
+    pub dependents_cnt: i32,
 }
 
 /// We literally never want to select `textsearchable_index_col`
@@ -59,13 +91,19 @@ pub struct Crate {
 type AllColumns = (crates::id, crates::name, crates::updated_at,
     crates::created_at, crates::downloads, crates::description,
     crates::homepage, crates::documentation, crates::readme, crates::license,
-    crates::repository, crates::max_upload_size);
+    crates::repository, crates::max_upload_size, crates::repository_verified,
+    crates::first_published_at, crates::first_published_by, crates::dependents_cnt);
 
 pub const ALL_COLUMNS: AllColumns = (crates::id, crates::name,
     crates::updated_at, crates::created_at, crates::downloads,
     crates::description, crates::homepage, crates::documentation,
     crates::readme, crates::license, crates::repository,
-    crates::max_upload_size);
+    crates::max_upload_size, crates::repository_verified, crates::first_published_at,
+    crates::first_published_by, crates::dependents_cnt);
+
+/// The first key of the advisory locks taken by publishes, the second one
+/// being the crate's id.
+const PUBLISH_LOCK_NAMESPACE: i32 = 1;
 
 type CrateQuery<'a> = crates::BoxedQuery<'a, Pg, <AllColumns as Expression>::SqlType>;
 
@@ -86,6 +124,11 @@ pub struct EncodableCrate {
     pub documentation: Option<String>,
     pub license: Option<String>,
     pub repository: Option<String>,
+    pub repository_verified: bool,
+    pub first_published_at: Option<String>,
+    pub dependents_cnt: i32,
+    /// Only included when showing a single crate
+    pub original_publisher: Option<EncodableUser>,
     pub links: CrateLinks,
 }
 
@@ -114,7 +157,7 @@ pub struct NewCrate<'a> {
 impl<'a> NewCrate<'a> {
     pub fn create_or_update(
         mut self,
-        conn: &PgConnection,
+        conn: &InstrumentedConnection,
         license_file: Option<&str>,
         uploader: i32,
     ) -> CargoResult<Crate> {
@@ -141,26 +184,6 @@ impl<'a> NewCrate<'a> {
     }
 
     fn validate(&mut self, license_file: Option<&str>) -> CargoResult<()> {
-        fn validate_url(url: Option<&str>, field: &str) -> CargoResult<()> {
-            let url = match url {
-                Some(s) => s,
-                None => return Ok(())
-            };
-            let url = Url::parse(url).map_err(|_| {
-                human(&format_args!("`{}` is not a valid url: `{}`", field, url))
-            })?;
-            match &url.scheme()[..] {
-                "http" | "https" => {}
-                s => return Err(human(&format_args!("`{}` has an invalid url \
-                                                    scheme: `{}`", field, s)))
-            }
-            if url.cannot_be_a_base() {
-                return Err(human(&format_args!("`{}` must have relative scheme \
-                                               data: {}", field, url)))
-            }
-            Ok(())
-        }
-
         validate_url(self.homepage, "homepage")?;
         validate_url(self.documentation, "documentation")?;
         validate_url(self.repository, "repository")?;
@@ -185,7 +208,7 @@ impl<'a> NewCrate<'a> {
         Ok(())
     }
 
-    fn ensure_name_not_reserved(&self, conn: &PgConnection) -> CargoResult<()> {
+    fn ensure_name_not_reserved(&self, conn: &InstrumentedConnection) -> CargoResult<()> {
         use schema::reserved_crate_names::dsl::*;
         use diesel::select;
         use diesel::expression::dsl::exists;
@@ -194,13 +217,21 @@ impl<'a> NewCrate<'a> {
             .filter(canon_crate_name(name).eq(canon_crate_name(self.name)))
             )).get_result::<bool>(conn)?;
         if reserved_name {
-            Err(human("cannot upload a crate with a reserved name"))
-        } else {
-            Ok(())
+            return Err(human("cannot upload a crate with a reserved name"))
+        }
+
+        let alias = select(exists(crate_aliases::table
+            .filter(canon_crate_name(crate_aliases::alias).eq(canon_crate_name(self.name)))
+            )).get_result::<bool>(conn)?;
+        if alias {
+            return Err(human("cannot upload a crate with the former name of \
+                              a renamed crate"))
         }
+        Ok(())
     }
 
-    fn save_new_crate(&self, conn: &PgConnection, user_id: i32) -> CargoResult<Option<Crate>> {
+    fn save_new_crate(&self, conn: &InstrumentedConnection,
+                      user_id: i32) -> CargoResult<Option<Crate>> {
         use schema::crates::dsl::*;
         use diesel::insert;
 
@@ -216,6 +247,7 @@ impl<'a> NewCrate<'a> {
                     owner_id: user_id,
                     created_by: user_id,
                     owner_kind: OwnerKind::User as i32,
+                    role: OwnerRole::Owner as i32,
                 };
                 insert(&owner).into(crate_owners::table)
                     .execute(conn)?;
@@ -226,6 +258,27 @@ impl<'a> NewCrate<'a> {
     }
 }
 
+/// Checks that `url`, the value of the metadata `field`, is an http(s) url.
+pub fn validate_url(url: Option<&str>, field: &str) -> CargoResult<()> {
+    let url = match url {
+        Some(s) => s,
+        None => return Ok(())
+    };
+    let url = Url::parse(url).map_err(|_| {
+        human(&format_args!("`{}` is not a valid url: `{}`", field, url))
+    })?;
+    match &url.scheme()[..] {
+        "http" | "https" => {}
+        s => return Err(human(&format_args!("`{}` has an invalid url \
+                                             scheme: `{}`", field, s)))
+    }
+    if url.cannot_be_a_base() {
+        return Err(human(&format_args!("`{}` must have relative scheme \
+                                        data: {}", field, url)))
+    }
+    Ok(())
+}
+
 impl Crate {
     pub fn by_name(name: &str) -> CrateQuery {
         Crate::all()
@@ -241,10 +294,9 @@ impl Crate {
 
     pub fn find_by_name(conn: &GenericConnection,
                         name: &str) -> CargoResult<Crate> {
-        let stmt = conn.prepare("SELECT * FROM crates \
-                                      WHERE canon_crate_name(name) =
-                                            canon_crate_name($1) LIMIT 1")?;
-        let rows = stmt.query(&[&name])?;
+        let rows = conn.query("SELECT * FROM crates \
+                                    WHERE canon_crate_name(name) =
+                                          canon_crate_name($1) LIMIT 1", &[&name])?;
         let row = rows.iter().next();
         let row = row.chain_error(|| NotFound)?;
         Ok(Model::from_row(&row))
@@ -291,41 +343,40 @@ impl Crate {
         }
 
         // TODO: like with users, this is sadly racy
-        let stmt = conn.prepare("UPDATE crates
-                                         SET documentation = $1,
-                                             homepage = $2,
-                                             description = $3,
-                                             readme = $4,
-                                             license = $5,
-                                             repository = $6
-                                       WHERE canon_crate_name(name) =
-                                             canon_crate_name($7)
-                                   RETURNING *")?;
-        let rows = stmt.query(&[&documentation, &homepage,
-            &description, &readme,
-            &license, &repository,
-            &name])?;
+        let rows = conn.query("UPDATE crates
+                                       SET documentation = $1,
+                                           homepage = $2,
+                                           description = $3,
+                                           readme = $4,
+                                           license = $5,
+                                           repository = $6
+                                     WHERE canon_crate_name(name) =
+                                           canon_crate_name($7)
+                                 RETURNING *",
+                              &[&documentation, &homepage,
+                                &description, &readme,
+                                &license, &repository,
+                                &name])?;
         if let Some(row) = rows.iter().next() {
             return Ok(Model::from_row(&row));
         }
 
-        let stmt = conn.prepare("SELECT 1 FROM reserved_crate_names
-                                 WHERE canon_crate_name(name) =
-                                       canon_crate_name($1)")?;
-        let rows = stmt.query(&[&name])?;
+        let rows = conn.query("SELECT 1 FROM reserved_crate_names
+                               WHERE canon_crate_name(name) =
+                                     canon_crate_name($1)", &[&name])?;
         if !rows.is_empty() {
             return Err(human("cannot upload a crate with a reserved name"))
         }
 
-        let stmt = conn.prepare("INSERT INTO crates
-                                      (name, description, homepage,
-                                       documentation, readme,
-                                       repository, license, max_upload_size)
-                                      VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&name, &description, &homepage,
-            &documentation, &readme,
-            &repository, &license, &max_upload_size])?;
+        let rows = conn.query("INSERT INTO crates
+                                    (name, description, homepage,
+                                     documentation, readme,
+                                     repository, license, max_upload_size)
+                                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                                    RETURNING *",
+                              &[&name, &description, &homepage,
+                                &documentation, &readme,
+                                &repository, &license, &max_upload_size])?;
         let ret: Crate = Model::from_row(&rows.iter().next().chain_error(|| {
             internal("no crate returned")
         })?);
@@ -368,6 +419,18 @@ impl Crate {
 
     }
 
+    /// Returns the current name of the crate which `name` is an alias of, if
+    /// any.
+    pub fn alias_target(conn: &GenericConnection,
+                        name: &str) -> CargoResult<Option<String>> {
+        let rows = conn.query("SELECT crates.name FROM crate_aliases
+                                    INNER JOIN crates
+                                       ON crates.id = crate_aliases.crate_id
+                                    WHERE canon_crate_name(crate_aliases.alias) =
+                                          canon_crate_name($1)", &[&name])?;
+        Ok(rows.iter().next().map(|row| row.get("name")))
+    }
+
     pub fn valid_name(name: &str) -> bool {
         if name.is_empty() { return false }
         name.chars().next().unwrap().is_alphabetic() &&
@@ -389,6 +452,29 @@ impl Crate {
         parts.next().is_none()
     }
 
+    /// Whether `value` is a valid feature value which only cargos reading
+    /// `features2` from the index understand, i.e. `dep:name` or
+    /// `name?/feature`.
+    pub fn valid_features2_value(value: &str) -> bool {
+        if value.starts_with("dep:") {
+            return Crate::valid_name(&value[4..])
+        }
+        let mut parts = value.splitn(2, "?/");
+        match (parts.next(), parts.next()) {
+            (Some(dep), Some(feature)) => {
+                Crate::valid_name(dep) && Crate::valid_name(feature)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `version` looks like a Rust version, e.g. `1.15` or `1.15.1`.
+    pub fn valid_rust_version(version: &str) -> bool {
+        let parts = version.split('.').collect::<Vec<_>>();
+        (parts.len() == 2 || parts.len() == 3) &&
+            parts.iter().all(|p| !p.is_empty() && p.chars().all(|c| c.is_digit(10)))
+    }
+
     pub fn minimal_encodable(self,
                              max_version: semver::Version,
                              badges: Option<Vec<Badge>>) -> EncodableCrate {
@@ -404,7 +490,8 @@ impl Crate {
                      -> EncodableCrate {
         let Crate {
             name, created_at, updated_at, downloads, description,
-            homepage, documentation, license, repository, ..
+            homepage, documentation, license, repository, repository_verified,
+            first_published_at, dependents_cnt, ..
         } = self;
         let versions_link = match versions {
             Some(..) => None,
@@ -431,6 +518,10 @@ impl Crate {
             description: description,
             license: license,
             repository: repository,
+            repository_verified: repository_verified,
+            first_published_at: first_published_at.map(::encode_time),
+            dependents_cnt: dependents_cnt,
+            original_publisher: None,
             links: CrateLinks {
                 version_downloads: format!("/api/v1/crates/{}/downloads", name),
                 versions: versions_link,
@@ -440,7 +531,16 @@ impl Crate {
         }
     }
 
-    pub fn max_version(&self, conn: &PgConnection) -> CargoResult<semver::Version> {
+    /// Takes the crate's publish lock until the end of the current
+    /// transaction, so that publishes of the same crate update the index and
+    /// the crate one after the other rather than interleaving.
+    pub fn lock_for_publish(&self, conn: &InstrumentedConnection) -> QueryResult<()> {
+        conn.execute(&format!("SELECT pg_advisory_xact_lock({}, {})",
+                              PUBLISH_LOCK_NAMESPACE, self.id))?;
+        Ok(())
+    }
+
+    pub fn max_version(&self, conn: &InstrumentedConnection) -> CargoResult<semver::Version> {
         use schema::versions::dsl::*;
 
         let vs = Version::belonging_to(self).select(num)
@@ -452,17 +552,15 @@ impl Crate {
     }
 
     pub fn max_version_old(&self, conn: &GenericConnection) -> CargoResult<semver::Version> {
-        let stmt = conn.prepare("SELECT num FROM versions WHERE crate_id = $1
-                                 AND yanked = 'f'")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT num FROM versions WHERE crate_id = $1
+                               AND yanked = 'f'", &[&self.id])?;
         Ok(Version::max(rows.iter().map(|r| r.get::<_, String>("num"))
            .map(|s| semver::Version::parse(&s).unwrap())))
     }
 
     pub fn versions(&self, conn: &GenericConnection) -> CargoResult<Vec<Version>> {
-        let stmt = conn.prepare("SELECT * FROM versions \
-                                      WHERE crate_id = $1")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT * FROM versions \
+                                    WHERE crate_id = $1", &[&self.id])?;
         let mut ret = rows.iter().map(|r| {
             Model::from_row(&r)
         }).collect::<Vec<Version>>();
@@ -470,9 +568,28 @@ impl Crate {
         Ok(ret)
     }
 
-    pub fn owners(&self, conn: &PgConnection) -> CargoResult<Vec<Owner>> {
+    /// The owners of this crate, who have full control over it.
+    pub fn owners(&self, conn: &InstrumentedConnection) -> CargoResult<Vec<Owner>> {
+        self.owners_with_role(conn, OwnerRole::Owner)
+    }
+
+    /// The collaborators of this crate, who can only publish new versions.
+    pub fn collaborators(&self, conn: &InstrumentedConnection) -> CargoResult<Vec<Owner>> {
+        self.owners_with_role(conn, OwnerRole::Collaborator)
+    }
+
+    /// Everyone allowed to publish new versions of this crate.
+    pub fn publishers(&self, conn: &InstrumentedConnection) -> CargoResult<Vec<Owner>> {
+        let mut publishers = self.owners(conn)?;
+        publishers.extend(self.collaborators(conn)?);
+        Ok(publishers)
+    }
+
+    fn owners_with_role(&self, conn: &InstrumentedConnection,
+                        role: OwnerRole) -> CargoResult<Vec<Owner>> {
         let base_query = CrateOwner::belonging_to(self)
-            .filter(crate_owners::deleted.eq(false));
+            .filter(crate_owners::deleted.eq(false))
+            .filter(crate_owners::role.eq(role as i32));
         let users = base_query.inner_join(users::table)
             .select(users::all_columns)
             .filter(crate_owners::owner_kind.eq(OwnerKind::User as i32))
@@ -485,49 +602,77 @@ impl Crate {
             .load(conn)?
             .into_iter()
             .map(Owner::Team);
+        let orgs = organizations::table
+            .filter(organizations::id.eq_any(
+                base_query.select(crate_owners::owner_id)
+                    .filter(crate_owners::owner_kind.eq(OwnerKind::Org as i32))
+            ))
+            .load(conn)?
+            .into_iter()
+            .map(Owner::Org);
 
-        Ok(users.chain(teams).collect())
+        Ok(users.chain(teams).chain(orgs).collect())
     }
 
     pub fn owners_old(&self, conn: &GenericConnection) -> CargoResult<Vec<Owner>> {
-        let stmt = conn.prepare("SELECT * FROM users
-                                      INNER JOIN crate_owners
-                                         ON crate_owners.owner_id = users.id
-                                      WHERE crate_owners.crate_id = $1
-                                        AND crate_owners.deleted = FALSE
-                                        AND crate_owners.owner_kind = $2")?;
-        let user_rows = stmt.query(&[&self.id, &(OwnerKind::User as i32)])?;
-
-        let stmt = conn.prepare("SELECT * FROM teams
-                                      INNER JOIN crate_owners
-                                         ON crate_owners.owner_id = teams.id
-                                      WHERE crate_owners.crate_id = $1
-                                        AND crate_owners.deleted = FALSE
-                                        AND crate_owners.owner_kind = $2")?;
-        let team_rows = stmt.query(&[&self.id, &(OwnerKind::Team as i32)])?;
+        let user_rows = conn.query("SELECT * FROM users
+                                         INNER JOIN crate_owners
+                                            ON crate_owners.owner_id = users.id
+                                         WHERE crate_owners.crate_id = $1
+                                           AND crate_owners.deleted = FALSE
+                                           AND crate_owners.owner_kind = $2
+                                           AND crate_owners.role = $3",
+                                   &[&self.id, &(OwnerKind::User as i32),
+                                     &(OwnerRole::Owner as i32)])?;
+
+        let team_rows = conn.query("SELECT * FROM teams
+                                         INNER JOIN crate_owners
+                                            ON crate_owners.owner_id = teams.id
+                                         WHERE crate_owners.crate_id = $1
+                                           AND crate_owners.deleted = FALSE
+                                           AND crate_owners.owner_kind = $2
+                                           AND crate_owners.role = $3",
+                                   &[&self.id, &(OwnerKind::Team as i32),
+                                     &(OwnerRole::Owner as i32)])?;
+
+        let org_rows = conn.query("SELECT * FROM organizations
+                                        INNER JOIN crate_owners
+                                           ON crate_owners.owner_id = organizations.id
+                                        WHERE crate_owners.crate_id = $1
+                                          AND crate_owners.deleted = FALSE
+                                          AND crate_owners.owner_kind = $2
+                                          AND crate_owners.role = $3",
+                                  &[&self.id, &(OwnerKind::Org as i32),
+                                    &(OwnerRole::Owner as i32)])?;
 
         let mut owners = vec![];
         owners.extend(user_rows.iter().map(|r| Owner::User(Model::from_row(&r))));
         owners.extend(team_rows.iter().map(|r| Owner::Team(Model::from_row(&r))));
+        owners.extend(org_rows.iter().map(|r| Owner::Org(Model::from_row(&r))));
         Ok(owners)
     }
 
     pub fn owner_add(
         &self,
         app: &App,
-        conn: &PgConnection,
+        conn: &InstrumentedConnection,
         req_user: &User,
         login: &str,
+        role: OwnerRole,
     ) -> CargoResult<()> {
         let owner = match Owner::find_by_login(conn, login) {
             Ok(owner @ Owner::User(_)) => { owner }
+            Ok(Owner::Org(org)) => {
+                org.ensure_admin(conn, req_user)?;
+                Owner::Org(org)
+            }
             Ok(Owner::Team(team)) => if team.contains_user(app, req_user)? {
                 Owner::Team(team)
             } else {
                 return Err(human(&format_args!("only members of {} can add it as \
                                           an owner", login)));
             },
-            Err(err) => if login.contains(':') {
+            Err(err) => if login.contains(':') && !login.starts_with(org::LOGIN_PREFIX) {
                 Owner::Team(Team::create(app, conn, login, req_user)?)
             } else {
                 return Err(err);
@@ -539,10 +684,12 @@ impl Crate {
             owner_id: owner.id(),
             created_by: req_user.id,
             owner_kind: owner.kind() as i32,
+            role: role as i32,
         };
         diesel::insert(&crate_owner.on_conflict(
                 crate_owners::table.primary_key(),
-                do_update().set(crate_owners::deleted.eq(false)),
+                do_update().set((crate_owners::deleted.eq(false),
+                                 crate_owners::role.eq(role as i32))),
             )).into(crate_owners::table)
             .execute(conn)?;
 
@@ -550,7 +697,7 @@ impl Crate {
     }
 
     pub fn owner_remove(&self,
-                        conn: &PgConnection,
+                        conn: &InstrumentedConnection,
                         _req_user: &User,
                         login: &str) -> CargoResult<()> {
         let owner = Owner::find_by_login(conn, login).map_err(|_| {
@@ -579,28 +726,25 @@ impl Crate {
     }
 
     pub fn keywords(&self, conn: &GenericConnection) -> CargoResult<Vec<Keyword>> {
-        let stmt = conn.prepare("SELECT keywords.* FROM keywords
-                                      LEFT JOIN crates_keywords
-                                      ON keywords.id = crates_keywords.keyword_id
-                                      WHERE crates_keywords.crate_id = $1")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT keywords.* FROM keywords
+                                    LEFT JOIN crates_keywords
+                                    ON keywords.id = crates_keywords.keyword_id
+                                    WHERE crates_keywords.crate_id = $1", &[&self.id])?;
         Ok(rows.iter().map(|r| Model::from_row(&r)).collect())
     }
 
     pub fn categories(&self, conn: &GenericConnection) -> CargoResult<Vec<Category>> {
-        let stmt = conn.prepare("SELECT categories.* FROM categories \
-                                      LEFT JOIN crates_categories \
-                                      ON categories.id = \
-                                         crates_categories.category_id \
-                                      WHERE crates_categories.crate_id = $1")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT categories.* FROM categories \
+                                    LEFT JOIN crates_categories \
+                                    ON categories.id = \
+                                       crates_categories.category_id \
+                                    WHERE crates_categories.crate_id = $1", &[&self.id])?;
         Ok(rows.iter().map(|r| Model::from_row(&r)).collect())
     }
 
     pub fn badges(&self, conn: &GenericConnection) -> CargoResult<Vec<Badge>> {
-        let stmt = conn.prepare("SELECT badges.* from badges \
-                                      WHERE badges.crate_id = $1")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT badges.* from badges \
+                                    WHERE badges.crate_id = $1", &[&self.id])?;
         Ok(rows.iter().map(|r| Model::from_row(&r)).collect())
     }
 
@@ -610,9 +754,8 @@ impl Crate {
                                 offset: i64,
                                 limit: i64)
                                 -> CargoResult<(Vec<ReverseDependency>, i64)> {
-        let stmt = conn.prepare(include_str!("krate_reverse_dependencies.sql"))?;
-
-        let rows = stmt.query(&[&self.id, &offset, &limit])?;
+        let rows = conn.query(include_str!("krate_reverse_dependencies.sql"),
+                              &[&self.id, &offset, &limit])?;
         let cnt = if rows.is_empty() {
             0i64
         } else {
@@ -642,21 +785,100 @@ impl Model for Crate {
             license: row.get("license"),
             repository: row.get("repository"),
             max_upload_size: row.get("max_upload_size"),
+            repository_verified: row.get("repository_verified"),
+            first_published_at: row.get("first_published_at"),
+            first_published_by: row.get("first_published_by"),
+            dependents_cnt: row.get("dependents_cnt"),
         }
     }
     fn table_name(_: Option<Crate>) -> &'static str { "crates" }
 }
 
 /// Handles the `GET /crates` route.
-#[allow(trivial_casts)]
 pub fn index(req: &mut Request) -> CargoResult<Response> {
-    use diesel::expression::dsl::sql;
-    use diesel::types::BigInt;
-
     let conn = req.db_conn()?;
     let (offset, limit) = req.pagination(10, 100)?;
     let params = req.query();
+    let include_yanked = params.get("include_yanked").map(|s| &**s) == Some("true");
+
+    let data = crates_page(req, &conn, &params, offset, limit)?;
+    // Pages past the end don't have a row to read the total from
+    let total = match data.get(0) {
+        Some(&(_, total)) => total,
+        None if offset > 0 => {
+            crates_page(req, &conn, &params, 0, 1)?.get(0).map(|&(_, t)| t).unwrap_or(0)
+        }
+        None => 0,
+    };
+    let crates = data.into_iter().map(|(c, _)| c).collect::<Vec<_>>();
+
+    let versions = Version::belonging_to(&crates)
+        .load::<Version>(&*conn)?
+        .grouped_by(&crates)
+        .into_iter()
+        .map(|versions| Version::max(versions.into_iter().map(|v| v.num)));
+
+    let crates = versions.zip(crates).map(|(max_version, krate)| {
+        // FIXME: If we add crate_id to the Badge enum we can eliminate
+        // this N+1
+        let badges = badges::table.filter(badges::crate_id.eq(krate.id))
+            .load::<Badge>(&*conn)?;
+        Ok(krate.minimal_encodable(max_version, Some(badges)))
+    }).collect::<Result<_, ::diesel::result::Error>>()?;
+
+    let facets = match params.get("q") {
+        Some(q) => Some(search_facets(req.tx()?, q, include_yanked)?),
+        None => None,
+    };
+    // Only searches without any results get suggestions, not pages past the
+    // end of ones which have some
+    let suggestions = match params.get("q") {
+        Some(q) if total == 0 => name_suggestions(req.tx()?, q)?,
+        _ => Vec::new(),
+    };
+
+    #[derive(RustcEncodable)]
+    struct R { crates: Vec<EncodableCrate>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta {
+        total: i64,
+        next_page: Option<String>,
+        prev_page: Option<String>,
+        facets: Option<SearchFacets>,
+        suggestions: Vec<String>,
+    }
+
+    let page = req.pagination_meta(offset, limit, total);
+    Ok(req.json(&R {
+        crates: crates,
+        meta: Meta {
+            total: page.total,
+            next_page: page.next_page,
+            prev_page: page.prev_page,
+            facets: facets,
+            suggestions: suggestions,
+        },
+    }))
+}
+
+/// Loads a page of the crates `GET /crates` lists for `params`, each along
+/// with how many crates there are on all the pages.
+#[allow(trivial_casts)]
+fn crates_page(req: &Request,
+               conn: &InstrumentedConnection,
+               params: &HashMap<String, String>,
+               offset: i64,
+               limit: i64) -> CargoResult<Vec<(Crate, i64)>> {
+    use diesel::expression::dsl::{any, sql};
+    use diesel::types::BigInt;
+
     let sort = params.get("sort").map(|s| &**s).unwrap_or("alpha");
+    let include_yanked = params.get("include_yanked").map(|s| &**s) == Some("true");
+    // Crates which only have yanked versions left are hidden unless asked
+    // for, except when listing the crates of a user or the ones they follow.
+    let mut hide_all_yanked = !include_yanked;
+    // The crates of a user or the ones they follow
+    let mut listed = None;
 
     let mut query = crates::table
         .select((ALL_COLUMNS, sql::<BigInt>("COUNT(*) OVER ()")))
@@ -666,6 +888,8 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
 
     if sort == "downloads" {
         query = query.order(crates::downloads.desc())
+    } else if sort == "dependents" {
+        query = query.order(crates::dependents_cnt.desc())
     } else {
         query = query.order(crates::name.asc())
     }
@@ -677,8 +901,11 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
         let perfect_match = crates::name.eq(q_string).desc();
         if sort == "downloads" {
             query = query.order((perfect_match, crates::downloads.desc()));
+        } else if sort == "dependents" {
+            query = query.order((perfect_match, crates::dependents_cnt.desc()));
         } else {
-            let rank = ts_rank_cd(crates::textsearchable_index_col, q);
+            let weights = req.app().config.search_weights.as_pg_array();
+            let rank = weighted::ts_rank_cd(weights, crates::textsearchable_index_col, q);
             query = query.order((perfect_match, rank.desc()))
         }
     } else if let Some(letter) = params.get("letter") {
@@ -699,53 +926,121 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
                         categories::slug.like(format!("{}::%", cat))))
         ));
     } else if let Some(user_id) = params.get("user_id").and_then(|s| s.parse::<i32>().ok()) {
-        query = query.filter(crates::id.eq_any(
-            crate_owners::table.select(crate_owners::crate_id)
-                .filter(crate_owners::owner_id.eq(user_id))
-                .filter(crate_owners::owner_kind.eq(OwnerKind::User as i32))
-        ));
+        hide_all_yanked = false;
+        let owned = crate_owners::table.select(crate_owners::crate_id)
+            .filter(crate_owners::owner_id.eq(user_id))
+            .filter(crate_owners::owner_kind.eq(OwnerKind::User as i32))
+            .load::<i32>(conn)?;
+        query = query.filter(crates::id.eq(any(owned.clone())));
+        listed = Some(owned);
     } else if params.get("following").is_some() {
-        query = query.filter(crates::id.eq_any(
-            follows::table.select(follows::crate_id)
-                .filter(follows::user_id.eq(req.user()?.id))
-        ));
+        hide_all_yanked = false;
+        let followed = follows::table.select(follows::crate_id)
+            .filter(follows::user_id.eq(req.user()?.id))
+            .load::<i32>(conn)?;
+        query = query.filter(crates::id.eq(any(followed.clone())));
+        listed = Some(followed);
     }
 
-    let data = query.load::<(Crate, i64)>(&*conn)?;
-    let total = data.get(0).map(|&(_, t)| t).unwrap_or(0);
-    let crates = data.into_iter().map(|(c, _)| c).collect::<Vec<_>>();
+    if hide_all_yanked {
+        query = query.filter(crates::all_yanked.eq(false));
+    }
+    match listed {
+        // Unlisted crates are only left out of searches and summaries, and
+        // private ones are listed to those who can see them
+        Some(crate_ids) => {
+            let readable = permission::readable_private_crates(req, conn, &crate_ids)?;
+            query = query.filter(crates::private.eq(false).or(crates::id.eq(any(readable))));
+        }
+        None => {
+            query = query.filter(crates::unlisted.eq(false))
+                .filter(crates::private.eq(false));
+        }
+    }
 
-    let versions = Version::belonging_to(&crates)
-        .load::<Version>(&*conn)?
-        .grouped_by(&crates)
-        .into_iter()
-        .map(|versions| Version::max(versions.into_iter().map(|v| v.num)));
+    Ok(query.load::<(Crate, i64)>(conn)?)
+}
 
-    let crates = versions.zip(crates).map(|(max_version, krate)| {
-        // FIXME: If we add crate_id to the Badge enum we can eliminate
-        // this N+1
-        let badges = badges::table.filter(badges::crate_id.eq(krate.id))
-            .load::<Badge>(&*conn)?;
-        Ok(krate.minimal_encodable(max_version, Some(badges)))
-    }).collect::<Result<_, ::diesel::result::Error>>()?;
+/// The number of crate names suggested for searches without results.
+const SUGGESTION_LIMIT: i64 = 5;
+
+/// Finds the names of crates which are spelled similarly to `q`, using
+/// trigram similarity, with the most similar and most downloaded first.
+fn name_suggestions(conn: &GenericConnection, q: &str) -> CargoResult<Vec<String>> {
+    let rows = conn.query("\
+        SELECT name FROM crates
+         WHERE canon_crate_name(name) % canon_crate_name($1)
+           AND NOT unlisted
+           AND NOT private
+         ORDER BY similarity(canon_crate_name(name), canon_crate_name($1)) DESC,
+                  downloads DESC
+         LIMIT $2", &[&q, &SUGGESTION_LIMIT])?;
+    Ok(rows.iter().map(|row| row.get("name")).collect())
+}
 
-    #[derive(RustcEncodable)]
-    struct R { crates: Vec<EncodableCrate>, meta: Meta }
-    #[derive(RustcEncodable)]
-    struct Meta { total: i64 }
+/// The number of values returned for each search facet.
+const FACET_LIMIT: i64 = 10;
 
-    Ok(req.json(&R {
-        crates: crates,
-        meta: Meta { total: total },
-    }))
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct SearchFacets {
+    pub categories: Vec<SearchFacet>,
+    pub keywords: Vec<SearchFacet>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct SearchFacet {
+    pub value: String,
+    pub crates: i64,
+}
+
+/// Counts the crates matching the search `q` in each category and for each
+/// of the most common keywords among them.
+fn search_facets(conn: &GenericConnection,
+                 q: &str,
+                 include_yanked: bool) -> CargoResult<SearchFacets> {
+    let facet = |join: &str, value: &str| -> CargoResult<Vec<SearchFacet>> {
+        let rows = conn.query(&format!("\
+            SELECT {value} AS value, COUNT(*) AS crates
+              FROM crates
+             {join}
+             WHERE plainto_tsquery($1) @@ crates.textsearchable_index_col
+               AND ($3 OR NOT crates.all_yanked)
+               AND NOT crates.unlisted
+               AND NOT crates.private
+             GROUP BY {value}
+             ORDER BY crates DESC, value ASC
+             LIMIT $2", join = join, value = value), &[&q, &FACET_LIMIT, &include_yanked])?;
+        Ok(rows.iter().map(|row| {
+            SearchFacet { value: row.get("value"), crates: row.get("crates") }
+        }).collect())
+    };
+
+    Ok(SearchFacets {
+        categories: facet("INNER JOIN crates_categories
+                                   ON crates_categories.crate_id = crates.id
+                           INNER JOIN categories
+                                   ON categories.id = crates_categories.category_id",
+                          "categories.slug")?,
+        keywords: facet("INNER JOIN crates_keywords
+                                 ON crates_keywords.crate_id = crates.id
+                         INNER JOIN keywords
+                                 ON keywords.id = crates_keywords.keyword_id",
+                        "keywords.keyword")?,
+    })
 }
 
 /// Handles the `GET /summary` route.
 pub fn summary(req: &mut Request) -> CargoResult<Response> {
     use schema::crates::dsl::*;
 
+    // The job snapshotting the registry's stats counts the crates once a
+    // week, which is recent enough for the front page
+    let latest_stats = RegistryStats::latest(req.tx()?)?;
     let conn = req.db_conn()?;
-    let num_crates = crates.count().get_result(&*conn)?;
+    let num_crates = match latest_stats {
+        Some(stats) => stats.total_crates,
+        None => crates.count().get_result(&*conn)?,
+    };
     let num_downloads = metadata::table.select(metadata::total_downloads)
         .get_result(&*conn)?;
 
@@ -762,16 +1057,22 @@ pub fn summary(req: &mut Request) -> CargoResult<Response> {
             }).collect()
     };
 
-    let new_crates = crates.order(created_at.desc())
+    let new_crates = crates.filter(unlisted.eq(false))
+        .filter(private.eq(false))
+        .order(created_at.desc())
         .select(ALL_COLUMNS)
         .limit(10)
         .load(&*conn)?;
     let just_updated = crates.filter(updated_at.ne(created_at))
+        .filter(unlisted.eq(false))
+        .filter(private.eq(false))
         .order(updated_at.desc())
         .select(ALL_COLUMNS)
         .limit(10)
         .load(&*conn)?;
-    let most_downloaded = crates.order(downloads.desc())
+    let most_downloaded = crates.filter(unlisted.eq(false))
+        .filter(private.eq(false))
+        .order(downloads.desc())
         .select(ALL_COLUMNS)
         .limit(10)
         .load(&*conn)?;
@@ -809,12 +1110,250 @@ pub fn summary(req: &mut Request) -> CargoResult<Response> {
     }))
 }
 
+/// The minimum number of downloads a crate needs in the previous week for its
+/// week-over-week growth to be considered by `/crates/trending`. Without this
+/// a crate going from 1 to 10 downloads would top the list.
+const TRENDING_MIN_BASELINE: i64 = 100;
+
+/// Loads the crates with the given ids, keeping the order of `ids`, and
+/// encodes them along with their max version. Ids of crates which no longer
+/// exist are skipped.
+fn encode_crates_in_order(conn: &InstrumentedConnection, ids: &[i32])
+                          -> CargoResult<Vec<(i32, EncodableCrate)>> {
+    use diesel::expression::dsl::any;
+
+    let krates = Crate::all()
+        .filter(crates::id.eq(any(ids)))
+        .load::<Crate>(conn)?;
+    let max_versions = Version::belonging_to(&krates)
+        .filter(versions::yanked.eq(false))
+        .load::<Version>(conn)?
+        .grouped_by(&krates)
+        .into_iter()
+        .map(|versions| Version::max(versions.into_iter().map(|v| v.num)));
+    let mut encoded = max_versions.zip(krates).map(|(max_version, krate)| {
+        (krate.id, krate.minimal_encodable(max_version, None))
+    }).collect::<HashMap<_, _>>();
+
+    Ok(ids.iter().filter_map(|id| encoded.remove(id).map(|krate| (*id, krate))).collect())
+}
+
+/// Handles the `GET /crates/most_downloaded` route.
+///
+/// Ranks crates by the number of downloads over the last 90 days. The ranking
+/// is cached for an hour.
+pub fn most_downloaded(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::sql;
+    use diesel::types::{BigInt, Integer};
+
+    let conn = req.db_conn()?;
+    let ranking = req.app().most_downloaded.get_or_try_insert_with(|| {
+        diesel::select(sql::<(Integer, BigInt)>("\
+            versions.crate_id, SUM(version_downloads.downloads)::int8 AS downloads
+              FROM version_downloads
+             INNER JOIN versions ON versions.id = version_downloads.version_id
+             INNER JOIN crates ON crates.id = versions.crate_id
+             WHERE version_downloads.date > CURRENT_DATE - 90
+               AND NOT crates.unlisted
+               AND NOT crates.private
+             GROUP BY versions.crate_id
+             ORDER BY downloads DESC
+             LIMIT 10"
+        )).load(&*conn).map_err(Into::into)
+    })?;
+
+    let ids = ranking.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+    let crates = encode_crates_in_order(&conn, &ids)?;
+
+    #[derive(RustcEncodable)]
+    struct RankedCrate { krate: EncodableCrate, recent_downloads: i64 }
+    #[derive(RustcEncodable)]
+    struct R { crates: Vec<RankedCrate> }
+    let downloads = ranking.into_iter().collect::<HashMap<_, _>>();
+    Ok(req.json(&R {
+        crates: crates.into_iter().map(|(id, krate)| {
+            RankedCrate { krate: krate, recent_downloads: downloads[&id] }
+        }).collect(),
+    }))
+}
+
+/// Handles the `GET /crates/trending` route.
+///
+/// Ranks crates by their relative growth in downloads this week compared to
+/// the week before, ignoring crates with fewer than `TRENDING_MIN_BASELINE`
+/// downloads in the previous week. The ranking is cached for an hour.
+pub fn trending(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::sql;
+    use diesel::types::{BigInt, Integer};
+
+    let conn = req.db_conn()?;
+    let ranking = req.app().trending.get_or_try_insert_with(|| {
+        diesel::select(sql::<(Integer, BigInt, BigInt)>(&format!("\
+            crate_id, this_week, last_week FROM (
+                SELECT versions.crate_id,
+                       SUM(CASE WHEN version_downloads.date > CURRENT_DATE - 7
+                                THEN version_downloads.downloads ELSE 0 END)::int8 AS this_week,
+                       SUM(CASE WHEN version_downloads.date <= CURRENT_DATE - 7
+                                THEN version_downloads.downloads ELSE 0 END)::int8 AS last_week
+                  FROM version_downloads
+                 INNER JOIN versions ON versions.id = version_downloads.version_id
+                 INNER JOIN crates ON crates.id = versions.crate_id
+                 WHERE version_downloads.date > CURRENT_DATE - 14
+                   AND NOT crates.unlisted
+                   AND NOT crates.private
+                 GROUP BY versions.crate_id
+            ) AS weekly
+             WHERE last_week >= {}
+             ORDER BY this_week::float8 / last_week DESC
+             LIMIT 10", TRENDING_MIN_BASELINE
+        ))).load(&*conn).map_err(Into::into)
+    })?;
+
+    let ids = ranking.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+    let crates = encode_crates_in_order(&conn, &ids)?;
+
+    #[derive(RustcEncodable)]
+    struct TrendingCrate {
+        krate: EncodableCrate,
+        downloads_this_week: i64,
+        downloads_last_week: i64,
+    }
+    #[derive(RustcEncodable)]
+    struct R { crates: Vec<TrendingCrate> }
+    let weeks = ranking.into_iter()
+        .map(|(id, this_week, last_week)| (id, (this_week, last_week)))
+        .collect::<HashMap<_, _>>();
+    Ok(req.json(&R {
+        crates: crates.into_iter().map(|(id, krate)| {
+            let (this_week, last_week) = weeks[&id];
+            TrendingCrate {
+                krate: krate,
+                downloads_this_week: this_week,
+                downloads_last_week: last_week,
+            }
+        }).collect(),
+    }))
+}
+
+/// How many crates `GET /crates/compare` compares at once.
+pub const MAX_COMPARED_CRATES: usize = 5;
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableCrateComparison {
+    pub name: String,
+    pub max_version: String,
+    pub downloads: i32,
+    /// Downloads over the last 90 days
+    pub recent_downloads: i64,
+    /// When the newest version which isn't yanked was published
+    pub last_release_at: Option<String>,
+    /// How many versions were published, including yanked ones
+    pub versions: i64,
+    /// How many crates depend on it in their newest version, which is the
+    /// crate's `dependents_cnt`
+    pub dependents: i64,
+    pub license: Option<String>,
+}
+
+/// Handles the `GET /crates/compare` route.
+///
+/// Puts the crates named in `?names=serde,rustc-serialize`, at most
+/// `MAX_COMPARED_CRATES` of them, side by side in the order they're named.
+pub fn compare(req: &mut Request) -> CargoResult<Response> {
+    let query = req.query();
+    let mut names = Vec::new();
+    for name in query.get("names").map(|s| &s[..]).unwrap_or("").split(',') {
+        let name = name.trim();
+        if !name.is_empty() && !names.contains(&name.to_string()) {
+            names.push(name.to_string());
+        }
+    }
+    if names.is_empty() || names.len() > MAX_COMPARED_CRATES {
+        return Err(human(&format_args!("between 1 and {} crates can be compared, \
+                                        named in `names`", MAX_COMPARED_CRATES)))
+    }
+
+    let conn = req.db_conn()?;
+    let mut krates = Vec::new();
+    for name in &names {
+        let krate = if permission::can_read(req, name)? {
+            Crate::by_name(name).first::<Crate>(&*conn).optional()?
+        } else {
+            None
+        };
+        krates.push(krate.chain_error(|| {
+            human(&format_args!("crate `{}` does not exist", name))
+        })?);
+    }
+
+    let ids = krates.iter().map(|k| k.id).collect::<Vec<_>>();
+    let recent_downloads = {
+        let rows = req.tx()?.query("\
+            SELECT versions.crate_id, SUM(version_downloads.downloads)::int8 AS downloads
+              FROM version_downloads
+             INNER JOIN versions ON versions.id = version_downloads.version_id
+             WHERE versions.crate_id = ANY($1)
+               AND version_downloads.date > CURRENT_DATE - 90
+             GROUP BY versions.crate_id", &[&ids])?;
+        rows.iter().map(|row| {
+            (row.get::<_, i32>("crate_id"), row.get::<_, i64>("downloads"))
+        }).collect::<HashMap<_, _>>()
+    };
+    let versions = Version::belonging_to(&krates)
+        .load::<Version>(&*conn)?
+        .grouped_by(&krates);
+
+    let crates = krates.into_iter().zip(versions).map(|(krate, versions)| {
+        let published = versions.len() as i64;
+        let available = versions.into_iter().filter(|v| !v.yanked).collect::<Vec<_>>();
+        let last_release_at = available.iter().map(|v| v.created_at).max();
+        let max_version = Version::max(available.into_iter().map(|v| v.num));
+        EncodableCrateComparison {
+            max_version: max_version.to_string(),
+            downloads: krate.downloads,
+            recent_downloads: recent_downloads.get(&krate.id).cloned().unwrap_or(0),
+            last_release_at: last_release_at.map(::encode_time),
+            versions: published,
+            dependents: krate.dependents_cnt as i64,
+            license: krate.license,
+            name: krate.name,
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { crates: Vec<EncodableCrateComparison> }
+    Ok(req.json(&R { crates: crates }))
+}
+
 /// Handles the `GET /crates/:crate_id` route.
+///
+/// With `?as_of=2017-03-01` (or a full `2017-03-01T12:00:00Z` time), the
+/// versions are the ones which had been published by then, yanked if they
+/// were yanked at the time, and `max_version` is worked out from those. The
+/// rest of the crate's metadata is as it is now.
+///
+/// Crates which were transferred to a new maintainer by an admin carry the
+/// `tombstone` of their latest transfer.
 pub fn show(req: &mut Request) -> CargoResult<Response> {
     let name = &req.params()["crate_id"];
+    permission::ensure_readable(req, name)?;
+    let as_of = match req.query().get("as_of") {
+        Some(date) => Some(parse_as_of(date)?),
+        None => None,
+    };
     let conn = req.db_conn()?;
-    let krate = Crate::by_name(name).first::<Crate>(&*conn)?;
-    let versions = Version::belonging_to(&krate).load::<Version>(&*conn)?;
+    let krate = match Crate::by_name(name).first::<Crate>(&*conn).optional()? {
+        Some(krate) => krate,
+        None => return redirect_to_alias(req, name),
+    };
+    let mut versions = Version::belonging_to(&krate).load::<Version>(&*conn)?;
+    if let Some(as_of) = as_of {
+        versions = versions_as_of(&conn, &krate, versions, as_of)?;
+        if versions.is_empty() {
+            return Err(human(&format_args!("crate `{}` had no versions at {}",
+                                           krate.name, ::encode_time(as_of))))
+        }
+    }
     let ids = versions.iter().map(|v| v.id).collect();
     let kws = CrateKeyword::belonging_to(&krate)
         .inner_join(keywords::table)
@@ -827,7 +1366,16 @@ pub fn show(req: &mut Request) -> CargoResult<Response> {
 
     let badges = badges::table.filter(badges::crate_id.eq(krate.id))
         .load(&*conn)?;
-    let max_version = krate.max_version(&conn)?;
+    let max_version = match as_of {
+        Some(_) => Version::max(versions.iter().filter(|v| !v.yanked).map(|v| v.num.clone())),
+        None => krate.max_version(&conn)?,
+    };
+    let deprecation_notice = CrateSettings::find(&conn, krate.id)?.deprecation_notice;
+    let original_publisher = match krate.first_published_by {
+        Some(id) => users::table.find(id).first::<User>(&*conn).optional()?,
+        None => None,
+    };
+    let tombstone = Tombstone::latest(&conn, krate.id)?.map(Tombstone::encodable);
 
     #[derive(RustcEncodable)]
     struct R {
@@ -835,29 +1383,132 @@ pub fn show(req: &mut Request) -> CargoResult<Response> {
         versions: Vec<EncodableVersion>,
         keywords: Vec<EncodableKeyword>,
         categories: Vec<EncodableCategory>,
+        deprecation_notice: Option<String>,
+        tombstone: Option<EncodableTombstone>,
     }
     Ok(req.json(&R {
-        krate: krate.clone().encodable(
-            max_version, Some(ids), Some(&kws), Some(&cats), Some(badges)
-        ),
+        krate: EncodableCrate {
+            original_publisher: original_publisher.map(User::encodable),
+            ..krate.clone().encodable(max_version, Some(ids), Some(&kws), Some(&cats),
+                                      Some(badges))
+        },
         versions: versions.into_iter().map(|v| {
             v.encodable(&krate.name)
         }).collect(),
         keywords: kws.into_iter().map(|k| k.encodable()).collect(),
         categories: cats.into_iter().map(|k| k.encodable()).collect(),
+        deprecation_notice: deprecation_notice,
+        tombstone: tombstone,
     }))
 }
 
+fn parse_as_of(date: &str) -> CargoResult<Timespec> {
+    time::strptime(date, "%Y-%m-%dT%H:%M:%SZ")
+        .or_else(|_| time::strptime(date, "%Y-%m-%d"))
+        .map(|tm| tm.to_timespec())
+        .map_err(|_| {
+            human(&format_args!("`{}` is not a date, `as_of` should look like \
+                                 `2017-03-01` or `2017-03-01T12:00:00Z`", date))
+        })
+}
+
+/// The versions of the crate which had been published at `as_of`, with
+/// `yanked` set to whether they were yanked then.
+///
+/// Yanks are replayed from `version_yanks`: a version is as its last yank or
+/// unyank before `as_of` left it, or the opposite of its first one after.
+/// Versions which were never yanked or unyanked since yanks were first
+/// recorded keep their current flag.
+fn versions_as_of(conn: &InstrumentedConnection,
+                  krate: &Crate,
+                  versions: Vec<Version>,
+                  as_of: Timespec) -> CargoResult<Vec<Version>> {
+    let yanks = version_yanks::table
+        .filter(version_yanks::version_id.eq_any(
+            Version::belonging_to(krate).select(versions::id)))
+        .order((version_yanks::created_at, version_yanks::id))
+        .select((version_yanks::version_id, version_yanks::yanked,
+                 version_yanks::created_at))
+        .load::<(i32, bool, Timespec)>(conn)?;
+    let mut yanked = HashMap::new();
+    for (version_id, was_yanked, created_at) in yanks {
+        if created_at <= as_of {
+            yanked.insert(version_id, was_yanked);
+        } else if !yanked.contains_key(&version_id) {
+            yanked.insert(version_id, !was_yanked);
+        }
+    }
+
+    Ok(versions.into_iter().filter(|v| v.created_at <= as_of).map(|mut v| {
+        if let Some(&was_yanked) = yanked.get(&v.id) {
+            v.yanked = was_yanked;
+        }
+        v
+    }).collect())
+}
+
+/// Redirects a request for a crate which doesn't exist to the crate it is an
+/// alias of, if there is one.
+///
+/// The registry index isn't covered by this: cargo keeps resolving the old
+/// name through the index entries that were published under it, and its
+/// downloads are redirected here.
+fn redirect_to_alias(req: &Request, name: &str) -> CargoResult<Response> {
+    match Crate::alias_target(req.tx()?, name)? {
+        Some(target) => Ok(redirect_to(req, name, &target)),
+        None => Err(Box::new(NotFound)),
+    }
+}
+
+/// Permanently redirects to the current path with the crate name `from`
+/// replaced by `to`.
+fn redirect_to(req: &Request, from: &str, to: &str) -> Response {
+    let mut replaced = false;
+    let mut path = req.path().split('/').map(|segment| {
+        if !replaced && segment == from {
+            replaced = true;
+            to
+        } else {
+            segment
+        }
+    }).collect::<Vec<_>>().join("/");
+    if let Some(query) = req.query_string() {
+        path.push('?');
+        path.push_str(query);
+    }
+    req.redirect_permanent(path)
+}
+
 /// Handles the `PUT /crates/new` route.
 pub fn new(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::exists;
+
     let app = req.app().clone();
-    let (new_crate, user) = parse_new_headers(req)?;
+    let (new_crate, user, metadata) = parse_new_headers(req)?;
+    let idempotency_key = req.headers().find(idempotency::HEADER).map(|k| k[0].to_string());
+    if let Some(ref key) = idempotency_key {
+        idempotency::validate(key)?;
+    }
 
     let name = &*new_crate.name;
     let vers = &*new_crate.vers;
     let features = new_crate.features.iter().map(|(k, v)| {
         (k[..].to_string(), v.iter().map(|v| v[..].to_string()).collect())
     }).collect::<HashMap<String, Vec<String>>>();
+    let (index_features, features2) = git::split_features(features.clone());
+    if features2.is_some() && !app.config.index_v2 {
+        return Err(human("features using `dep:` or `?/` need a registry with \
+                          version 2 index entries enabled"))
+    }
+    if let Some(ref rust_version) = new_crate.rust_version {
+        if !Crate::valid_rust_version(rust_version) {
+            return Err(human(&format_args!("invalid rust-version specified: {}",
+                                           rust_version)))
+        }
+    }
+    if let Some(ref provenance) = new_crate.provenance {
+        provenance::validate(provenance)?;
+    }
     let keywords = new_crate.keywords.as_ref().map(|kws| {
         kws.iter().map(|kw| &**kw).collect()
     }).unwrap_or_else(Vec::new);
@@ -880,10 +1531,23 @@ pub fn new(req: &mut Request) -> CargoResult<Response> {
             max_upload_size: None,
         };
         let license_file = new_crate.license_file.as_ref().map(|s| &**s);
+        let org_id = req.api_token().and_then(|token| token.organization_id);
+        let is_new = org_id.is_some() &&
+            Crate::by_name(name).first::<Crate>(&*conn).optional()?.is_none();
         let krate = persist.create_or_update(&conn, license_file, user.id)?;
+        krate.lock_for_publish(&conn)?;
+
+        // Crates first published with an organization's token belong to the
+        // organization rather than to whoever created the token
+        if let (true, Some(org_id)) = (is_new, org_id) {
+            diesel::update(crate_owners::table.filter(crate_owners::crate_id.eq(krate.id)))
+                .set((crate_owners::owner_id.eq(org_id),
+                      crate_owners::owner_kind.eq(OwnerKind::Org as i32)))
+                .execute(&*conn)?;
+        }
 
-        let owners = krate.owners(&conn)?;
-        if rights(req.app(), &owners, &user)? < Rights::Publish {
+        let publishers = krate.publishers(&conn)?;
+        if request_rights(req, &conn, &publishers)? < Rights::Publish {
             return Err(human("crate name has already been claimed by \
                               another user"))
         }
@@ -900,9 +1564,52 @@ pub fn new(req: &mut Request) -> CargoResult<Response> {
         if length > max {
             return Err(human(&format_args!("max upload size is: {}", max)))
         }
+        let tarball = read_tarball(req, max)?;
+
+        // A retry of a publish which already went through gets the response
+        // it got the first time
+        let fingerprint = idempotency::fingerprint(metadata.as_bytes(), &tarball);
+        if let Some(ref key) = idempotency_key {
+            if let Some(publish) = IdempotentPublish::find(&conn, user.id, key)? {
+                return Ok(util::raw_json_response(publish.replay(&fingerprint)?.to_string()))
+            }
+        }
+
+        // Scan the contents before anything is stored. Crate files which
+        // can't be inspected can't be published either, or they would get
+        // past every scanner.
+        let files = util::unpack(&tarball)?;
+        let findings = scanner::scan(&app.config.scanners, &files);
+        let scan_action = app.config.scan_policy.action(&findings);
+        let notable_findings = findings.iter()
+            .filter(|finding| finding.severity > Severity::Info)
+            .map(Finding::describe)
+            .collect::<Vec<_>>();
+        if scan_action == Some(ScanAction::Reject) {
+            return Err(human(&format_args!("this crate was rejected by the registry's \
+                                            scanners: {}", notable_findings.join(", "))))
+        }
+
+        // Newcomers get a report of what they published with their first
+        // version, whichever crate it's of. Versions published before
+        // `published_by` was recorded don't say who published them, so
+        // versions of crates the user owns count as theirs too.
+        let owned_crates = crate_owners::table
+            .filter(crate_owners::owner_id.eq(user.id))
+            .filter(crate_owners::owner_kind.eq(OwnerKind::User as i32))
+            .filter(crate_owners::deleted.eq(false))
+            .select(crate_owners::crate_id);
+        let first_publish = !diesel::select(exists(
+            versions::table.filter(versions::published_by.eq(user.id))
+        )).get_result::<bool>(&*conn)? && !diesel::select(exists(
+            versions::table.filter(versions::crate_id.eq_any(owned_crates))
+        )).get_result::<bool>(&*conn)?;
 
         // Persist the new version of this crate
-        let version = NewVersion::new(krate.id, vers, &features)?
+        let dependencies_before = dependency::newest_dependencies(&conn, krate.id)?;
+        let token_id = req.api_token().map(|token| token.id);
+        let version = NewVersion::new(krate.id, vers, &features, Some(user.id), token_id,
+                                      krate.license.clone())?
             .save(&conn, &new_crate.authors)?;
 
         // Link this new version to all dependencies
@@ -911,6 +1618,26 @@ pub fn new(req: &mut Request) -> CargoResult<Response> {
             .map(|dep| dep.git_encode(&krate.name))
             .collect();
 
+        // Whether this version is now the newest one decides which crates
+        // count this one among their dependents
+        let dependencies_after = dependency::newest_dependencies(&conn, krate.id)?;
+        dependency::update_dependents_cnt(&conn, &dependencies_before, &dependencies_after)?;
+
+        // Followers relying on the crate's license need to hear about it
+        // changing, which is easy to miss in a version bump. Backports are
+        // compared with the version they follow rather than the newest one.
+        if let Some(preceding) = Version::preceding(&conn, krate.id, vers)? {
+            if preceding.license != version.license {
+                notification::license_changed(&conn, &krate, &version,
+                                              preceding.license.as_ref().map(|s| &s[..]),
+                                              user.id)?;
+            }
+        }
+
+        // Keep the keywords and categories being replaced, so that owners
+        // can restore them if this publish dropped some by mistake
+        tag_history::record(&conn, &krate, &keywords, &categories, Some(version.id), user.id)?;
+
         // Update all keywords for this crate
         Keyword::update_crate(&conn, &krate, &keywords)?;
 
@@ -927,23 +1654,106 @@ pub fn new(req: &mut Request) -> CargoResult<Response> {
         )?;
         let max_version = krate.max_version(&conn)?;
 
+        // Crates which don't say what they're about get some categories to
+        // consider for their next version
+        let suggested_categories = if keywords.is_empty() && categories.is_empty() {
+            let description = new_crate.description.as_ref().map(|s| &s[..]);
+            Category::suggest(&conn, version.id, description)?
+        } else {
+            Vec::new()
+        };
+
         // Upload the crate, return way to delete the crate from the server
         // If the git commands fail below, we shouldn't keep the crate on the
         // server.
-        let (cksum, mut bomb) = app.config.uploader.upload(req, &krate, max, vers)?;
+        let (cksum, mut bomb) = app.config.uploader.upload(req, &krate, vers, &tarball)?;
 
-        // Register this crate in our local git repo.
+        // Register this crate in our local git repo, unless it looks
+        // suspicious enough to be reviewed by an admin first.
         let git_crate = git::Crate {
             name: name.to_string(),
             vers: vers.to_string(),
             cksum: cksum.to_hex(),
-            features: features,
+            features: index_features,
             deps: deps,
             yanked: Some(false),
+            links: if app.config.index_v2 { new_crate.links.clone() } else { None },
+            rust_version: if app.config.index_v2 {
+                new_crate.rust_version.clone()
+            } else {
+                None
+            },
+            v: features2.as_ref().map(|_| 2),
+            features2: features2,
+        };
+        scanner::record(&conn, version.id, &findings)?;
+        files::record(&conn, version.id, &files)?;
+        let readme_rendered = readme::render_version(&conn, version.id, &files)?;
+        if let Some(ref provenance) = new_crate.provenance {
+            let verified = provenance::verify(&app, &user, provenance, &krate);
+            provenance::record(&conn, version.id, provenance, verified)?;
+        }
+        audit::record(&conn, user.id, "publish_version", Some(&krate.name), &git_crate.vers)?;
+        if let Some(ref key) = app.config.signing_key {
+            signature::sign_version(&conn, key, version.id, &git_crate.name,
+                                    &git_crate.vers, &git_crate.cksum)?;
+        }
+        let mut quarantine_reasons = quarantine::reasons(&conn, &krate, user.id, &files)?;
+        let mut other_warnings = Vec::new();
+        match scan_action {
+            Some(ScanAction::Quarantine) => quarantine_reasons.extend(notable_findings),
+            Some(ScanAction::Warn) => other_warnings.extend(notable_findings),
+            Some(ScanAction::Reject) | None => {}
+        }
+        let mut entries = other_warnings.iter().map(|finding| {
+            PublishWarning::new("scanner_finding", None, finding.clone())
+        }).collect::<Vec<_>>();
+        if quarantine_reasons.is_empty() {
+            git::add_crate(&**req.app(), &git_crate).chain_error(|| {
+                internal(&format_args!("could not add crate `{}` to the git repo", name))
+            })?;
+        } else {
+            quarantine::hold(&conn, &version, &quarantine_reasons, &git_crate)?;
+            let message = format!("this version is held for review by the registry \
+                                   admins and can't be downloaded until it's \
+                                   approved: {}", quarantine_reasons.join(", "));
+            entries.push(PublishWarning::new("held_for_review", None, message.clone()));
+            other_warnings.push(message);
+        }
+        entries.extend(ignored_invalid_categories.iter().map(|category| {
+            PublishWarning::new("invalid_category", Some("categories"),
+                                format!("`{}` is not a category slug and was ignored",
+                                        category))
+        }));
+        entries.extend(ignored_invalid_badges.iter().map(|badge| {
+            PublishWarning::new("invalid_badge", Some("badges"),
+                                format!("`{}` is not a known badge, or its attributes \
+                                         are invalid, and was ignored", badge))
+        }));
+        entries.extend(suggested_categories.iter().map(|category| {
+            PublishWarning::new("suggested_category", Some("categories"),
+                                format!("this crate has no categories, `{}` might fit it",
+                                        category))
+        }));
+        entries.extend(metadata_warnings(&new_crate));
+
+        let first_publish = if first_publish {
+            Some(FirstPublishReport {
+                files: files.len() as u64,
+                size: tarball.len() as u64,
+                unpacked_size: files.iter().map(|file| file.size).sum(),
+                readme_rendered: readme_rendered,
+                license: krate.license.clone(),
+                license_recognized: new_crate.license.is_some(),
+                keywords: keywords.iter().map(|k| k.to_string()).collect(),
+                categories: categories.iter()
+                    .filter(|c| !ignored_invalid_categories.contains(c))
+                    .map(|c| c.to_string())
+                    .collect(),
+            })
+        } else {
+            None
         };
-        git::add_crate(&**req.app(), &git_crate).chain_error(|| {
-            internal(&format_args!("could not add crate `{}` to the git repo", name))
-        })?;
 
         // Now that we've come this far, we're committed!
         bomb.path = None;
@@ -952,22 +1762,75 @@ pub fn new(req: &mut Request) -> CargoResult<Response> {
         struct Warnings<'a> {
             invalid_categories: Vec<&'a str>,
             invalid_badges: Vec<&'a str>,
+            suggested_categories: Vec<String>,
+            other: Vec<String>,
+            entries: Vec<PublishWarning>,
         }
         let warnings = Warnings {
             invalid_categories: ignored_invalid_categories,
             invalid_badges: ignored_invalid_badges,
+            suggested_categories: suggested_categories,
+            other: other_warnings,
+            entries: entries,
         };
 
         #[derive(RustcEncodable)]
-        struct R<'a> { krate: EncodableCrate, warnings: Warnings<'a> }
-        Ok(req.json(&R {
+        struct R<'a> {
+            krate: EncodableCrate,
+            warnings: Warnings<'a>,
+            /// Only for the publisher's very first version
+            first_publish: Option<FirstPublishReport>,
+        }
+        let body = util::json_body(&R {
             krate: krate.minimal_encodable(max_version, None),
-            warnings: warnings
-        }))
+            warnings: warnings,
+            first_publish: first_publish,
+        });
+        if let Some(ref key) = idempotency_key {
+            IdempotentPublish::save(&conn, user.id, key, &fingerprint, &body)?;
+        }
+        Ok(util::raw_json_response(body))
     })
 }
 
-fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User)> {
+/// Warnings about metadata which is allowed but which makes a crate harder to
+/// use.
+fn metadata_warnings(new_crate: &upload::NewCrate) -> Vec<PublishWarning> {
+    let mut warnings = Vec::new();
+    if new_crate.readme.as_ref().map(|r| r.trim().is_empty()).unwrap_or(true) {
+        warnings.push(PublishWarning::new("missing_readme", Some("readme"),
+                                          "this crate has no README".to_string()));
+    }
+    match new_crate.license {
+        Some(ref license) if license.contains('/') => {
+            let message = format!("`{}` separates licenses with `/`, which is deprecated, \
+                                   use an SPDX expression like `{}` instead",
+                                  license, license.replace('/', " OR "));
+            warnings.push(PublishWarning::new("dubious_license", Some("license"), message));
+        }
+        Some(..) => {}
+        None => {
+            warnings.push(PublishWarning::new("dubious_license", Some("license"),
+                                              "this crate's license is only given as a \
+                                               file, so tools can't tell what it allows"
+                                                  .to_string()));
+        }
+    }
+    warnings
+}
+
+/// Reads the crate file, which follows the metadata in the body of a publish
+/// request.
+fn read_tarball(req: &mut Request, max: u64) -> CargoResult<Vec<u8>> {
+    read_le_u32(req.body())?;
+    let mut tarball = Vec::new();
+    LimitErrorReader::new(req.body(), max).read_to_end(&mut tarball)?;
+    Ok(tarball)
+}
+
+/// Reads the json metadata of a publish, returning it along with the raw
+/// json, which makes up the publish's idempotency fingerprint.
+fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User, String)> {
     // Read the json upload request
     let amt = read_le_u32(req.body())? as u64;
     let max = req.app().config.max_upload_size;
@@ -1002,27 +1865,50 @@ fn parse_new_headers(req: &mut Request) -> CargoResult<(upload::NewCrate, User)>
             how to upload metadata", missing.join(", "))));
     }
 
-    let user = req.user()?;
-    Ok((new, user.clone()))
+    let user = req.publisher()?;
+    Ok((new, user.clone(), json))
 }
 
 /// Handles the `GET /crates/:crate_id/:version/download` route.
 pub fn download(req: &mut Request) -> CargoResult<Response> {
     let crate_name = &req.params()["crate_id"];
     let version = &req.params()["version"];
+    permission::ensure_readable(req, crate_name)?;
+
+    let counted = match req.headers().find(mirror::TOKEN_HEADER) {
+        Some(token) => count_mirror_download(req, token[0], crate_name, version),
+        None => increment_download_counts(req, crate_name, version),
+    };
 
     // If we are a mirror, ignore failure to update download counts.
     // API-only mirrors won't have any crates in their database, and
     // incrementing the download count will look up the crate in the
     // database. Mirrors just want to pass along a redirect URL.
     if req.app().config.mirror == Replica::ReadOnlyMirror {
-        let _ = increment_download_counts(req, crate_name, version);
-    } else {
-        increment_download_counts(req, crate_name, version)?;
+        let _ = counted;
+    } else if !counted? {
+        if let Some(target) = Crate::alias_target(req.tx()?, crate_name)? {
+            return Ok(redirect_to(req, crate_name, &target));
+        }
+        return Err(human("crate or version not found"))
+    }
+
+    let app = req.app().clone();
+    if app.config.serve_downloads {
+        let body = app.config.uploader.fetch(&mut app.handle(), crate_name, version)?;
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), vec!["application/gzip".to_string()]);
+        headers.insert("Content-Length".to_string(), vec![body.len().to_string()]);
+        return Ok(Response {
+            status: (200, "OK"),
+            headers: headers,
+            body: Box::new(io::Cursor::new(body)),
+        })
     }
 
-    let redirect_url = req.app().config.uploader
-        .crate_location(crate_name, version).ok_or_else(||
+    let template = app.config.download_url_template.as_ref().map(|s| &s[..]);
+    let redirect_url = app.config.uploader
+        .download_url(template, crate_name, version).ok_or_else(||
             human("crate files not found")
         )?;
 
@@ -1035,48 +1921,93 @@ pub fn download(req: &mut Request) -> CargoResult<Response> {
     }
 }
 
-fn increment_download_counts(req: &Request, crate_name: &str, version: &str) -> CargoResult<()> {
+/// The id of the version, as long as it can be downloaded.
+fn downloadable_version_id(tx: &GenericConnection,
+                           crate_name: &str,
+                           version: &str) -> CargoResult<Option<i32>> {
+    let rows = tx.query("SELECT versions.id as version_id
+                              FROM crates
+                              INNER JOIN versions ON
+                                  crates.id = versions.crate_id
+                              WHERE canon_crate_name(crates.name) =
+                                    canon_crate_name($1)
+                                AND versions.num = $2
+                                AND NOT versions.quarantined
+                              LIMIT 1", &[&crate_name, &version])?;
+    Ok(rows.iter().next().map(|row| row.get("version_id")))
+}
+
+/// Downloads by known mirrors are only counted towards the mirror's traffic.
+///
+/// Returns whether there's a downloadable version to count the download of.
+fn count_mirror_download(req: &Request,
+                         token: &str,
+                         crate_name: &str,
+                         version: &str) -> CargoResult<bool> {
     let tx = req.tx()?;
-    let stmt = tx.prepare("SELECT versions.id as version_id
-                                FROM crates
-                                INNER JOIN versions ON
-                                    crates.id = versions.crate_id
-                                WHERE canon_crate_name(crates.name) =
-                                      canon_crate_name($1)
-                                  AND versions.num = $2
-                                LIMIT 1")?;
-    let rows = stmt.query(&[&crate_name, &version])?;
-    let row = rows.iter().next().chain_error(|| {
-        human("crate or version not found")
+    let mirror = Mirror::find_active(tx, token)?.chain_error(|| {
+        human("unknown or revoked mirror token")
     })?;
-    let version_id: i32 = row.get("version_id");
-    let now = ::now();
+    if downloadable_version_id(tx, crate_name, version)?.is_none() {
+        return Ok(false)
+    }
+    mirror.record_download(tx)?;
+    Ok(true)
+}
+
+/// Returns whether there's a downloadable version to count the download of.
+fn increment_download_counts(req: &Request,
+                             crate_name: &str,
+                             version: &str) -> CargoResult<bool> {
+    let tx = req.tx()?;
+    let version_id = match downloadable_version_id(tx, crate_name, version)? {
+        Some(version_id) => version_id,
+        None => return Ok(false),
+    };
 
     // Bump download counts.
     //
-    // Note that this is *not* an atomic update, and that's somewhat
-    // intentional. It doesn't appear that postgres supports an atomic update of
-    // a counter, so we just do the hopefully "least racy" thing. This is
-    // largely ok because these download counters are just that, counters. No
-    // need to have super high-fidelity counter.
-    //
-    // Also, we only update the counter for *today*, nothing else. We have lots
-    // of other counters, but they're all updated later on via the
+    // The increment is only buffered in memory here, and is written out to
+    // `version_downloads` together with every other pending increment once
+    // the batch is due and the request is done (see
+    // `download::FlushMiddleware`), unless batching is still being rolled
+    // out. Repeated downloads by the same client within the dedupe window
+    // aren't counted again, though they still show up in the download
+    // statistics. We only count downloads for *today*, nothing else. We have
+    // lots of other counters, but they're all updated later on via the
     // update-downloads script.
-    let amt = tx.execute("UPDATE version_downloads
-                               SET downloads = downloads + 1
-                               WHERE version_id = $1 AND date($2) = date(date)",
-                         &[&version_id, &now])?;
-    if amt == 0 {
-        tx.execute("INSERT INTO version_downloads
-                         (version_id) VALUES ($1)", &[&version_id])?;
+    let app = req.app();
+    let today = UTC::now().naive_utc().date();
+    if app.recent_downloads.count(req.client_ip(), version_id) {
+        match app.config.async_downloads {
+            Rollout::Off => download::record(tx, version_id, today)?,
+            Rollout::Shadow => {
+                download::record(tx, version_id, today)?;
+                // Only shadowed once the count above is committed, so that
+                // requests which fail later on don't set the tables apart
+                let app = app.clone();
+                req.after_commit(Box::new(move || {
+                    app.shadow_downloads.increment(version_id, today)
+                }));
+            }
+            Rollout::On => app.pending_downloads.increment(version_id, today),
+        }
     }
-    Ok(())
+
+    if app.config.download_stats {
+        download::record_stats(req, version_id, today);
+    }
+    Ok(true)
 }
 
 /// Handles the `GET /crates/:crate_id/downloads` route.
+///
+/// A client's repeated downloads of a version within
+/// `meta.dedupe_window_secs` of the last one counted are counted once, see
+/// `download::RecentDownloads`.
 pub fn downloads(req: &mut Request) -> CargoResult<Response> {
     let crate_name = &req.params()["crate_id"];
+    permission::ensure_readable(req, crate_name)?;
     let tx = req.tx()?;
     let krate = Crate::find_by_name(tx, crate_name)?;
     let mut versions = krate.versions(tx)?;
@@ -1087,15 +2018,15 @@ pub fn downloads(req: &mut Request) -> CargoResult<Response> {
     let ids = to_show.iter().map(|i| i.id).collect::<Vec<_>>();
 
     let cutoff_date = ::now() + Duration::days(-90);
-    let stmt = tx.prepare("SELECT * FROM version_downloads
-                                 WHERE date > $1
-                                   AND version_id = ANY($2)
-                                 ORDER BY date ASC")?;
-    let downloads = stmt.query(&[&cutoff_date, &ids])?.iter().map(|row| {
+    let downloads = tx.query("SELECT * FROM version_downloads
+                                    WHERE date > $1
+                                      AND version_id = ANY($2)
+                                    ORDER BY date ASC",
+                             &[&cutoff_date, &ids])?.iter().map(|row| {
         VersionDownload::from_row(&row).encodable()
     }).collect::<Vec<_>>();
 
-    let stmt = tx.prepare("\
+    let extra = tx.query("\
           SELECT COALESCE(to_char(DATE(version_downloads.date), 'YYYY-MM-DD'), '') AS date,
                  SUM(version_downloads.downloads) AS downloads
             FROM version_downloads
@@ -1105,8 +2036,8 @@ pub fn downloads(req: &mut Request) -> CargoResult<Response> {
              AND versions.crate_id = $2
              AND versions.id != ALL($3)
         GROUP BY DATE(version_downloads.date)
-        ORDER BY DATE(version_downloads.date) ASC")?;
-    let extra = stmt.query(&[&cutoff_date, &krate.id, &ids])?.iter().map(|row| {
+        ORDER BY DATE(version_downloads.date) ASC",
+                         &[&cutoff_date, &krate.id, &ids])?.iter().map(|row| {
         ExtraDownload {
             downloads: row.get("downloads"),
             date: row.get("date")
@@ -1118,11 +2049,70 @@ pub fn downloads(req: &mut Request) -> CargoResult<Response> {
     #[derive(RustcEncodable)]
     struct R { version_downloads: Vec<EncodableVersionDownload>, meta: Meta }
     #[derive(RustcEncodable)]
-    struct Meta { extra_downloads: Vec<ExtraDownload> }
-    let meta = Meta { extra_downloads: extra };
+    struct Meta { extra_downloads: Vec<ExtraDownload>, dedupe_window_secs: u64 }
+    let meta = Meta {
+        extra_downloads: extra,
+        dedupe_window_secs: req.app().config.download_dedupe_secs,
+    };
     Ok(req.json(&R{ version_downloads: downloads, meta: meta }))
 }
 
+/// Handles the `GET /crates/:crate_id/download_stats` route.
+///
+/// Only owners can see these. Downloads over the last 90 days are broken down
+/// by version and cargo version, and separately by country. Both are empty
+/// unless the registry has download statistics enabled. Unlike the download
+/// counts these aren't deduplicated: every download is counted, however
+/// soon the same client downloaded the version before.
+pub fn download_stats(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = &req.params()["crate_id"];
+    let tx = req.tx()?;
+    let krate = Crate::find_by_name(tx, crate_name)?;
+    let owners = krate.owners_old(tx)?;
+    if request_rights(req, &*req.db_conn()?, &owners)? < Rights::Publish {
+        return Err(human("only owners of a crate can view its download statistics"))
+    }
+
+    let user_agents = tx.query("\
+        SELECT versions.num, stats.user_agent, SUM(stats.downloads) AS downloads
+          FROM version_download_stats stats
+         INNER JOIN versions ON versions.id = stats.version_id
+         WHERE versions.crate_id = $1
+           AND stats.date > CURRENT_DATE - 90
+         GROUP BY versions.num, stats.user_agent
+         ORDER BY downloads DESC",
+                               &[&krate.id])?.iter().map(|row| {
+        UserAgentDownloads {
+            version: row.get("num"),
+            user_agent: row.get("user_agent"),
+            downloads: row.get("downloads"),
+        }
+    }).collect();
+
+    let countries = tx.query("\
+        SELECT stats.country, SUM(stats.downloads) AS downloads
+          FROM version_download_stats stats
+         INNER JOIN versions ON versions.id = stats.version_id
+         WHERE versions.crate_id = $1
+           AND stats.date > CURRENT_DATE - 90
+         GROUP BY stats.country
+         ORDER BY downloads DESC",
+                             &[&krate.id])?.iter().map(|row| {
+        CountryDownloads {
+            country: row.get("country"),
+            downloads: row.get("downloads"),
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct UserAgentDownloads { version: String, user_agent: String, downloads: i64 }
+    #[derive(RustcEncodable)]
+    struct CountryDownloads { country: String, downloads: i64 }
+    #[derive(RustcEncodable)]
+    struct R { user_agents: Vec<UserAgentDownloads>, countries: Vec<CountryDownloads> }
+    Ok(req.json(&R { user_agents: user_agents, countries: countries }))
+}
+
 #[derive(Insertable, Queryable, Identifiable, Associations)]
 #[belongs_to(User)]
 #[primary_key(user_id, crate_id)]
@@ -1134,8 +2124,9 @@ pub struct Follow {
 
 fn follow_target(req: &mut Request) -> CargoResult<Follow> {
     let user = req.user()?;
-    let conn = req.db_conn()?;
     let crate_name = &req.params()["crate_id"];
+    permission::ensure_readable(req, crate_name)?;
+    let conn = req.db_conn()?;
     let crate_id = Crate::by_name(crate_name)
         .select(crates::id)
         .first(&*conn)?;
@@ -1145,13 +2136,38 @@ fn follow_target(req: &mut Request) -> CargoResult<Follow> {
     })
 }
 
+#[derive(RustcDecodable, Default)]
+struct FollowOptions {
+    legal_notifications: Option<bool>,
+}
+
 /// Handles the `PUT /crates/:crate_id/follow` route.
+///
+/// The body is optional. `{"legal_notifications": true}` also notifies the
+/// follower when a new version changes the crate's license, and `false`
+/// stops that while still following the crate.
 pub fn follow(req: &mut Request) -> CargoResult<Response> {
     let follow = follow_target(req)?;
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    let options = if body.trim().is_empty() {
+        FollowOptions::default()
+    } else {
+        json::decode::<FollowOptions>(&body).map_err(|_| human("invalid json request"))?
+    };
+
     let conn = req.db_conn()?;
-    diesel::insert(&follow.on_conflict_do_nothing())
-        .into(follows::table)
-        .execute(&*conn)?;
+    conn.transaction(|| -> CargoResult<()> {
+        diesel::insert(&follow.on_conflict_do_nothing())
+            .into(follows::table)
+            .execute(&*conn)?;
+        if let Some(legal_notifications) = options.legal_notifications {
+            diesel::update(&follow)
+                .set(follows::legal_notifications.eq(legal_notifications))
+                .execute(&*conn)?;
+        }
+        Ok(())
+    })?;
     #[derive(RustcEncodable)]
     struct R { ok: bool }
     Ok(req.json(&R { ok: true }))
@@ -1169,47 +2185,171 @@ pub fn unfollow(req: &mut Request) -> CargoResult<Response> {
 
 /// Handles the `GET /crates/:crate_id/following` route.
 pub fn following(req: &mut Request) -> CargoResult<Response> {
-    use diesel::expression::dsl::exists;
-
     let follow = follow_target(req)?;
     let conn = req.db_conn()?;
-    let following = diesel::select(exists(follows::table.find(follow.id())))
-        .get_result(&*conn)?;
+    let legal_notifications = follows::table.find(follow.id())
+        .select(follows::legal_notifications)
+        .first::<bool>(&*conn)
+        .optional()?;
     #[derive(RustcEncodable)]
-    struct R { following: bool }
-    Ok(req.json(&R { following: following }))
+    struct R { following: bool, legal_notifications: bool }
+    Ok(req.json(&R {
+        following: legal_notifications.is_some(),
+        legal_notifications: legal_notifications.unwrap_or(false),
+    }))
 }
 
 /// Handles the `GET /crates/:crate_id/versions` route.
+///
+/// Versions are sorted by semver precedence, newest first, unless
+/// `sort=date` is given in which case the most recently published come first.
 pub fn versions(req: &mut Request) -> CargoResult<Response> {
     let crate_name = &req.params()["crate_id"];
+    let query = req.query();
+    let sort = query.get("sort").map(|s| s.to_string())
+        .unwrap_or_else(|| "semver".to_string());
+    permission::ensure_readable(req, crate_name)?;
     let tx = req.tx()?;
     let krate = Crate::find_by_name(tx, crate_name)?;
-    let versions = krate.versions(tx)?;
-    let versions = versions.into_iter().map(|v| v.encodable(crate_name))
-                           .collect();
+    let mut versions = krate.versions(tx)?;
+    if sort == "date" {
+        versions.sort_by(|a, b| {
+            match b.created_at.cmp(&a.created_at) {
+                cmp::Ordering::Equal => b.num.cmp(&a.num),
+                ord => ord,
+            }
+        });
+    }
+
+    // The highest version which is neither yanked nor a pre-release
+    let highest_stable = versions.iter()
+        .filter(|v| !v.yanked && v.num.pre.is_empty())
+        .map(|v| &v.num)
+        .max()
+        .map(|num| num.to_string());
+
+    // The crate page needs every version, so they're only paged when a page
+    // is asked for
+    let total = versions.len() as i64;
+    let page = if query.contains_key("page") || query.contains_key("per_page") {
+        let (offset, limit) = req.pagination(100, 100)?;
+        versions = versions.into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        req.pagination_meta(offset, limit, total)
+    } else {
+        PaginationMeta::unpaginated(total)
+    };
+
+    let versions = Version::encodable_with_publishers(tx, versions, crate_name)?;
 
     #[derive(RustcEncodable)]
-    struct R { versions: Vec<EncodableVersion> }
-    Ok(req.json(&R{ versions: versions }))
+    struct R { versions: Vec<EncodableVersion>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta {
+        total: i64,
+        next_page: Option<String>,
+        prev_page: Option<String>,
+        highest_stable: Option<String>,
+    }
+    Ok(req.json(&R{
+        versions: versions,
+        meta: Meta {
+            total: page.total,
+            next_page: page.next_page,
+            prev_page: page.prev_page,
+            highest_stable: highest_stable,
+        },
+    }))
+}
+
+/// Handles the `PUT /crates/:crate_id/repository_verification` route.
+///
+/// Asks GitHub whether the signed in owner can push to the crate's
+/// `repository`, and if so marks the repository as verified until it's
+/// changed. Only GitHub repositories can be verified.
+pub fn verify_repository(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(&*conn)?;
+    let owners = krate.owners(&conn)?;
+    if request_rights(req, &conn, &owners)? < Rights::Full {
+        return Err(human("only owners can verify a crate's repository"))
+    }
+    let repository = match krate.repository {
+        Some(ref repository) => repository.clone(),
+        None => return Err(human("this crate doesn't have a repository to verify")),
+    };
+    let path = match provenance::repository_path(&repository) {
+        Some((ref host, ref segments)) if host == "github.com" && segments.len() == 2 => {
+            format!("/repos/{}/{}", segments[0], segments[1])
+        }
+        _ => {
+            return Err(human(&format_args!("`{}` is not a GitHub repository, only those \
+                                            can be verified", repository)))
+        }
+    };
+
+    #[derive(RustcDecodable)]
+    struct Permissions { push: bool }
+    #[derive(RustcDecodable)]
+    struct GithubRepository { permissions: Option<Permissions> }
+
+    let user = req.user()?;
+    let token = http::token(user.gh_access_token.clone());
+    let (mut handle, data) = http::github(req.app(), &path, &token)?;
+    // GitHub hides private repositories the user can't see
+    if handle.response_code().unwrap() == 404 {
+        return Err(human(&format_args!("GitHub doesn't know of a repository `{}`", repository)))
+    }
+    let github_repository: GithubRepository = http::parse_github_response(handle, &data)?;
+    if !github_repository.permissions.map(|p| p.push).unwrap_or(false) {
+        return Err(human(&format_args!("you can't push to `{}` on GitHub, so it can't be \
+                                        verified as this crate's repository", repository)))
+    }
+    // Only if the repository wasn't changed while GitHub was asked
+    diesel::update(crates::table.find(krate.id).filter(crates::repository.eq(&repository)))
+        .set(crates::repository_verified.eq(true))
+        .execute(&*conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
 }
 
 /// Handles the `GET /crates/:crate_id/owners` route.
 pub fn owners(req: &mut Request) -> CargoResult<Response> {
     let crate_name = &req.params()["crate_id"];
+    permission::ensure_readable(req, crate_name)?;
     let conn = req.db_conn()?;
     let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
-    let owners = krate.owners(&conn)?
-        .into_iter()
-        .map(Owner::encodable)
-        .collect();
+    let with_role = |owners: Vec<Owner>, role: OwnerRole| {
+        owners.into_iter().map(move |owner| EncodableOwner {
+            role: Some(role.as_str().to_string()),
+            ..owner.encodable()
+        })
+    };
+    let owners = with_role(krate.owners(&conn)?, OwnerRole::Owner)
+        .chain(with_role(krate.collaborators(&conn)?, OwnerRole::Collaborator))
+        .collect::<Vec<_>>();
 
+    // Cargo expects every owner in one response, so this list isn't paged.
     #[derive(RustcEncodable)]
-    struct R { users: Vec<EncodableOwner> }
-    Ok(req.json(&R{ users: owners }))
+    struct R { users: Vec<EncodableOwner>, meta: PaginationMeta }
+    let meta = PaginationMeta::unpaginated(owners.len() as i64);
+    Ok(req.json(&R{ users: owners, meta: meta }))
 }
 
 /// Handles the `PUT /crates/:crate_id/owners` route.
+///
+/// New owners get full control over the crate unless they're added as
+/// collaborators, who can only publish new versions.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "users": ["octocat"], "role": "collaborator" }
+/// ```
 pub fn add_owners(req: &mut Request) -> CargoResult<Response> {
     modify_owners(req, true)
 }
@@ -1228,10 +2368,11 @@ fn modify_owners(req: &mut Request, add: bool) -> CargoResult<Response> {
         .first::<Crate>(&*conn)?;
     let owners = krate.owners(&conn)?;
 
-    match rights(req.app(), &owners, user)? {
+    match request_rights(req, &conn, &owners)? {
         Rights::Full => {} // Yes!
         Rights::Publish => {
-            return Err(human("team members don't have permission to modify owners"));
+            return Err(human("team members and organization publishers don't \
+                              have permission to modify owners"));
         }
         Rights::None => {
             return Err(human("only owners have permission to modify owners"));
@@ -1243,6 +2384,8 @@ fn modify_owners(req: &mut Request, add: bool) -> CargoResult<Response> {
         // identical, for back-compat (owners preferred)
         users: Option<Vec<String>>,
         owners: Option<Vec<String>>,
+        // "owner" (the default) or "collaborator", only used when adding
+        role: Option<String>,
     }
 
     let request: Request = json::decode(&body).map_err(|_| {
@@ -1252,46 +2395,190 @@ fn modify_owners(req: &mut Request, add: bool) -> CargoResult<Response> {
     let logins = request.owners.or(request.users).ok_or_else(|| {
         human("invalid json request")
     })?;
+    let role = match request.role {
+        Some(ref role) => OwnerRole::parse(role)?,
+        None => OwnerRole::Owner,
+    };
+    let needs_approval = owner_approval::required(&conn, &krate, user, &owners)?;
+
+    // Crates which need a second owner to approve owner changes only get
+    // them asked for here
+    if needs_approval {
+        let collaborators = krate.collaborators(&conn)?;
+        for login in &logins {
+            check_owner_change(user, &owners, &collaborators, login, role, add).map_err(|e| {
+                human(&format_args!("`{}`: {}", login, e.description()))
+            })?;
+        }
+        let action = ApprovalAction::owner_change(role, add);
+        let results = conn.transaction(|| -> CargoResult<Vec<EncodableOwnerChange>> {
+            logins.iter().map(|login| {
+                let approval = owner_approval::request(&conn, &krate, user, action, login)?;
+                Ok(EncodableOwnerChange {
+                    login: login.clone(),
+                    ok: true,
+                    error: None,
+                    approval_id: Some(approval.id),
+                })
+            }).collect()
+        })?;
+
+        #[derive(RustcEncodable)]
+        struct R { ok: bool, results: Vec<EncodableOwnerChange> }
+        return Ok(req.json(&R { ok: true, results: results }))
+    }
 
-    for login in &logins {
-        if add {
-            if owners.iter().any(|owner| owner.login() == *login) {
+    // Either every login is added or removed or none are, so that a failure
+    // partway through doesn't leave the crate with half of the changes
+    let mut results = Vec::new();
+    let changed = conn.transaction(|| -> CargoResult<()> {
+        for login in &logins {
+            let change = conn.transaction(|| {
+                modify_owner(req.app(), &conn, user, &krate, login, role, add)
+            });
+            let error = match change {
+                Ok(()) => None,
+                Err(ref e) if e.human() => Some(e.description().to_string()),
+                Err(e) => return Err(e),
+            };
+            results.push(EncodableOwnerChange {
+                login: login.clone(),
+                ok: error.is_none(),
+                error: error,
+                approval_id: None,
+            });
+        }
+        if results.iter().any(|result| !result.ok) {
+            return Err(human("rolling back the owner changes"))
+        }
+        Ok(())
+    });
+    if let Err(e) = changed {
+        let failures = results.iter().filter_map(|result| {
+            result.error.as_ref().map(|error| (&result.login, error))
+        }).collect::<Vec<_>>();
+        if failures.is_empty() {
+            return Err(e)
+        }
+        let detail = if logins.len() == 1 {
+            failures[0].1.clone()
+        } else {
+            let failures = failures.iter()
+                .map(|&(login, error)| format!("`{}`: {}", login, error))
+                .collect::<Vec<_>>();
+            format!("none of the owners were changed because some couldn't be: {}",
+                    failures.join(", "))
+        };
+
+        // Still an error as far as cargo is concerned
+        #[derive(RustcEncodable)]
+        struct Error { detail: String }
+        #[derive(RustcEncodable)]
+        struct R { ok: bool, results: Vec<EncodableOwnerChange>, errors: Vec<Error> }
+        return Ok(req.json(&R {
+            ok: false,
+            results: results,
+            errors: vec![Error { detail: detail }],
+        }))
+    }
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool, results: Vec<EncodableOwnerChange> }
+    Ok(req.json(&R { ok: true, results: results }))
+}
+
+/// Adds or removes one owner, for `modify_owners` and for the changes
+/// approved through `owner_approval`.
+pub fn modify_owner(app: &App,
+                    conn: &InstrumentedConnection,
+                    user: &User,
+                    krate: &Crate,
+                    login: &str,
+                    role: OwnerRole,
+                    add: bool) -> CargoResult<()> {
+    let owners = krate.owners(conn)?;
+    let collaborators = krate.collaborators(conn)?;
+    check_owner_change(user, &owners, &collaborators, login, role, add)?;
+    if add {
+        krate.owner_add(app, conn, user, login, role)?;
+        let action = match role {
+            OwnerRole::Owner => "add_owner",
+            OwnerRole::Collaborator => "add_collaborator",
+        };
+        audit::record(conn, user.id, action, Some(&krate.name), login)?;
+    } else {
+        krate.owner_remove(conn, user, login)?;
+        audit::record(conn, user.id, "remove_owner", Some(&krate.name), login)?;
+        handoff::start(conn, krate, user.id, &format!("{} was removed", login))?;
+    }
+    Ok(())
+}
+
+/// Fails if `user` can't add or remove `login`, before anything is looked up.
+///
+/// Adding a collaborator as an owner gives them full control, but owners
+/// can't be made collaborators this way: they have to be removed first.
+fn check_owner_change(user: &User,
+                      owners: &[Owner],
+                      collaborators: &[Owner],
+                      login: &str,
+                      role: OwnerRole,
+                      add: bool) -> CargoResult<()> {
+    if add {
+        let is_owner = owners.iter().any(|owner| owner.login() == login);
+        let is_collaborator = collaborators.iter().any(|owner| owner.login() == login);
+        match role {
+            OwnerRole::Owner if is_owner => {
                 return Err(human(&format_args!("`{}` is already an owner", login)))
             }
-            krate.owner_add(req.app(), &conn, user, login)?;
-        } else {
-            // Removing the team that gives you rights is prevented because
-            // team members only have Rights::Publish
-            if *login == user.gh_login {
-                return Err(human("cannot remove yourself as an owner"))
+            OwnerRole::Collaborator if is_collaborator => {
+                return Err(human(&format_args!("`{}` is already a collaborator", login)))
+            }
+            OwnerRole::Collaborator if is_owner => {
+                return Err(human(&format_args!("`{}` is already an owner, remove them \
+                                                first to make them a collaborator", login)))
             }
-            krate.owner_remove(&conn, user, login)?;
+            _ => {}
+        }
+        if role == OwnerRole::Collaborator && login == user.gh_login {
+            return Err(human("cannot make yourself a collaborator"))
         }
+    } else if login == user.gh_login {
+        // Removing the team that gives you rights is prevented because
+        // team members only have Rights::Publish
+        return Err(human("cannot remove yourself as an owner"))
     }
-
-    #[derive(RustcEncodable)]
-    struct R { ok: bool }
-    Ok(req.json(&R{ ok: true }))
+    Ok(())
 }
 
 /// Handles the `GET /crates/:crate_id/reverse_dependencies` route.
 pub fn reverse_dependencies(req: &mut Request) -> CargoResult<Response> {
     let name = &req.params()["crate_id"];
+    let (offset, limit) = req.pagination(10, 100)?;
+    permission::ensure_readable(req, name)?;
     let conn = req.tx()?;
     let krate = Crate::find_by_name(conn, name)?;
-    let (offset, limit) = req.pagination(10, 100)?;
     let (rev_deps, total) = krate.reverse_dependencies(conn, offset, limit)?;
     let rev_deps = rev_deps.into_iter()
         .map(ReverseDependency::encodable)
         .collect();
 
     #[derive(RustcEncodable)]
-    struct R { dependencies: Vec<EncodableDependency>, meta: Meta }
-    #[derive(RustcEncodable)]
-    struct Meta { total: i64 }
-    Ok(req.json(&R{ dependencies: rev_deps, meta: Meta { total: total } }))
+    struct R { dependencies: Vec<EncodableDependency>, meta: PaginationMeta }
+    let meta = req.pagination_meta(offset, limit, total);
+    Ok(req.json(&R{ dependencies: rev_deps, meta: meta }))
 }
 
 use diesel::types::Text;
 sql_function!(canon_crate_name, canon_crate_name_t, (x: Text) -> Text);
 sql_function!(lower, lower_t, (x: Text) -> Text);
+
+/// `ts_rank_cd` with explicit weights for each label of the search document,
+/// which `diesel_full_text_search` doesn't provide.
+pub mod weighted {
+    use diesel::types::{Array, Float};
+    use diesel_full_text_search::{TsQuery, TsVector};
+
+    sql_function!(ts_rank_cd, ts_rank_cd_t,
+                  (weights: Array<Float>, vector: TsVector, query: TsQuery) -> Float);
+}
@@ -12,6 +12,7 @@
 #[macro_use] extern crate log;
 #[macro_use] extern crate serde_json;
 #[macro_use] extern crate serde_derive;
+extern crate ammonia;
 extern crate chrono;
 extern crate curl;
 extern crate diesel_full_text_search;
@@ -22,6 +23,7 @@ extern crate license_exprs;
 extern crate oauth2;
 extern crate openssl;
 extern crate postgres as pg;
+extern crate pulldown_cmark;
 extern crate r2d2;
 extern crate r2d2_diesel;
 extern crate r2d2_postgres;
@@ -38,7 +40,6 @@ extern crate conduit_conditional_get;
 extern crate conduit_cookie;
 extern crate conduit_git_http_backend;
 extern crate conduit_json_parser;
-extern crate conduit_log_requests;
 extern crate conduit_middleware;
 extern crate conduit_router;
 extern crate conduit_static;
@@ -59,27 +60,52 @@ pub use self::uploaders::{Uploader, Bomb};
 use std::sync::Arc;
 use std::error::Error;
 
-use conduit_router::RouteBuilder;
 use conduit_middleware::MiddlewareBuilder;
 
-use util::{C, R, R404};
+use util::{C, R, R404, NamedRouteBuilder};
 
+pub mod admin;
+pub mod alert;
 pub mod app;
+pub mod audit;
+pub mod availability;
 pub mod badge;
 pub mod categories;
 pub mod category;
+pub mod cleanup;
 pub mod config;
 pub mod db;
 pub mod dependency;
+pub mod diff;
 pub mod dist;
 pub mod download;
+pub mod files;
 pub mod git;
+pub mod handoff;
 pub mod http;
+pub mod hub;
+pub mod idempotency;
 pub mod keyword;
 pub mod krate;
+pub mod mirror;
 pub mod model;
+pub mod notification;
+pub mod org;
 pub mod owner;
+pub mod owner_approval;
+pub mod permission;
+pub mod provenance;
+pub mod quarantine;
+pub mod readme;
+pub mod report;
+pub mod scanner;
+pub mod search;
 pub mod schema;
+pub mod settings;
+pub mod signature;
+pub mod stats;
+pub mod tag_history;
+pub mod token;
 pub mod upload;
 pub mod uploaders;
 pub mod user;
@@ -102,38 +128,107 @@ pub enum Replica {
 }
 
 pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
-    let mut api_router = RouteBuilder::new();
+    let mut api_router = NamedRouteBuilder::new();
 
     api_router.get("/crates", C(krate::index));
+    api_router.get("/crates/most_downloaded", C(krate::most_downloaded));
+    api_router.get("/crates/trending", C(krate::trending));
+    api_router.get("/crates/compare", C(krate::compare));
     api_router.get("/crates/:crate_id", C(krate::show));
     api_router.put("/crates/new", C(krate::new));
     api_router.get("/crates/:crate_id/:version", C(version::show));
+    api_router.patch("/crates/:crate_id/:version", C(version::update_metadata));
     api_router.get("/crates/:crate_id/:version/download", C(krate::download));
     api_router.get("/crates/:crate_id/:version/dependencies", C(version::dependencies));
+    api_router.get("/crates/:crate_id/:version/outdated", C(version::outdated));
     api_router.get("/crates/:crate_id/:version/downloads", C(version::downloads));
     api_router.get("/crates/:crate_id/:version/authors", C(version::authors));
+    api_router.get("/crates/:crate_id/:version/findings", C(scanner::findings));
+    api_router.get("/crates/:crate_id/:version/files", C(files::list));
+    api_router.get("/crates/:crate_id/:version/diff/:other_version", C(diff::show));
+    api_router.get("/crates/:crate_id/:version/signature", C(signature::show));
+    api_router.put("/crates/:crate_id/:version/signature", C(signature::upload));
+    api_router.get("/crates/:crate_id/:version/provenance", C(provenance::show));
+    api_router.get("/crates/:crate_id/:version/readme", C(readme::show));
     api_router.get("/crates/:crate_id/downloads", C(krate::downloads));
+    api_router.get("/crates/:crate_id/availability", C(availability::show));
+    api_router.get("/crates/:crate_id/download_stats", C(krate::download_stats));
+    api_router.get("/crates/:crate_id/download_alerts", C(alert::list));
     api_router.get("/crates/:crate_id/versions", C(krate::versions));
     api_router.put("/crates/:crate_id/follow", C(krate::follow));
     api_router.delete("/crates/:crate_id/follow", C(krate::unfollow));
     api_router.get("/crates/:crate_id/following", C(krate::following));
     api_router.get("/crates/:crate_id/owners", C(krate::owners));
+    api_router.put("/crates/:crate_id/repository_verification", C(krate::verify_repository));
+    api_router.get("/crates/:crate_id/settings", C(settings::show));
+    api_router.put("/crates/:crate_id/settings", C(settings::update));
+    api_router.get("/crates/:crate_id/settings_handoff", C(handoff::show));
+    api_router.put("/crates/:crate_id/settings_handoff", C(handoff::update));
+    api_router.get("/crates/:crate_id/tag_history", C(tag_history::index));
+    api_router.put("/crates/:crate_id/tag_history/:snapshot_id/restore",
+                   C(tag_history::restore));
+    api_router.get("/crates/:crate_id/permissions", C(permission::list));
+    api_router.put("/crates/:crate_id/permissions", C(permission::grant));
+    api_router.delete("/crates/:crate_id/permissions", C(permission::revoke));
     api_router.put("/crates/:crate_id/owners", C(krate::add_owners));
     api_router.delete("/crates/:crate_id/owners", C(krate::remove_owners));
+    api_router.get("/crates/:crate_id/owner_approvals", C(owner_approval::list));
+    api_router.put("/crates/:crate_id/owner_approvals/:approval_id/approve",
+                   C(owner_approval::approve));
+    api_router.put("/crates/:crate_id/owner_approvals/:approval_id/reject",
+                   C(owner_approval::reject));
     api_router.delete("/crates/:crate_id/:version/yank", C(version::yank));
     api_router.put("/crates/:crate_id/:version/unyank", C(version::unyank));
+    api_router.delete("/crates/:crate_id/yank", C(version::yank_range));
     api_router.get("/crates/:crate_id/reverse_dependencies", C(krate::reverse_dependencies));
+    api_router.get("/crates/:crate_id/activity", C(audit::activity));
+    api_router.put("/crates/:crate_id/reports", C(report::create));
+    api_router.get("/events", C(audit::events));
+    api_router.put("/index/subscriptions", C(hub::subscribe));
+    api_router.delete("/index/subscriptions", C(hub::unsubscribe));
     api_router.get("/versions", C(version::index));
     api_router.get("/versions/:version_id", C(version::show));
     api_router.get("/keywords", C(keyword::index));
     api_router.get("/keywords/:keyword_id", C(keyword::show));
     api_router.get("/categories", C(category::index));
     api_router.get("/categories/:category_id", C(category::show));
+    api_router.get("/categories/:category_id/stats", C(stats::category));
     api_router.get("/category_slugs", C(category::slugs));
+    api_router.get("/signing_key", C(signature::signing_key));
+    api_router.get("/search", C(search::search));
+    api_router.get("/stats", C(stats::index));
     api_router.get("/users/:user_id", C(user::show));
-    let api_router = Arc::new(R404(api_router));
+    api_router.get("/users/:user_id/avatar", C(user::avatar::show));
+    api_router.get("/users/:user_id/versions", C(user::published_versions));
+    api_router.get("/me/crates/export", C(user::export_crates));
+    api_router.put("/orgs", C(org::new));
+    api_router.get("/orgs/:org_id", C(org::show));
+    api_router.put("/orgs/:org_id/members", C(org::add_member));
+    api_router.delete("/orgs/:org_id/members/:user_id", C(org::remove_member));
+    api_router.get("/orgs/:org_id/tokens", C(org::tokens));
+    api_router.put("/orgs/:org_id/tokens", C(org::new_token));
+    api_router.delete("/orgs/:org_id/tokens/:id", C(org::revoke_token));
+    api_router.put("/admin/reserved_names", C(admin::reserve_name));
+    api_router.delete("/admin/reserved_names/:name", C(admin::unreserve_name));
+    api_router.put("/admin/crates/:crate_id/transfer", C(admin::transfer));
+    api_router.put("/admin/crate_aliases", C(admin::add_alias));
+    api_router.delete("/admin/crate_aliases/:alias", C(admin::remove_alias));
+    api_router.get("/admin/audit_log", C(admin::audit_log));
+    api_router.get("/admin/publishes", C(admin::publishes));
+    api_router.get("/admin/quarantine", C(admin::quarantined));
+    api_router.put("/admin/quarantine/:version_id/approve", C(admin::approve_version));
+    api_router.delete("/admin/quarantine/:version_id", C(admin::reject_version));
+    api_router.get("/admin/reports", C(admin::reports));
+    api_router.put("/admin/reports/:report_id/resolve", C(admin::resolve_report));
+    api_router.get("/admin/mirrors", C(admin::mirrors));
+    api_router.put("/admin/mirrors", C(admin::register_mirror));
+    api_router.delete("/admin/mirrors/:mirror_id", C(admin::revoke_mirror));
+    api_router.get("/admin/readme_backfill", C(readme::backfill_progress));
+    api_router.put("/admin/readme_backfill", C(readme::start_backfill));
+    api_router.get("/admin/download_flushes", C(download::flushes));
+    let api_router = Arc::new(R404(api_router.into_inner()));
 
-    let mut router = RouteBuilder::new();
+    let mut router = NamedRouteBuilder::new();
 
     // Mount the router under the /api/v1 path so we're at least somewhat at the
     // liberty to change things in the future!
@@ -141,6 +236,7 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
     router.put("/api/v1/*path", R(api_router.clone()));
     router.post("/api/v1/*path", R(api_router.clone()));
     router.head("/api/v1/*path", R(api_router.clone()));
+    router.patch("/api/v1/*path", R(api_router.clone()));
     router.delete("/api/v1/*path", R(api_router));
 
     router.get("/authorize_url", C(user::github_authorize));
@@ -149,6 +245,11 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
     router.get("/me", C(user::me));
     router.put("/me/reset_token", C(user::reset_token));
     router.get("/me/updates", C(user::updates));
+    router.get("/me/notifications", C(notification::list));
+    router.get("/me/tokens", C(token::list));
+    router.put("/me/tokens", C(token::new));
+    router.delete("/me/tokens/:id", C(token::revoke));
+    router.get("/me/tokens/:id/usage", C(token::usage));
     router.get("/summary", C(krate::summary));
 
     let env = app.config.env;
@@ -159,19 +260,23 @@ pub fn middleware(app: Arc<App>) -> MiddlewareBuilder {
         router.post("/git/index/*path", R(s));
     }
 
-    let mut m = MiddlewareBuilder::new(R404(router));
+    let mut m = MiddlewareBuilder::new(R404(router.into_inner()));
     if env == Env::Development {
         m.add(DebugMiddleware);
     }
     if env != Env::Test {
-        m.add(conduit_log_requests::LogRequests(log::LogLevel::Info));
+        m.add(util::LogRequests);
     }
     m.around(util::Head::default());
+    m.around(util::BodyLimits::new(&app.config));
     m.add(conduit_conditional_get::ConditionalGet);
     m.add(conduit_cookie::Middleware::new(app.session_key.as_bytes()));
     m.add(conduit_cookie::SessionMiddleware::new("cargo_session",
                                                  env == Env::Production));
+    m.add(util::ClientIpMiddleware::new(app.config.trusted_proxies.clone()));
     m.add(app::AppMiddleware::new(app));
+    m.add(download::FlushMiddleware);
+    m.add(token::FlushMiddleware);
     if env != Env::Test {
         m.add(db::TransactionMiddleware);
     }
@@ -218,7 +323,7 @@ pub fn now() -> time::Timespec {
 }
 
 pub fn encode_time(ts: time::Timespec) -> String {
-    time::at_utc(ts).rfc3339().to_string()
+    util::format::rfc3339(ts)
 }
 
 pub fn env(s: &str) -> String {
@@ -0,0 +1,110 @@
+//! Mirrors which are known to the registry.
+//!
+//! Mirrors fetch every crate file they don't have yet, which would inflate
+//! download counts. Admins register the mirrors they know about through
+//! `PUT /admin/mirrors`, and a mirror which sends its token in the
+//! `X-Mirror-Token` header when downloading isn't counted as a download of
+//! the crate. Its traffic is counted per mirror and day in
+//! `mirror_downloads` instead.
+
+use pg::GenericConnection;
+use pg::rows::Row;
+use time::Timespec;
+
+use Model;
+use util::{CargoResult, human};
+
+/// The header mirrors send their token in.
+pub const TOKEN_HEADER: &'static str = "X-Mirror-Token";
+
+pub struct Mirror {
+    pub id: i32,
+    pub name: String,
+    pub token: String,
+    pub created_at: Timespec,
+    pub revoked: bool,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableMirror {
+    pub id: i32,
+    pub name: String,
+    /// Only included when the mirror is registered
+    pub token: Option<String>,
+    pub created_at: String,
+    pub revoked: bool,
+    pub downloads_last_30_days: i64,
+}
+
+impl Mirror {
+    pub fn create(conn: &GenericConnection, name: &str) -> CargoResult<Mirror> {
+        let rows = conn.query("INSERT INTO mirrors (name) VALUES ($1) \
+                               ON CONFLICT DO NOTHING RETURNING *", &[&name])?;
+        match rows.iter().next() {
+            Some(row) => Ok(Model::from_row(&row)),
+            None => Err(human(&format_args!("a mirror named `{}` already exists", name))),
+        }
+    }
+
+    /// The mirror `token` belongs to, unless it was revoked.
+    pub fn find_active(conn: &GenericConnection, token: &str) -> CargoResult<Option<Mirror>> {
+        let rows = conn.query("SELECT * FROM mirrors WHERE token = $1 AND NOT revoked", &[&token])?;
+        Ok(rows.iter().next().map(|row| Model::from_row(&row)))
+    }
+
+    /// Revokes the mirror's token, returning its name.
+    pub fn revoke(conn: &GenericConnection, id: i32) -> CargoResult<String> {
+        let rows = conn.query("UPDATE mirrors SET revoked = TRUE \
+                               WHERE id = $1 AND NOT revoked RETURNING name", &[&id])?;
+        match rows.iter().next() {
+            Some(row) => Ok(row.get("name")),
+            None => Err(human("no such mirror, or it was already revoked")),
+        }
+    }
+
+    /// Every mirror, with how many crate files it downloaded in the last 30
+    /// days.
+    pub fn all(conn: &GenericConnection) -> CargoResult<Vec<(Mirror, i64)>> {
+        let rows = conn.query("\
+            SELECT mirrors.*,
+                   COALESCE((SELECT SUM(downloads) FROM mirror_downloads
+                              WHERE mirror_id = mirrors.id
+                                AND date > CURRENT_DATE - 30), 0)::int8 AS recent_downloads
+              FROM mirrors
+             ORDER BY mirrors.name", &[])?;
+        Ok(rows.iter().map(|row| (Model::from_row(&row), row.get("recent_downloads"))).collect())
+    }
+
+    pub fn record_download(&self, conn: &GenericConnection) -> CargoResult<()> {
+        conn.execute("INSERT INTO mirror_downloads (mirror_id, downloads) VALUES ($1, 1) \
+                      ON CONFLICT (mirror_id, date) \
+                      DO UPDATE SET downloads = mirror_downloads.downloads + 1",
+                     &[&self.id])?;
+        Ok(())
+    }
+
+    pub fn encodable(self, recent_downloads: i64, with_token: bool) -> EncodableMirror {
+        EncodableMirror {
+            id: self.id,
+            name: self.name,
+            token: if with_token { Some(self.token) } else { None },
+            created_at: ::encode_time(self.created_at),
+            revoked: self.revoked,
+            downloads_last_30_days: recent_downloads,
+        }
+    }
+}
+
+impl Model for Mirror {
+    fn from_row(row: &Row) -> Mirror {
+        Mirror {
+            id: row.get("id"),
+            name: row.get("name"),
+            token: row.get("token"),
+            created_at: row.get("created_at"),
+            revoked: row.get("revoked"),
+        }
+    }
+
+    fn table_name(_: Option<Mirror>) -> &'static str { "mirrors" }
+}
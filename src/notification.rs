@@ -0,0 +1,137 @@
+//! Notifications for the users following a crate.
+//!
+//! Following a crate lists its new versions in `GET /me/updates`. Followers
+//! who also opted into legal notifications, by following it with
+//! `{"legal_notifications": true}`, are notified when a new version is
+//! published under another license than the version before it: silently
+//! relicensing a crate leaves its users out of compliance. Users list their
+//! notifications with `GET /me/notifications`, newest first.
+
+use conduit::{Request, Response};
+use diesel;
+use diesel::prelude::*;
+use time::Timespec;
+
+use audit;
+use db::{InstrumentedConnection, RequestTransaction};
+use schema::*;
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, PaginationMeta};
+use {Crate, Version};
+
+/// The kind of notifications, and of audit log entries, about a new version
+/// changing the crate's license.
+pub const LICENSE_CHANGE: &'static str = "license_change";
+
+#[derive(Clone, Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(Crate)]
+pub struct Notification {
+    pub id: i32,
+    pub user_id: i32,
+    pub crate_id: i32,
+    pub kind: String,
+    pub details: String,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="notifications"]
+struct NewNotification<'a> {
+    user_id: i32,
+    crate_id: i32,
+    kind: &'a str,
+    details: &'a str,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableNotification {
+    pub id: i32,
+    /// Only `license_change` for now
+    pub kind: String,
+    pub crate_name: String,
+    /// The version and both licenses for license changes
+    pub details: String,
+    pub created_at: String,
+}
+
+impl Notification {
+    pub fn encodable(self, crate_name: String) -> EncodableNotification {
+        EncodableNotification {
+            id: self.id,
+            kind: self.kind,
+            crate_name: crate_name,
+            details: self.details,
+            created_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+/// Records in the crate's activity that `version` changed its license from
+/// `before`, and notifies the followers who opted into legal notifications.
+pub fn license_changed(conn: &InstrumentedConnection,
+                       krate: &Crate,
+                       version: &Version,
+                       before: Option<&str>,
+                       user_id: i32) -> CargoResult<()> {
+    let details = format!("{}: {} -> {}", version.num, before.unwrap_or("none"),
+                          version.license.as_ref().map(|s| &s[..]).unwrap_or("none"));
+    audit::record(conn, user_id, LICENSE_CHANGE, Some(&krate.name), &details)?;
+
+    let followers = follows::table
+        .filter(follows::crate_id.eq(krate.id))
+        .filter(follows::legal_notifications.eq(true))
+        .select(follows::user_id)
+        .load::<i32>(conn)?;
+    let new_notifications = followers.into_iter().map(|user_id| NewNotification {
+        user_id: user_id,
+        crate_id: krate.id,
+        kind: LICENSE_CHANGE,
+        details: &details,
+    }).collect::<Vec<_>>();
+    if !new_notifications.is_empty() {
+        diesel::insert(&new_notifications).into(notifications::table).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Handles the `GET /me/notifications` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::sql;
+    use diesel::types::BigInt;
+
+    let user = req.user()?;
+    let (offset, limit) = req.pagination(10, 100)?;
+    let conn = req.db_conn()?;
+
+    let data = notifications::table.inner_join(crates::table)
+        .filter(notifications::user_id.eq(user.id))
+        .order(notifications::id.desc())
+        .limit(limit)
+        .offset(offset)
+        .select((
+            notifications::all_columns,
+            crates::name,
+            sql::<BigInt>("COUNT(*) OVER ()"),
+        ))
+        .load::<(Notification, String, i64)>(&*conn)?;
+    // Pages past the end don't have a row to read the total from
+    let total = match data.get(0) {
+        Some(&(_, _, count)) => count,
+        None => notifications::table
+            .filter(notifications::user_id.eq(user.id))
+            .count()
+            .get_result(&*conn)?,
+    };
+
+    let notifications = data.into_iter().map(|(notification, crate_name, _)| {
+        notification.encodable(crate_name)
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R {
+        notifications: Vec<EncodableNotification>,
+        meta: PaginationMeta,
+    }
+    let meta = req.pagination_meta(offset, limit, total);
+    Ok(req.json(&R { notifications: notifications, meta: meta }))
+}
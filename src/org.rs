@@ -0,0 +1,431 @@
+//! Organizations are accounts which live in the registry itself rather than
+//! on GitHub. They can own crates just like users and teams, with their
+//! members getting rights on those crates according to their role, and they
+//! can hold API tokens of their own so that CI doesn't need a personal token.
+//!
+//! Organizations are referred to as `org:<login>` wherever users and teams
+//! can be given, e.g. when adding crate owners.
+
+use std::io::Read;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::pg::upsert::*;
+use diesel::prelude::*;
+use pg::rows::Row;
+use rustc_serialize::json;
+use time::Timespec;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use krate::lower;
+use owner::Rights;
+use schema::*;
+use token::{self, ApiToken, EncodableApiToken, NewApiToken};
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, human};
+use {Model, User};
+
+/// The prefix which tells organizations apart from users in owner logins.
+pub const LOGIN_PREFIX: &'static str = "org:";
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+pub struct Organization {
+    pub id: i32,
+    pub login: String,
+    pub name: Option<String>,
+    pub created_at: Timespec,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableOrganization {
+    pub id: i32,
+    pub login: String,
+    pub name: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableOrgMember {
+    pub login: String,
+    pub role: String,
+}
+
+/// What a member of an organization may do.
+/// NOTE: The order of these variants matters!
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrgRole {
+    /// Can read the organization's private crates.
+    Reader = 0,
+    /// Can also publish and yank the organization's crates.
+    Publisher = 1,
+    /// Can also manage the crates' owners, and the organization's members
+    /// and API tokens.
+    Admin = 2,
+}
+
+impl OrgRole {
+    pub fn from_i32(role: i32) -> Option<OrgRole> {
+        match role {
+            0 => Some(OrgRole::Reader),
+            1 => Some(OrgRole::Publisher),
+            2 => Some(OrgRole::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<OrgRole> {
+        match role {
+            "reader" => Some(OrgRole::Reader),
+            "publisher" => Some(OrgRole::Publisher),
+            "admin" => Some(OrgRole::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OrgRole::Reader => "reader",
+            OrgRole::Publisher => "publisher",
+            OrgRole::Admin => "admin",
+        }
+    }
+
+    /// The rights this role gives on crates owned by the organization.
+    pub fn rights(&self) -> Rights {
+        match *self {
+            OrgRole::Reader => Rights::None,
+            OrgRole::Publisher => Rights::Publish,
+            OrgRole::Admin => Rights::Full,
+        }
+    }
+}
+
+#[derive(Insertable, Associations, Identifiable)]
+#[belongs_to(Organization)]
+#[belongs_to(User)]
+#[table_name="organization_members"]
+#[primary_key(organization_id, user_id)]
+pub struct OrgMember {
+    pub organization_id: i32,
+    pub user_id: i32,
+    pub role: i32,
+}
+
+impl Organization {
+    /// Finds an organization by its login, with or without the `org:` prefix.
+    pub fn find_by_login(conn: &InstrumentedConnection, login: &str) -> CargoResult<Organization> {
+        let login = if login.starts_with(LOGIN_PREFIX) {
+            &login[LOGIN_PREFIX.len()..]
+        } else {
+            login
+        };
+        organizations::table.filter(lower(organizations::login).eq(login.to_lowercase()))
+            .first(conn)
+            .map_err(|_| {
+                human(&format_args!("could not find organization with login `{}`", login))
+            })
+    }
+
+    /// The role `user_id` has in this organization, if they are a member.
+    pub fn role_of(&self, conn: &InstrumentedConnection,
+                   user_id: i32) -> CargoResult<Option<OrgRole>> {
+        let role = organization_members::table
+            .find((self.id, user_id))
+            .select(organization_members::role)
+            .first::<i32>(conn)
+            .optional()?;
+        Ok(role.and_then(OrgRole::from_i32))
+    }
+
+    /// Fails unless `user` is an admin of this organization.
+    pub fn ensure_admin(&self, conn: &InstrumentedConnection, user: &User) -> CargoResult<()> {
+        match self.role_of(conn, user.id)? {
+            Some(OrgRole::Admin) => Ok(()),
+            _ => Err(human(&format_args!("only admins of `{}` can do that", self.login))),
+        }
+    }
+
+    pub fn members(&self, conn: &InstrumentedConnection) -> CargoResult<Vec<EncodableOrgMember>> {
+        let members = organization_members::table
+            .inner_join(users::table)
+            .filter(organization_members::organization_id.eq(self.id))
+            .select((users::gh_login, organization_members::role))
+            .order(users::gh_login.asc())
+            .load::<(String, i32)>(conn)?;
+        Ok(members.into_iter().filter_map(|(login, role)| {
+            OrgRole::from_i32(role).map(|role| {
+                EncodableOrgMember { login: login, role: role.as_str().to_string() }
+            })
+        }).collect())
+    }
+
+    pub fn encodable(self) -> EncodableOrganization {
+        EncodableOrganization {
+            id: self.id,
+            login: self.login,
+            name: self.name,
+            created_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+impl Model for Organization {
+    fn from_row(row: &Row) -> Organization {
+        Organization {
+            id: row.get("id"),
+            login: row.get("login"),
+            name: row.get("name"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    fn table_name(_: Option<Organization>) -> &'static str { "organizations" }
+}
+
+fn organization(req: &Request, conn: &InstrumentedConnection) -> CargoResult<Organization> {
+    Organization::find_by_login(conn, &req.params()["org_id"])
+}
+
+/// Handles the `PUT /orgs` route.
+///
+/// The user creating the organization becomes its first admin.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "login": "acme", "name": "Acme Corporation" }
+/// ```
+pub fn new(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request { login: String, name: Option<String> }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let login = request.login.trim();
+    if !::Crate::valid_name(login) {
+        return Err(human(&format_args!("`{}` is not a valid organization login", login)))
+    }
+
+    #[derive(Insertable)]
+    #[table_name="organizations"]
+    struct NewOrganization<'a> {
+        login: &'a str,
+        name: Option<&'a str>,
+    }
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = conn.transaction(|| {
+        let new_org = NewOrganization {
+            login: login,
+            name: request.name.as_ref().map(|s| &**s),
+        };
+        let org = diesel::insert(&new_org.on_conflict_do_nothing())
+            .into(organizations::table)
+            .get_result::<Organization>(&*conn)
+            .optional()?
+            .ok_or_else(|| {
+                human(&format_args!("organization `{}` already exists", login))
+            })?;
+        let admin = OrgMember {
+            organization_id: org.id,
+            user_id: user.id,
+            role: OrgRole::Admin as i32,
+        };
+        diesel::insert(&admin).into(organization_members::table)
+            .execute(&*conn)?;
+        Ok(org)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { org: EncodableOrganization }
+    Ok(req.json(&R { org: org.encodable() }))
+}
+
+/// Handles the `GET /orgs/:org_id` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    let members = org.members(&conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { org: EncodableOrganization, members: Vec<EncodableOrgMember> }
+    Ok(req.json(&R { org: org.encodable(), members: members }))
+}
+
+/// Handles the `PUT /orgs/:org_id/members` route, which adds a member or
+/// changes the role of an existing one.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "user": "octocat", "role": "publisher" }
+/// ```
+pub fn add_member(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request { user: String, role: String }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let role = OrgRole::parse(&request.role).ok_or_else(|| {
+        human("role must be one of `admin`, `publisher` or `reader`")
+    })?;
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    org.ensure_admin(&conn, user)?;
+    let member = users::table.filter(users::gh_login.eq(&request.user))
+        .first::<User>(&*conn)
+        .map_err(|_| {
+            human(&format_args!("could not find user with login `{}`", request.user))
+        })?;
+
+    conn.transaction(|| {
+        let new_member = OrgMember {
+            organization_id: org.id,
+            user_id: member.id,
+            role: role as i32,
+        };
+        diesel::insert(&new_member.on_conflict(
+                organization_members::table.primary_key(),
+                do_update().set(organization_members::role.eq(role as i32)),
+            )).into(organization_members::table)
+            .execute(&*conn)?;
+        ensure_an_admin_remains(&conn, &org)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `DELETE /orgs/:org_id/members/:user_id` route.
+pub fn remove_member(req: &mut Request) -> CargoResult<Response> {
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    org.ensure_admin(&conn, user)?;
+    let login = &req.params()["user_id"];
+    let member_id = users::table.filter(users::gh_login.eq(login))
+        .select(users::id)
+        .first::<i32>(&*conn)?;
+
+    conn.transaction(|| {
+        diesel::delete(organization_members::table.find((org.id, member_id)))
+            .execute(&*conn)?;
+        ensure_an_admin_remains(&conn, &org)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+fn ensure_an_admin_remains(conn: &InstrumentedConnection, org: &Organization) -> CargoResult<()> {
+    let admins = organization_members::table
+        .filter(organization_members::organization_id.eq(org.id))
+        .filter(organization_members::role.eq(OrgRole::Admin as i32))
+        .count()
+        .get_result::<i64>(conn)?;
+    if admins == 0 {
+        return Err(human("an organization must keep at least one admin"))
+    }
+    Ok(())
+}
+
+/// Handles the `GET /orgs/:org_id/tokens` route.
+pub fn tokens(req: &mut Request) -> CargoResult<Response> {
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    org.ensure_admin(&conn, user)?;
+    let tokens = api_tokens::table
+        .filter(api_tokens::organization_id.eq(org.id))
+        .filter(api_tokens::revoked.eq(false))
+        .order(api_tokens::created_at.desc())
+        .load::<ApiToken>(&*conn)?
+        .into_iter()
+        .map(ApiToken::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { api_tokens: Vec<EncodableApiToken> }
+    Ok(req.json(&R { api_tokens: tokens }))
+}
+
+/// Handles the `PUT /orgs/:org_id/tokens` route.
+///
+/// Requests authenticated with these tokens can only publish and yank the
+/// crates owned by the organization, whoever created the token.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "api_token": { "name": "ci", "expires_in_days": 365 } }
+/// ```
+pub fn new_token(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct NewToken {
+        name: String,
+        expires_in_days: Option<u32>,
+        allowed_cidrs: Option<Vec<String>>,
+    }
+    #[derive(RustcDecodable)]
+    struct Request { api_token: NewToken }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let name = request.api_token.name.trim();
+    if name.is_empty() {
+        return Err(human("the new token must be given a name"))
+    }
+    let expires_at = token::expiry(request.api_token.expires_in_days)?;
+    let allowed_cidrs = token::allowed_cidrs(request.api_token.allowed_cidrs)?;
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    org.ensure_admin(&conn, user)?;
+    let token = NewApiToken {
+        user_id: user.id,
+        name: name,
+        organization_id: Some(org.id),
+        expires_at: expires_at,
+        allowed_cidrs: allowed_cidrs,
+    }.save(&conn)?;
+    let secret = token.token.clone();
+    let mut token = token.encodable();
+    token.token = Some(secret);
+
+    #[derive(RustcEncodable)]
+    struct R { api_token: EncodableApiToken }
+    Ok(req.json(&R { api_token: token }))
+}
+
+/// Handles the `DELETE /orgs/:org_id/tokens/:id` route.
+pub fn revoke_token(req: &mut Request) -> CargoResult<Response> {
+    let id = req.params()["id"].parse::<i32>().map_err(|_| {
+        human("invalid token id")
+    })?;
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let org = organization(req, &conn)?;
+    org.ensure_admin(&conn, user)?;
+    let token = api_tokens::table
+        .filter(api_tokens::organization_id.eq(org.id))
+        .filter(api_tokens::id.eq(id));
+    diesel::update(token).set(api_tokens::revoked.eq(true))
+        .execute(&*conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
@@ -1,11 +1,17 @@
+use std::cmp;
+
 use diesel;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
 use pg::rows::Row;
 
-use app::App;
+use conduit::Request;
+
+use app::{App, RequestApp};
+use db::InstrumentedConnection;
 use http;
+use org::{self, Organization};
 use schema::*;
+use user::{avatar, RequestUser};
 use util::{CargoResult, human};
 use {Model, User, Crate};
 
@@ -20,18 +26,50 @@ pub struct CrateOwner {
     pub owner_id: i32,
     pub created_by: i32,
     pub owner_kind: i32,
+    pub role: i32,
 }
 
 #[repr(u32)]
 pub enum OwnerKind {
     User = 0,
     Team = 1,
+    Org = 2,
+}
+
+/// What an owner is allowed to do with a crate.
+///
+/// Collaborators can publish new versions, but can't yank them or change
+/// who owns the crate.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OwnerRole {
+    Owner = 0,
+    Collaborator = 1,
 }
 
-/// Unifies the notion of a User or a Team.
+impl OwnerRole {
+    pub fn parse(role: &str) -> CargoResult<OwnerRole> {
+        match role {
+            "owner" => Ok(OwnerRole::Owner),
+            "collaborator" => Ok(OwnerRole::Collaborator),
+            _ => Err(human(&format_args!("unknown owner role `{}`, expected \
+                                          `owner` or `collaborator`", role))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OwnerRole::Owner => "owner",
+            OwnerRole::Collaborator => "collaborator",
+        }
+    }
+}
+
+/// Unifies the notion of a User, a Team or an Organization.
 pub enum Owner {
     User(User),
     Team(Team),
+    Org(Organization),
 }
 
 /// For now, just a Github Team. Can be upgraded to other teams
@@ -62,6 +100,20 @@ pub struct EncodableOwner {
     pub url: Option<String>,
     pub name: Option<String>,
     pub avatar: Option<String>,
+    /// Only set when listing the owners of a crate
+    pub role: Option<String>,
+}
+
+/// What became of one of the logins in a request adding or removing owners.
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct EncodableOwnerChange {
+    pub login: String,
+    pub ok: bool,
+    /// Why the login couldn't be added or removed
+    pub error: Option<String>,
+    /// Set when the change waits for another owner's approval, see the
+    /// `owner_approval` module
+    pub approval_id: Option<i32>,
 }
 
 /// Access rights to the crate (publishing and ownership management)
@@ -76,7 +128,7 @@ pub enum Rights {
 impl Team {
     /// Tries to create the Team in the DB (assumes a `:` has already been found).
     pub fn create(app: &App,
-                  conn: &PgConnection,
+                  conn: &InstrumentedConnection,
                   login: &str,
                   req_user: &User)
                   -> CargoResult<Self> {
@@ -103,7 +155,7 @@ impl Team {
     /// Tries to create a Github Team from scratch. Assumes `org` and `team` are
     /// correctly parsed out of the full `name`. `name` is passed as a
     /// convenience to avoid rebuilding it.
-    pub fn create_github_team(app: &App, conn: &PgConnection, login: &str,
+    pub fn create_github_team(app: &App, conn: &InstrumentedConnection, login: &str,
                               org_name: &str, team_name: &str, req_user: &User)
                               -> CargoResult<Self> {
         // GET orgs/:org/teams
@@ -158,7 +210,7 @@ impl Team {
         Team::insert(conn, login, team.id, team.name, org.avatar_url)
     }
 
-    pub fn insert(conn: &PgConnection,
+    pub fn insert(conn: &InstrumentedConnection,
                   login: &str,
                   github_id: i32,
                   name: Option<String>,
@@ -190,6 +242,21 @@ impl Team {
     pub fn contains_user(&self, app: &App, user: &User) -> CargoResult<bool> {
         team_with_gh_id_contains_user(app, self.github_id, user)
     }
+
+    /// Asks Github for the ids of every team the User is a member of. As with
+    /// `contains_user`, the User has to be the one interested in the answer.
+    pub fn github_ids_of(app: &App, user: &User) -> CargoResult<Vec<i32>> {
+        #[derive(RustcDecodable)]
+        struct GithubTeam {
+            id: i32,
+        }
+
+        // FIXME: like `create_github_team`, pagination links aren't followed
+        let token = http::token(user.gh_access_token.clone());
+        let (handle, data) = http::github(app, "/user/teams?per_page=100", &token)?;
+        let teams: Vec<GithubTeam> = http::parse_github_response(handle, &data)?;
+        Ok(teams.into_iter().map(|team| team.id).collect())
+    }
 }
 
 fn team_with_gh_id_contains_user(app: &App, github_id: i32, user: &User)
@@ -237,9 +304,11 @@ impl Owner {
     /// Finds the owner by name, failing out if it doesn't exist.
     /// May be a user's GH login, or a full team name. This is case
     /// sensitive.
-    pub fn find_by_login(conn: &PgConnection,
+    pub fn find_by_login(conn: &InstrumentedConnection,
                          name: &str) -> CargoResult<Owner> {
-        if name.contains(':') {
+        if name.starts_with(org::LOGIN_PREFIX) {
+            Organization::find_by_login(conn, name).map(Owner::Org)
+        } else if name.contains(':') {
             teams::table.filter(teams::login.eq(name))
                 .first(conn)
                 .map(Owner::Team)
@@ -260,6 +329,7 @@ impl Owner {
         match *self {
             Owner::User(_) => OwnerKind::User as i32,
             Owner::Team(_) => OwnerKind::Team as i32,
+            Owner::Org(_) => OwnerKind::Org as i32,
         }
     }
 
@@ -267,6 +337,7 @@ impl Owner {
         match *self {
             Owner::User(ref user) => &user.gh_login,
             Owner::Team(ref team) => &team.login,
+            Owner::Org(ref org) => &org.login,
         }
     }
 
@@ -274,6 +345,7 @@ impl Owner {
         match *self {
             Owner::User(ref user) => user.id,
             Owner::Team(ref team) => team.id,
+            Owner::Org(ref org) => org.id,
         }
     }
 
@@ -281,14 +353,16 @@ impl Owner {
         match self {
             Owner::User(User { id, email, name, gh_login, gh_avatar, .. }) => {
                 let url = format!("https://github.com/{}", gh_login);
+                let avatar = gh_avatar.map(|_| avatar::path(&gh_login));
                 EncodableOwner {
                     id: id,
                     login: gh_login,
                     email: email,
-                    avatar: gh_avatar,
+                    avatar: avatar,
                     url: Some(url),
                     name: name,
                     kind: String::from("user"),
+                    role: None,
                 }
             }
             Owner::Team(Team { id, name, login, avatar, .. }) => {
@@ -306,6 +380,19 @@ impl Owner {
                     avatar: avatar,
                     name: name,
                     kind: String::from("team"),
+                    role: None,
+                }
+            }
+            Owner::Org(Organization { id, name, login, .. }) => {
+                EncodableOwner {
+                    id: id,
+                    login: format!("{}{}", org::LOGIN_PREFIX, login),
+                    email: None,
+                    url: None,
+                    avatar: None,
+                    name: name,
+                    kind: String::from("org"),
+                    role: None,
                 }
             }
         }
@@ -320,7 +407,12 @@ impl Owner {
 /// `Publish` as well, but this is a non-obvious invariant so we don't bother.
 /// Sweet free optimization if teams are proving burdensome to check.
 /// More than one team isn't really expected, though.
-pub fn rights(app: &App, owners: &[Owner], user: &User) -> CargoResult<Rights> {
+///
+/// Members of an organization get the rights that come with their role.
+pub fn rights(app: &App,
+              conn: &InstrumentedConnection,
+              owners: &[Owner],
+              user: &User) -> CargoResult<Rights> {
     let mut best = Rights::None;
     for owner in owners {
         match *owner {
@@ -328,10 +420,40 @@ pub fn rights(app: &App, owners: &[Owner], user: &User) -> CargoResult<Rights> {
                 return Ok(Rights::Full);
             },
             Owner::Team(ref team) => if team.contains_user(app, user)? {
-                best = Rights::Publish;
+                best = cmp::max(best, Rights::Publish);
+            },
+            Owner::Org(ref org) => if let Some(role) = org.role_of(conn, user.id)? {
+                best = cmp::max(best, role.rights());
             },
         }
     }
     Ok(best)
 }
 
+/// The rights the request is allowed to use on a crate with these owners.
+///
+/// This is usually the rights of the user making the request, but requests
+/// authenticated with an organization's API token can only publish the
+/// crates that organization owns, and only while whoever created the token
+/// is still a member of it.
+pub fn request_rights(req: &Request,
+                      conn: &InstrumentedConnection,
+                      owners: &[Owner]) -> CargoResult<Rights> {
+    let user = req.publisher()?;
+    match req.api_token().and_then(|token| token.organization_id) {
+        Some(org_id) => {
+            let org = owners.iter().filter_map(|owner| match *owner {
+                Owner::Org(ref org) if org.id == org_id => Some(org),
+                _ => None,
+            }).next();
+            let org = match org {
+                Some(org) => org,
+                None => return Ok(Rights::None),
+            };
+            let member = org.role_of(conn, user.id)?.is_some();
+            Ok(if member { Rights::Publish } else { Rights::None })
+        }
+        None => rights(req.app(), conn, owners, user),
+    }
+}
+
@@ -0,0 +1,442 @@
+//! Owner changes which wait for a second owner's approval.
+//!
+//! Owners can turn on `require_owner_approval` in a crate's settings so that
+//! a single compromised account can't hand the crate to someone else. While
+//! it's on, and the crate has another owner who could approve, adding or
+//! removing owners and collaborators (and turning the setting back off) only
+//! asks for the change. It's made once another owner approves it with
+//! `PUT /crates/:crate_id/owner_approvals/:approval_id/approve`.
+//!
+//! An approval starts out `pending`, and becomes `approved`, `rejected`, or
+//! `expired` if nobody approved it within `EXPIRY_DAYS`. Only pending
+//! approvals can change state.
+
+use std::collections::HashMap;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use time::{Duration, Timespec};
+
+use app::{App, RequestApp};
+use audit;
+use db::InstrumentedConnection;
+use krate;
+use owner::{self, request_rights, Owner, OwnerRole, Rights};
+use schema::*;
+use settings::CrateSettings;
+use user::RequestUser;
+use util::errors::NotFound;
+use util::{CargoResult, ChainError, human};
+use {Crate, User};
+
+/// How long an owner change can wait for its approval.
+pub const EXPIRY_DAYS: i64 = 7;
+
+/// The change an approval is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalAction {
+    AddOwner,
+    AddCollaborator,
+    RemoveOwner,
+    /// Turning `require_owner_approval` off
+    DisableApproval,
+}
+
+impl ApprovalAction {
+    /// The action for adding or removing an owner with `role`, as
+    /// `PUT` and `DELETE /crates/:crate_id/owners` do.
+    pub fn owner_change(role: OwnerRole, add: bool) -> ApprovalAction {
+        match (add, role) {
+            (true, OwnerRole::Owner) => ApprovalAction::AddOwner,
+            (true, OwnerRole::Collaborator) => ApprovalAction::AddCollaborator,
+            (false, _) => ApprovalAction::RemoveOwner,
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<ApprovalAction> {
+        match action {
+            "add_owner" => Some(ApprovalAction::AddOwner),
+            "add_collaborator" => Some(ApprovalAction::AddCollaborator),
+            "remove_owner" => Some(ApprovalAction::RemoveOwner),
+            "disable_owner_approval" => Some(ApprovalAction::DisableApproval),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ApprovalAction::AddOwner => "add_owner",
+            ApprovalAction::AddCollaborator => "add_collaborator",
+            ApprovalAction::RemoveOwner => "remove_owner",
+            ApprovalAction::DisableApproval => "disable_owner_approval",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApprovalState {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+}
+
+impl ApprovalState {
+    pub fn parse(state: &str) -> Option<ApprovalState> {
+        match state {
+            "pending" => Some(ApprovalState::Pending),
+            "approved" => Some(ApprovalState::Approved),
+            "rejected" => Some(ApprovalState::Rejected),
+            "expired" => Some(ApprovalState::Expired),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ApprovalState::Pending => "pending",
+            ApprovalState::Approved => "approved",
+            ApprovalState::Rejected => "rejected",
+            ApprovalState::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name="owner_approvals"]
+pub struct OwnerApproval {
+    pub id: i32,
+    pub crate_id: i32,
+    pub action: String,
+    /// The owner added or removed, empty when turning the setting off
+    pub login: String,
+    pub requested_by: i32,
+    pub state: String,
+    pub resolved_by: Option<i32>,
+    pub created_at: Timespec,
+    pub resolved_at: Option<Timespec>,
+}
+
+#[derive(Insertable)]
+#[table_name="owner_approvals"]
+struct NewOwnerApproval<'a> {
+    crate_id: i32,
+    action: &'a str,
+    login: &'a str,
+    requested_by: i32,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct EncodableOwnerApproval {
+    pub id: i32,
+    pub action: String,
+    pub login: String,
+    /// The login of the owner who asked for the change
+    pub requested_by: Option<String>,
+    pub state: String,
+    /// The login of the owner who approved or rejected it
+    pub resolved_by: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    pub resolved_at: Option<String>,
+}
+
+impl OwnerApproval {
+    /// The state of the approval at `now`: pending approvals expire once
+    /// they're `EXPIRY_DAYS` old, whether or not that's been saved yet.
+    pub fn state_at(&self, now: Timespec) -> CargoResult<ApprovalState> {
+        let state = ApprovalState::parse(&self.state).chain_error(|| {
+            human(&format_args!("unknown owner approval state `{}`", self.state))
+        })?;
+        if state == ApprovalState::Pending && now >= self.expires_at() {
+            return Ok(ApprovalState::Expired)
+        }
+        Ok(state)
+    }
+
+    pub fn expires_at(&self) -> Timespec {
+        self.created_at + Duration::days(EXPIRY_DAYS)
+    }
+
+    pub fn action(&self) -> CargoResult<ApprovalAction> {
+        ApprovalAction::parse(&self.action).chain_error(|| {
+            human(&format_args!("unknown owner approval action `{}`", self.action))
+        })
+    }
+
+    /// The change, as the audit log describes it.
+    fn describe(&self) -> String {
+        format!("{} {}", self.action, self.login).trim().to_string()
+    }
+}
+
+/// Whether the owners include a user other than `user`, who could approve
+/// the changes `user` asks for.
+pub fn has_other_owner(owners: &[Owner], user: &User) -> bool {
+    owners.iter().any(|owner| match *owner {
+        Owner::User(ref other) => other.id != user.id,
+        _ => false,
+    })
+}
+
+/// Whether the owner changes `user` makes to the crate have to be approved
+/// by another owner.
+pub fn required(conn: &InstrumentedConnection,
+                krate: &Crate,
+                user: &User,
+                owners: &[Owner]) -> CargoResult<bool> {
+    let settings = CrateSettings::find(conn, krate.id)?;
+    Ok(settings.require_owner_approval && has_other_owner(owners, user))
+}
+
+/// Asks for an owner change, unless the same change is already waiting for
+/// its approval.
+pub fn request(conn: &InstrumentedConnection,
+               krate: &Crate,
+               user: &User,
+               action: ApprovalAction,
+               login: &str) -> CargoResult<OwnerApproval> {
+    expire_stale(conn, krate.id)?;
+    let pending = owner_approvals::table
+        .filter(owner_approvals::crate_id.eq(krate.id))
+        .filter(owner_approvals::state.eq(ApprovalState::Pending.as_str()))
+        .filter(owner_approvals::action.eq(action.as_str()))
+        .filter(owner_approvals::login.eq(login))
+        .first::<OwnerApproval>(conn)
+        .optional()?;
+    if let Some(approval) = pending {
+        return Ok(approval)
+    }
+
+    let new_approval = NewOwnerApproval {
+        crate_id: krate.id,
+        action: action.as_str(),
+        login: login,
+        requested_by: user.id,
+    };
+    let approval = diesel::insert(&new_approval).into(owner_approvals::table)
+        .get_result::<OwnerApproval>(conn)?;
+    audit::record(conn, user.id, "request_owner_change", Some(&krate.name),
+                  &approval.describe())?;
+    Ok(approval)
+}
+
+/// Saves the expiry of the crate's approvals which waited too long.
+fn expire_stale(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<()> {
+    let cutoff = ::now() + Duration::days(-EXPIRY_DAYS);
+    let stale = owner_approvals::table
+        .filter(owner_approvals::crate_id.eq(crate_id))
+        .filter(owner_approvals::state.eq(ApprovalState::Pending.as_str()))
+        .filter(owner_approvals::created_at.le(cutoff));
+    diesel::update(stale)
+        .set((owner_approvals::state.eq(ApprovalState::Expired.as_str()),
+              owner_approvals::resolved_at.eq(Some(::now()))))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Loads the crate named in the request, failing unless the current user
+/// has full rights to it.
+fn owned_crate(req: &Request, conn: &InstrumentedConnection) -> CargoResult<Crate> {
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(conn)?;
+    let owners = krate.owners(conn)?;
+    if request_rights(req, conn, &owners)? < Rights::Full {
+        return Err(human("only owners can see and approve changes to the owners \
+                          of a crate"))
+    }
+    Ok(krate)
+}
+
+/// Handles the `GET /crates/:crate_id/owner_approvals` route.
+///
+/// Lists the owner changes asked for, newest first.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let krate = owned_crate(req, &conn)?;
+    expire_stale(&conn, krate.id)?;
+    let approvals = owner_approvals::table
+        .filter(owner_approvals::crate_id.eq(krate.id))
+        .order(owner_approvals::id.desc())
+        .load::<OwnerApproval>(&*conn)?;
+    let approvals = encode_all(&conn, approvals)?;
+
+    #[derive(RustcEncodable)]
+    struct R { approvals: Vec<EncodableOwnerApproval> }
+    Ok(req.json(&R { approvals: approvals }))
+}
+
+/// Handles the `PUT /crates/:crate_id/owner_approvals/:approval_id/approve`
+/// route.
+///
+/// Makes the change, on behalf of the owner who asked for it. Changes have
+/// to be approved by another owner than that one.
+pub fn approve(req: &mut Request) -> CargoResult<Response> {
+    resolve(req, ApprovalState::Approved)
+}
+
+/// Handles the `PUT /crates/:crate_id/owner_approvals/:approval_id/reject`
+/// route.
+///
+/// Any owner can reject a change, including the one who asked for it.
+pub fn reject(req: &mut Request) -> CargoResult<Response> {
+    resolve(req, ApprovalState::Rejected)
+}
+
+fn resolve(req: &mut Request, to: ApprovalState) -> CargoResult<Response> {
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = owned_crate(req, &conn)?;
+    let id = req.params()["approval_id"].parse::<i32>().map_err(|_| NotFound)?;
+    expire_stale(&conn, krate.id)?;
+    let approval = owner_approvals::table.find(id)
+        .filter(owner_approvals::crate_id.eq(krate.id))
+        .first::<OwnerApproval>(&*conn)
+        .optional()?
+        .chain_error(|| NotFound)?;
+
+    match approval.state_at(::now())? {
+        ApprovalState::Pending => {}
+        state => {
+            return Err(human(&format_args!("this change is already {}", state.as_str())))
+        }
+    }
+    if to == ApprovalState::Approved && approval.requested_by == user.id {
+        return Err(human("owner changes have to be approved by another owner than \
+                          the one who asked for them"))
+    }
+
+    conn.transaction(|| -> CargoResult<()> {
+        if to == ApprovalState::Approved {
+            apply(req.app(), &conn, &krate, &approval)?;
+        }
+        diesel::update(&approval)
+            .set((owner_approvals::state.eq(to.as_str()),
+                  owner_approvals::resolved_by.eq(Some(user.id)),
+                  owner_approvals::resolved_at.eq(Some(::now()))))
+            .execute(&*conn)?;
+        let action = match to {
+            ApprovalState::Approved => "approve_owner_change",
+            _ => "reject_owner_change",
+        };
+        audit::record(&conn, user.id, action, Some(&krate.name), &approval.describe())
+    })?;
+
+    let approval = owner_approvals::table.find(id).first::<OwnerApproval>(&*conn)?;
+    let approval = encode_all(&conn, vec![approval])?.pop().unwrap();
+
+    #[derive(RustcEncodable)]
+    struct R { approval: EncodableOwnerApproval }
+    Ok(req.json(&R { approval: approval }))
+}
+
+/// Makes an approved change, as the owner who asked for it would have. They
+/// have to still be an owner.
+fn apply(app: &App,
+         conn: &InstrumentedConnection,
+         krate: &Crate,
+         approval: &OwnerApproval) -> CargoResult<()> {
+    let requester = users::table.find(approval.requested_by).first::<User>(conn)?;
+    let owners = krate.owners(conn)?;
+    if owner::rights(app, conn, &owners, &requester)? < Rights::Full {
+        return Err(human(&format_args!("`{}`, who asked for this change, is no longer \
+                                        an owner", requester.gh_login)))
+    }
+
+    let login = &approval.login;
+    match approval.action()? {
+        ApprovalAction::AddOwner => {
+            krate::modify_owner(app, conn, &requester, krate, login, OwnerRole::Owner, true)
+        }
+        ApprovalAction::AddCollaborator => {
+            krate::modify_owner(app, conn, &requester, krate, login,
+                                OwnerRole::Collaborator, true)
+        }
+        ApprovalAction::RemoveOwner => {
+            krate::modify_owner(app, conn, &requester, krate, login, OwnerRole::Owner, false)
+        }
+        ApprovalAction::DisableApproval => {
+            let mut settings = CrateSettings::find(conn, krate.id)?;
+            settings.require_owner_approval = false;
+            settings.save(conn)?;
+            audit::record(conn, requester.id, "update_settings", Some(&krate.name),
+                          "changed require_owner_approval")
+        }
+    }
+}
+
+fn encode_all(conn: &InstrumentedConnection, approvals: Vec<OwnerApproval>)
+              -> CargoResult<Vec<EncodableOwnerApproval>> {
+    use diesel::expression::dsl::any;
+
+    let user_ids = approvals.iter()
+        .flat_map(|a| Some(a.requested_by).into_iter().chain(a.resolved_by))
+        .collect::<Vec<_>>();
+    let logins = users::table.filter(users::id.eq(any(user_ids)))
+        .load::<User>(conn)?
+        .into_iter()
+        .map(|user| (user.id, user.gh_login))
+        .collect::<HashMap<_, _>>();
+
+    let now = ::now();
+    approvals.into_iter().map(|approval| -> CargoResult<EncodableOwnerApproval> {
+        Ok(EncodableOwnerApproval {
+            id: approval.id,
+            state: approval.state_at(now)?.as_str().to_string(),
+            requested_by: logins.get(&approval.requested_by).cloned(),
+            resolved_by: approval.resolved_by.and_then(|id| logins.get(&id).cloned()),
+            created_at: ::encode_time(approval.created_at),
+            expires_at: ::encode_time(approval.expires_at()),
+            resolved_at: approval.resolved_at.map(::encode_time),
+            action: approval.action,
+            login: approval.login,
+        })
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, Timespec};
+    use owner::OwnerRole;
+    use super::*;
+
+    fn approval(state: &str) -> OwnerApproval {
+        OwnerApproval {
+            id: 1,
+            crate_id: 1,
+            action: "add_owner".to_string(),
+            login: "foo".to_string(),
+            requested_by: 1,
+            state: state.to_string(),
+            resolved_by: None,
+            created_at: Timespec::new(1488369600, 0),
+            resolved_at: None,
+        }
+    }
+
+    #[test]
+    fn pending_approvals_expire() {
+        let pending = approval("pending");
+        let created_at = pending.created_at;
+        assert_eq!(pending.state_at(created_at + Duration::days(6)).unwrap(),
+                   ApprovalState::Pending);
+        assert_eq!(pending.state_at(created_at + Duration::days(7)).unwrap(),
+                   ApprovalState::Expired);
+        assert_eq!(approval("approved").state_at(created_at + Duration::days(30)).unwrap(),
+                   ApprovalState::Approved);
+        assert!(approval("bogus").state_at(created_at).is_err());
+    }
+
+    #[test]
+    fn owner_change_actions() {
+        assert_eq!(ApprovalAction::owner_change(OwnerRole::Owner, true),
+                   ApprovalAction::AddOwner);
+        assert_eq!(ApprovalAction::owner_change(OwnerRole::Collaborator, true),
+                   ApprovalAction::AddCollaborator);
+        assert_eq!(ApprovalAction::owner_change(OwnerRole::Collaborator, false),
+                   ApprovalAction::RemoveOwner);
+        let action = ApprovalAction::DisableApproval;
+        assert_eq!(ApprovalAction::parse(action.as_str()), Some(action));
+    }
+}
@@ -0,0 +1,242 @@
+//! Read access to private crates, for self-hosted registries which enable
+//! `Config::private_crates`.
+//!
+//! Owners can mark a crate as private, after which its metadata and downloads
+//! are only available to them and to the users and teams listed in the
+//! `crate_permissions` table. Everyone else is told that the crate doesn't
+//! exist. Note that private crates are still part of the registry index.
+
+use std::io::Read;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::expression::dsl::any;
+use diesel::pg::upsert::*;
+use diesel::prelude::*;
+use rustc_serialize::json;
+
+use app::RequestApp;
+use db::{InstrumentedConnection, RequestTransaction};
+use krate::canon_crate_name;
+use org::{self, Organization};
+use owner::{request_rights, Owner, OwnerKind, Rights, Team, EncodableOwner};
+use schema::*;
+use user::RequestUser;
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, human};
+use {Crate, User};
+
+#[derive(Insertable)]
+#[table_name="crate_permissions"]
+struct NewCratePermission {
+    crate_id: i32,
+    owner_id: i32,
+    owner_kind: i32,
+    created_by: i32,
+}
+
+/// Whether the user making the request may see the crate named `crate_name`.
+///
+/// Crates which don't exist are considered readable, so that handlers can
+/// report them as missing the way they usually do.
+pub fn can_read(req: &Request, crate_name: &str) -> CargoResult<bool> {
+    if !req.app().config.private_crates {
+        return Ok(true)
+    }
+
+    let conn = req.db_conn()?;
+    let krate = crates::table
+        .filter(canon_crate_name(crates::name).eq(canon_crate_name(crate_name)))
+        .select((crates::id, crates::private))
+        .first::<(i32, bool)>(&*conn)
+        .optional()?;
+    match krate {
+        Some((crate_id, true)) => match req.publisher() {
+            Ok(user) => user_can_read(req, &conn, crate_id, user),
+            Err(..) => Ok(false),
+        },
+        _ => Ok(true),
+    }
+}
+
+/// Fails with a 404 if the user making the request may not see the crate
+/// named `crate_name`. Every endpoint exposing something about a single crate
+/// should call this first.
+pub fn ensure_readable(req: &Request, crate_name: &str) -> CargoResult<()> {
+    if can_read(req, crate_name)? {
+        Ok(())
+    } else {
+        Err(Box::new(NotFound))
+    }
+}
+
+/// The private crates among `crate_ids` which the user making the request
+/// may see, for the listings which show them to those who can.
+pub fn readable_private_crates(req: &Request,
+                               conn: &InstrumentedConnection,
+                               crate_ids: &[i32]) -> CargoResult<Vec<i32>> {
+    let private = crates::table
+        .filter(crates::id.eq(any(crate_ids.to_vec())))
+        .filter(crates::private.eq(true))
+        .select(crates::id)
+        .load::<i32>(conn)?;
+    if !req.app().config.private_crates {
+        return Ok(private)
+    }
+    let user = match req.publisher() {
+        Ok(user) => user,
+        Err(..) => return Ok(Vec::new()),
+    };
+    let mut readable = Vec::new();
+    for crate_id in private {
+        if user_can_read(req, conn, crate_id, user)? {
+            readable.push(crate_id);
+        }
+    }
+    Ok(readable)
+}
+
+fn user_can_read(req: &Request,
+                 conn: &InstrumentedConnection,
+                 crate_id: i32,
+                 user: &User) -> CargoResult<bool> {
+    let krate = Crate::all().filter(crates::id.eq(crate_id)).first::<Crate>(conn)?;
+    let owners = krate.publishers(conn)?;
+    if request_rights(req, conn, &owners)? >= Rights::Publish {
+        return Ok(true)
+    }
+    // An organization's token sees no more than the crates it can publish
+    if req.api_token().and_then(|token| token.organization_id).is_some() {
+        return Ok(false)
+    }
+
+    // Readers of an owning organization don't get any rights on its crates,
+    // but they can see them
+    let orgs = owners.into_iter().filter(|owner| owner.kind() == OwnerKind::Org as i32);
+    for owner in orgs.chain(grantees(conn, crate_id)?) {
+        let allowed = match owner {
+            Owner::User(ref other) => other.id == user.id,
+            Owner::Team(ref team) => team.contains_user(req.app(), user)?,
+            Owner::Org(ref org) => org.role_of(conn, user.id)?.is_some(),
+        };
+        if allowed {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}
+
+/// The users, teams and organizations which were given read access to the
+/// crate.
+fn grantees(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<Vec<Owner>> {
+    let granted = |kind: OwnerKind| {
+        crate_permissions::table
+            .select(crate_permissions::owner_id)
+            .filter(crate_permissions::crate_id.eq(crate_id))
+            .filter(crate_permissions::owner_kind.eq(kind as i32))
+    };
+    let users = users::table.filter(users::id.eq_any(granted(OwnerKind::User)))
+        .load::<User>(conn)?
+        .into_iter()
+        .map(Owner::User);
+    let teams = teams::table.filter(teams::id.eq_any(granted(OwnerKind::Team)))
+        .load::<Team>(conn)?
+        .into_iter()
+        .map(Owner::Team);
+    let orgs = organizations::table.filter(organizations::id.eq_any(granted(OwnerKind::Org)))
+        .load::<Organization>(conn)?
+        .into_iter()
+        .map(Owner::Org);
+    Ok(users.chain(teams).chain(orgs).collect())
+}
+
+/// Loads the crate named in the request, failing unless the current user is
+/// one of its owners with full rights.
+fn owned_crate(req: &mut Request, conn: &InstrumentedConnection) -> CargoResult<(Crate, User)> {
+    let user = req.user()?;
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(conn)?;
+    let owners = krate.owners(conn)?;
+    if request_rights(req, conn, &owners)? < Rights::Full {
+        return Err(human("only owners can change who can see a crate"))
+    }
+    Ok((krate, user.clone()))
+}
+
+/// Handles the `GET /crates/:crate_id/permissions` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let (krate, _) = owned_crate(req, &conn)?;
+    let users = grantees(&conn, krate.id)?
+        .into_iter()
+        .map(Owner::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { users: Vec<EncodableOwner> }
+    Ok(req.json(&R { users: users }))
+}
+
+/// Handles the `PUT /crates/:crate_id/permissions` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "users": ["octocat", "github:rust-lang:core"] }
+/// ```
+pub fn grant(req: &mut Request) -> CargoResult<Response> {
+    modify_permissions(req, true)
+}
+
+/// Handles the `DELETE /crates/:crate_id/permissions` route.
+pub fn revoke(req: &mut Request) -> CargoResult<Response> {
+    modify_permissions(req, false)
+}
+
+fn modify_permissions(req: &mut Request, grant: bool) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request { users: Vec<String> }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+
+    let conn = req.db_conn()?;
+    let (krate, user) = owned_crate(req, &conn)?;
+    conn.transaction(|| {
+        for login in &request.users {
+            let owner = match Owner::find_by_login(&conn, login) {
+                Ok(owner) => owner,
+                Err(..) if grant && login.contains(':') &&
+                            !login.starts_with(org::LOGIN_PREFIX) => {
+                    Owner::Team(Team::create(req.app(), &conn, login, &user)?)
+                }
+                Err(err) => return Err(err),
+            };
+
+            if grant {
+                let permission = NewCratePermission {
+                    crate_id: krate.id,
+                    owner_id: owner.id(),
+                    owner_kind: owner.kind(),
+                    created_by: user.id,
+                };
+                diesel::insert(&permission.on_conflict_do_nothing())
+                    .into(crate_permissions::table)
+                    .execute(&*conn)?;
+            } else {
+                let target = crate_permissions::table
+                    .filter(crate_permissions::crate_id.eq(krate.id))
+                    .filter(crate_permissions::owner_id.eq(owner.id()))
+                    .filter(crate_permissions::owner_kind.eq(owner.kind()));
+                diesel::delete(target).execute(&*conn)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
@@ -0,0 +1,288 @@
+//! Where versions published from CI were built.
+//!
+//! A publish can say which CI provider built the crate file, the URL of the
+//! build and the commit it was built from. All of that is supplied by the
+//! publisher, so a provenance is only marked as verified, and its version as
+//! a `verified_build`, when:
+//!
+//! * the crate's `repository` was verified by one of its owners (see
+//!   `krate::verify_repository`),
+//! * the build is one of that repository's builds, and
+//! * the CI provider confirms the build exists and ran for the provenance's
+//!   commit.
+//!
+//! Only GitHub Actions is asked so far, provenances of other providers are
+//! recorded but never verified. The registry still can't check that the
+//! build really produced the crate file.
+
+use conduit::{Request, Response};
+use diesel;
+use diesel::prelude::*;
+use time::Timespec;
+use url::Url;
+
+use app::App;
+use db::{InstrumentedConnection, RequestTransaction};
+use http;
+use schema::*;
+use upload::Provenance;
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, ChainError, human};
+use version::version_and_crate;
+use {Crate, User};
+
+#[derive(Queryable)]
+pub struct VersionProvenance {
+    pub version_id: i32,
+    pub ci_provider: String,
+    pub build_url: String,
+    pub commit_sha: String,
+    pub verified: bool,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="version_provenance"]
+struct NewVersionProvenance<'a> {
+    version_id: i32,
+    ci_provider: &'a str,
+    build_url: &'a str,
+    commit_sha: &'a str,
+    verified: bool,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableProvenance {
+    pub ci_provider: String,
+    pub build_url: String,
+    pub commit_sha: String,
+    /// Whether the CI provider confirmed the build, see the module docs
+    pub verified: bool,
+    pub created_at: String,
+}
+
+/// Checks that a provenance is well formed before anything is published.
+pub fn validate(provenance: &Provenance) -> CargoResult<()> {
+    if provenance.ci_provider.trim().is_empty() {
+        return Err(human("the provenance needs a CI provider"))
+    }
+    match Url::parse(&provenance.build_url) {
+        Ok(ref url) if url.scheme() == "https" => {}
+        _ => return Err(human(&format_args!("the provenance's build url `{}` must be an \
+                                             https url", provenance.build_url))),
+    }
+    let sha = &provenance.commit_sha;
+    if sha.len() != 40 || !sha.chars().all(|c| c.is_digit(16)) {
+        return Err(human(&format_args!("the provenance's commit `{}` must be a full, \
+                                        40 character commit hash", sha)))
+    }
+    Ok(())
+}
+
+/// Whether the build of a version of `krate` is confirmed by its CI
+/// provider. GitHub is asked on behalf of `publisher`.
+pub fn verify(app: &App, publisher: &User, provenance: &Provenance, krate: &Crate) -> bool {
+    if !krate.repository_verified {
+        return false
+    }
+    if !matches_repository(provenance, krate.repository.as_ref().map(|s| &**s)) {
+        return false
+    }
+    match &provenance.ci_provider[..] {
+        "github-actions" => {
+            match github_run_commit(app, publisher, provenance) {
+                Ok(Some(sha)) => sha.eq_ignore_ascii_case(&provenance.commit_sha),
+                Ok(None) => false,
+                Err(e) => {
+                    println!("failed to look up the build {}: {}", provenance.build_url, e);
+                    false
+                }
+            }
+        }
+        _ => false,
+    }
+}
+
+/// The commit a GitHub Actions run was for, or `None` if GitHub doesn't
+/// know of the run.
+fn github_run_commit(app: &App,
+                     publisher: &User,
+                     provenance: &Provenance) -> CargoResult<Option<String>> {
+    let build = Url::parse(&provenance.build_url)?;
+    let path = {
+        // https://github.com/<owner>/<repo>/actions/runs/<id>, as matched
+        // by `matches_repository`
+        let segments = build.path_segments()
+            .map(|s| s.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+            .unwrap_or_else(Vec::new);
+        if segments.len() < 5 || !segments[4].chars().all(|c| c.is_digit(10)) {
+            return Ok(None)
+        }
+        format!("/repos/{}/{}/actions/runs/{}", segments[0], segments[1], segments[4])
+    };
+
+    #[derive(RustcDecodable)]
+    struct Run { head_sha: String }
+
+    let token = http::token(publisher.gh_access_token.clone());
+    let (mut handle, data) = http::github(app, &path, &token)?;
+    if handle.response_code().unwrap() == 404 {
+        return Ok(None)
+    }
+    let run: Run = http::parse_github_response(handle, &data)?;
+    Ok(Some(run.head_sha))
+}
+
+/// Whether the build the provenance points to is one of `repository`'s,
+/// going by the urls alone.
+///
+/// Only builds of `github-actions`, `gitlab-ci` and `travis-ci` can be
+/// matched with a repository, other providers never match.
+pub fn matches_repository(provenance: &Provenance, repository: Option<&str>) -> bool {
+    let repository = match repository.and_then(repository_path) {
+        Some(repository) => repository,
+        None => return false,
+    };
+    let build = match Url::parse(&provenance.build_url) {
+        Ok(url) => url,
+        Err(..) => return false,
+    };
+    let segments = build.path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+        .unwrap_or_else(Vec::new);
+    let (host, repository_host) = (build.host_str().unwrap_or(""), &repository.0[..]);
+    match &provenance.ci_provider[..] {
+        // https://github.com/<owner>/<repo>/actions/runs/<id>
+        "github-actions" => {
+            host == "github.com" && repository_host == "github.com" &&
+                segments.len() >= 5 && segments[2] == "actions" && segments[3] == "runs" &&
+                same_repository(&segments[..2], &repository.1)
+        }
+        // https://gitlab.com/<group>/.../<project>/-/jobs/<id>
+        "gitlab-ci" => {
+            let dash = segments.iter().position(|s| *s == "-").unwrap_or(segments.len());
+            host == "gitlab.com" && repository_host == "gitlab.com" &&
+                segments.len() > dash + 2 &&
+                (segments[dash + 1] == "jobs" || segments[dash + 1] == "pipelines") &&
+                same_repository(&segments[..dash], &repository.1)
+        }
+        // https://travis-ci.org/<owner>/<repo>/builds/<id>, for GitHub repositories
+        "travis-ci" => {
+            (host == "travis-ci.org" || host == "travis-ci.com") &&
+                repository_host == "github.com" && segments.len() >= 4 &&
+                (segments[2] == "builds" || segments[2] == "jobs") &&
+                same_repository(&segments[..2], &repository.1)
+        }
+        _ => false,
+    }
+}
+
+/// The host and the path segments of a repository url, lowercased and
+/// without a `.git` suffix.
+pub fn repository_path(repository: &str) -> Option<(String, Vec<String>)> {
+    let url = match Url::parse(repository) {
+        Ok(url) => url,
+        Err(..) => return None,
+    };
+    let host = match url.host_str() {
+        Some(host) => host.trim_left_matches("www.").to_lowercase(),
+        None => return None,
+    };
+    let mut segments = match url.path_segments() {
+        Some(segments) => segments.filter(|s| !s.is_empty())
+            .map(|s| s.to_lowercase())
+            .collect::<Vec<_>>(),
+        None => return None,
+    };
+    if let Some(last) = segments.last_mut() {
+        if last.ends_with(".git") {
+            let len = last.len() - 4;
+            last.truncate(len);
+        }
+    }
+    Some((host, segments))
+}
+
+fn same_repository(build: &[&str], repository: &[String]) -> bool {
+    !build.is_empty() && build.len() == repository.len() &&
+        build.iter().zip(repository).all(|(a, b)| a.to_lowercase() == *b)
+}
+
+/// Stores the provenance of a version being published, marking it as a
+/// verified build if `verify` confirmed the provenance.
+pub fn record(conn: &InstrumentedConnection,
+              version_id: i32,
+              provenance: &Provenance,
+              verified: bool) -> CargoResult<()> {
+    let new = NewVersionProvenance {
+        version_id: version_id,
+        ci_provider: &provenance.ci_provider,
+        build_url: &provenance.build_url,
+        commit_sha: &provenance.commit_sha,
+        verified: verified,
+    };
+    diesel::insert(&new).into(version_provenance::table).execute(conn)?;
+    if verified {
+        diesel::update(versions::table.find(version_id))
+            .set(versions::verified_build.eq(true))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/:version/provenance` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let provenance = version_provenance::table.find(version.id)
+        .first::<VersionProvenance>(&*conn)
+        .optional()?
+        .chain_error(|| NotFound)?;
+
+    #[derive(RustcEncodable)]
+    struct R { provenance: EncodableProvenance }
+    Ok(req.json(&R {
+        provenance: EncodableProvenance {
+            ci_provider: provenance.ci_provider,
+            build_url: provenance.build_url,
+            commit_sha: provenance.commit_sha,
+            verified: provenance.verified,
+            created_at: ::encode_time(provenance.created_at),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches_repository;
+    use upload::Provenance;
+
+    fn provenance(ci_provider: &str, build_url: &str) -> Provenance {
+        Provenance {
+            ci_provider: ci_provider.to_string(),
+            build_url: build_url.to_string(),
+            commit_sha: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        }
+    }
+
+    #[test]
+    fn builds_must_belong_to_the_repository() {
+        let gha = provenance("github-actions",
+                             "https://github.com/rust-lang/Cargo/actions/runs/42");
+        assert!(matches_repository(&gha, Some("https://github.com/rust-lang/cargo")));
+        assert!(matches_repository(&gha, Some("https://github.com/rust-lang/cargo.git")));
+        assert!(!matches_repository(&gha, Some("https://github.com/rust-lang/rust")));
+        assert!(!matches_repository(&gha, Some("https://gitlab.com/rust-lang/cargo")));
+        assert!(!matches_repository(&gha, None));
+
+        let gitlab = provenance("gitlab-ci", "https://gitlab.com/a/b/c/-/jobs/7");
+        assert!(matches_repository(&gitlab, Some("https://gitlab.com/a/b/c")));
+        assert!(!matches_repository(&gitlab, Some("https://gitlab.com/a/b")));
+
+        let travis = provenance("travis-ci", "https://travis-ci.org/foo/bar/builds/1");
+        assert!(matches_repository(&travis, Some("https://github.com/foo/bar")));
+
+        let unknown = provenance("jenkins", "https://ci.example.com/foo/bar/1");
+        assert!(!matches_repository(&unknown, Some("https://github.com/foo/bar")));
+    }
+}
@@ -0,0 +1,127 @@
+//! Quarantine for suspicious publishes.
+//!
+//! A version is quarantined when it's published by a new account and either
+//! its crate is named like a more popular crate or it ships precompiled
+//! binaries. Its file is uploaded and it's saved like any other version, but
+//! it's left out of the index and can't be downloaded until an admin approves
+//! it through `PUT /admin/quarantine/:version_id/approve`. Rejected versions
+//! stay quarantined and are yanked.
+//!
+//! Deployments can also have versions quarantined based on what the
+//! scanners find in them, see the `scanner` module.
+
+use diesel;
+use diesel::prelude::*;
+use diesel::types::{Float, Text};
+use rustc_serialize::json;
+use time::{self, Duration, Timespec};
+
+use db::InstrumentedConnection;
+use git;
+use scanner;
+use schema::*;
+use util::{CargoResult, TarballFile};
+use {Crate, Version};
+
+/// Versions of crates named this similarly to one of the most downloaded
+/// crates, which is also more popular than them, are suspicious. Similarity
+/// is measured by `pg_trgm`'s `similarity`.
+pub const SIMILAR_NAME_THRESHOLD: f32 = 0.6;
+/// How many of the most downloaded crates new crate names are compared to.
+pub const POPULAR_CRATES: i64 = 500;
+/// Publishers whose first version was published less than this many days
+/// ago count as new.
+pub const NEW_PUBLISHER_DAYS: i32 = 14;
+
+sql_function!(similarity, similarity_t, (a: Text, b: Text) -> Float);
+
+#[derive(Clone, Debug, Queryable)]
+pub struct QuarantinedVersion {
+    pub version_id: i32,
+    pub reasons: Vec<String>,
+    /// The JSON of the index entry, which is added once the version is
+    /// approved
+    pub index_entry: String,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="quarantined_versions"]
+struct NewQuarantinedVersion<'a> {
+    version_id: i32,
+    reasons: Vec<String>,
+    index_entry: &'a str,
+}
+
+/// Why a new version of `krate` published by `user_id` should be held back,
+/// if it should be.
+pub fn reasons(conn: &InstrumentedConnection,
+               krate: &Crate,
+               user_id: i32,
+               files: &[TarballFile]) -> CargoResult<Vec<String>> {
+    if !is_new_publisher(conn, user_id)? {
+        return Ok(Vec::new())
+    }
+
+    let mut reasons = Vec::new();
+    if let Some(similar) = similar_popular_crate(conn, krate)? {
+        reasons.push(format!("its name is similar to `{}`", similar));
+    }
+    let binaries = scanner::binaries(files);
+    if !binaries.is_empty() {
+        reasons.push(format!("it contains precompiled binaries: {}", binaries.join(", ")));
+    }
+    if !reasons.is_empty() {
+        reasons.insert(0, "it was published by a new account".to_string());
+    }
+    Ok(reasons)
+}
+
+fn is_new_publisher(conn: &InstrumentedConnection, user_id: i32) -> CargoResult<bool> {
+    use diesel::expression::dsl::exists;
+
+    let cutoff = time::now_utc().to_timespec() - Duration::days(NEW_PUBLISHER_DAYS as i64);
+    let earlier = versions::table
+        .filter(versions::published_by.eq(user_id))
+        .filter(versions::created_at.lt(cutoff));
+    let established = diesel::select(exists(earlier)).get_result::<bool>(conn)?;
+    Ok(!established)
+}
+
+/// The most similarly named of the most downloaded crates, if it's similar
+/// enough and more popular than `krate`.
+pub fn similar_popular_crate(conn: &InstrumentedConnection,
+                             krate: &Crate) -> CargoResult<Option<String>> {
+    let popular = crates::table
+        .select(crates::id)
+        .order(crates::downloads.desc())
+        .limit(POPULAR_CRATES);
+    let similar = crates::table
+        .select(crates::name)
+        .filter(crates::id.eq_any(popular))
+        .filter(crates::downloads.gt(krate.downloads))
+        .filter(similarity(crates::name, &*krate.name).ge(SIMILAR_NAME_THRESHOLD))
+        .order(similarity(crates::name, &*krate.name).desc())
+        .first::<String>(conn)
+        .optional()?;
+    Ok(similar)
+}
+
+/// Holds `version` back until an admin approves it. `entry` is added to the
+/// index then.
+pub fn hold(conn: &InstrumentedConnection,
+            version: &Version,
+            reasons: &[String],
+            entry: &git::Crate) -> CargoResult<()> {
+    diesel::update(versions::table.find(version.id))
+        .set(versions::quarantined.eq(true))
+        .execute(conn)?;
+    let index_entry = json::encode(entry).unwrap();
+    let held = NewQuarantinedVersion {
+        version_id: version.id,
+        reasons: reasons.to_vec(),
+        index_entry: &index_entry,
+    };
+    diesel::insert(&held).into(quarantined_versions::table).execute(conn)?;
+    Ok(())
+}
@@ -0,0 +1,431 @@
+//! READMEs rendered from the crate files of versions.
+//!
+//! New versions have their README rendered when they're published. Versions
+//! published before that are rendered by a backfill, which an admin starts
+//! with `PUT /admin/readme_backfill` and the `render-readmes` binary works
+//! through: versions are rendered in order of id, `batch_size` at a time and
+//! pausing `delay_ms` between crate files so as not to hammer the storage.
+//! Progress is saved after every version so that the backfill resumes where
+//! it stopped if the binary is restarted, and only one instance of the binary
+//! works through it at a time.
+
+use std::io::Read;
+use std::time::Duration;
+
+use ammonia;
+use conduit::{Request, Response};
+use diesel;
+use diesel::expression::dsl::sql;
+use diesel::prelude::*;
+use diesel::types::{Bool, Nullable, Timestamp};
+use pulldown_cmark::{html as cmark_html, Parser};
+use rustc_serialize::json;
+use time::Timespec;
+use toml;
+
+use admin;
+use audit;
+use db::{InstrumentedConnection, RequestTransaction};
+use files::package_path;
+use schema::*;
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, ChainError, TarballFile, human};
+use version::version_and_crate;
+
+/// Files which are looked for at the root of a package, in order.
+const README_NAMES: &'static [&'static str] = &[
+    "readme.md",
+    "readme.markdown",
+    "readme",
+    "readme.txt",
+];
+
+/// READMEs larger than this aren't rendered.
+const MAX_README_SIZE: u64 = 512 * 1024;
+
+const MAX_BATCH_SIZE: i32 = 1000;
+
+/// The first key of the advisory lock held by whoever works through the
+/// backfill. Keys starting with 1 and 2 are taken by publishes and
+/// migrations.
+const BACKFILL_LOCK_NAMESPACE: i32 = 3;
+
+#[derive(Clone, Debug, Queryable)]
+pub struct VersionReadme {
+    pub version_id: i32,
+    /// The path of the README in the package
+    pub path: String,
+    pub html: String,
+    pub rendered_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="version_readmes"]
+struct NewVersionReadme<'a> {
+    version_id: i32,
+    path: &'a str,
+    html: &'a str,
+}
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name="readme_backfills"]
+pub struct ReadmeBackfill {
+    pub id: i32,
+    pub requested_by: i32,
+    pub batch_size: i32,
+    pub delay_ms: i32,
+    /// Every version up to this one has been tried
+    pub last_version_id: i32,
+    pub rendered: i32,
+    /// Versions whose crate file couldn't be fetched or unpacked
+    pub failed: i32,
+    pub created_at: Timespec,
+    pub finished_at: Option<Timespec>,
+}
+
+#[derive(Insertable)]
+#[table_name="readme_backfills"]
+struct NewReadmeBackfill {
+    requested_by: i32,
+    batch_size: i32,
+    delay_ms: i32,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableReadme {
+    pub path: String,
+    pub html: String,
+    pub rendered_at: String,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableReadmeBackfill {
+    pub id: i32,
+    pub batch_size: i32,
+    pub delay_ms: i32,
+    pub last_version_id: i32,
+    pub rendered: i32,
+    pub failed: i32,
+    /// How many versions are left to try
+    pub remaining: i64,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+}
+
+/// Renders the contents of the README at `path` to HTML.
+///
+/// Markdown READMEs, going by their extension, are rendered and then
+/// sanitized so that they can't run scripts or restyle the page they're
+/// shown on. Other READMEs are escaped and shown as preformatted text.
+pub fn render(contents: &str, path: &str) -> String {
+    let lower = path.to_lowercase();
+    if lower.ends_with(".md") || lower.ends_with(".markdown") {
+        let mut html = String::with_capacity(contents.len() * 3 / 2);
+        cmark_html::push_html(&mut html, Parser::new(contents));
+        return ammonia::clean(&html)
+    }
+
+    let mut html = String::with_capacity(contents.len() + 11);
+    html.push_str("<pre>");
+    for c in contents.chars() {
+        match c {
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '&' => html.push_str("&amp;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html.push_str("</pre>");
+    html
+}
+
+/// The README of an unpacked crate file, if it has one: the file named by
+/// `package.readme` in its manifest, or else the first of `README_NAMES` at
+/// the root of the package.
+pub fn find(files: &[TarballFile]) -> Option<&TarballFile> {
+    if let Some(path) = manifest_readme(files) {
+        return files.iter().find(|file| package_path(&file.path) == path)
+    }
+    README_NAMES.iter().filter_map(|name| {
+        files.iter().find(|file| package_path(&file.path).to_lowercase() == *name)
+    }).next()
+}
+
+/// The `package.readme` path of the package's `Cargo.toml`, relative to the
+/// root of the package.
+fn manifest_readme(files: &[TarballFile]) -> Option<String> {
+    let manifest = match files.iter().find(|file| package_path(&file.path) == "Cargo.toml") {
+        Some(file) => String::from_utf8_lossy(&file.contents).into_owned(),
+        None => return None,
+    };
+    let manifest = match toml::Parser::new(&manifest).parse() {
+        Some(manifest) => manifest,
+        None => return None,
+    };
+    manifest.get("package")
+        .and_then(|package| package.lookup("readme"))
+        .and_then(toml::Value::as_str)
+        .map(|path| path.trim_left_matches("./").to_string())
+}
+
+/// Renders and stores the README of a version, replacing the one rendered
+/// before. Returns whether the version has a README which could be rendered.
+pub fn render_version(conn: &InstrumentedConnection, version_id: i32, files: &[TarballFile])
+                      -> CargoResult<bool> {
+    let file = match find(files) {
+        Some(file) if file.size <= MAX_README_SIZE => file,
+        _ => return Ok(false),
+    };
+    let path = package_path(&file.path);
+    let contents = String::from_utf8_lossy(&file.contents);
+    let html = render(&contents, path);
+    let new = NewVersionReadme {
+        version_id: version_id,
+        path: path,
+        html: &html,
+    };
+    diesel::delete(version_readmes::table.find(version_id)).execute(conn)?;
+    diesel::insert(&new).into(version_readmes::table).execute(conn)?;
+    Ok(true)
+}
+
+impl ReadmeBackfill {
+    /// The backfill which isn't finished yet, if any.
+    pub fn active(conn: &InstrumentedConnection) -> CargoResult<Option<ReadmeBackfill>> {
+        let backfill = readme_backfills::table
+            .filter(readme_backfills::finished_at.is_null())
+            .first(conn)
+            .optional()?;
+        Ok(backfill)
+    }
+
+    /// How many versions the backfill still has to try.
+    pub fn remaining(&self, conn: &InstrumentedConnection) -> CargoResult<i64> {
+        let remaining = versions::table
+            .filter(versions::id.gt(self.last_version_id))
+            .count()
+            .get_result(conn)?;
+        Ok(remaining)
+    }
+
+    pub fn encodable(self, remaining: i64) -> EncodableReadmeBackfill {
+        EncodableReadmeBackfill {
+            id: self.id,
+            batch_size: self.batch_size,
+            delay_ms: self.delay_ms,
+            last_version_id: self.last_version_id,
+            rendered: self.rendered,
+            failed: self.failed,
+            remaining: remaining,
+            created_at: ::encode_time(self.created_at),
+            finished_at: self.finished_at.map(::encode_time),
+        }
+    }
+}
+
+/// Makes this connection the one working through the backfill, unless another
+/// one already is. The lock is released when the connection is closed.
+pub fn try_lock_backfill(conn: &InstrumentedConnection) -> CargoResult<bool> {
+    let try_lock = format!("pg_try_advisory_lock({}, 0)", BACKFILL_LOCK_NAMESPACE);
+    Ok(diesel::select(sql::<Bool>(&try_lock)).get_result::<bool>(conn)?)
+}
+
+/// Renders the READMEs of the next batch of versions of the active backfill,
+/// finishing it once every version was tried. `fetch` returns the unpacked
+/// crate file of a crate's version and `pause` is called between crate
+/// files. Returns the backfill's progress, or `None` if there's no backfill
+/// to work on.
+pub fn run_batch(conn: &InstrumentedConnection,
+                 fetch: &Fn(&str, &str) -> CargoResult<Vec<TarballFile>>,
+                 pause: &Fn(Duration))
+                 -> CargoResult<Option<ReadmeBackfill>> {
+    let mut backfill = match ReadmeBackfill::active(conn)? {
+        Some(backfill) => backfill,
+        None => return Ok(None),
+    };
+    let batch = versions::table.inner_join(crates::table)
+        .filter(versions::id.gt(backfill.last_version_id))
+        .order(versions::id)
+        .limit(backfill.batch_size as i64)
+        .select((versions::id, crates::name, versions::num))
+        .load::<(i32, String, String)>(conn)?;
+    if batch.is_empty() {
+        let backfill = diesel::update(&backfill)
+            .set(readme_backfills::finished_at.eq(sql::<Nullable<Timestamp>>("now()")))
+            .get_result(conn)?;
+        return Ok(Some(backfill))
+    }
+
+    for (i, (version_id, name, num)) in batch.into_iter().enumerate() {
+        if i > 0 {
+            pause(Duration::from_millis(backfill.delay_ms as u64));
+        }
+        let rendered = fetch(&name, &num).and_then(|files| {
+            render_version(conn, version_id, &files)
+        });
+        match rendered {
+            Ok(true) => backfill.rendered += 1,
+            Ok(false) => {}
+            Err(e) => {
+                println!("unable to render the README of {} {}: {}", name, num, e);
+                backfill.failed += 1;
+            }
+        }
+        backfill.last_version_id = version_id;
+        diesel::update(&backfill).set((
+            readme_backfills::last_version_id.eq(backfill.last_version_id),
+            readme_backfills::rendered.eq(backfill.rendered),
+            readme_backfills::failed.eq(backfill.failed),
+        )).execute(conn)?;
+    }
+    Ok(Some(backfill))
+}
+
+/// Handles the `GET /crates/:crate_id/:version/readme` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let readme = version_readmes::table.find(version.id)
+        .first::<VersionReadme>(&*conn)
+        .optional()?
+        .chain_error(|| NotFound)?;
+
+    #[derive(RustcEncodable)]
+    struct R { readme: EncodableReadme }
+    Ok(req.json(&R {
+        readme: EncodableReadme {
+            path: readme.path,
+            html: readme.html,
+            rendered_at: ::encode_time(readme.rendered_at),
+        },
+    }))
+}
+
+#[derive(RustcDecodable)]
+struct BackfillRequest {
+    batch_size: Option<i32>,
+    delay_ms: Option<i32>,
+}
+
+/// Handles the `PUT /admin/readme_backfill` route.
+///
+/// Starts rendering the READMEs of every version, unless a backfill is
+/// already running.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "batch_size": 100, "delay_ms": 200 }
+/// ```
+pub fn start_backfill(req: &mut Request) -> CargoResult<Response> {
+    let admin = admin::require_admin(req)?;
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    let request: BackfillRequest = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let batch_size = request.batch_size.unwrap_or(100);
+    let delay_ms = request.delay_ms.unwrap_or(200);
+    if batch_size < 1 || batch_size > MAX_BATCH_SIZE {
+        return Err(human(&format_args!("the batch size must be between 1 and {}",
+                                       MAX_BATCH_SIZE)))
+    }
+    if delay_ms < 0 {
+        return Err(human("the delay can't be negative"))
+    }
+
+    let conn = req.db_conn()?;
+    let backfill = conn.transaction(|| -> CargoResult<ReadmeBackfill> {
+        if ReadmeBackfill::active(&conn)?.is_some() {
+            return Err(human("a README backfill is already running"))
+        }
+        let new = NewReadmeBackfill {
+            requested_by: admin.id,
+            batch_size: batch_size,
+            delay_ms: delay_ms,
+        };
+        let backfill = diesel::insert(&new).into(readme_backfills::table)
+            .get_result::<ReadmeBackfill>(&*conn)?;
+        let details = format!("backfill {}", backfill.id);
+        audit::record(&conn, admin.id, "start_readme_backfill", None, &details)?;
+        Ok(backfill)
+    })?;
+    let remaining = backfill.remaining(&conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { backfill: EncodableReadmeBackfill }
+    Ok(req.json(&R { backfill: backfill.encodable(remaining) }))
+}
+
+/// Handles the `GET /admin/readme_backfill` route.
+///
+/// Shows the progress of the running backfill, or of the latest one if
+/// none is running.
+pub fn backfill_progress(req: &mut Request) -> CargoResult<Response> {
+    admin::require_admin(req)?;
+    let conn = req.db_conn()?;
+    let backfill = match ReadmeBackfill::active(&conn)? {
+        Some(backfill) => backfill,
+        None => {
+            readme_backfills::table.order(readme_backfills::id.desc())
+                .first::<ReadmeBackfill>(&*conn)
+                .optional()?
+                .chain_error(|| NotFound)?
+        }
+    };
+    let remaining = backfill.remaining(&conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { backfill: EncodableReadmeBackfill }
+    Ok(req.json(&R { backfill: backfill.encodable(remaining) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use util::TarballFile;
+    use super::{find, render};
+
+    fn file(path: &str, contents: &[u8]) -> TarballFile {
+        TarballFile {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            contents: contents.to_vec(),
+        }
+    }
+
+    #[test]
+    fn readmes_are_found_at_the_root() {
+        let files = [
+            file("foo-0.1.0/src/README.md", b"nested"),
+            file("foo-0.1.0/README", b"plain"),
+            file("foo-0.1.0/Readme.md", b"markdown"),
+        ];
+        assert_eq!(find(&files).unwrap().contents, b"markdown");
+        assert!(find(&files[..1]).is_none());
+    }
+
+    #[test]
+    fn the_manifest_names_the_readme() {
+        let files = [
+            file("foo-0.1.0/Cargo.toml",
+                 b"[package]\nname = \"foo\"\nreadme = \"docs/intro.md\"\n"),
+            file("foo-0.1.0/README.md", b"root"),
+            file("foo-0.1.0/docs/intro.md", b"intro"),
+        ];
+        assert_eq!(find(&files).unwrap().contents, b"intro");
+    }
+
+    #[test]
+    fn markdown_readmes_are_sanitized() {
+        let html = render("# foo\n\n<script>alert(1)</script>", "README.md");
+        assert!(html.contains("<h1>foo</h1>"), "{}", html);
+        assert!(!html.contains("<script"), "{}", html);
+    }
+
+    #[test]
+    fn other_readmes_are_escaped() {
+        assert_eq!(render("# foo <script>&", "README"), "<pre># foo &lt;script&gt;&amp;</pre>");
+    }
+}
@@ -0,0 +1,130 @@
+//! Abuse reports users file about crates, e.g. for malware, squatting or
+//! impersonation.
+//!
+//! Signed in users report a crate with `PUT /crates/:crate_id/reports`, and
+//! can only have one open report about each crate. Admins go through the
+//! open reports with `GET /admin/reports`, oldest first, and close them with
+//! `PUT /admin/reports/:report_id/resolve`, which is recorded in the audit
+//! log.
+
+use std::io::Read;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::expression::dsl::exists;
+use diesel::prelude::*;
+use rustc_serialize::json;
+use time::Timespec;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use permission;
+use schema::*;
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, human};
+use Crate;
+
+const MAX_REASON_LENGTH: usize = 1000;
+
+#[derive(Clone, Debug, Queryable)]
+pub struct AbuseReport {
+    pub id: i32,
+    pub crate_id: i32,
+    pub reported_by: i32,
+    pub reason: String,
+    pub created_at: Timespec,
+    pub resolved_at: Option<Timespec>,
+    pub resolved_by: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name="abuse_reports"]
+struct NewAbuseReport<'a> {
+    crate_id: i32,
+    reported_by: i32,
+    reason: &'a str,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableAbuseReport {
+    pub id: i32,
+    pub crate_name: String,
+    pub reported_by: String,
+    pub reason: String,
+    pub created_at: String,
+}
+
+impl AbuseReport {
+    /// The open report with the id, if there is one.
+    pub fn find_open(conn: &InstrumentedConnection, id: i32) -> CargoResult<Option<AbuseReport>> {
+        let report = abuse_reports::table.find(id)
+            .filter(abuse_reports::resolved_at.is_null())
+            .first(conn)
+            .optional()?;
+        Ok(report)
+    }
+
+    pub fn encodable(self, crate_name: String, reported_by: String) -> EncodableAbuseReport {
+        EncodableAbuseReport {
+            id: self.id,
+            crate_name: crate_name,
+            reported_by: reported_by,
+            reason: self.reason,
+            created_at: ::encode_time(self.created_at),
+        }
+    }
+}
+
+/// Handles the `PUT /crates/:crate_id/reports` route.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "reason": "the build script downloads and runs a binary" }
+/// ```
+pub fn create(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request {
+        reason: String,
+    }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let reason = request.reason.trim();
+    if reason.is_empty() {
+        return Err(human("a reason is required to report a crate"))
+    }
+    if reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(human(&format_args!("the reason can't be longer than {} characters",
+                                       MAX_REASON_LENGTH)))
+    }
+
+    let crate_name = &req.params()["crate_id"];
+    permission::ensure_readable(req, crate_name)?;
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
+    conn.transaction(|| {
+        let open = abuse_reports::table
+            .filter(abuse_reports::crate_id.eq(krate.id))
+            .filter(abuse_reports::reported_by.eq(user.id))
+            .filter(abuse_reports::resolved_at.is_null());
+        if diesel::select(exists(open)).get_result::<bool>(&*conn)? {
+            return Err(human(&format_args!("you already reported `{}`, an admin will \
+                                            look into it", krate.name)))
+        }
+        let new = NewAbuseReport {
+            crate_id: krate.id,
+            reported_by: user.id,
+            reason: reason,
+        };
+        diesel::insert(&new).into(abuse_reports::table).execute(&*conn)?;
+        Ok(())
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
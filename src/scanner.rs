@@ -0,0 +1,446 @@
+//! Scanners which look for dangerous content in published crates.
+//!
+//! Every scanner configured in `Config::scanners` runs over the files of each
+//! new version while it's being published, and everything they find is
+//! recorded in `version_findings`. What happens to the publish depends on the
+//! most severe finding and the deployment's `Config::scan_policy`: it can go
+//! ahead with a warning, be quarantined (see the `quarantine` module) or be
+//! rejected outright. Deployments can add their own scanners by implementing
+//! `Scanner`.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use conduit::{Request, Response};
+use diesel;
+use diesel::prelude::*;
+use openssl::hash::{hash, MessageDigest};
+use rustc_serialize::hex::ToHex;
+use time::Timespec;
+
+use db::{InstrumentedConnection, RequestTransaction};
+use schema::*;
+use util::{RequestUtils, CargoResult, TarballFile};
+use version::version_and_crate;
+
+/// The first bytes of executables and static libraries for the platforms
+/// Rust supports: ELF, Mach-O (both byte orders, 32 and 64 bit, and
+/// universal binaries) and `ar` archives. PE files are recognized by
+/// `is_pe`, since `MZ` alone is too common a start of a text file.
+const BINARY_MAGIC: &'static [&'static [u8]] = &[
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    b"!<arch>\n",
+];
+
+/// Things build scripts have no business doing in a crate, since they
+/// usually mean that code is fetched from somewhere at build time.
+const BUILD_SCRIPT_DOWNLOADS: &'static [&'static str] = &[
+    "\"curl\"",
+    "\"wget\"",
+    "Invoke-WebRequest",
+    "reqwest::",
+    "hyper::",
+    "TcpStream::connect",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Only recorded
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(s: &str) -> Option<Severity> {
+        match s {
+            "info" => Some(Severity::Info),
+            "warning" => Some(Severity::Warning),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub scanner: &'static str,
+    pub severity: Severity,
+    /// The file the finding is about, if it's about a single one
+    pub path: Option<String>,
+    pub message: String,
+}
+
+/// Looks for something in the files of a crate which is being published.
+pub trait Scanner: Send + Sync {
+    /// A short name identifying the scanner in the recorded findings.
+    fn name(&self) -> &'static str;
+
+    fn scan(&self, files: &[TarballFile]) -> Vec<Finding>;
+}
+
+/// Finds precompiled executables and libraries.
+pub struct BinaryScanner;
+
+impl Scanner for BinaryScanner {
+    fn name(&self) -> &'static str { "binaries" }
+
+    fn scan(&self, files: &[TarballFile]) -> Vec<Finding> {
+        binaries(files).into_iter().map(|path| Finding {
+            scanner: self.name(),
+            severity: Severity::Warning,
+            path: Some(path.to_string()),
+            message: "precompiled binary".to_string(),
+        }).collect()
+    }
+}
+
+/// Finds build scripts which look like they download code.
+pub struct BuildScriptScanner;
+
+impl Scanner for BuildScriptScanner {
+    fn name(&self) -> &'static str { "build_script" }
+
+    fn scan(&self, files: &[TarballFile]) -> Vec<Finding> {
+        // Build scripts sit next to the manifest, at the root of the package
+        files.iter()
+            .filter(|file| {
+                let mut parts = file.path.split('/');
+                parts.next().is_some() && parts.next() == Some("build.rs") &&
+                    parts.next().is_none()
+            })
+            .filter_map(|file| {
+                let contents = String::from_utf8_lossy(&file.contents);
+                BUILD_SCRIPT_DOWNLOADS.iter()
+                    .find(|pattern| contents.contains(*pattern))
+                    .map(|pattern| Finding {
+                        scanner: self.name(),
+                        severity: Severity::Warning,
+                        path: Some(file.path.clone()),
+                        message: format!("build script looks like it downloads code \
+                                          (uses `{}`)", pattern),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Finds files whose SHA-256 is on a list of known malware.
+pub struct KnownHashScanner {
+    hashes: HashSet<String>,
+}
+
+impl KnownHashScanner {
+    /// `hashes` are hex encoded SHA-256 digests.
+    pub fn new(hashes: HashSet<String>) -> KnownHashScanner {
+        KnownHashScanner {
+            hashes: hashes.into_iter().map(|h| h.trim().to_lowercase()).collect(),
+        }
+    }
+}
+
+impl Scanner for KnownHashScanner {
+    fn name(&self) -> &'static str { "known_malware" }
+
+    fn scan(&self, files: &[TarballFile]) -> Vec<Finding> {
+        if self.hashes.is_empty() {
+            return Vec::new()
+        }
+        files.iter()
+            .filter(|file| {
+                let digest = hash(MessageDigest::sha256(), &file.contents).unwrap();
+                self.hashes.contains(&digest.to_hex())
+            })
+            .map(|file| Finding {
+                scanner: self.name(),
+                severity: Severity::Critical,
+                path: Some(file.path.clone()),
+                message: "file is known malware".to_string(),
+            })
+            .collect()
+    }
+}
+
+/// The scanners every deployment gets, with the SHA-256 digests of known
+/// malware it was given.
+pub fn default_scanners(known_malware: HashSet<String>) -> Vec<Arc<Scanner>> {
+    vec![
+        Arc::new(BinaryScanner),
+        Arc::new(BuildScriptScanner),
+        Arc::new(KnownHashScanner::new(known_malware)),
+    ]
+}
+
+/// Runs all of `scanners` over `files`.
+pub fn scan(scanners: &[Arc<Scanner>], files: &[TarballFile]) -> Vec<Finding> {
+    scanners.iter().flat_map(|scanner| scanner.scan(files)).collect()
+}
+
+/// The paths of the files which look like executables or libraries.
+pub fn binaries(files: &[TarballFile]) -> Vec<&str> {
+    files.iter()
+        .filter(|file| {
+            BINARY_MAGIC.iter().any(|magic| file.contents.starts_with(magic)) ||
+                is_pe(&file.contents)
+        })
+        .map(|file| &file.path[..])
+        .collect()
+}
+
+/// Whether `contents` are a PE file: the `MZ` of the DOS header, followed
+/// by the `PE\0\0` signature at the offset the DOS header ends with.
+fn is_pe(contents: &[u8]) -> bool {
+    if !contents.starts_with(b"MZ") || contents.len() < 0x40 {
+        return false
+    }
+    let offset = (contents[0x3c] as usize) |
+                 (contents[0x3d] as usize) << 8 |
+                 (contents[0x3e] as usize) << 16 |
+                 (contents[0x3f] as usize) << 24;
+    match offset.checked_add(4) {
+        Some(end) if end <= contents.len() => &contents[offset..end] == b"PE\0\0",
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScanAction {
+    /// Publish the version and tell the publisher about the findings
+    Warn,
+    /// Publish the version but hold it back until an admin approves it
+    Quarantine,
+    /// Refuse to publish the version
+    Reject,
+}
+
+impl ScanAction {
+    fn parse(s: &str) -> Option<ScanAction> {
+        match s {
+            "warn" => Some(ScanAction::Warn),
+            "quarantine" => Some(ScanAction::Quarantine),
+            "reject" => Some(ScanAction::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a publish with findings of each severity. Info findings
+/// are only ever recorded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScanPolicy {
+    pub warning: ScanAction,
+    pub critical: ScanAction,
+}
+
+impl ScanPolicy {
+    /// Parses a policy given as e.g. `warning=quarantine,critical=reject`.
+    /// Severities which aren't mentioned keep their default action.
+    pub fn parse(s: &str) -> Option<ScanPolicy> {
+        let mut policy = ScanPolicy::default();
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let mut parts = rule.splitn(2, '=');
+            let severity = parts.next().and_then(|s| Severity::parse(s.trim()));
+            let action = parts.next().and_then(|s| ScanAction::parse(s.trim()));
+            match (severity, action) {
+                (Some(Severity::Warning), Some(action)) => policy.warning = action,
+                (Some(Severity::Critical), Some(action)) => policy.critical = action,
+                _ => return None,
+            }
+        }
+        Some(policy)
+    }
+
+    /// The action for the most severe of `findings`, if any needs one.
+    pub fn action(&self, findings: &[Finding]) -> Option<ScanAction> {
+        findings.iter().filter_map(|finding| match finding.severity {
+            Severity::Info => None,
+            Severity::Warning => Some(self.warning),
+            Severity::Critical => Some(self.critical),
+        }).max()
+    }
+}
+
+impl Default for ScanPolicy {
+    fn default() -> ScanPolicy {
+        ScanPolicy { warning: ScanAction::Warn, critical: ScanAction::Reject }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+pub struct VersionFinding {
+    pub id: i32,
+    pub version_id: i32,
+    pub scanner: String,
+    pub severity: String,
+    pub path: Option<String>,
+    pub message: String,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="version_findings"]
+struct NewVersionFinding<'a> {
+    version_id: i32,
+    scanner: &'a str,
+    severity: &'a str,
+    path: Option<&'a str>,
+    message: &'a str,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableFinding {
+    pub scanner: String,
+    pub severity: String,
+    pub path: Option<String>,
+    pub message: String,
+}
+
+impl Finding {
+    /// How the finding is shown to the publisher.
+    pub fn describe(&self) -> String {
+        match self.path {
+            Some(ref path) => format!("{}: {}", path, self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl VersionFinding {
+    pub fn encodable(self) -> EncodableFinding {
+        EncodableFinding {
+            scanner: self.scanner,
+            severity: self.severity,
+            path: self.path,
+            message: self.message,
+        }
+    }
+}
+
+/// Records the findings on the version they were made for.
+pub fn record(conn: &InstrumentedConnection,
+              version_id: i32, findings: &[Finding]) -> CargoResult<()> {
+    let findings = findings.iter().map(|finding| NewVersionFinding {
+        version_id: version_id,
+        scanner: finding.scanner,
+        severity: finding.severity.as_str(),
+        path: finding.path.as_ref().map(|s| &s[..]),
+        message: &finding.message,
+    }).collect::<Vec<_>>();
+    if !findings.is_empty() {
+        diesel::insert(&findings).into(version_findings::table).execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/:version/findings` route.
+pub fn findings(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+
+    let findings = version_findings::table
+        .filter(version_findings::version_id.eq(version.id))
+        .order(version_findings::id)
+        .load::<VersionFinding>(&*conn)?
+        .into_iter()
+        .map(VersionFinding::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { findings: Vec<EncodableFinding> }
+    Ok(req.json(&R { findings: findings }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use util::TarballFile;
+    use super::*;
+
+    fn file(path: &str, contents: &[u8]) -> TarballFile {
+        TarballFile {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            contents: contents.to_vec(),
+        }
+    }
+
+    fn finding(severity: Severity) -> Finding {
+        Finding { scanner: "test", severity: severity, path: None, message: String::new() }
+    }
+
+    #[test]
+    fn finds_binaries() {
+        let mut pe = vec![0; 0x84];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x80;
+        pe[0x80..].copy_from_slice(b"PE\0\0");
+        let files = vec![
+            file("foo-0.1.0/src/lib.rs", b"pub fn foo() {}\n"),
+            file("foo-0.1.0/bin/helper", b"\x7fELF\x02\x01\x01"),
+            file("foo-0.1.0/lib/helper.lib", b"!<arch>\n/ "),
+            file("foo-0.1.0/README.md", b"MY CRATE"),
+            file("foo-0.1.0/NOTES.md",
+                 b"MZ is where the DOS header starts, these notes are about PE files."),
+            file("foo-0.1.0/bin/helper.exe", &pe),
+        ];
+        assert_eq!(binaries(&files), ["foo-0.1.0/bin/helper", "foo-0.1.0/lib/helper.lib",
+                                      "foo-0.1.0/bin/helper.exe"]);
+    }
+
+    #[test]
+    fn finds_downloading_build_scripts() {
+        let files = vec![
+            file("foo-0.1.0/build.rs", b"Command::new(\"curl\").arg(URL).status();"),
+            file("foo-0.1.0/src/build.rs", b"Command::new(\"curl\")"),
+            file("bar-0.1.0/build.rs", b"fn main() { println!(\"cargo:rustc-link-lib=z\"); }"),
+        ];
+        let findings = BuildScriptScanner.scan(&files);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].path.as_ref().unwrap(), "foo-0.1.0/build.rs");
+    }
+
+    #[test]
+    fn finds_known_hashes() {
+        // The SHA-256 of an empty file
+        let empty = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        let scanner = KnownHashScanner::new(vec![empty.to_string()].into_iter()
+                                                .collect::<HashSet<_>>());
+        let files = vec![file("foo-0.1.0/empty", b""), file("foo-0.1.0/full", b"x")];
+        let findings = scanner.scan(&files);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert_eq!(findings[0].path.as_ref().unwrap(), "foo-0.1.0/empty");
+    }
+
+    #[test]
+    fn policy() {
+        assert_eq!(ScanPolicy::parse(""), Some(ScanPolicy::default()));
+        assert_eq!(ScanPolicy::parse("warning=quarantine"),
+                   Some(ScanPolicy { warning: ScanAction::Quarantine,
+                                     critical: ScanAction::Reject }));
+        assert_eq!(ScanPolicy::parse("info=reject"), None);
+        assert_eq!(ScanPolicy::parse("critical=delete"), None);
+
+        let policy = ScanPolicy::default();
+        assert_eq!(policy.action(&[]), None);
+        assert_eq!(policy.action(&[finding(Severity::Info)]), None);
+        assert_eq!(policy.action(&[finding(Severity::Warning), finding(Severity::Info)]),
+                   Some(ScanAction::Warn));
+        assert_eq!(policy.action(&[finding(Severity::Warning), finding(Severity::Critical)]),
+                   Some(ScanAction::Reject));
+    }
+}
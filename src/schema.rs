@@ -1,5 +1,54 @@
 // This file can be regenerated with `diesel print-schema`
 
+table! {
+    abuse_reports (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        reported_by -> Int4,
+        reason -> Varchar,
+        created_at -> Timestamp,
+        resolved_at -> Nullable<Timestamp>,
+        resolved_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token -> Varchar,
+        name -> Varchar,
+        created_at -> Timestamp,
+        revoked -> Bool,
+        organization_id -> Nullable<Int4>,
+        last_used_at -> Nullable<Timestamp>,
+        last_used_ip -> Nullable<Varchar>,
+        last_used_user_agent -> Nullable<Varchar>,
+        expires_at -> Nullable<Timestamp>,
+        allowed_cidrs -> Array<Varchar>,
+    }
+}
+
+table! {
+    api_token_usage (token_id, date, endpoint) {
+        token_id -> Int4,
+        date -> Date,
+        endpoint -> Varchar,
+        requests -> Int4,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int4,
+        user_id -> Int4,
+        action -> Varchar,
+        crate_name -> Nullable<Varchar>,
+        details -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     badges (crate_id,
     badge_type) {
@@ -20,6 +69,24 @@ table! {
     }
 }
 
+table! {
+    category_stats (category_id, month) {
+        category_id -> Int4,
+        month -> Date,
+        crates -> Int8,
+        downloads -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_aliases (alias) {
+        alias -> Varchar,
+        crate_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     crate_downloads (crate_id, date) {
         crate_id -> Int4,
@@ -37,6 +104,52 @@ table! {
         deleted -> Bool,
         updated_at -> Timestamp,
         owner_kind -> Int4,
+        role -> Int4,
+    }
+}
+
+table! {
+    crate_permissions (crate_id, owner_id, owner_kind) {
+        crate_id -> Int4,
+        owner_id -> Int4,
+        owner_kind -> Int4,
+        created_by -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_settings (crate_id) {
+        crate_id -> Int4,
+        deprecation_notice -> Nullable<Varchar>,
+        webhook_urls -> Array<Varchar>,
+        download_thresholds -> Array<Int4>,
+        spike_alerts -> Bool,
+        require_owner_approval -> Bool,
+        email_alerts -> Bool,
+    }
+}
+
+table! {
+    crate_tag_snapshots (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        keywords -> Array<Varchar>,
+        categories -> Array<Varchar>,
+        replaced_by_version_id -> Nullable<Int4>,
+        replaced_by -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    crate_tombstones (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        previous_owners -> Array<Varchar>,
+        new_owner -> Varchar,
+        moved_to -> Nullable<Varchar>,
+        created_at -> Timestamp,
     }
 }
 
@@ -55,6 +168,14 @@ table! {
         license -> Nullable<Varchar>,
         repository -> Nullable<Varchar>,
         max_upload_size -> Nullable<Int4>,
+        readme_in_search -> Bool,
+        all_yanked -> Bool,
+        unlisted -> Bool,
+        private -> Bool,
+        repository_verified -> Bool,
+        first_published_at -> Nullable<Timestamp>,
+        first_published_by -> Nullable<Int4>,
+        dependents_cnt -> Int4,
     }
 }
 
@@ -88,11 +209,37 @@ table! {
     }
 }
 
+table! {
+    download_alerts (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        version_id -> Nullable<Int4>,
+        kind -> Varchar,
+        threshold -> Nullable<Int4>,
+        date -> Nullable<Date>,
+        downloads -> Int4,
+        created_at -> Timestamp,
+        delivered -> Bool,
+    }
+}
+
 table! {
     follows (user_id,
     crate_id) {
         user_id -> Int4,
         crate_id -> Int4,
+        legal_notifications -> Bool,
+    }
+}
+
+table! {
+    index_subscriptions (id) {
+        id -> Int4,
+        mirror_id -> Int4,
+        callback -> Varchar,
+        secret -> Varchar,
+        last_notified_seq -> Int4,
+        created_at -> Timestamp,
     }
 }
 
@@ -111,12 +258,130 @@ table! {
     }
 }
 
+table! {
+    mirror_downloads (mirror_id,
+    date) {
+        mirror_id -> Int4,
+        date -> Date,
+        downloads -> Int4,
+    }
+}
+
+table! {
+    mirrors (id) {
+        id -> Int4,
+        name -> Varchar,
+        token -> Varchar,
+        created_at -> Timestamp,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        crate_id -> Int4,
+        kind -> Varchar,
+        details -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    organization_members (organization_id, user_id) {
+        organization_id -> Int4,
+        user_id -> Int4,
+        role -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    organizations (id) {
+        id -> Int4,
+        login -> Varchar,
+        name -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    owner_approvals (id) {
+        id -> Int4,
+        crate_id -> Int4,
+        action -> Varchar,
+        login -> Varchar,
+        requested_by -> Int4,
+        state -> Varchar,
+        resolved_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+        resolved_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    publish_idempotency_keys (user_id,
+    key) {
+        user_id -> Int4,
+        key -> Varchar,
+        fingerprint -> Varchar,
+        response -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    quarantined_versions (version_id) {
+        version_id -> Int4,
+        reasons -> Array<Varchar>,
+        index_entry -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    readme_backfills (id) {
+        id -> Int4,
+        requested_by -> Int4,
+        batch_size -> Int4,
+        delay_ms -> Int4,
+        last_version_id -> Int4,
+        rendered -> Int4,
+        failed -> Int4,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    registry_stats (date) {
+        date -> Date,
+        total_crates -> Int8,
+        total_versions -> Int8,
+        total_downloads -> Int8,
+        total_users -> Int8,
+        new_users -> Int8,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     reserved_crate_names (name) {
         name -> Text,
     }
 }
 
+table! {
+    settings_handoffs (crate_id) {
+        crate_id -> Int4,
+        settings -> Array<Varchar>,
+        reason -> Varchar,
+        started_by -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     teams (id) {
         id -> Int4,
@@ -149,6 +414,30 @@ table! {
     }
 }
 
+table! {
+    version_diffs (from_version_id,
+    to_version_id) {
+        from_version_id -> Int4,
+        to_version_id -> Int4,
+        patches -> Nullable<Varchar>,
+        requested_at -> Timestamp,
+        computed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    version_download_stats (version_id,
+    date,
+    user_agent,
+    country) {
+        version_id -> Int4,
+        date -> Date,
+        user_agent -> Varchar,
+        country -> Varchar,
+        downloads -> Int4,
+    }
+}
+
 table! {
     version_downloads (id) {
         id -> Int4,
@@ -160,6 +449,78 @@ table! {
     }
 }
 
+table! {
+    version_downloads_shadow (version_id,
+    date) {
+        version_id -> Int4,
+        date -> Date,
+        downloads -> Int4,
+    }
+}
+
+table! {
+    version_files (version_id,
+    path) {
+        version_id -> Int4,
+        path -> Varchar,
+        size -> Int8,
+        checksum -> Varchar,
+    }
+}
+
+table! {
+    version_findings (id) {
+        id -> Int4,
+        version_id -> Int4,
+        scanner -> Varchar,
+        severity -> Varchar,
+        path -> Nullable<Varchar>,
+        message -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    version_provenance (version_id) {
+        version_id -> Int4,
+        ci_provider -> Varchar,
+        build_url -> Varchar,
+        commit_sha -> Varchar,
+        verified -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    version_readmes (version_id) {
+        version_id -> Int4,
+        path -> Varchar,
+        html -> Text,
+        rendered_at -> Timestamp,
+    }
+}
+
+table! {
+    version_signatures (version_id,
+    kind) {
+        version_id -> Int4,
+        kind -> Varchar,
+        signature -> Varchar,
+        key_fingerprint -> Nullable<Varchar>,
+        uploaded_by -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    version_yanks (id) {
+        id -> Int4,
+        version_id -> Int4,
+        yanked -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     versions (id) {
         id -> Int4,
@@ -170,5 +531,19 @@ table! {
         downloads -> Int4,
         features -> Nullable<Varchar>,
         yanked -> Bool,
+        published_by -> Nullable<Int4>,
+        published_with_token_id -> Nullable<Int4>,
+        quarantined -> Bool,
+        verified_build -> Bool,
+        license -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    versions_published (crate_name,
+    num) {
+        crate_name -> Varchar,
+        num -> Varchar,
+        created_at -> Timestamp,
     }
 }
@@ -0,0 +1,170 @@
+//! Searching crates, users, teams and keywords at once, for the frontend's
+//! search box.
+//!
+//! Crates are matched like `GET /crates?q=` matches them, the others by their
+//! name containing the query, which trigram indexes on the lowercased names
+//! keep fast. Each group has its own total, and `per_page` (5 by default)
+//! applies to each group.
+
+use conduit::{Request, Response};
+use diesel::expression::dsl::sql;
+use diesel::prelude::*;
+use diesel::types::BigInt;
+use diesel_full_text_search::*;
+
+use app::RequestApp;
+use db::{InstrumentedConnection, RequestTransaction};
+use keyword::{EncodableKeyword, Keyword};
+use krate::{weighted, lower, EncodableCrate, ALL_COLUMNS};
+use owner::{EncodableOwner, Owner, Team};
+use schema::*;
+use user::EncodableUser;
+use util::{RequestUtils, CargoResult, human};
+use {Crate, User, Version};
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct SearchGroup<T> {
+    /// `crate`, `user`, `team` or `keyword`
+    pub kind: String,
+    /// How many matched in all, not only on this page
+    pub total: i64,
+    pub results: Vec<T>,
+}
+
+impl<T> SearchGroup<T> {
+    fn new(kind: &str, total: i64, results: Vec<T>) -> SearchGroup<T> {
+        SearchGroup { kind: kind.to_string(), total: total, results: results }
+    }
+}
+
+/// Handles the `GET /search` route.
+pub fn search(req: &mut Request) -> CargoResult<Response> {
+    let q = req.query().get("q").map(|q| q.trim().to_string()).unwrap_or_else(String::new);
+    if q.is_empty() {
+        return Err(human("the search query `q` can't be empty"))
+    }
+    let (offset, limit) = req.pagination(5, 20)?;
+    let conn = req.db_conn()?;
+
+    let crates = search_crates(req, &conn, &q, offset, limit)?;
+    let pattern = format!("%{}%", escape_like(&q.to_lowercase()));
+
+    let users_total = users::table.filter(lower(users::gh_login).like(&pattern))
+        .count()
+        .get_result(&*conn)?;
+    let users = users::table.filter(lower(users::gh_login).like(&pattern))
+        .order(users::gh_login)
+        .limit(limit)
+        .offset(offset)
+        .load::<User>(&*conn)?
+        .into_iter()
+        .map(|user| EncodableUser { email: None, ..user.encodable() })
+        .collect();
+
+    let teams_total = teams::table.filter(lower(teams::login).like(&pattern))
+        .count()
+        .get_result(&*conn)?;
+    let teams = teams::table.filter(lower(teams::login).like(&pattern))
+        .order(teams::login)
+        .limit(limit)
+        .offset(offset)
+        .load::<Team>(&*conn)?
+        .into_iter()
+        .map(|team| Owner::Team(team).encodable())
+        .collect();
+
+    let keywords_total = keywords::table.filter(lower(keywords::keyword).like(&pattern))
+        .count()
+        .get_result(&*conn)?;
+    let keywords = keywords::table.filter(lower(keywords::keyword).like(&pattern))
+        .order((keywords::crates_cnt.desc(), keywords::keyword))
+        .limit(limit)
+        .offset(offset)
+        .load::<Keyword>(&*conn)?
+        .into_iter()
+        .map(Keyword::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R {
+        crates: SearchGroup<EncodableCrate>,
+        users: SearchGroup<EncodableUser>,
+        teams: SearchGroup<EncodableOwner>,
+        keywords: SearchGroup<EncodableKeyword>,
+    }
+    Ok(req.json(&R {
+        crates: crates,
+        users: SearchGroup::new("user", users_total, users),
+        teams: SearchGroup::new("team", teams_total, teams),
+        keywords: SearchGroup::new("keyword", keywords_total, keywords),
+    }))
+}
+
+/// The crates `GET /crates?q=` would list first, leaving out the ones which
+/// are private, unlisted or only have yanked versions.
+fn search_crates(req: &Request,
+                 conn: &InstrumentedConnection,
+                 q: &str,
+                 offset: i64,
+                 limit: i64) -> CargoResult<SearchGroup<EncodableCrate>> {
+    let query = plainto_tsquery(q);
+    let matches = query.matches(crates::textsearchable_index_col);
+    let weights = req.app().config.search_weights.as_pg_array();
+    let rank = weighted::ts_rank_cd(weights, crates::textsearchable_index_col, query);
+    let data = crates::table
+        .select((ALL_COLUMNS, sql::<BigInt>("COUNT(*) OVER ()")))
+        .filter(matches)
+        .filter(crates::private.eq(false))
+        .filter(crates::unlisted.eq(false))
+        .filter(crates::all_yanked.eq(false))
+        .order((crates::name.eq(q).desc(), rank.desc()))
+        .limit(limit)
+        .offset(offset)
+        .load::<(Crate, i64)>(conn)?;
+    // Pages past the end don't have a row to read the total from
+    let total = match data.get(0) {
+        Some(&(_, total)) => total,
+        None => crates::table
+            .filter(plainto_tsquery(q).matches(crates::textsearchable_index_col))
+            .filter(crates::private.eq(false))
+            .filter(crates::unlisted.eq(false))
+            .filter(crates::all_yanked.eq(false))
+            .count()
+            .get_result(conn)?,
+    };
+    let crates = data.into_iter().map(|(c, _)| c).collect::<Vec<_>>();
+
+    let max_versions = Version::belonging_to(&crates)
+        .filter(versions::yanked.eq(false))
+        .load::<Version>(conn)?
+        .grouped_by(&crates)
+        .into_iter()
+        .map(|versions| Version::max(versions.into_iter().map(|v| v.num)));
+    let crates = max_versions.zip(crates).map(|(max_version, krate)| {
+        krate.minimal_encodable(max_version, None)
+    }).collect();
+    Ok(SearchGroup::new("crate", total, crates))
+}
+
+/// Escapes the wildcards of `LIKE` patterns.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("foo_bar%"), "foo\\_bar\\%");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+    }
+}
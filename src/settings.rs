@@ -0,0 +1,307 @@
+//! Settings owners can change on their crates without publishing a new
+//! version: a deprecation notice, webhooks, whether the crate shows up in
+//! listings and search, the download alerts they want to receive (see the
+//! `alert` module) and whether owner changes need a second owner's approval
+//! (see the `owner_approval` module).
+//!
+//! Every change made through `PUT /crates/:crate_id/settings` is recorded in
+//! the audit log. When the crate changes hands, some of these settings have
+//! to be handed over to the new owners, see the `handoff` module.
+
+use std::io::Read;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::pg::upsert::*;
+use diesel::prelude::*;
+use rustc_serialize::json;
+use url::Url;
+
+use app::RequestApp;
+use audit;
+use db::{InstrumentedConnection, RequestTransaction};
+use handoff::{self, Decision};
+use owner::{request_rights, Rights};
+use owner_approval::{self, ApprovalAction};
+use schema::*;
+use user::RequestUser;
+use util::{RequestUtils, CargoResult, check_public_host, human};
+use Crate;
+
+const MAX_DEPRECATION_NOTICE_LENGTH: usize = 500;
+const MAX_WEBHOOKS: usize = 5;
+const MAX_DOWNLOAD_THRESHOLDS: usize = 10;
+
+#[derive(Clone, Debug, Default, Queryable, Insertable, AsChangeset)]
+#[table_name="crate_settings"]
+#[primary_key(crate_id)]
+#[changeset_options(treat_none_as_null="true")]
+pub struct CrateSettings {
+    pub crate_id: i32,
+    pub deprecation_notice: Option<String>,
+    pub webhook_urls: Vec<String>,
+    pub download_thresholds: Vec<i32>,
+    pub spike_alerts: bool,
+    pub require_owner_approval: bool,
+    pub email_alerts: bool,
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug)]
+pub struct EncodableCrateSettings {
+    pub deprecation_notice: Option<String>,
+    pub webhook_urls: Vec<String>,
+    pub download_thresholds: Vec<i32>,
+    pub spike_alerts: bool,
+    pub email_alerts: bool,
+    pub unlisted: bool,
+    pub private: bool,
+    pub readme_in_search: bool,
+    pub require_owner_approval: bool,
+}
+
+impl CrateSettings {
+    /// The settings of the crate, which are all empty until an owner
+    /// changes them.
+    pub fn find(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<CrateSettings> {
+        let settings = crate_settings::table.find(crate_id)
+            .first(conn)
+            .optional()?;
+        Ok(settings.unwrap_or_else(|| {
+            CrateSettings { crate_id: crate_id, ..CrateSettings::default() }
+        }))
+    }
+
+    pub fn save(&self, conn: &InstrumentedConnection) -> CargoResult<()> {
+        diesel::insert(&self.on_conflict(crate_settings::crate_id, do_update().set(self)))
+            .into(crate_settings::table)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn encodable(self, krate: &Crate, conn: &InstrumentedConnection)
+                     -> CargoResult<EncodableCrateSettings> {
+        let (unlisted, private, readme_in_search) = crates::table.find(krate.id)
+            .select((crates::unlisted, crates::private, crates::readme_in_search))
+            .first::<(bool, bool, bool)>(conn)?;
+        Ok(EncodableCrateSettings {
+            deprecation_notice: self.deprecation_notice,
+            webhook_urls: self.webhook_urls,
+            download_thresholds: self.download_thresholds,
+            spike_alerts: self.spike_alerts,
+            email_alerts: self.email_alerts,
+            require_owner_approval: self.require_owner_approval,
+            unlisted: unlisted,
+            private: private,
+            readme_in_search: readme_in_search,
+        })
+    }
+}
+
+fn validate_deprecation_notice(notice: &str) -> CargoResult<Option<String>> {
+    let notice = notice.trim();
+    if notice.chars().count() > MAX_DEPRECATION_NOTICE_LENGTH {
+        return Err(human(&format_args!("the deprecation notice can't be longer \
+                                        than {} characters",
+                                       MAX_DEPRECATION_NOTICE_LENGTH)))
+    }
+    Ok(if notice.is_empty() { None } else { Some(notice.to_string()) })
+}
+
+fn validate_webhook_urls(urls: Vec<String>) -> CargoResult<Vec<String>> {
+    if urls.len() > MAX_WEBHOOKS {
+        return Err(human(&format_args!("a crate can't have more than {} webhooks",
+                                       MAX_WEBHOOKS)))
+    }
+    let mut validated = Vec::new();
+    for url in urls {
+        let parsed = Url::parse(&url).map_err(|_| {
+            human(&format_args!("`{}` is not a valid webhook url", url))
+        })?;
+        if parsed.scheme() != "https" {
+            return Err(human(&format_args!("webhook url `{}` must use https", url)))
+        }
+        check_public_host(&parsed)?;
+        if !validated.contains(&url) {
+            validated.push(url);
+        }
+    }
+    Ok(validated)
+}
+
+fn validate_download_thresholds(mut thresholds: Vec<i32>) -> CargoResult<Vec<i32>> {
+    if thresholds.len() > MAX_DOWNLOAD_THRESHOLDS {
+        return Err(human(&format_args!("a crate can't have more than {} download \
+                                        thresholds", MAX_DOWNLOAD_THRESHOLDS)))
+    }
+    if thresholds.iter().any(|&threshold| threshold <= 0) {
+        return Err(human("download thresholds must be positive"))
+    }
+    thresholds.sort();
+    thresholds.dedup();
+    Ok(thresholds)
+}
+
+/// Loads the crate named in the request, failing unless the current user is
+/// allowed to change its settings.
+fn owned_crate(req: &mut Request, conn: &InstrumentedConnection) -> CargoResult<(Crate, Rights)> {
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(conn)?;
+    let owners = krate.owners(conn)?;
+    let rights = request_rights(req, conn, &owners)?;
+    if rights < Rights::Publish {
+        return Err(human("only owners can see and change the settings of a crate"))
+    }
+    Ok((krate, rights))
+}
+
+/// Handles the `GET /crates/:crate_id/settings` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let conn = req.db_conn()?;
+    let (krate, _) = owned_crate(req, &conn)?;
+    let settings = CrateSettings::find(&conn, krate.id)?.encodable(&krate, &conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { settings: EncodableCrateSettings }
+    Ok(req.json(&R { settings: settings }))
+}
+
+/// Handles the `PUT /crates/:crate_id/settings` route.
+///
+/// Only the settings present in the body are changed. An empty deprecation
+/// notice removes it. Webhook urls have to use https and point to public
+/// addresses. Making a crate private requires full ownership rights, and so
+/// does requiring owner changes to be approved. Turning that back off is an
+/// owner change itself: while another owner could approve it, it's only
+/// asked for, and the setting stays on until they do.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// {
+///     "deprecation_notice": "use `bar` instead",
+///     "webhook_urls": ["https://example.com/hooks/crates"],
+///     "download_thresholds": [1000, 10000],
+///     "spike_alerts": true,
+///     "email_alerts": true,
+///     "unlisted": true
+/// }
+/// ```
+pub fn update(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request {
+        deprecation_notice: Option<String>,
+        webhook_urls: Option<Vec<String>>,
+        download_thresholds: Option<Vec<i32>>,
+        spike_alerts: Option<bool>,
+        email_alerts: Option<bool>,
+        unlisted: Option<bool>,
+        private: Option<bool>,
+        readme_in_search: Option<bool>,
+        require_owner_approval: Option<bool>,
+    }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let (krate, rights) = owned_crate(req, &conn)?;
+    if request.private.is_some() {
+        if !req.app().config.private_crates {
+            return Err(human("private crates are not enabled on this registry"))
+        }
+        if rights < Rights::Full {
+            return Err(human("only owners can change who can see a crate"))
+        }
+    }
+    if request.require_owner_approval.is_some() && rights < Rights::Full {
+        return Err(human("only owners can change whether owner changes need approval"))
+    }
+
+    let mut settings = CrateSettings::find(&conn, krate.id)?;
+    let mut changed = Vec::new();
+    if let Some(ref notice) = request.deprecation_notice {
+        settings.deprecation_notice = validate_deprecation_notice(notice)?;
+        changed.push("deprecation_notice");
+    }
+    if let Some(urls) = request.webhook_urls {
+        settings.webhook_urls = validate_webhook_urls(urls)?;
+        changed.push("webhook_urls");
+    }
+    if let Some(thresholds) = request.download_thresholds {
+        settings.download_thresholds = validate_download_thresholds(thresholds)?;
+        changed.push("download_thresholds");
+    }
+    if let Some(enabled) = request.spike_alerts {
+        settings.spike_alerts = enabled;
+        changed.push("spike_alerts");
+    }
+    if let Some(enabled) = request.email_alerts {
+        settings.email_alerts = enabled;
+        changed.push("email_alerts");
+    }
+    let mut disable_approval = false;
+    match request.require_owner_approval {
+        Some(true) if !settings.require_owner_approval => {
+            if !owner_approval::has_other_owner(&krate.owners(&conn)?, user) {
+                return Err(human("owner changes can only need approval on crates \
+                                  with another owner to approve them"))
+            }
+            settings.require_owner_approval = true;
+            changed.push("require_owner_approval");
+        }
+        Some(false) if settings.require_owner_approval => {
+            if owner_approval::has_other_owner(&krate.owners(&conn)?, user) {
+                disable_approval = true;
+            } else {
+                settings.require_owner_approval = false;
+                changed.push("require_owner_approval");
+            }
+        }
+        _ => {}
+    }
+
+    conn.transaction(|| {
+        settings.save(&conn)?;
+        // Changing a setting which was waiting to be handed off replaces it
+        let replaced = changed.iter().map(|&setting| (setting, Decision::Replace))
+            .collect::<Vec<_>>();
+        handoff::decide(&conn, &krate, user.id, &replaced)?;
+        if let Some(unlisted) = request.unlisted {
+            diesel::update(&krate).set(crates::unlisted.eq(unlisted))
+                .execute(&*conn)?;
+            changed.push("unlisted");
+        }
+        if let Some(private) = request.private {
+            diesel::update(&krate).set(crates::private.eq(private))
+                .execute(&*conn)?;
+            changed.push("private");
+        }
+        if let Some(enabled) = request.readme_in_search {
+            diesel::update(&krate).set(crates::readme_in_search.eq(enabled))
+                .execute(&*conn)?;
+            changed.push("readme_in_search");
+        }
+        if disable_approval {
+            owner_approval::request(&conn, &krate, user, ApprovalAction::DisableApproval, "")?;
+        }
+        if changed.is_empty() {
+            return Ok(())
+        }
+        if changed.contains(&"deprecation_notice") {
+            let notice = settings.deprecation_notice.as_ref().map(|s| &s[..]).unwrap_or("");
+            audit::record(&conn, user.id, "update_deprecation_notice", Some(&krate.name),
+                          notice)?;
+        }
+        let details = format!("changed {}", changed.join(", "));
+        audit::record(&conn, user.id, "update_settings", Some(&krate.name), &details)
+    })?;
+
+    let settings = settings.encodable(&krate, &conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { settings: EncodableCrateSettings }
+    Ok(req.json(&R { settings: settings }))
+}
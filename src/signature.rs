@@ -0,0 +1,267 @@
+//! Signatures of published versions.
+//!
+//! Registries configured with a signing key sign every version published to
+//! them. What's signed is the version's line of a checksums file,
+//! `<name> <version> <sha256 of the crate file>\n`, so the signature stays
+//! valid when the version is yanked. The key's fingerprint is published in
+//! the index's `config.json` as `signing-key` so that mirrors know which key
+//! to expect, and the public key itself is served by `GET /signing_key`.
+//!
+//! Owners can also attach a detached signature of their own to a version,
+//! made with whatever tool and key they like. The registry stores it as-is.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use conduit::{Request, Response};
+use diesel;
+use diesel::prelude::*;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::hex::ToHex;
+use time::Timespec;
+
+use app::RequestApp;
+use db::{InstrumentedConnection, RequestTransaction};
+use owner::{request_rights, Rights};
+use schema::*;
+use user::RequestUser;
+use util::errors::CargoError;
+use util::{RequestUtils, CargoResult, human, internal};
+use version::version_and_crate;
+
+/// Signatures uploaded by owners can't be bigger than this.
+const MAX_SIGNATURE_SIZE: u64 = 16 * 1024;
+
+/// The kind of the signature made with the registry's key.
+pub const REGISTRY: &'static str = "registry";
+/// The kind of the signatures uploaded by owners.
+pub const OWNER: &'static str = "owner";
+
+/// The key the registry signs published versions with.
+pub struct SigningKey {
+    key: PKey,
+    fingerprint: String,
+}
+
+impl SigningKey {
+    /// Reads a PEM encoded RSA or EC private key.
+    pub fn from_pem(pem: &[u8]) -> CargoResult<SigningKey> {
+        let key = PKey::private_key_from_pem(pem).map_err(|_| {
+            internal("the signing key isn't a PEM encoded private key")
+        })?;
+        let der = key.public_key_to_der().map_err(|_| {
+            internal("the signing key's public key can't be encoded")
+        })?;
+        let fingerprint = format!("SHA256:{}", hash(MessageDigest::sha256(), &der)
+            .unwrap().to_hex());
+        Ok(SigningKey { key: key, fingerprint: fingerprint })
+    }
+
+    /// The hex encoded SHA-256 of the DER encoded public key, prefixed with
+    /// `SHA256:`.
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn public_key_pem(&self) -> String {
+        String::from_utf8(self.key.public_key_to_pem().unwrap()).unwrap()
+    }
+
+    /// The base64 encoded SHA-256 signature of `data`.
+    pub fn sign(&self, data: &[u8]) -> String {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+        signer.update(data).unwrap();
+        signer.finish().unwrap().to_base64(STANDARD)
+    }
+}
+
+/// What the registry signs for a version.
+pub fn signed_line(crate_name: &str, num: &str, cksum: &str) -> String {
+    format!("{} {} {}\n", crate_name, num, cksum)
+}
+
+#[derive(Clone, Debug, Queryable)]
+pub struct VersionSignature {
+    pub version_id: i32,
+    pub kind: String,
+    pub signature: String,
+    pub key_fingerprint: Option<String>,
+    pub uploaded_by: Option<i32>,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="version_signatures"]
+struct NewVersionSignature<'a> {
+    version_id: i32,
+    kind: &'a str,
+    signature: &'a str,
+    key_fingerprint: Option<&'a str>,
+    uploaded_by: Option<i32>,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableSignature {
+    pub kind: String,
+    pub signature: String,
+    pub key_fingerprint: Option<String>,
+    pub uploaded_by: Option<String>,
+    pub created_at: String,
+}
+
+/// Signs a newly published version with the registry's key.
+pub fn sign_version(conn: &InstrumentedConnection,
+                    key: &SigningKey,
+                    version_id: i32,
+                    crate_name: &str,
+                    num: &str,
+                    cksum: &str) -> CargoResult<()> {
+    let signature = key.sign(signed_line(crate_name, num, cksum).as_bytes());
+    let new = NewVersionSignature {
+        version_id: version_id,
+        kind: REGISTRY,
+        signature: &signature,
+        key_fingerprint: Some(key.fingerprint()),
+        uploaded_by: None,
+    };
+    diesel::insert(&new).into(version_signatures::table).execute(conn)?;
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/:version/signature` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let signatures = version_signatures::table
+        .filter(version_signatures::version_id.eq(version.id))
+        .order(version_signatures::kind.desc())
+        .load::<VersionSignature>(&*conn)?;
+    let uploader_ids = signatures.iter().filter_map(|s| s.uploaded_by).collect::<Vec<_>>();
+    let logins = users::table
+        .filter(users::id.eq_any(uploader_ids))
+        .select((users::id, users::gh_login))
+        .load::<(i32, String)>(&*conn)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let signatures = signatures.into_iter().map(|s| {
+        EncodableSignature {
+            uploaded_by: s.uploaded_by.and_then(|id| logins.get(&id).cloned()),
+            kind: s.kind,
+            signature: s.signature,
+            key_fingerprint: s.key_fingerprint,
+            created_at: ::encode_time(s.created_at),
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { signatures: Vec<EncodableSignature> }
+    Ok(req.json(&R { signatures: signatures }))
+}
+
+/// Handles the `PUT /crates/:crate_id/:version/signature` route.
+///
+/// The body is the detached signature, e.g. an ASCII armored PGP signature
+/// of the crate file. Uploading another one replaces it.
+pub fn upload(req: &mut Request) -> CargoResult<Response> {
+    let (version, krate) = version_and_crate(req)?;
+    let user = req.user()?.clone();
+
+    let mut signature = String::new();
+    req.body().take(MAX_SIGNATURE_SIZE + 1).read_to_string(&mut signature).map_err(|_| {
+        human("the signature must be text, e.g. an ASCII armored PGP signature")
+    })?;
+    if signature.len() as u64 > MAX_SIGNATURE_SIZE {
+        return Err(human(&format_args!("signatures can't be bigger than {} bytes",
+                                       MAX_SIGNATURE_SIZE)))
+    }
+    if signature.trim().is_empty() {
+        return Err(human("the signature is empty"))
+    }
+
+    let conn = req.db_conn()?;
+    let owners = krate.owners(&conn)?;
+    if request_rights(req, &conn, &owners)? < Rights::Publish {
+        return Err(human("must already be an owner to sign a version"))
+    }
+
+    let new = NewVersionSignature {
+        version_id: version.id,
+        kind: OWNER,
+        signature: &signature,
+        key_fingerprint: None,
+        uploaded_by: Some(user.id),
+    };
+    conn.transaction::<_, Box<CargoError>, _>(|| {
+        let previous = version_signatures::table
+            .filter(version_signatures::version_id.eq(version.id))
+            .filter(version_signatures::kind.eq(OWNER));
+        diesel::delete(previous).execute(&*conn)?;
+        diesel::insert(&new).into(version_signatures::table).execute(&*conn)?;
+        Ok(())
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+/// Handles the `GET /signing_key` route.
+pub fn signing_key(req: &mut Request) -> CargoResult<Response> {
+    let key = match req.app().config.signing_key {
+        Some(ref key) => key.clone(),
+        None => return Err(human("this registry doesn't sign the versions published to it")),
+    };
+
+    #[derive(RustcEncodable)]
+    struct SigningKeyInfo { fingerprint: String, public_key: String }
+    #[derive(RustcEncodable)]
+    struct R { signing_key: SigningKeyInfo }
+    Ok(req.json(&R {
+        signing_key: SigningKeyInfo {
+            fingerprint: key.fingerprint().to_string(),
+            public_key: key.public_key_pem(),
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+    use rustc_serialize::base64::FromBase64;
+
+    use super::{signed_line, SigningKey};
+
+    #[test]
+    fn signatures_verify_against_the_public_key() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let pem = PKey::from_rsa(rsa).unwrap().private_key_to_pem().unwrap();
+        let key = SigningKey::from_pem(&pem).unwrap();
+        assert!(key.fingerprint().starts_with("SHA256:"));
+        assert_eq!(key.fingerprint().len(), 7 + 64);
+
+        let line = signed_line("foo", "1.0.0", "abcd");
+        assert_eq!(line, "foo 1.0.0 abcd\n");
+        let signature = key.sign(line.as_bytes()).from_base64().unwrap();
+
+        let public = PKey::public_key_from_pem(key.public_key_pem().as_bytes()).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public).unwrap();
+        verifier.update(line.as_bytes()).unwrap();
+        assert!(verifier.finish(&signature).unwrap());
+
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public).unwrap();
+        verifier.update(b"foo 1.0.1 abcd\n").unwrap();
+        assert!(!verifier.finish(&signature).unwrap());
+    }
+
+    #[test]
+    fn garbage_keys_are_rejected() {
+        assert!(SigningKey::from_pem(b"not a key").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Snapshots of registry-wide statistics.
+//!
+//! Counting every crate, version and user is too slow to do on each request,
+//! so the `snapshot-stats` job stores the totals in `registry_stats` once a
+//! week and `GET /api/v1/stats` serves them as a time series.
+//!
+//! The same job stores how many crates each category had and how often they
+//! were downloaded every month in `category_stats`, which
+//! `GET /api/v1/categories/:category_id/stats` serves. A category counts the
+//! crates of its subcategories, and the months before a crate was put in a
+//! category are counted as if it had always been in it.
+
+use chrono::NaiveDate;
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use pg::GenericConnection;
+use pg::rows::Row;
+use time::Timespec;
+
+use Model;
+use category::Category;
+use db::RequestTransaction;
+use util::{RequestUtils, CargoResult};
+
+pub struct RegistryStats {
+    pub date: NaiveDate,
+    pub total_crates: i64,
+    pub total_versions: i64,
+    pub total_downloads: i64,
+    pub total_users: i64,
+    /// Users who signed up since the previous snapshot, or every user for
+    /// the first one
+    pub new_users: i64,
+    pub created_at: Timespec,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableRegistryStats {
+    pub date: String,
+    pub total_crates: i64,
+    pub total_versions: i64,
+    pub total_downloads: i64,
+    pub total_users: i64,
+    pub new_users: i64,
+}
+
+impl RegistryStats {
+    /// Counts everything and stores it as today's snapshot, replacing the
+    /// one taken earlier today if there is one.
+    pub fn snapshot(conn: &GenericConnection) -> CargoResult<RegistryStats> {
+        let rows = conn.query("\
+            WITH totals AS (
+                SELECT (SELECT COUNT(*) FROM crates) AS total_crates,
+                       (SELECT COUNT(*) FROM versions) AS total_versions,
+                       (SELECT total_downloads FROM metadata) AS total_downloads,
+                       (SELECT COUNT(*) FROM users) AS total_users
+            )
+            INSERT INTO registry_stats (total_crates, total_versions, total_downloads,
+                                        total_users, new_users)
+            SELECT total_crates, total_versions, total_downloads, total_users,
+                   total_users - COALESCE((SELECT total_users FROM registry_stats
+                                            WHERE date < CURRENT_DATE
+                                            ORDER BY date DESC LIMIT 1), 0)
+              FROM totals
+            ON CONFLICT (date) DO UPDATE
+               SET total_crates = EXCLUDED.total_crates,
+                   total_versions = EXCLUDED.total_versions,
+                   total_downloads = EXCLUDED.total_downloads,
+                   total_users = EXCLUDED.total_users,
+                   new_users = EXCLUDED.new_users,
+                   created_at = now()
+            RETURNING *", &[])?;
+        Ok(Model::from_row(&rows.get(0)))
+    }
+
+    /// The most recent snapshot, if one was taken yet.
+    pub fn latest(conn: &GenericConnection) -> CargoResult<Option<RegistryStats>> {
+        let rows = conn.query("SELECT * FROM registry_stats ORDER BY date DESC LIMIT 1", &[])?;
+        Ok(rows.iter().next().map(|row| Model::from_row(&row)))
+    }
+
+    pub fn encodable(self) -> EncodableRegistryStats {
+        EncodableRegistryStats {
+            date: self.date.to_string(),
+            total_crates: self.total_crates,
+            total_versions: self.total_versions,
+            total_downloads: self.total_downloads,
+            total_users: self.total_users,
+            new_users: self.new_users,
+        }
+    }
+}
+
+impl Model for RegistryStats {
+    fn from_row(row: &Row) -> RegistryStats {
+        RegistryStats {
+            date: row.get("date"),
+            total_crates: row.get("total_crates"),
+            total_versions: row.get("total_versions"),
+            total_downloads: row.get("total_downloads"),
+            total_users: row.get("total_users"),
+            new_users: row.get("new_users"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    fn table_name(_: Option<RegistryStats>) -> &'static str { "registry_stats" }
+}
+
+/// Handles the `GET /stats` route.
+///
+/// Returns the snapshots oldest first. `per_page` is the number of weeks,
+/// counting back from the latest snapshot.
+pub fn index(req: &mut Request) -> CargoResult<Response> {
+    let (offset, limit) = req.pagination(52, 520)?;
+    let tx = req.tx()?;
+    let stats = tx.query("SELECT * FROM (
+                              SELECT * FROM registry_stats
+                               ORDER BY date DESC
+                               LIMIT $1 OFFSET $2
+                          ) recent ORDER BY date ASC",
+                         &[&limit, &offset])?.iter()
+        .map(|row| RegistryStats::from_row(&row).encodable())
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { stats: Vec<EncodableRegistryStats> }
+    Ok(req.json(&R { stats: stats }))
+}
+
+pub struct CategoryStats {
+    pub category_id: i32,
+    /// The first day of the month
+    pub month: NaiveDate,
+    /// The crates in the category by the end of the month
+    pub crates: i64,
+    /// Downloads of those crates during the month
+    pub downloads: i64,
+    pub created_at: Timespec,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableCategoryStats {
+    /// The month, like `2017-04`
+    pub month: String,
+    pub crates: i64,
+    pub downloads: i64,
+}
+
+impl CategoryStats {
+    /// Counts the crates and downloads of every category for the months
+    /// since the one before the latest counted, which may have had downloads
+    /// counted late, or since the first crate was created the first time.
+    /// Returns how many months of categories were stored.
+    pub fn snapshot(conn: &GenericConnection) -> CargoResult<u64> {
+        let n = conn.execute("\
+            WITH since AS (
+                SELECT date_trunc('month', COALESCE(
+                    (SELECT MAX(month) - INTERVAL '1 month' FROM category_stats),
+                    (SELECT MIN(created_at) FROM crates),
+                    CURRENT_DATE
+                ))::date AS month
+            ), months AS (
+                SELECT generate_series(since.month::timestamp,
+                                       date_trunc('month', CURRENT_DATE)::timestamp,
+                                       INTERVAL '1 month')::date AS month
+                  FROM since
+            ), members AS (
+                SELECT DISTINCT categories.id AS category_id, crates_categories.crate_id
+                  FROM categories
+                 INNER JOIN categories subcategories
+                    ON subcategories.slug = categories.slug
+                    OR subcategories.slug LIKE categories.slug || '::%'
+                 INNER JOIN crates_categories
+                    ON crates_categories.category_id = subcategories.id
+            ), crate_counts AS (
+                SELECT members.category_id, months.month, COUNT(*) AS crates
+                  FROM members
+                 INNER JOIN crates ON crates.id = members.crate_id
+                 INNER JOIN months
+                    ON COALESCE(crates.first_published_at, crates.created_at)
+                       < months.month + INTERVAL '1 month'
+                 GROUP BY members.category_id, months.month
+            ), download_counts AS (
+                SELECT members.category_id,
+                       date_trunc('month', version_downloads.date)::date AS month,
+                       SUM(version_downloads.downloads) AS downloads
+                  FROM members
+                 INNER JOIN versions ON versions.crate_id = members.crate_id
+                 INNER JOIN version_downloads ON version_downloads.version_id = versions.id
+                 WHERE version_downloads.date >= (SELECT month FROM since)
+                 GROUP BY 1, 2
+            )
+            INSERT INTO category_stats (category_id, month, crates, downloads)
+            SELECT categories.id, months.month,
+                   COALESCE(crate_counts.crates, 0),
+                   COALESCE(download_counts.downloads, 0)
+              FROM categories
+             CROSS JOIN months
+              LEFT JOIN crate_counts
+                ON crate_counts.category_id = categories.id
+               AND crate_counts.month = months.month
+              LEFT JOIN download_counts
+                ON download_counts.category_id = categories.id
+               AND download_counts.month = months.month
+            ON CONFLICT (category_id, month) DO UPDATE
+               SET crates = EXCLUDED.crates,
+                   downloads = EXCLUDED.downloads,
+                   created_at = now()", &[])?;
+        Ok(n)
+    }
+
+    pub fn encodable(self) -> EncodableCategoryStats {
+        EncodableCategoryStats {
+            month: self.month.format("%Y-%m").to_string(),
+            crates: self.crates,
+            downloads: self.downloads,
+        }
+    }
+}
+
+impl Model for CategoryStats {
+    fn from_row(row: &Row) -> CategoryStats {
+        CategoryStats {
+            category_id: row.get("category_id"),
+            month: row.get("month"),
+            crates: row.get("crates"),
+            downloads: row.get("downloads"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    fn table_name(_: Option<CategoryStats>) -> &'static str { "category_stats" }
+}
+
+/// Handles the `GET /categories/:category_id/stats` route.
+///
+/// Returns the months oldest first. `per_page` is the number of months,
+/// counting back from the latest one.
+pub fn category(req: &mut Request) -> CargoResult<Response> {
+    let (offset, limit) = req.pagination(24, 240)?;
+    let tx = req.tx()?;
+    let category = Category::find_by_slug(tx, &req.params()["category_id"])?;
+    let stats = tx.query("SELECT * FROM (
+                              SELECT * FROM category_stats
+                               WHERE category_id = $1
+                               ORDER BY month DESC
+                               LIMIT $2 OFFSET $3
+                          ) recent ORDER BY month ASC",
+                         &[&category.id, &limit, &offset])?.iter()
+        .map(|row| CategoryStats::from_row(&row).encodable())
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { stats: Vec<EncodableCategoryStats> }
+    Ok(req.json(&R { stats: stats }))
+}
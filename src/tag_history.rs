@@ -0,0 +1,216 @@
+//! The keywords and categories crates used to have.
+//!
+//! Every publish replaces a crate's keywords and categories with the ones of
+//! its manifest, so publishing from an old checkout can drop categories the
+//! owners curated. Before they're replaced, the old ones are kept as a
+//! snapshot which owners and admins can put back with
+//! `PUT /crates/:crate_id/tag_history/:snapshot_id/restore`.
+
+use std::collections::HashMap;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use time::Timespec;
+
+use admin;
+use app::RequestApp;
+use audit;
+use category::CrateCategory;
+use db::{InstrumentedConnection, RequestTransaction};
+use keyword::CrateKeyword;
+use owner::{request_rights, Rights};
+use permission;
+use schema::*;
+use user::RequestUser;
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, ChainError, human};
+use {Category, Crate, Keyword, User, Version};
+
+#[derive(Clone, Debug, Queryable, Identifiable)]
+#[table_name="crate_tag_snapshots"]
+pub struct TagSnapshot {
+    pub id: i32,
+    pub crate_id: i32,
+    pub keywords: Vec<String>,
+    /// The slugs of the categories
+    pub categories: Vec<String>,
+    /// The version whose publish replaced them, unless it was a restore
+    pub replaced_by_version_id: Option<i32>,
+    pub replaced_by: i32,
+    pub created_at: Timespec,
+}
+
+#[derive(Insertable)]
+#[table_name="crate_tag_snapshots"]
+struct NewTagSnapshot {
+    crate_id: i32,
+    keywords: Vec<String>,
+    categories: Vec<String>,
+    replaced_by_version_id: Option<i32>,
+    replaced_by: i32,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableTagSnapshot {
+    pub id: i32,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// The version whose publish replaced them, or `None` if an owner
+    /// restored another snapshot
+    pub replaced_by_version: Option<String>,
+    /// The login of whoever replaced them
+    pub replaced_by: Option<String>,
+    pub created_at: String,
+}
+
+/// The crate's keywords and the slugs of its categories, sorted.
+pub fn current(conn: &InstrumentedConnection, krate: &Crate)
+               -> CargoResult<(Vec<String>, Vec<String>)> {
+    let mut keywords = CrateKeyword::belonging_to(krate)
+        .inner_join(keywords::table)
+        .select(keywords::keyword)
+        .load::<String>(conn)?;
+    let mut categories = CrateCategory::belonging_to(krate)
+        .inner_join(categories::table)
+        .select(categories::slug)
+        .load::<String>(conn)?;
+    keywords.sort();
+    categories.sort();
+    Ok((keywords, categories))
+}
+
+/// Keeps the crate's keywords and categories as a snapshot if they're about
+/// to be replaced by different ones. Nothing is kept for crates which had
+/// neither.
+pub fn record(conn: &InstrumentedConnection,
+              krate: &Crate,
+              keywords: &[&str],
+              categories: &[&str],
+              version_id: Option<i32>,
+              user_id: i32) -> CargoResult<()> {
+    let (old_keywords, old_categories) = current(conn, krate)?;
+    if old_keywords.is_empty() && old_categories.is_empty() {
+        return Ok(())
+    }
+    fn normalize(names: &[&str]) -> Vec<String> {
+        let mut names = names.iter().map(|s| s.to_lowercase()).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+    let lowercase = old_keywords.iter().map(|s| s.to_lowercase()).collect::<Vec<_>>();
+    if lowercase == normalize(keywords) && old_categories == normalize(categories) {
+        return Ok(())
+    }
+
+    let snapshot = NewTagSnapshot {
+        crate_id: krate.id,
+        keywords: old_keywords,
+        categories: old_categories,
+        replaced_by_version_id: version_id,
+        replaced_by: user_id,
+    };
+    diesel::insert(&snapshot).into(crate_tag_snapshots::table).execute(conn)?;
+    Ok(())
+}
+
+/// Handles the `GET /crates/:crate_id/tag_history` route.
+///
+/// Lists the keywords and categories the crate had before, newest first.
+pub fn index(req: &mut Request) -> CargoResult<Response> {
+    let crate_name = &req.params()["crate_id"];
+    permission::ensure_readable(req, crate_name)?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
+    let snapshots = crate_tag_snapshots::table
+        .filter(crate_tag_snapshots::crate_id.eq(krate.id))
+        .order(crate_tag_snapshots::id.desc())
+        .load::<TagSnapshot>(&*conn)?;
+    let snapshots = encode_all(&conn, snapshots)?;
+
+    #[derive(RustcEncodable)]
+    struct R { snapshots: Vec<EncodableTagSnapshot> }
+    Ok(req.json(&R { snapshots: snapshots }))
+}
+
+/// Handles the `PUT /crates/:crate_id/tag_history/:snapshot_id/restore`
+/// route.
+///
+/// Gives the crate the keywords and categories of the snapshot back. The
+/// ones it had are kept as a snapshot too, so that a restore can be undone.
+pub fn restore(req: &mut Request) -> CargoResult<Response> {
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(&*conn)?;
+    let owners = krate.owners(&conn)?;
+    let rights = request_rights(req, &conn, &owners)?;
+    if rights < Rights::Full && !admin::is_admin(req.app(), user) {
+        return Err(human("only owners and admins can restore a crate's keywords \
+                          and categories"))
+    }
+    let id = req.params()["snapshot_id"].parse::<i32>().map_err(|_| NotFound)?;
+    let snapshot = crate_tag_snapshots::table.find(id)
+        .filter(crate_tag_snapshots::crate_id.eq(krate.id))
+        .first::<TagSnapshot>(&*conn)
+        .optional()?
+        .chain_error(|| NotFound)?;
+
+    let invalid_categories = conn.transaction(|| -> CargoResult<Vec<String>> {
+        let keywords = snapshot.keywords.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        let categories = snapshot.categories.iter().map(|s| &s[..]).collect::<Vec<_>>();
+        record(&conn, &krate, &keywords, &categories, None, user.id)?;
+        Keyword::update_crate(&conn, &krate, &keywords)?;
+        let invalid = Category::update_crate(&conn, &krate, &categories)?;
+        audit::record(&conn, user.id, "restore_tags", Some(&krate.name),
+                      &format!("restored snapshot {}", snapshot.id))?;
+        Ok(invalid.into_iter().map(|s| s.to_string()).collect())
+    })?;
+    let (keywords, categories) = current(&conn, &krate)?;
+
+    #[derive(RustcEncodable)]
+    struct R {
+        keywords: Vec<String>,
+        categories: Vec<String>,
+        /// Categories of the snapshot which no longer exist
+        invalid_categories: Vec<String>,
+    }
+    Ok(req.json(&R {
+        keywords: keywords,
+        categories: categories,
+        invalid_categories: invalid_categories,
+    }))
+}
+
+fn encode_all(conn: &InstrumentedConnection, snapshots: Vec<TagSnapshot>)
+              -> CargoResult<Vec<EncodableTagSnapshot>> {
+    use diesel::expression::dsl::any;
+
+    let user_ids = snapshots.iter().map(|s| s.replaced_by).collect::<Vec<_>>();
+    let logins = users::table.filter(users::id.eq(any(user_ids)))
+        .load::<User>(conn)?
+        .into_iter()
+        .map(|user| (user.id, user.gh_login))
+        .collect::<HashMap<_, _>>();
+    let version_ids = snapshots.iter()
+        .filter_map(|s| s.replaced_by_version_id)
+        .collect::<Vec<_>>();
+    let nums = versions::table.filter(versions::id.eq(any(version_ids)))
+        .load::<Version>(conn)?
+        .into_iter()
+        .map(|version| (version.id, version.num.to_string()))
+        .collect::<HashMap<_, _>>();
+
+    Ok(snapshots.into_iter().map(|snapshot| {
+        EncodableTagSnapshot {
+            id: snapshot.id,
+            replaced_by_version: snapshot.replaced_by_version_id
+                .and_then(|id| nums.get(&id).cloned()),
+            replaced_by: logins.get(&snapshot.replaced_by).cloned(),
+            created_at: ::encode_time(snapshot.created_at),
+            keywords: snapshot.keywords,
+            categories: snapshot.categories,
+        }
+    }).collect())
+}
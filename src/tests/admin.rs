@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::iter::repeat;
+use std::time::Duration;
+
+use conduit::{Handler, Method};
+use diesel::prelude::*;
+
+use cargo_registry::{git, quarantine, readme, App, User, Version};
+use cargo_registry::admin::{EncodablePublish, EncodableQuarantinedVersion, EncodableTombstone};
+use cargo_registry::audit::EncodableAuditEntry;
+use cargo_registry::db::RequestTransaction;
+use cargo_registry::download::EncodableFlushMetrics;
+use cargo_registry::mirror::EncodableMirror;
+use cargo_registry::readme::{EncodableReadme, EncodableReadmeBackfill};
+use cargo_registry::report::EncodableAbuseReport;
+use cargo_registry::schema::versions;
+use cargo_registry::user::EncodableUser;
+use cargo_registry::util::{human, TarballFile};
+
+#[derive(RustcDecodable)]
+struct O { ok: bool }
+#[derive(RustcDecodable)]
+struct AuditLog { entries: Vec<EncodableAuditEntry> }
+#[derive(RustcDecodable)]
+struct Owners { users: Vec<EncodableUser> }
+#[derive(RustcDecodable)]
+struct Quarantine { versions: Vec<EncodableQuarantinedVersion> }
+#[derive(RustcDecodable)]
+struct CrateTombstone { tombstone: Option<EncodableTombstone> }
+#[derive(RustcDecodable)]
+struct Flushes { flushes: Vec<EncodableFlushMetrics> }
+
+#[test]
+fn only_admins_can_reserve_names() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/reserved_names");
+    ::sign_in(&mut req, &app);
+    let body = r#"{"name":"foo_reserved","reason":"squatting"}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("must be an admin"), "{:?}", json.errors);
+}
+
+#[test]
+fn reserve_and_unreserve_name() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/reserved_names");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let body = r#"{"name":"foo_reserved","reason":"squatting"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("already reserved"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Delete)
+                                               .with_path("/api/v1/admin/reserved_names/foo-reserved")));
+    assert!(::json::<O>(&mut response).ok);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/admin/audit_log")));
+    let json: AuditLog = ::json(&mut response);
+    let actions = json.entries.iter().map(|e| &e.action[..]).collect::<Vec<_>>();
+    assert_eq!(actions, ["unreserve_name", "reserve_name"]);
+    assert_eq!(json.entries[1].details, "squatting");
+}
+
+#[test]
+fn transfer_crate() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/crates/foo_abandoned/transfer");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let old = ::new_user("old").create_or_update(&conn).unwrap();
+        ::new_user("new").create_or_update(&conn).unwrap();
+        ::new_crate("foo_abandoned").create_or_update(&conn, None, old.id).unwrap();
+        ::new_crate("foo_abandoned_legacy").create_or_update(&conn, None, old.id).unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let body = r#"{"owner":"new","reason":""}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("reason is required"), "{:?}", json.errors);
+    let body = r#"{"owner":"new","reason":"issue 1234","moved_to":"foo_missing"}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("does not exist"), "{:?}", json.errors);
+
+    let body = r#"{"owner":"new","reason":"issue 1234","moved_to":"foo_abandoned_legacy"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+
+    // The crate's page points the previous maintainers' users to where they went
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_abandoned")));
+    let tombstone = ::json::<CrateTombstone>(&mut response).tombstone
+        .expect("a transferred crate should have a tombstone");
+    assert_eq!(tombstone.previous_owners, ["old"]);
+    assert_eq!(tombstone.new_owner, "new");
+    assert_eq!(tombstone.moved_to, Some("foo_abandoned_legacy".to_string()));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_abandoned/owners")));
+    let json: Owners = ::json(&mut response);
+    assert_eq!(json.users.len(), 1);
+    assert_eq!(json.users[0].login, "new");
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/admin/audit_log")
+                                               .with_query("crate=foo_abandoned")));
+    let json: AuditLog = ::json(&mut response);
+    assert_eq!(json.entries.len(), 1);
+    assert_eq!(json.entries[0].details,
+               "from old to new: issue 1234 (moved to foo_abandoned_legacy)");
+}
+
+#[test]
+fn add_and_remove_alias() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/crate_aliases");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_crate("foo_renamed").create_or_update(&conn, None, user.id).unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let body = r#"{"alias":"foo_renamed","target":"foo_renamed","reason":"x"}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("still exists"), "{:?}", json.errors);
+
+    let body = r#"{"alias":"foo_before","target":"foo_renamed","reason":"renamed"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("already an alias"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Delete)
+                                               .with_path("/api/v1/admin/crate_aliases/foo_before")));
+    assert!(::json::<O>(&mut response).ok);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/admin/audit_log")));
+    let json: AuditLog = ::json(&mut response);
+    assert_eq!(json.entries[1].details, "alias of foo_renamed: renamed");
+}
+
+#[test]
+fn recent_publishes_are_flagged() {
+    #[derive(RustcDecodable)]
+    struct Publishes { publishes: Vec<EncodablePublish> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/admin/publishes");
+    let user = ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_popular_crate"));
+    ::mock_crate(&mut req, ::krate("foo_popular_crates"));
+    ::mock_crate(&mut req, ::krate("foo_unrelated"));
+    {
+        let tx = req.tx().unwrap();
+        tx.execute("UPDATE crates SET downloads = 1000000 \
+                    WHERE name = 'foo_popular_crate'", &[]).unwrap();
+        tx.execute("UPDATE versions SET published_by = $1", &[&user.id]).unwrap();
+    }
+
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(json.errors[0].detail.contains("must be an admin"), "{:?}", json.errors);
+
+    ::mock_user(&mut req, User { gh_id: ::ADMIN_GH_ID, ..::user("admin") });
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Publishes = ::json(&mut response);
+    assert_eq!(json.publishes.len(), 3);
+    assert!(json.publishes.iter().all(|p| p.flags.contains(&"new_publisher".to_string())));
+
+    let mut response = ok_resp!(middle.call(req.with_query("flagged=1")));
+    let json: Publishes = ::json(&mut response);
+    let similar = json.publishes.iter()
+        .find(|p| p.crate_name == "foo_popular_crates")
+        .unwrap();
+    assert_eq!(similar.similar_to.as_ref().map(|s| &s[..]), Some("foo_popular_crate"));
+    assert_eq!(similar.flags, ["new_publisher", "similar_name"]);
+    let unrelated = json.publishes.iter()
+        .find(|p| p.crate_name == "foo_unrelated")
+        .unwrap();
+    assert_eq!(unrelated.flags, ["new_publisher"]);
+}
+
+#[test]
+fn abuse_reports() {
+    #[derive(RustcDecodable)]
+    struct Reports { reports: Vec<EncodableAbuseReport> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_reported/reports");
+    let admin;
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let reporter = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_crate("foo_reported").create_or_update(&conn, None, owner.id).unwrap();
+        admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &reporter);
+    }
+
+    let json = bad_resp!(middle.call(req.with_body(br#"{"reason":" "}"#)));
+    assert!(json.errors[0].detail.contains("reason is required"), "{:?}", json.errors);
+    let body = br#"{"reason":"typosquats foo"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body)));
+    assert!(::json::<O>(&mut response).ok);
+    let json = bad_resp!(middle.call(req.with_body(body)));
+    assert!(json.errors[0].detail.contains("already reported"), "{:?}", json.errors);
+
+    let json = bad_resp!(middle.call(req.with_method(Method::Get)
+                                        .with_path("/api/v1/admin/reports")));
+    assert!(json.errors[0].detail.contains("must be an admin"), "{:?}", json.errors);
+    ::sign_in_as(&mut req, &admin);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Reports = ::json(&mut response);
+    assert_eq!(json.reports.len(), 1);
+    assert_eq!(json.reports[0].crate_name, "foo_reported");
+    assert_eq!(json.reports[0].reported_by, "bar");
+    assert_eq!(json.reports[0].reason, "typosquats foo");
+
+    let path = format!("/api/v1/admin/reports/{}/resolve", json.reports[0].id);
+    let body = br#"{"resolution":"not squatting"}"#;
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put)
+                                               .with_path(&path)
+                                               .with_body(body)));
+    assert!(::json::<O>(&mut response).ok);
+    let json = bad_resp!(middle.call(req.with_body(body)));
+    assert!(json.errors[0].detail.contains("not open"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/admin/reports")));
+    assert_eq!(::json::<Reports>(&mut response).reports.len(), 0);
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/admin/audit_log")
+                                               .with_query("crate=foo_reported")));
+    let json: AuditLog = ::json(&mut response);
+    assert_eq!(json.entries[0].action, "resolve_report");
+}
+
+fn held_version(app: &App, name: &str) -> Version {
+    let conn = app.diesel_database.get().unwrap();
+    let user = ::new_user("foo").create_or_update(&conn).unwrap();
+    let krate = ::new_crate(name).create_or_update(&conn, None, user.id).unwrap();
+    let version = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+    let entry = git::Crate {
+        name: name.to_string(),
+        vers: "1.0.0".to_string(),
+        deps: Vec::new(),
+        cksum: repeat("0").take(64).collect(),
+        features: HashMap::new(),
+        yanked: Some(false),
+        links: None,
+        rust_version: None,
+        features2: None,
+        v: None,
+    };
+    let reasons = ["it contains precompiled binaries: bin/tool".to_string()];
+    quarantine::hold(&conn, &version, &reasons, &entry).unwrap();
+    version
+}
+
+#[test]
+fn approve_quarantined_version() {
+    let (_b, app, middle) = ::app();
+    let version = held_version(&app, "fqa");
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/admin/quarantine");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Quarantine = ::json(&mut response);
+    assert_eq!(json.versions.len(), 1);
+    assert_eq!(json.versions[0].version.id, version.id);
+    assert!(json.versions[0].version.quarantined);
+    assert_eq!(json.versions[0].reasons, ["it contains precompiled binaries: bin/tool"]);
+    assert!(!::git::checkout().join("3/f/fqa").exists());
+
+    let path = format!("/api/v1/admin/quarantine/{}/approve", version.id);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put).with_path(&path)));
+    assert!(::json::<O>(&mut response).ok);
+    assert!(::git::checkout().join("3/f/fqa").exists());
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(json.errors[0].detail.contains("not waiting for review"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/admin/quarantine")));
+    assert!(::json::<Quarantine>(&mut response).versions.is_empty());
+    let conn = app.diesel_database.get().unwrap();
+    let quarantined = versions::table.find(version.id)
+        .select(versions::quarantined)
+        .first::<bool>(&*conn)
+        .unwrap();
+    assert!(!quarantined);
+}
+
+#[test]
+fn reject_quarantined_version() {
+    let (_b, app, middle) = ::app();
+    let version = held_version(&app, "foo_rejected");
+    let path = format!("/api/v1/admin/quarantine/{}", version.id);
+    let mut req = ::req(app.clone(), Method::Delete, &path);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let json = bad_resp!(middle.call(req.with_body(br#"{"reason":""}"#)));
+    assert!(json.errors[0].detail.contains("reason is required"), "{:?}", json.errors);
+    let mut response = ok_resp!(middle.call(req.with_body(br#"{"reason":"malware"}"#)));
+    assert!(::json::<O>(&mut response).ok);
+
+    let conn = app.diesel_database.get().unwrap();
+    let (quarantined, yanked) = versions::table.find(version.id)
+        .select((versions::quarantined, versions::yanked))
+        .first::<(bool, bool)>(&*conn)
+        .unwrap();
+    assert!(quarantined);
+    assert!(yanked);
+}
+
+#[test]
+fn mirror_downloads_are_counted_separately() {
+    #[derive(RustcDecodable)]
+    struct NewMirror { mirror: EncodableMirror }
+    #[derive(RustcDecodable)]
+    struct Mirrors { mirrors: Vec<EncodableMirror> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/mirrors");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_mirrored"));
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let mut response = ok_resp!(middle.call(req.with_body(br#"{"name":"mirror.example.com"}"#)));
+    let mirror = ::json::<NewMirror>(&mut response).mirror;
+    let token = mirror.token.unwrap();
+    bad_resp!(middle.call(&mut req));
+
+    req.header("X-Mirror-Token", &token);
+    let response = t_resp!(middle.call(req.with_method(Method::Get)
+                                          .with_path("/api/v1/crates/foo_mirrored/1.0.0/download")
+                                          .with_body(&[])));
+    assert_eq!(response.status.0, 302);
+    assert!(app.pending_downloads.is_empty());
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/admin/mirrors")));
+    let json: Mirrors = ::json(&mut response);
+    assert_eq!(json.mirrors.len(), 1);
+    assert_eq!(json.mirrors[0].name, "mirror.example.com");
+    assert_eq!(json.mirrors[0].downloads_last_30_days, 1);
+    assert!(json.mirrors[0].token.is_none());
+
+    let path = format!("/api/v1/admin/mirrors/{}", mirror.id);
+    ok_resp!(middle.call(req.with_method(Method::Delete).with_path(&path)));
+    let json = bad_resp!(middle.call(req.with_method(Method::Get)
+                                        .with_path("/api/v1/crates/foo_mirrored/1.0.0/download")));
+    assert!(json.errors[0].detail.contains("mirror token"), "{:?}", json.errors);
+}
+
+#[test]
+fn readme_backfill() {
+    #[derive(RustcDecodable)]
+    struct Backfill { backfill: EncodableReadmeBackfill }
+    #[derive(RustcDecodable)]
+    struct Readme { readme: EncodableReadme }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/readme_backfill");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_readme").create_or_update(&conn, None, user.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        ::new_version(krate.id, "1.1.0").save(&conn, &[]).unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+
+    let json = bad_resp!(middle.call(req.with_body(br#"{"batch_size":0}"#)));
+    assert!(json.errors[0].detail.contains("batch size"), "{:?}", json.errors);
+    let mut response = ok_resp!(middle.call(req.with_body(br#"{"batch_size":1}"#)));
+    let backfill = ::json::<Backfill>(&mut response).backfill;
+    assert_eq!(backfill.remaining, 2);
+    let json = bad_resp!(middle.call(req.with_body(br#"{"batch_size":1}"#)));
+    assert!(json.errors[0].detail.contains("already running"), "{:?}", json.errors);
+
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let fetch = |name: &str, num: &str| {
+            if num == "1.0.0" {
+                return Err(human("not found"))
+            }
+            Ok(vec![TarballFile {
+                path: format!("{}-{}/README.md", name, num),
+                size: 4,
+                contents: b"# hi".to_vec(),
+            }])
+        };
+        let pause = |_: Duration| {};
+        let backfill = readme::run_batch(&conn, &fetch, &pause).unwrap().unwrap();
+        assert_eq!((backfill.rendered, backfill.failed), (0, 1));
+        let backfill = readme::run_batch(&conn, &fetch, &pause).unwrap().unwrap();
+        assert_eq!((backfill.rendered, backfill.failed), (1, 1));
+        assert!(backfill.finished_at.is_none());
+        let backfill = readme::run_batch(&conn, &fetch, &pause).unwrap().unwrap();
+        assert!(backfill.finished_at.is_some());
+        assert!(readme::run_batch(&conn, &fetch, &pause).unwrap().is_none());
+    }
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let backfill = ::json::<Backfill>(&mut response).backfill;
+    assert_eq!((backfill.rendered, backfill.failed, backfill.remaining), (1, 1, 0));
+    assert!(backfill.finished_at.is_some());
+
+    let path = "/api/v1/crates/foo_readme/1.1.0/readme";
+    let mut response = ok_resp!(middle.call(req.with_path(path)));
+    let json: Readme = ::json(&mut response);
+    assert_eq!(json.readme.path, "README.md");
+    assert_eq!(json.readme.html.trim(), "<h1>hi</h1>");
+    let path = "/api/v1/crates/foo_readme/1.0.0/readme";
+    let response = t_resp!(middle.call(req.with_path(path)));
+    assert_eq!(response.status.0, 404);
+}
+
+#[test]
+fn download_flush_metrics() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_flushed/1.0.0/download");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_flushed"));
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 302);
+
+    bad_resp!(middle.call(req.with_path("/api/v1/admin/download_flushes")));
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+    }
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Flushes = ::json(&mut response);
+    assert_eq!(json.flushes[0].table, "version_downloads");
+    assert_eq!(json.flushes[0].flushes, 1);
+    assert_eq!(json.flushes[0].rows, 1);
+    assert_eq!(json.flushes[0].downloads, 1);
+    assert_eq!(json.flushes[0].pending_rows, 0);
+    assert_eq!(json.flushes[0].failed_flushes, 0);
+    // Nothing is shadowed while batching is fully rolled out
+    assert_eq!(json.flushes[1].table, "version_downloads_shadow");
+    assert_eq!(json.flushes[1].flushes, 0);
+}
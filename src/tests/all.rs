@@ -19,17 +19,21 @@ extern crate s3;
 
 use rustc_serialize::json::{self, Json};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 use std::sync::{Once, ONCE_INIT, Arc};
 
 use cargo_registry::app::App;
 use cargo_registry::category::NewCategory;
+use cargo_registry::config::Rollout;
 use cargo_registry::db::{self, RequestTransaction};
 use cargo_registry::dependency::Kind;
 use cargo_registry::krate::NewCrate;
+use cargo_registry::token::ApiToken;
+use cargo_registry::scanner;
 use cargo_registry::upload as u;
+use cargo_registry::util::Cidr;
 use cargo_registry::user::NewUser;
 use cargo_registry::version::NewVersion;
 use cargo_registry::{User, Crate, Version, Keyword, Dependency, Category, Model, Replica};
@@ -72,17 +76,31 @@ struct Error { detail: String }
 #[derive(RustcDecodable)]
 struct Bad { errors: Vec<Error> }
 
+mod admin;
 mod badge;
 mod category;
 mod git;
+mod hub;
 mod keyword;
 mod krate;
+mod org;
+mod permission;
 mod record;
+mod search;
+mod settings;
 mod team;
+mod token;
 mod user;
 mod version;
 
 fn app() -> (record::Bomb, Arc<App>, conduit_middleware::MiddlewareBuilder) {
+    app_with(|_| {})
+}
+
+/// Like `app`, with the test configuration changed by `configure`.
+fn app_with<F>(configure: F) -> (record::Bomb, Arc<App>, conduit_middleware::MiddlewareBuilder)
+    where F: FnOnce(&mut cargo_registry::Config)
+{
     dotenv::dotenv().ok();
     static INIT: Once = ONCE_INIT;
     git::init();
@@ -102,7 +120,7 @@ fn app() -> (record::Bomb, Arc<App>, conduit_middleware::MiddlewareBuilder) {
         proxy: Some(proxy),
     };
 
-    let config = cargo_registry::Config {
+    let mut config = cargo_registry::Config {
         uploader: uploader,
         session_key: "test".to_string(),
         git_repo_checkout: git::checkout(),
@@ -113,7 +131,26 @@ fn app() -> (record::Bomb, Arc<App>, conduit_middleware::MiddlewareBuilder) {
         max_upload_size: 1000,
         mirror: Replica::Primary,
         api_protocol: api_protocol,
+        download_stats: true,
+        admin_gh_ids: vec![ADMIN_GH_ID],
+        search_weights: Default::default(),
+        private_crates: true,
+        stale_token_days: None,
+        trusted_proxies: vec![Cidr::parse("127.0.0.1").unwrap()],
+        scanners: scanner::default_scanners(HashSet::new()),
+        scan_policy: Default::default(),
+        signing_key: None,
+        index_v2: false,
+        slow_query_ms: None,
+        max_per_page: 100,
+        download_url_template: None,
+        serve_downloads: false,
+        async_downloads: Rollout::On,
+        download_dedupe_secs: 0,
+        max_body_size: 1024 * 1024,
+        body_timeout_secs: 30,
     };
+    configure(&mut config);
     INIT.call_once(|| db_setup(&config.db_url));
     let app = App::new(&config);
     t!(t!(app.diesel_database.get()).begin_test_transaction());
@@ -201,6 +238,13 @@ fn new_user(login: &str) -> NewUser {
     }
 }
 
+/// The GitHub id of the one user configured as an admin during tests.
+const ADMIN_GH_ID: i32 = -1_000;
+
+fn new_admin(login: &str) -> NewUser {
+    NewUser { gh_id: ADMIN_GH_ID, ..new_user(login) }
+}
+
 fn user(login: &str) -> User {
     User {
         id: NEXT_ID.fetch_add(1, Ordering::SeqCst) as i32,
@@ -223,7 +267,7 @@ fn new_crate(name: &str) -> NewCrate {
 
 fn new_version(crate_id: i32, num: &str) -> NewVersion {
     let num = semver::Version::parse(num).unwrap();
-    NewVersion::new(crate_id, &num, &HashMap::new()).unwrap()
+    NewVersion::new(crate_id, &num, &HashMap::new(), None, None, None).unwrap()
 }
 
 fn krate(name: &str) -> Crate {
@@ -240,6 +284,10 @@ fn krate(name: &str) -> Crate {
         license: None,
         repository: None,
         max_upload_size: None,
+        repository_verified: false,
+        first_published_at: None,
+        first_published_by: None,
+        dependents_cnt: 0,
     }
 }
 
@@ -316,6 +364,7 @@ fn mock_category(req: &mut Request, name: &str, slug: &str) -> Category {
 
 fn logout(req: &mut Request) {
     req.mut_extensions().pop::<User>();
+    req.mut_extensions().pop::<ApiToken>();
 }
 
 fn request_with_user_and_mock_crate(
@@ -401,7 +450,27 @@ fn new_req_body(krate: Crate, version: &str, deps: Vec<u::CrateDependency>,
         license_file: None,
         repository: krate.repository,
         badges: Some(badges),
-    }, &[])
+        links: None,
+        rust_version: None,
+        provenance: None,
+    }, EMPTY_TARBALL)
+}
+
+/// A gzipped tarball without any files in it.
+const EMPTY_TARBALL: &'static [u8] = b"\
+    \x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x63\x60\x18\x05\xa3\x60\
+    \x14\x8c\x54\x00\x00\x2e\xaf\xb5\xef\x00\x04\x00\x00";
+
+/// `EMPTY_TARBALL` padded out to `size` bytes with the extra field of the
+/// gzip header.
+fn padded_tarball(size: usize) -> Vec<u8> {
+    let padding = size - EMPTY_TARBALL.len() - 2;
+    let mut tarball = EMPTY_TARBALL[..10].to_vec();
+    tarball[3] |= 0x04;
+    tarball.extend(&[padding as u8, (padding >> 8) as u8]);
+    tarball.extend(vec![0; padding]);
+    tarball.extend(&EMPTY_TARBALL[10..]);
+    tarball
 }
 
 fn new_crate_to_body(new_crate: &u::NewCrate, krate: &[u8]) -> Vec<u8> {
@@ -3,6 +3,7 @@ use conduit_test::MockRequest;
 
 use cargo_registry::db::RequestTransaction;
 use cargo_registry::category::{Category, EncodableCategory, EncodableCategoryWithSubcategories};
+use cargo_registry::stats::{CategoryStats, EncodableCategoryStats};
 
 #[derive(RustcDecodable)]
 struct CategoryList { categories: Vec<EncodableCategory>, meta: CategoryMeta }
@@ -142,3 +143,41 @@ fn update_crate() {
     assert_eq!(cnt(&mut req, "cat1::bar"), 1);
     assert_eq!(cnt(&mut req, "category-2"), 0);
 }
+
+#[test]
+fn stats() {
+    #[derive(RustcDecodable)] struct R { stats: Vec<EncodableCategoryStats> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/categories/foo-stats/stats");
+    let response = t_resp!(middle.call(&mut req));
+    assert_eq!(response.status.0, 404);
+
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_category(&mut req, "Foo Stats", "foo-stats");
+    ::mock_category(&mut req, "Foo Stats::Bar", "foo-stats::bar");
+    let (krate, version) = ::mock_crate(&mut req, ::krate("foo_in_stats"));
+    Category::update_crate_old(req.tx().unwrap(), &krate, &["foo-stats".to_string()]).unwrap();
+    let (krate, _) = ::mock_crate(&mut req, ::krate("bar_in_stats"));
+    Category::update_crate_old(req.tx().unwrap(), &krate,
+                               &["foo-stats::bar".to_string()]).unwrap();
+    ::mock_crate(&mut req, ::krate("baz_not_in_stats"));
+    req.tx().unwrap().execute("INSERT INTO version_downloads (version_id, downloads, date)
+                               VALUES ($1, 10, CURRENT_DATE)", &[&version.id]).unwrap();
+
+    // Counting again replaces the months counted before
+    CategoryStats::snapshot(req.tx().unwrap()).unwrap();
+    CategoryStats::snapshot(req.tx().unwrap()).unwrap();
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let stats = ::json::<R>(&mut response).stats;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].month.len(), "2017-04".len());
+    assert_eq!(stats[0].crates, 2);
+    assert_eq!(stats[0].downloads, 10);
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/categories/foo-stats::bar/stats")));
+    let stats = ::json::<R>(&mut response).stats;
+    assert_eq!(stats[0].crates, 1);
+    assert_eq!(stats[0].downloads, 0);
+}
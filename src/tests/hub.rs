@@ -0,0 +1,108 @@
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+use conduit::{Handler, Method};
+use rustc_serialize::json;
+use url::Url;
+
+use cargo_registry::db::RequestTransaction;
+use cargo_registry::hub::{self, IndexSubscription, Ping};
+use cargo_registry::mirror::{self, Mirror};
+use cargo_registry::user::User;
+
+/// A callback which confirms every verification it's sent, and hands over
+/// the head and body of the first `n` requests it gets.
+fn callback(n: usize) -> (String, mpsc::Receiver<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hub", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(n) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" { break }
+                if line.to_lowercase().starts_with("content-length:") {
+                    content_length = line[15..].trim().parse().unwrap();
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let path = head.split(' ').nth(1).unwrap().to_string();
+            let url = Url::parse(&format!("http://localhost{}", path)).unwrap();
+            let challenge = url.query_pairs()
+                .find(|&(ref k, _)| k == "hub.challenge")
+                .map(|(_, v)| v.into_owned())
+                .unwrap_or(String::new());
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   challenge.len(), challenge).unwrap();
+            tx.send((head, String::from_utf8(body).unwrap())).unwrap();
+        }
+    });
+    (url, rx)
+}
+
+#[test]
+fn mirrors_subscribe_and_get_signed_pings() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Put, "/api/v1/index/subscriptions");
+    let (callback, requests) = callback(3);
+    let token = Mirror::create(req.tx().unwrap(), "mirror.example.com").unwrap().token;
+    let body = format!(r#"{{"callback":"{}","secret":"hunter2"}}"#, callback);
+
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    req.header(mirror::TOKEN_HEADER, &token);
+    ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let (head, _) = requests.recv().unwrap();
+    assert!(head.starts_with("GET /hub?hub.mode=subscribe&hub.topic="), "{}", head);
+
+    // Subscribers are only told about what happens after they subscribed
+    assert_eq!(hub::notify_all(req.tx().unwrap()).unwrap(), 0);
+    {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        for action in &["publish_version", "update_settings", "yank_version"] {
+            tx.execute("INSERT INTO audit_log (user_id, action, crate_name, details) \
+                        VALUES ($1, $2, 'foo', '1.0.0')",
+                       &[&user.id, action]).unwrap();
+        }
+    }
+    let seq = hub::latest_seq(req.tx().unwrap()).unwrap();
+    assert_eq!(hub::notify_all(req.tx().unwrap()).unwrap(), 1);
+    let (head, ping) = requests.recv().unwrap();
+    let signature = hub::sign("hunter2", ping.as_bytes()).unwrap();
+    assert!(head.contains(&format!("X-Hub-Signature: {}", signature)), "{}", head);
+    let ping: Ping = json::decode(&ping).unwrap();
+    assert_eq!(ping.seq, seq);
+    assert!(ping.since < seq);
+    assert_eq!(hub::notify_all(req.tx().unwrap()).unwrap(), 0);
+
+    let body = format!(r#"{{"callback":"{}"}}"#, callback);
+    ok_resp!(middle.call(req.with_method(Method::Delete).with_body(body.as_bytes())));
+    let (head, _) = requests.recv().unwrap();
+    assert!(head.starts_with("GET /hub?hub.mode=unsubscribe&"), "{}", head);
+    assert!(IndexSubscription::behind(req.tx().unwrap(), seq + 1).unwrap().is_empty());
+}
+
+#[test]
+fn callbacks_must_be_urls_with_a_secret() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Put, "/api/v1/index/subscriptions");
+    let token = Mirror::create(req.tx().unwrap(), "mirror.example.com").unwrap().token;
+    req.header(mirror::TOKEN_HEADER, &token);
+
+    for body in &[r#"{"callback":"not a url","secret":"s"}"#,
+                  r#"{"callback":"ftp://mirror.example.com/","secret":"s"}"#,
+                  r#"{"callback":"https://mirror.example.com/"}"#] {
+        bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    }
+}
@@ -3,29 +3,46 @@ use std::io::prelude::*;
 use std::fs::{self, File};
 
 use conduit::{Handler, Method};
+use diesel;
 use diesel::prelude::*;
 
 use git2;
 use rustc_serialize::json;
 use semver;
+use time;
 
+use cargo_registry::audit::{self, EncodableActivity, EncodableEvent};
+use cargo_registry::config::Rollout;
 use cargo_registry::db::RequestTransaction;
 use cargo_registry::dependency::EncodableDependency;
 use cargo_registry::download::EncodableVersionDownload;
 use cargo_registry::keyword::{Keyword, EncodableKeyword};
-use cargo_registry::krate::{Crate, EncodableCrate};
+use cargo_registry::krate::{Crate, EncodableCrate, SearchFacets};
+use cargo_registry::stats::{EncodableRegistryStats, RegistryStats};
+use cargo_registry::tag_history::EncodableTagSnapshot;
 use cargo_registry::upload as u;
 use cargo_registry::user::EncodableUser;
+use cargo_registry::util::PaginationMeta;
 use cargo_registry::version::EncodableVersion;
 use cargo_registry::category::Category;
 
 #[derive(RustcDecodable)]
 struct CrateList { crates: Vec<EncodableCrate>, meta: CrateMeta }
 #[derive(RustcDecodable)]
-struct VersionsList { versions: Vec<EncodableVersion> }
+struct VersionsList { versions: Vec<EncodableVersion>, meta: VersionsMeta }
+#[derive(RustcDecodable)]
+struct VersionsMeta {
+    total: i64,
+    next_page: Option<String>,
+    highest_stable: Option<String>,
+}
 #[derive(RustcDecodable)]
 struct CrateMeta { total: i32 }
 #[derive(RustcDecodable)]
+struct SearchList { meta: SearchMeta }
+#[derive(RustcDecodable)]
+struct SearchMeta { facets: Option<SearchFacets>, suggestions: Vec<String> }
+#[derive(RustcDecodable)]
 struct GitCrate { name: String, vers: String, deps: Vec<String>, cksum: String }
 #[derive(RustcDecodable)]
 struct Warnings { invalid_categories: Vec<String>, invalid_badges: Vec<String> }
@@ -57,6 +74,9 @@ fn new_crate(name: &str) -> u::NewCrate {
         license_file: None,
         repository: None,
         badges: None,
+        links: None,
+        rust_version: None,
+        provenance: None,
     }
 }
 
@@ -119,11 +139,22 @@ fn index_queries() {
     assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
     let mut response = ok_resp!(middle.call(req.with_query("q=kw1")));
     assert_eq!(::json::<CrateList>(&mut response).meta.total, 2);
-    let mut response = ok_resp!(middle.call(req.with_query("q=readme")));
-    assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
     let mut response = ok_resp!(middle.call(req.with_query("q=description")));
     assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
 
+    // READMEs are only searched for crates which opted in
+    let mut response = ok_resp!(middle.call(req.with_query("q=readme")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 0);
+    {
+        use cargo_registry::schema::crates;
+
+        let conn = app.diesel_database.get().unwrap();
+        diesel::update(&krate).set(crates::readme_in_search.eq(true))
+            .execute(&*conn).unwrap();
+    }
+    let mut response = ok_resp!(middle.call(req.with_query("q=readme")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
+
     let query = format!("user_id={}", u.id);
     let mut response = ok_resp!(middle.call(req.with_query(&query)));
     assert_eq!(::json::<CrateList>(&mut response).crates.len(), 2);
@@ -212,6 +243,63 @@ fn exact_match_first_on_queries() {
     assert_eq!(json.crates[2].name, "foo_exact");
 }
 
+#[test]
+fn search_facets() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates");
+    ::mock_user(&mut req, ::user("foo"));
+    let (one, _) = ::mock_crate(&mut req, ::krate("foo_facets_one"));
+    let (two, _) = ::mock_crate(&mut req, ::krate("foo_facets_two"));
+    let (other, _) = ::mock_crate(&mut req, ::krate("bar_facets"));
+    ::mock_category(&mut req, "Cat 1", "cat1");
+    {
+        let tx = req.tx().unwrap();
+        Category::update_crate_old(tx, &one, &["cat1".to_string()]).unwrap();
+        Category::update_crate_old(tx, &two, &["cat1".to_string()]).unwrap();
+        Category::update_crate_old(tx, &other, &["cat1".to_string()]).unwrap();
+        Keyword::update_crate_old(tx, &one, &["kw1".to_string()]).unwrap();
+        Keyword::update_crate_old(tx, &two, &["kw1".to_string(), "kw2".to_string()]).unwrap();
+    }
+
+    let mut response = ok_resp!(middle.call(req.with_query("q=foo")));
+    let facets = ::json::<SearchList>(&mut response).meta.facets.unwrap();
+    let categories = facets.categories.iter()
+        .map(|f| (&f.value[..], f.crates))
+        .collect::<Vec<_>>();
+    assert_eq!(categories, [("cat1", 2)]);
+    let keywords = facets.keywords.iter()
+        .map(|f| (&f.value[..], f.crates))
+        .collect::<Vec<_>>();
+    assert_eq!(keywords, [("kw1", 2), ("kw2", 1)]);
+
+    let mut response = ok_resp!(middle.call(req.with_query("letter=f")));
+    assert!(::json::<SearchList>(&mut response).meta.facets.is_none());
+}
+
+#[test]
+fn search_suggestions() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("serde_suggest"));
+    ::mock_crate(&mut req, ::krate("unrelated"));
+
+    let mut response = ok_resp!(middle.call(req.with_query("q=serd-sugest")));
+    let meta = ::json::<SearchList>(&mut response).meta;
+    assert_eq!(meta.suggestions, ["serde_suggest"]);
+
+    let mut response = ok_resp!(middle.call(req.with_query("letter=s")));
+    assert!(::json::<SearchList>(&mut response).meta.suggestions.is_empty());
+
+    // Nor do pages past the end of a search which has results
+    let mut response = ok_resp!(middle.call(req.with_query("q=serde_suggest&page=2")));
+    let json: CrateList = ::json(&mut response);
+    assert_eq!(json.crates.len(), 0);
+    assert_eq!(json.meta.total, 1);
+    let mut response = ok_resp!(middle.call(req.with_query("q=serde_suggest&page=2")));
+    assert!(::json::<SearchList>(&mut response).meta.suggestions.is_empty());
+}
+
 #[test]
 fn exact_match_on_queries_with_sort() {
     use diesel::update;
@@ -328,6 +416,155 @@ fn versions() {
     assert_eq!(json.versions.len(), 1);
 }
 
+#[test]
+fn versions_sorting() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_versions_sort/versions");
+    ::mock_user(&mut req, ::user("foo"));
+    let krate = ::krate("foo_versions_sort");
+    for num in &["0.2.1", "0.10.0", "1.0.0-beta.1", "0.9.0"] {
+        ::mock_crate_vers(&mut req, krate.clone(), &semver::Version::parse(num).unwrap());
+    }
+    req.tx().unwrap().execute("UPDATE versions \
+                                  SET created_at = created_at - (id || ' days')::interval",
+                              &[]).unwrap();
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: VersionsList = ::json(&mut response);
+    let nums = json.versions.iter().map(|v| &v.num[..]).collect::<Vec<_>>();
+    assert_eq!(nums, ["1.0.0-beta.1", "0.10.0", "0.9.0", "0.2.1"]);
+    assert_eq!(json.meta.highest_stable, Some("0.10.0".to_string()));
+    assert_eq!(json.meta.total, 4);
+    assert_eq!(json.meta.next_page, None);
+
+    let mut response = ok_resp!(middle.call(req.with_query("page=2&per_page=3")));
+    let json: VersionsList = ::json(&mut response);
+    let nums = json.versions.iter().map(|v| &v.num[..]).collect::<Vec<_>>();
+    assert_eq!(nums, ["0.2.1"]);
+    assert_eq!(json.meta.total, 4);
+    assert_eq!(json.meta.highest_stable, Some("0.10.0".to_string()));
+
+    let mut response = ok_resp!(middle.call(req.with_query("sort=date")));
+    let json: VersionsList = ::json(&mut response);
+    let nums = json.versions.iter().map(|v| &v.num[..]).collect::<Vec<_>>();
+    assert_eq!(nums, ["0.2.1", "0.10.0", "1.0.0-beta.1", "0.9.0"]);
+}
+
+#[test]
+fn versions_include_publisher() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_publisher/versions");
+    let user = ::mock_user(&mut req, ::user("foo"));
+    let (_, version) = ::mock_crate(&mut req, ::krate("foo_publisher"));
+    {
+        let tx = req.tx().unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name) \
+                             VALUES ($1, 'travis') RETURNING id",
+                            &[&user.id]).unwrap();
+        let token_id: i32 = rows.get(0).get("id");
+        tx.execute("UPDATE versions SET published_by = $1, \
+                                        published_with_token_id = $2 \
+                    WHERE id = $3",
+                   &[&user.id, &token_id, &version.id]).unwrap();
+    }
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: VersionsList = ::json(&mut response);
+    let published_by = json.versions[0].published_by.as_ref().unwrap();
+    assert_eq!(published_by.login, "foo");
+    assert_eq!(json.versions[0].published_with_token, Some("travis".to_string()));
+}
+
+#[test]
+fn aliases_redirect_to_the_renamed_crate() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_old_name");
+    ::mock_user(&mut req, ::user("foo"));
+    let (krate, _) = ::mock_crate(&mut req, ::krate("foo_new_name"));
+    req.tx().unwrap().execute("INSERT INTO crate_aliases (alias, crate_id) \
+                               VALUES ('foo_old_name', $1)",
+                              &[&krate.id]).unwrap();
+
+    let resp = t_resp!(middle.call(&mut req));
+    assert_eq!(resp.status.0, 301);
+    assert_eq!(resp.headers["Location"], ["/api/v1/crates/foo_new_name"]);
+
+    let resp = t_resp!(middle.call(req.with_path("/api/v1/crates/foo-old-name/1.0.0/download")));
+    assert_eq!(resp.status.0, 301);
+    assert_eq!(resp.headers["Location"], ["/api/v1/crates/foo_new_name/1.0.0/download"]);
+
+    bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_unknown")));
+
+    // Only missing versions are looked up as aliases, other failures aren't
+    // hidden behind a redirect
+    req.header("X-Mirror-Token", "revoked");
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_old_name/1.0.0/download")));
+    assert!(json.errors[0].detail.contains("mirror token"), "{:?}", json.errors);
+}
+
+#[test]
+fn readme_search_opt_in() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_readme_search/settings");
+    let owner = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let mut krate = ::new_crate("foo_readme_search");
+        krate.readme = Some("# Xylophones\n\nSee [the docs](https://example.com/zither).");
+        krate.create_or_update(&conn, None, owner.id).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &other);
+        owner
+    };
+
+    let body = r#"{"readme_in_search":true}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+
+    ::sign_in_as(&mut req, &owner);
+    ok_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates")
+                                               .with_query("q=xylophones")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
+    // Link targets aren't part of the rendered text
+    let mut response = ok_resp!(middle.call(req.with_query("q=zither")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 0);
+}
+
+#[test]
+fn unlisted_crates_are_hidden() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_unlisted/settings");
+    let owner = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_crate("foo_unlisted").create_or_update(&conn, None, owner.id).unwrap();
+        owner
+    };
+    ::sign_in_as(&mut req, &owner);
+
+    let body = r#"{"unlisted":true}"#;
+    ok_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates")
+                                               .with_query("q=foo_unlisted")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 0);
+
+    // The owner's own crates still list it
+    let query = format!("user_id={}", owner.id);
+    let mut response = ok_resp!(middle.call(req.with_query(&query)));
+    let json: CrateList = ::json(&mut response);
+    assert_eq!(json.crates.len(), 1);
+    assert_eq!(json.crates[0].name, "foo_unlisted");
+
+    // Anyone who knows the name can still see the crate
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_unlisted")
+                                               .with_query("")));
+    assert_eq!(::json::<CrateResponse>(&mut response).krate.name, "foo_unlisted");
+}
+
 #[test]
 fn new_wrong_token() {
     let (_b, app, middle) = ::app();
@@ -427,6 +664,86 @@ fn new_krate_with_dependency() {
     ::json::<GoodCrate>(&mut response);
 }
 
+#[test]
+fn dependents_cnt_follows_publishes_and_yanks() {
+    let (_b, app, middle) = ::app();
+    let dep = u::CrateDependency {
+        name: u::CrateName("foo_depended".to_string()),
+        optional: false,
+        default_features: true,
+        features: Vec::new(),
+        version_req: u::CrateVersionReq(semver::VersionReq::parse("^1.0").unwrap()),
+        target: None,
+        kind: None,
+    };
+    let mut req = ::new_req_full(app.clone(), ::krate("foo_dependent"), "1.0.0", vec![dep]);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::new_crate("foo_depended").create_or_update(&conn, None, user.id).unwrap();
+    }
+    ok_resp!(middle.call(&mut req));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates")
+                                               .with_query("sort=dependents")));
+    let json: CrateList = ::json(&mut response);
+    assert_eq!(json.crates[0].name, "foo_depended");
+    assert_eq!(json.crates[0].dependents_cnt, 1);
+    assert_eq!(json.crates[1].dependents_cnt, 0);
+
+    // Once the dependent's only version is yanked it no longer counts
+    ok_resp!(middle.call(req.with_method(Method::Delete)
+                            .with_path("/api/v1/crates/foo_dependent/1.0.0/yank")
+                            .with_query("")));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates")
+                                               .with_query("sort=dependents")));
+    let json: CrateList = ::json(&mut response);
+    assert_eq!(json.crates.len(), 1);
+    assert_eq!(json.crates[0].dependents_cnt, 0);
+}
+
+#[test]
+fn new_krate_without_categories_gets_suggestions() {
+    #[derive(RustcDecodable)]
+    struct Warnings { suggested_categories: Vec<String> }
+    #[derive(RustcDecodable)]
+    struct R { warnings: Warnings }
+
+    let (_b, app, middle) = ::app();
+    let mut new_crate = new_crate("foo_suggest");
+    new_crate.description = Some("A streaming parser for INI files".to_string());
+    new_crate.deps.push(u::CrateDependency {
+        name: u::CrateName("foo_suggest_dep".to_string()),
+        optional: false,
+        default_features: true,
+        features: Vec::new(),
+        version_req: u::CrateVersionReq(semver::VersionReq::parse(">= 0").unwrap()),
+        target: None,
+        kind: None,
+    });
+    let mut req = ::new_req(app.clone(), "foo_suggest", "1.1.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::new_category("Asynchronous", "asynchronous").find_or_create(&conn).unwrap();
+        ::new_category("Parser implementations", "parser-implementations")
+            .find_or_create(&conn).unwrap();
+        ::new_category("Text editors", "text-editors").find_or_create(&conn).unwrap();
+        let dep = ::new_crate("foo_suggest_dep").create_or_update(&conn, None, user.id).unwrap();
+        Category::update_crate(&conn, &dep, &["asynchronous"]).unwrap();
+    }
+
+    let body = ::new_crate_to_body(&new_crate, ::EMPTY_TARBALL);
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let json = ::json::<R>(&mut response);
+    assert_eq!(json.warnings.suggested_categories,
+               ["asynchronous", "parser-implementations"]);
+}
+
 #[test]
 fn new_krate_with_wildcard_dependency() {
     let (_b, app, middle) = ::app();
@@ -571,6 +888,16 @@ fn new_krate_too_big() {
     bad_resp!(middle.call(req.with_body(&body)));
 }
 
+#[test]
+fn new_krate_with_invalid_tarball() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_not_gzip", "1.0.0");
+    ::sign_in(&mut req, &app);
+    let body = ::new_crate_to_body(&new_crate("foo_not_gzip"), b"not a tarball");
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert!(json.errors[0].detail.contains("not a valid gzipped tarball"), "{:?}", json.errors);
+}
+
 #[test]
 fn new_krate_too_big_but_whitelisted() {
     let (_b, app, middle) = ::app();
@@ -583,11 +910,218 @@ fn new_krate_too_big_but_whitelisted() {
         krate.max_upload_size = Some(2_000_000);
         krate.create_or_update(&conn, None, user.id).unwrap();
     }
-    let body = ::new_crate_to_body(&new_crate("foo_whitelist"), &[b'a'; 2000]);
+    let body = ::new_crate_to_body(&new_crate("foo_whitelist"), &::padded_tarball(2000));
     let mut response = ok_resp!(middle.call(req.with_body(&body)));
     ::json::<GoodCrate>(&mut response);
 }
 
+#[test]
+fn new_krate_with_features2_needs_index_v2() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_features2", "1.1.0");
+    ::sign_in(&mut req, &app);
+    let mut new_crate = new_crate("foo_features2");
+    new_crate.features.insert(u::CrateName("serde".to_string()),
+                              vec![u::Feature("dep:serde_json".to_string())]);
+    let body = ::new_crate_to_body(&new_crate, ::EMPTY_TARBALL);
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert!(json.errors[0].detail.contains("version 2 index entries"),
+            "{:?}", json.errors);
+    assert!(!::git::checkout().join("fo/o_/foo_features2").exists());
+}
+
+#[test]
+fn publish_with_provenance() {
+    use cargo_registry::provenance::EncodableProvenance;
+    use cargo_registry::schema::crates;
+
+    #[derive(RustcDecodable)]
+    struct V { version: EncodableVersion }
+    #[derive(RustcDecodable)]
+    struct P { provenance: EncodableProvenance }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_provenance", "1.1.0");
+    ::sign_in(&mut req, &app);
+    let mut new_crate = new_crate("foo_provenance");
+    new_crate.repository = Some("https://github.com/foo/provenance".to_string());
+    new_crate.provenance = Some(u::Provenance {
+        ci_provider: "github-actions".to_string(),
+        build_url: "https://github.com/foo/provenance/actions/runs/1".to_string(),
+        commit_sha: "abc".to_string(),
+    });
+    let json = bad_resp!(middle.call(req.with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL))));
+    assert!(json.errors[0].detail.contains("commit"), "{:?}", json.errors);
+
+    // Builds of crates whose repository isn't verified aren't verified
+    new_crate.provenance.as_mut().unwrap().commit_sha =
+        "0123456789abcdef0123456789abcdef01234567".to_string();
+    ok_resp!(middle.call(req.with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL))));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_provenance/1.1.0")));
+    assert!(!::json::<V>(&mut response).version.verified_build);
+    let mut response = ok_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_provenance/1.1.0/provenance")));
+    let provenance = ::json::<P>(&mut response).provenance;
+    assert!(!provenance.verified);
+    assert_eq!(provenance.ci_provider, "github-actions");
+
+    {
+        let conn = app.diesel_database.get().unwrap();
+        diesel::update(crates::table.filter(crates::name.eq("foo_provenance")))
+            .set(crates::repository_verified.eq(true))
+            .execute(&*conn)
+            .unwrap();
+    }
+
+    // GitHub confirms that run 1 was for the commit
+    new_crate.vers = u::CrateVersion(semver::Version::parse("1.2.0").unwrap());
+    ok_resp!(middle.call(req.with_method(Method::Put)
+                            .with_path("/api/v1/crates/new")
+                            .with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL))));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_provenance/1.2.0")));
+    assert!(::json::<V>(&mut response).version.verified_build);
+    let mut response = ok_resp!(middle.call(
+        req.with_path("/api/v1/crates/foo_provenance/1.2.0/provenance")));
+    assert!(::json::<P>(&mut response).provenance.verified);
+
+    // Run 2 was for another commit
+    new_crate.vers = u::CrateVersion(semver::Version::parse("1.3.0").unwrap());
+    new_crate.provenance.as_mut().unwrap().build_url =
+        "https://github.com/foo/provenance/actions/runs/2".to_string();
+    ok_resp!(middle.call(req.with_method(Method::Put)
+                            .with_path("/api/v1/crates/new")
+                            .with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL))));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_provenance/1.3.0")));
+    assert!(!::json::<V>(&mut response).version.verified_build);
+}
+
+#[test]
+fn show_first_published() {
+    use cargo_registry::version::NewVersion;
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_first_published");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_first_published")
+            .create_or_update(&conn, None, user.id)
+            .unwrap();
+        for &(num, publisher) in &[("1.0.0", &user), ("1.1.0", &other)] {
+            let num = semver::Version::parse(num).unwrap();
+            NewVersion::new(krate.id, &num, &HashMap::new(), Some(publisher.id), None, None)
+                .unwrap()
+                .save(&conn, &[])
+                .unwrap();
+        }
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: CrateResponse = ::json(&mut response);
+    assert!(json.krate.first_published_at.is_some());
+    assert_eq!(json.krate.original_publisher.unwrap().login, "foo");
+}
+
+#[test]
+fn show_as_of() {
+    use cargo_registry::schema::{version_yanks, versions};
+    use cargo_registry::version;
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_as_of");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_as_of").create_or_update(&conn, None, user.id).unwrap();
+        let mut yanked_id = 0;
+        for &(num, date) in &[("1.0.0", "2017-01-01"), ("1.1.0", "2017-02-01"),
+                              ("2.0.0", "2017-04-01")] {
+            let version = ::new_version(krate.id, num).save(&conn, &[]).unwrap();
+            let created_at = time::strptime(date, "%Y-%m-%d").unwrap().to_timespec();
+            diesel::update(versions::table.find(version.id))
+                .set(versions::created_at.eq(created_at))
+                .execute(&*conn)
+                .unwrap();
+            if num == "1.1.0" {
+                yanked_id = version.id;
+            }
+        }
+        // 1.1.0 was yanked on 2017-03-15, long after the date asked about
+        diesel::update(versions::table.find(yanked_id))
+            .set(versions::yanked.eq(true))
+            .execute(&*conn)
+            .unwrap();
+        version::record_yanks(&conn, &[yanked_id], true).unwrap();
+        let yanked_at = time::strptime("2017-03-15", "%Y-%m-%d").unwrap().to_timespec();
+        diesel::update(version_yanks::table)
+            .set(version_yanks::created_at.eq(yanked_at))
+            .execute(&*conn)
+            .unwrap();
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: CrateResponse = ::json(&mut response);
+    assert_eq!(json.versions.len(), 3);
+    assert_eq!(json.krate.max_version, "2.0.0");
+
+    let mut response = ok_resp!(middle.call(req.with_query("as_of=2017-03-01")));
+    let json: CrateResponse = ::json(&mut response);
+    let mut nums = json.versions.iter().map(|v| (&v.num[..], v.yanked)).collect::<Vec<_>>();
+    nums.sort();
+    assert_eq!(nums, [("1.0.0", false), ("1.1.0", false)]);
+    assert_eq!(json.krate.max_version, "1.1.0");
+
+    let mut response = ok_resp!(middle.call(req.with_query("as_of=2017-03-20T00:00:00Z")));
+    let json: CrateResponse = ::json(&mut response);
+    assert_eq!(json.krate.max_version, "1.0.0");
+
+    let json = bad_resp!(middle.call(req.with_query("as_of=2016-12-01")));
+    assert!(json.errors[0].detail.contains("had no versions"), "{:?}", json.errors);
+    let json = bad_resp!(middle.call(req.with_query("as_of=yesterday")));
+    assert!(json.errors[0].detail.contains("is not a date"), "{:?}", json.errors);
+}
+
+#[test]
+fn repository_verification() {
+    use cargo_registry::schema::crates;
+
+    let verify = "/api/v1/crates/foo_verified/repository_verification";
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, verify);
+    let krate;
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let mut new_krate = ::new_crate("foo_verified");
+        new_krate.repository = Some("https://github.com/crates-test-org/foo_verified");
+        krate = new_krate.create_or_update(&conn, None, user.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+    }
+
+    ok_resp!(middle.call(&mut req));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_verified")));
+    assert!(::json::<CrateResponse>(&mut response).krate.repository_verified);
+
+    // Pointing the crate somewhere else needs a new verification
+    {
+        let conn = app.diesel_database.get().unwrap();
+        diesel::update(&krate)
+            .set(crates::repository.eq("https://gitlab.com/crates-test-org/foo_verified"))
+            .execute(&*conn)
+            .unwrap();
+    }
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert!(!::json::<CrateResponse>(&mut response).krate.repository_verified);
+    let json = bad_resp!(middle.call(req.with_method(Method::Put).with_path(verify)));
+    assert!(json.errors[0].detail.contains("not a GitHub repository"), "{:?}", json.errors);
+}
+
 #[test]
 fn new_krate_duplicate_version() {
     let (_b, app, middle) = ::app();
@@ -605,6 +1139,36 @@ fn new_krate_duplicate_version() {
             "{:?}", json.errors);
 }
 
+#[test]
+fn new_krate_previously_published_version() {
+    use diesel::delete;
+    use cargo_registry::schema::version_authors;
+
+    #[derive(RustcDecodable)]
+    struct CodedError { detail: String, code: String }
+    #[derive(RustcDecodable)]
+    struct Bad { errors: Vec<CodedError> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_tombstone", "1.0.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let krate = ::new_crate("foo_tombstone").create_or_update(&conn, None, user.id)
+            .unwrap();
+        let version = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        delete(version_authors::table.filter(version_authors::version_id.eq(version.id)))
+            .execute(&*conn).unwrap();
+        delete(&version).execute(&*conn).unwrap();
+    }
+    let mut response = t!(middle.call(&mut req));
+    let json: Bad = ::json(&mut response);
+    assert_eq!(json.errors[0].code, "version_previously_published");
+    assert!(json.errors[0].detail.contains("can never be published again"),
+            "{:?}", json.errors[0].detail);
+}
+
 #[test]
 fn new_crate_similar_name() {
     let (_b, app, middle) = ::app();
@@ -667,7 +1231,7 @@ fn new_krate_git_upload() {
     assert_eq!(p.vers, "1.0.0");
     assert!(p.deps.is_empty());
     assert_eq!(p.cksum,
-               "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+               "deb230b21e0d7ef6a0a2d65a19599d4251b5f08888a5155882fbef2ef71e1e11");
 }
 
 #[test]
@@ -746,19 +1310,118 @@ fn summary_doesnt_die() {
 }
 
 #[test]
-fn download() {
-    use ::time::{Duration, now_utc, strftime};
+fn bad_pagination_is_rejected() {
     let (_b, app, middle) = ::app();
-    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_download/1.0.0/download");
-    ::mock_user(&mut req, ::user("foo"));
-    ::mock_crate(&mut req, ::krate("foo_download"));
-    let resp = t_resp!(middle.call(&mut req));
-    assert_eq!(resp.status.0, 302);
+    let mut req = ::req(app, Method::Get, "/api/v1/crates");
+    for query in &["page=0", "per_page=0", "per_page=101", "page=-1", "per_page=lots"] {
+        bad_resp!(middle.call(req.with_query(query)));
+    }
+    for path in &["/api/v1/keywords", "/api/v1/categories", "/api/v1/crates/foo/reverse_dependencies"] {
+        let json = bad_resp!(middle.call(req.with_path(path).with_query("page=0")));
+        assert!(json.errors[0].detail.contains("page 0"), "{:?}", json.errors);
+    }
+    ok_resp!(middle.call(req.with_path("/api/v1/crates").with_query("page=2&per_page=100")));
+}
 
-    req.with_path("/api/v1/crates/foo_download/1.0.0/downloads");
-    let mut resp = ok_resp!(middle.call(&mut req));
-    let downloads = ::json::<Downloads>(&mut resp);
-    assert_eq!(downloads.version_downloads.len(), 1);
+#[test]
+fn pages_link_to_their_neighbours() {
+    #[derive(RustcDecodable)]
+    struct Page { crates: Vec<EncodableCrate>, meta: PaginationMeta }
+
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let u = ::new_user("foo").create_or_update(&conn).unwrap();
+        for name in &["page_a", "page_b", "page_c"] {
+            ::new_crate(name).create_or_update(&conn, None, u.id).unwrap();
+        }
+    }
+    let mut req = ::req(app, Method::Get, "/api/v1/crates");
+
+    let mut response = ok_resp!(middle.call(req.with_query("sort=alpha&per_page=2")));
+    let json: Page = ::json(&mut response);
+    assert_eq!(json.crates.len(), 2);
+    assert_eq!(json.meta.total, 3);
+    assert_eq!(json.meta.next_page, Some("?sort=alpha&page=2&per_page=2".to_string()));
+    assert_eq!(json.meta.prev_page, None);
+
+    let next = json.meta.next_page.unwrap();
+    let mut response = ok_resp!(middle.call(req.with_query(&next[1..])));
+    let json: Page = ::json(&mut response);
+    assert_eq!(json.crates.len(), 1);
+    assert_eq!(json.meta.next_page, None);
+    assert_eq!(json.meta.prev_page, Some("?sort=alpha&page=1&per_page=2".to_string()));
+}
+
+#[test]
+fn requests_know_their_route() {
+    use conduit::Request;
+    use cargo_registry::util::RouteName;
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/users/foo/versions");
+    ::sign_in(&mut req, &app);
+    ok_resp!(middle.call(&mut req));
+    assert_eq!(req.extensions().find::<RouteName>(),
+               Some(&RouteName("/users/:user_id/versions")));
+
+    ok_resp!(middle.call(req.with_path("/summary")));
+    assert_eq!(req.extensions().find::<RouteName>(), Some(&RouteName("/summary")));
+}
+
+#[test]
+fn registry_stats() {
+    #[derive(RustcDecodable)] struct R { stats: Vec<EncodableRegistryStats> }
+    #[derive(RustcDecodable)] struct Summary { num_crates: i64 }
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/stats");
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert_eq!(::json::<R>(&mut response).stats.len(), 0);
+
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_stats"));
+    RegistryStats::snapshot(req.tx().unwrap()).unwrap();
+    ::mock_crate(&mut req, ::krate("bar_stats"));
+    RegistryStats::snapshot(req.tx().unwrap()).unwrap();
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let stats = ::json::<R>(&mut response).stats;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].total_crates, 2);
+    assert_eq!(stats[0].total_versions, 2);
+    assert_eq!(stats[0].total_users, 1);
+    assert_eq!(stats[0].new_users, 1);
+
+    let mut response = ok_resp!(middle.call(req.with_path("/summary")));
+    assert_eq!(::json::<Summary>(&mut response).num_crates, 2);
+}
+
+#[test]
+fn quarantined_versions_cannot_be_downloaded() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_held/1.0.0/download");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_held"));
+    req.tx().unwrap().execute("UPDATE versions SET quarantined = TRUE", &[]).unwrap();
+
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(json.errors[0].detail.contains("not found"), "{:?}", json.errors);
+}
+
+#[test]
+fn download() {
+    use ::time::{Duration, now_utc, strftime};
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_download/1.0.0/download");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_download"));
+    let resp = t_resp!(middle.call(&mut req));
+    assert_eq!(resp.status.0, 302);
+
+    req.with_path("/api/v1/crates/foo_download/1.0.0/downloads");
+    let mut resp = ok_resp!(middle.call(&mut req));
+    let downloads = ::json::<Downloads>(&mut resp);
+    assert_eq!(downloads.version_downloads.len(), 1);
 
     req.with_path("/api/v1/crates/FOO_DOWNLOAD/1.0.0/download");
     let resp = t_resp!(middle.call(&mut req));
@@ -785,6 +1448,209 @@ fn download() {
 
 }
 
+#[test]
+fn repeated_downloads_are_counted_once() {
+    for &rollout in &[Rollout::Off, Rollout::Shadow, Rollout::On] {
+        let (_b, app, middle) = ::app_with(|config| {
+            config.async_downloads = rollout;
+            config.download_dedupe_secs = 60;
+        });
+        let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_dedupe/1.0.0/download");
+        ::mock_user(&mut req, ::user("foo"));
+        ::mock_crate(&mut req, ::krate("foo_dedupe"));
+        req.header("X-Forwarded-For", "10.0.0.1");
+        t_resp!(middle.call(&mut req));
+        t_resp!(middle.call(&mut req));
+        req.header("X-Forwarded-For", "10.0.0.2");
+        t_resp!(middle.call(&mut req));
+
+        req.with_path("/api/v1/crates/foo_dedupe/1.0.0/downloads");
+        let mut resp = ok_resp!(middle.call(&mut req));
+        let downloads = ::json::<Downloads>(&mut resp);
+        assert_eq!(downloads.version_downloads.len(), 1);
+        assert_eq!(downloads.version_downloads[0].downloads, 2, "{:?}", rollout);
+    }
+}
+
+#[test]
+fn compare() {
+    use cargo_registry::dependency;
+    use cargo_registry::krate::EncodableCrateComparison;
+    use cargo_registry::schema::versions;
+
+    #[derive(RustcDecodable)]
+    struct R { crates: Vec<EncodableCrateComparison> }
+
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let mut new_crate = ::new_crate("foo_compared");
+        new_crate.license = Some("MIT");
+        let krate = new_crate.create_or_update(&conn, None, user.id).unwrap();
+        let v1 = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        let v2 = ::new_version(krate.id, "2.0.0").save(&conn, &[]).unwrap();
+        diesel::update(versions::table.find(v2.id))
+            .set(versions::yanked.eq(true))
+            .execute(&*conn).unwrap();
+        let other = ::new_crate("bar_compared").create_or_update(&conn, None, user.id).unwrap();
+        let other_version = ::new_version(other.id, "0.1.0").save(&conn, &[]).unwrap();
+        conn.execute(&format!("INSERT INTO version_downloads (version_id, downloads, date)
+                               VALUES ({0}, 100, CURRENT_DATE - 100),
+                                      ({0}, 30, CURRENT_DATE)",
+                              v1.id)).unwrap();
+        conn.execute(&format!("INSERT INTO dependencies
+                                   (version_id, crate_id, req, optional, default_features,
+                                    features, kind)
+                               VALUES ({}, {}, '^1.0', false, true, '{{}}', 0)",
+                              other_version.id, krate.id)).unwrap();
+        let dependencies = dependency::newest_dependencies(&conn, other.id).unwrap();
+        dependency::update_dependents_cnt(&conn, &[], &dependencies).unwrap();
+    }
+
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/compare");
+    let mut response = ok_resp!(middle.call(req.with_query("names=foo_compared,bar_compared,foo_compared")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.crates.len(), 2);
+    let foo = &json.crates[0];
+    assert_eq!(foo.name, "foo_compared");
+    assert_eq!(foo.max_version, "1.0.0");
+    assert_eq!(foo.recent_downloads, 30);
+    assert_eq!(foo.versions, 2);
+    assert_eq!(foo.dependents, 1);
+    assert_eq!(foo.license, Some("MIT".to_string()));
+    assert!(foo.last_release_at.is_some());
+    assert_eq!(json.crates[1].name, "bar_compared");
+    assert_eq!(json.crates[1].dependents, 0);
+
+    let json = bad_resp!(middle.call(req.with_query("names=foo_compared,nope")));
+    assert!(json.errors[0].detail.contains("`nope`"), "{:?}", json.errors);
+    let json = bad_resp!(middle.call(req.with_query("names=a,b,c,d,e,f")));
+    assert!(json.errors[0].detail.contains("between 1 and 5"), "{:?}", json.errors);
+}
+
+#[test]
+fn name_availability() {
+    use cargo_registry::availability::EncodableAvailability;
+
+    #[derive(RustcDecodable)]
+    struct R { availability: EncodableAvailability }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_taken/availability");
+    ::mock_user(&mut req, ::user("foo"));
+    let (krate, _) = ::mock_crate(&mut req, ::krate("foo_taken"));
+    ::mock_crate(&mut req, ::krate("foo_taken-rs"));
+    req.tx().unwrap().execute("INSERT INTO crate_aliases (alias, crate_id) \
+                               VALUES ('foo_renamed', $1)",
+                              &[&krate.id]).unwrap();
+    req.tx().unwrap().execute("INSERT INTO reserved_crate_names (name) \
+                               VALUES ('foo_reserved')", &[]).unwrap();
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.availability.status, "taken");
+    assert_eq!(json.availability.conflict, None);
+    assert_eq!(json.availability.suggestions[0], "foo_taken_rust");
+    assert!(!json.availability.suggestions.contains(&"foo_taken_rs".to_string()));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/Foo-Taken/availability")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.availability.status, "collision");
+    assert_eq!(json.availability.conflict, Some("foo_taken".to_string()));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo-reserved/availability")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.availability.status, "reserved");
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_renamed/availability")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.availability.status, "reserved");
+    assert_eq!(json.availability.conflict, Some("foo_taken".to_string()));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_free/availability")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.availability.status, "free");
+    assert!(json.availability.suggestions.is_empty());
+
+    bad_resp!(middle.call(req.with_path("/api/v1/crates/1foo/availability")));
+}
+
+#[test]
+fn most_downloaded_and_trending() {
+    #[derive(RustcDecodable)]
+    struct Ranked { krate: EncodableCrate, recent_downloads: i64 }
+    #[derive(RustcDecodable)]
+    struct MostDownloaded { crates: Vec<Ranked> }
+    #[derive(RustcDecodable)]
+    struct Trending { krate: EncodableCrate, downloads_this_week: i64, downloads_last_week: i64 }
+    #[derive(RustcDecodable)]
+    struct TrendingList { crates: Vec<Trending> }
+
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_popular").create_or_update(&conn, None, user.id).unwrap();
+        let version = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        let quiet = ::new_crate("foo_quiet").create_or_update(&conn, None, user.id).unwrap();
+        let quiet_version = ::new_version(quiet.id, "1.0.0").save(&conn, &[]).unwrap();
+        conn.execute(&format!("INSERT INTO version_downloads (version_id, downloads, date)
+                               VALUES ({0}, 150, CURRENT_DATE - 10),
+                                      ({0}, 400, CURRENT_DATE),
+                                      ({1}, 5, CURRENT_DATE - 10),
+                                      ({1}, 50, CURRENT_DATE)",
+                              version.id, quiet_version.id)).unwrap();
+    }
+
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/most_downloaded");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: MostDownloaded = ::json(&mut response);
+    assert_eq!(json.crates.len(), 2);
+    assert_eq!(json.crates[0].krate.name, "foo_popular");
+    assert_eq!(json.crates[0].recent_downloads, 550);
+
+    // `foo_quiet` grew more, but from a baseline too small to count
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/trending")));
+    let json: TrendingList = ::json(&mut response);
+    assert_eq!(json.crates.len(), 1);
+    assert_eq!(json.crates[0].krate.name, "foo_popular");
+    assert_eq!(json.crates[0].downloads_this_week, 400);
+    assert_eq!(json.crates[0].downloads_last_week, 150);
+}
+
+#[test]
+fn download_stats() {
+    #[derive(RustcDecodable)]
+    struct UserAgent { version: String, user_agent: String, downloads: i64 }
+    #[derive(RustcDecodable)]
+    struct Country { country: String, downloads: i64 }
+    #[derive(RustcDecodable)]
+    struct Stats { user_agents: Vec<UserAgent>, countries: Vec<Country> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_stats/1.0.0/download");
+    ::mock_user(&mut req, ::user("foo"));
+    ::mock_crate(&mut req, ::krate("foo_stats"));
+    req.header("User-Agent", "cargo 0.18.0 (5db6d64 2017-03-03)");
+    req.header("CF-IPCountry", "de");
+    let resp = t_resp!(middle.call(&mut req));
+    assert_eq!(resp.status.0, 302);
+
+    let mut resp = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_stats/download_stats")));
+    let stats = ::json::<Stats>(&mut resp);
+    assert_eq!(stats.user_agents.len(), 1);
+    assert_eq!(stats.user_agents[0].version, "1.0.0");
+    assert_eq!(stats.user_agents[0].user_agent, "cargo 0.18");
+    assert_eq!(stats.user_agents[0].downloads, 1);
+    assert_eq!(stats.countries.len(), 1);
+    assert_eq!(stats.countries[0].country, "DE");
+
+    ::sign_in_as(&mut req, &::user("bar"));
+    let mut resp = ok_resp!(middle.call(&mut req));
+    ::json::<::Bad>(&mut resp);
+}
+
 #[test]
 fn download_bad() {
     let (_b, app, middle) = ::app();
@@ -829,6 +1695,136 @@ fn diesel_not_found_results_in_404() {
     assert_eq!((404, "Not Found"), response.status);
 }
 
+#[test]
+fn owner_changes_are_all_or_nothing() {
+    use cargo_registry::owner::EncodableOwnerChange;
+
+    #[derive(RustcDecodable)]
+    struct R { ok: bool, results: Vec<EncodableOwnerChange>, errors: Option<Vec<::Error>> }
+    #[derive(RustcDecodable)]
+    struct Owner { login: String }
+    #[derive(RustcDecodable)]
+    struct Owners { users: Vec<Owner> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_bulk_owners/owners");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_user("baz").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::new_crate("foo_bulk_owners").create_or_update(&conn, None, user.id).unwrap();
+    }
+
+    let body = r#"{"users":["bar","nobody"]}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let json = ::json::<R>(&mut response);
+    assert!(!json.ok);
+    assert!(json.results[0].ok);
+    assert!(!json.results[1].ok);
+    assert!(json.results[1].error.is_some());
+    assert!(json.errors.unwrap()[0].detail.contains("`nobody`"));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let owners = ::json::<Owners>(&mut response).users;
+    assert_eq!(owners.iter().map(|o| &o.login[..]).collect::<Vec<_>>(), ["foo"]);
+
+    let body = r#"{"users":["bar","baz"]}"#;
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put)
+                                               .with_body(body.as_bytes())));
+    let json = ::json::<R>(&mut response);
+    assert!(json.ok);
+    assert_eq!(json.results.iter().map(|r| &r.login[..]).collect::<Vec<_>>(), ["bar", "baz"]);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    assert_eq!(::json::<Owners>(&mut response).users.len(), 3);
+}
+
+#[test]
+fn owner_changes_can_need_a_second_owners_approval() {
+    use cargo_registry::owner::EncodableOwnerChange;
+    use cargo_registry::owner_approval::EncodableOwnerApproval;
+    use cargo_registry::settings::EncodableCrateSettings;
+
+    #[derive(RustcDecodable)]
+    struct R { ok: bool, results: Vec<EncodableOwnerChange> }
+    #[derive(RustcDecodable)]
+    struct Owners { users: Vec<EncodableUser> }
+    #[derive(RustcDecodable)]
+    struct A { approval: EncodableOwnerApproval }
+    #[derive(RustcDecodable)]
+    struct Approvals { approvals: Vec<EncodableOwnerApproval> }
+    #[derive(RustcDecodable)]
+    struct S { settings: EncodableCrateSettings }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_approved/settings");
+    let (foo, bar) = {
+        let conn = app.diesel_database.get().unwrap();
+        let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+        let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_user("baz").create_or_update(&conn).unwrap();
+        ::new_crate("foo_approved").create_or_update(&conn, None, foo.id).unwrap();
+        (foo, bar)
+    };
+    ::sign_in_as(&mut req, &foo);
+
+    // A single owner has nobody to approve their changes
+    let body = br#"{"require_owner_approval":true}"#;
+    let json = bad_resp!(middle.call(req.with_body(body)));
+    assert!(json.errors[0].detail.contains("another owner"), "{:?}", json.errors);
+
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_approved/owners")
+                            .with_body(br#"{"users":["bar"]}"#)));
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_approved/settings")
+                                               .with_body(body)));
+    assert!(::json::<S>(&mut response).settings.require_owner_approval);
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_approved/owners")
+                                               .with_body(br#"{"users":["baz"]}"#)));
+    let json = ::json::<R>(&mut response);
+    assert!(json.ok);
+    let approval_id = json.results[0].approval_id.unwrap();
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    assert_eq!(::json::<Owners>(&mut response).users.len(), 2);
+
+    let path = format!("/api/v1/crates/foo_approved/owner_approvals/{}/approve", approval_id);
+    let json = bad_resp!(middle.call(req.with_method(Method::Put).with_path(&path)));
+    assert!(json.errors[0].detail.contains("another owner"), "{:?}", json.errors);
+
+    ::sign_in_as(&mut req, &bar);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let approval = ::json::<A>(&mut response).approval;
+    assert_eq!(approval.state, "approved");
+    assert_eq!(approval.requested_by, Some("foo".to_string()));
+    assert_eq!(approval.resolved_by, Some("bar".to_string()));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_approved/owners")));
+    assert_eq!(::json::<Owners>(&mut response).users.len(), 3);
+    let json = bad_resp!(middle.call(req.with_method(Method::Put).with_path(&path)));
+    assert!(json.errors[0].detail.contains("already approved"), "{:?}", json.errors);
+
+    // Turning the setting off needs approval too, which can be rejected
+    ::sign_in_as(&mut req, &foo);
+    let body = br#"{"require_owner_approval":false}"#;
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_approved/settings")
+                                               .with_body(body)));
+    assert!(::json::<S>(&mut response).settings.require_owner_approval);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_approved/owner_approvals")));
+    let approvals = ::json::<Approvals>(&mut response).approvals;
+    assert_eq!(approvals.len(), 2);
+    assert_eq!(approvals[0].action, "disable_owner_approval");
+    assert_eq!(approvals[0].state, "pending");
+
+    ::sign_in_as(&mut req, &bar);
+    let path = format!("/api/v1/crates/foo_approved/owner_approvals/{}/reject", approvals[0].id);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put).with_path(&path)));
+    assert_eq!(::json::<A>(&mut response).approval.state, "rejected");
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_approved/settings")));
+    assert!(::json::<S>(&mut response).settings.require_owner_approval);
+}
+
 #[test]
 fn following() {
     #[derive(RustcDecodable)] struct F { following: bool }
@@ -886,6 +1882,89 @@ fn following() {
     assert_eq!(::json::<CrateList>(&mut response).crates.len(), 0);
 }
 
+#[test]
+fn large_request_bodies_are_rejected() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Put, "/api/v1/crates/foo_large_body/follow");
+    let body = vec![b' '; 1024 * 1024 + 1];
+    let response = t_resp!(middle.call(req.with_body(&body)));
+    assert_eq!(response.status.0, 413);
+}
+
+#[test]
+fn license_changes_notify_followers() {
+    use cargo_registry::notification::EncodableNotification;
+
+    #[derive(RustcDecodable)] struct F { following: bool, legal_notifications: bool }
+    #[derive(RustcDecodable)] struct A { activity: Vec<EncodableActivity> }
+    #[derive(RustcDecodable)] struct N { notifications: Vec<EncodableNotification> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_relicensed", "1.0.0");
+    let (owner, follower, bystander);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        follower = ::new_user("bar").create_or_update(&conn).unwrap();
+        bystander = ::new_user("baz").create_or_update(&conn).unwrap();
+    }
+    ::sign_in_as(&mut req, &owner);
+    ok_resp!(middle.call(&mut req));
+
+    ::sign_in_as(&mut req, &follower);
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_relicensed/follow")
+                            .with_method(Method::Put)
+                            .with_body(br#"{"legal_notifications":true}"#)));
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_relicensed/following")
+                                               .with_method(Method::Get)));
+    let json: F = ::json(&mut response);
+    assert!(json.following);
+    assert!(json.legal_notifications);
+    ::sign_in_as(&mut req, &bystander);
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_relicensed/follow")
+                            .with_method(Method::Put)
+                            .with_body(b"")));
+
+    // Another version under the same license changes nothing
+    let mut new_crate = new_crate("foo_relicensed");
+    ::sign_in_as(&mut req, &owner);
+    let body = ::new_crate_to_body(&new_crate, ::EMPTY_TARBALL);
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/new")
+                            .with_method(Method::Put)
+                            .with_body(&body)));
+    new_crate.vers = u::CrateVersion(semver::Version::parse("2.0.0").unwrap());
+    new_crate.license = Some("Apache-2.0".to_string());
+    let body = ::new_crate_to_body(&new_crate, ::EMPTY_TARBALL);
+    ok_resp!(middle.call(req.with_body(&body)));
+
+    // A backport is compared with the version it follows, not the newest one
+    new_crate.vers = u::CrateVersion(semver::Version::parse("1.1.1").unwrap());
+    new_crate.license = Some("MIT".to_string());
+    let body = ::new_crate_to_body(&new_crate, ::EMPTY_TARBALL);
+    ok_resp!(middle.call(req.with_body(&body)));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_relicensed/activity")
+                                               .with_method(Method::Get)));
+    let json: A = ::json(&mut response);
+    let changes = json.activity.iter()
+        .filter(|a| a.action == "license_change")
+        .collect::<Vec<_>>();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].details, "2.0.0: MIT -> Apache-2.0");
+
+    ::sign_in_as(&mut req, &follower);
+    let mut response = ok_resp!(middle.call(req.with_path("/me/notifications")));
+    let json: N = ::json(&mut response);
+    assert_eq!(json.notifications.len(), 1);
+    assert_eq!(json.notifications[0].kind, "license_change");
+    assert_eq!(json.notifications[0].crate_name, "foo_relicensed");
+    assert_eq!(json.notifications[0].details, "2.0.0: MIT -> Apache-2.0");
+
+    ::sign_in_as(&mut req, &bystander);
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert_eq!(::json::<N>(&mut response).notifications.len(), 0);
+}
+
 #[test]
 fn owners() {
     #[derive(RustcDecodable)] struct R { users: Vec<EncodableUser> }
@@ -938,6 +2017,100 @@ fn owners() {
     assert!(::json::<O>(&mut response).ok);
 }
 
+#[test]
+fn collaborators() {
+    #[derive(RustcDecodable)] struct Owner { login: String, role: Option<String> }
+    #[derive(RustcDecodable)] struct R { users: Vec<Owner> }
+    #[derive(RustcDecodable)] struct O { ok: bool }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_collab/owners");
+    let collaborator;
+    {
+        let conn = app.diesel_database.get().unwrap();
+        collaborator = ::new_user("bar").create_or_update(&conn).unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::new_crate("foo_collab").create_or_update(&conn, None, user.id).unwrap();
+    }
+
+    let body = r#"{"users":["bar"],"role":"collaborator"}"#;
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put)
+                                               .with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put)
+                                               .with_body(body.as_bytes())));
+    ::json::<::Bad>(&mut response);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let r: R = ::json(&mut response);
+    let roles = r.users.iter()
+        .map(|owner| (&owner.login[..], owner.role.as_ref().map(|s| &s[..])))
+        .collect::<Vec<_>>();
+    assert_eq!(roles, [("foo", Some("owner")), ("bar", Some("collaborator"))]);
+
+    // Collaborators can publish new versions...
+    let mut req = ::new_req(app.clone(), "foo_collab", "1.0.0");
+    ::sign_in_as(&mut req, &collaborator);
+    let mut response = ok_resp!(middle.call(&mut req));
+    ::json::<GoodCrate>(&mut response);
+
+    // ... but they can't yank them or change who owns the crate
+    let json = bad_resp!(middle.call(req.with_method(Method::Delete)
+                                        .with_path("/api/v1/crates/foo_collab/1.0.0/yank")));
+    assert!(json.errors[0].detail.contains("collaborators"), "{:?}", json.errors);
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_collab/yank")
+                                        .with_body(br#"{"range": "*"}"#)));
+    assert!(json.errors[0].detail.contains("collaborators"), "{:?}", json.errors);
+
+    let body = r#"{"users":["bar"]}"#;
+    let json = bad_resp!(middle.call(req.with_method(Method::Put)
+                                        .with_path("/api/v1/crates/foo_collab/owners")
+                                        .with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+}
+
+#[test]
+fn owners_are_not_made_collaborators() {
+    #[derive(RustcDecodable)] struct Owner { login: String, role: Option<String> }
+    #[derive(RustcDecodable)] struct R { users: Vec<Owner> }
+    #[derive(RustcDecodable)] struct O { ok: bool }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_demote/owners");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_user("baz").create_or_update(&conn).unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        ::new_crate("foo_demote").create_or_update(&conn, None, user.id).unwrap();
+    }
+
+    let body = r#"{"users":["baz"]}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+    let body = r#"{"users":["baz"],"role":"collaborator"}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("remove them first"), "{:?}", json.errors);
+
+    // Collaborators can be made owners though
+    let body = r#"{"users":["bar"],"role":"collaborator"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+    let body = r#"{"users":["bar"]}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<O>(&mut response).ok);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let r: R = ::json(&mut response);
+    let mut roles = r.users.iter()
+        .map(|owner| (&owner.login[..], owner.role.as_ref().map(|s| &s[..])))
+        .collect::<Vec<_>>();
+    roles.sort();
+    assert_eq!(roles, [("bar", Some("owner")), ("baz", Some("owner")), ("foo", Some("owner"))]);
+}
+
 #[test]
 fn yank() {
     #[derive(RustcDecodable)] struct O { ok: bool }
@@ -970,6 +2143,14 @@ fn yank() {
                                         .with_path("/api/v1/crates/fyk/1.0.0")));
     assert!(::json::<V>(&mut r).version.yanked);
 
+    // crates without any available version are hidden from search
+    let mut r = ok_resp!(middle.call(req.with_path("/api/v1/crates")
+                                        .with_query("q=fyk")));
+    assert_eq!(::json::<CrateList>(&mut r).meta.total, 0);
+    let mut r = ok_resp!(middle.call(req.with_query("q=fyk&include_yanked=true")));
+    assert_eq!(::json::<CrateList>(&mut r).meta.total, 1);
+    req.with_query("");
+
     // un-yank it
     let mut r = ok_resp!(middle.call(req.with_method(Method::Put)
                                         .with_path("/api/v1/crates/fyk/1.0.0/unyank")));
@@ -980,6 +2161,129 @@ fn yank() {
     let mut r = ok_resp!(middle.call(req.with_method(Method::Get)
                                         .with_path("/api/v1/crates/fyk/1.0.0")));
     assert!(!::json::<V>(&mut r).version.yanked);
+    let mut r = ok_resp!(middle.call(req.with_path("/api/v1/crates")
+                                        .with_query("q=fyk")));
+    assert_eq!(::json::<CrateList>(&mut r).meta.total, 1);
+}
+
+#[test]
+fn yank_range() {
+    #[derive(RustcDecodable)] struct R { ok: bool, yanked: Vec<String> }
+    #[derive(RustcDecodable)] struct A { activity: Vec<EncodableActivity> }
+    let (_b, app, middle) = ::app();
+    let path = ::git::checkout().join("fy/k_/fyk_range");
+
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/new");
+    ::sign_in(&mut req, &app);
+    for version in &["0.9.0", "0.9.2", "0.9.3", "1.0.0"] {
+        let body = ::new_req_body(::krate("fyk_range"), version, Vec::new(), Vec::new(),
+                                  Vec::new(), HashMap::new());
+        ok_resp!(middle.call(req.with_body(&body)));
+    }
+
+    req.with_method(Method::Delete).with_path("/api/v1/crates/fyk_range/yank");
+    let json = bad_resp!(middle.call(req.with_body(br#"{"range": "not a range"}"#)));
+    assert!(json.errors[0].detail.contains("not a valid semver range"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_body(br#"{"range": "< 0.9.3"}"#)));
+    let json: R = ::json(&mut response);
+    assert!(json.ok);
+    assert_eq!(json.yanked, ["0.9.0", "0.9.2"]);
+    let mut contents = String::new();
+    File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+    let yanked = contents.lines()
+        .filter(|line| line.contains("\"yanked\":true"))
+        .count();
+    assert_eq!(yanked, 2);
+
+    // Versions which are already yanked are left alone
+    let mut response = ok_resp!(middle.call(req.with_body(br#"{"range": "< 0.9.3"}"#)));
+    assert!(::json::<R>(&mut response).yanked.is_empty());
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/fyk_range/activity")));
+    let json: A = ::json(&mut response);
+    assert_eq!(json.activity[0].action, "yank_versions");
+    assert_eq!(json.activity[0].details, "< 0.9.3: 0.9.0, 0.9.2");
+}
+
+#[test]
+fn activity() {
+    #[derive(RustcDecodable)] struct R { activity: Vec<EncodableActivity> }
+    let (_b, app, middle) = ::app();
+
+    let mut req = ::new_req(app.clone(), "foo_activity", "1.0.0");
+    ::sign_in(&mut req, &app);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_user("bar").create_or_update(&conn).unwrap();
+    }
+    ok_resp!(middle.call(&mut req));
+    ok_resp!(middle.call(req.with_method(Method::Delete)
+                            .with_path("/api/v1/crates/foo_activity/1.0.0/yank")));
+    ok_resp!(middle.call(req.with_method(Method::Put)
+                            .with_path("/api/v1/crates/foo_activity/owners")
+                            .with_body(br#"{"users":["bar"]}"#)));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_activity/activity")));
+    let activity = ::json::<R>(&mut response).activity;
+    let actions = activity.iter()
+        .map(|a| (&a.action[..], &a.details[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(actions, [("add_owner", "bar"),
+                         ("yank_version", "1.0.0"),
+                         ("publish_version", "1.0.0")]);
+    assert!(activity.iter().all(|a| a.user == Some("foo".to_string())));
+
+    let mut response = ok_resp!(middle.call(req.with_query("per_page=1")));
+    assert_eq!(::json::<R>(&mut response).activity.len(), 1);
+}
+
+#[test]
+fn registry_events() {
+    use cargo_registry::schema::crates;
+
+    #[derive(RustcDecodable)] struct R { events: Vec<EncodableEvent>, meta: Meta }
+    #[derive(RustcDecodable)] struct Meta { next_since: i32 }
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let u = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_crate("foo_public").create_or_update(&conn, None, u.id).unwrap();
+        let hidden = ::new_crate("foo_hidden").create_or_update(&conn, None, u.id).unwrap();
+        diesel::update(&hidden).set(crates::private.eq(true)).execute(&*conn).unwrap();
+
+        audit::record(&conn, u.id, "publish_version", Some("foo_public"), "1.0.0").unwrap();
+        audit::record(&conn, u.id, "publish_version", Some("foo_hidden"), "1.0.0").unwrap();
+        audit::record(&conn, u.id, "update_settings", Some("foo_public"), "").unwrap();
+        audit::record(&conn, u.id, "yank_version", Some("foo_public"), "1.0.0").unwrap();
+    }
+
+    let mut req = ::req(app, Method::Get, "/api/v1/events");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json = ::json::<R>(&mut response);
+    let events = json.events.iter()
+        .map(|e| (&e.action[..], &e.crate_name[..], &e.details[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(events, [("publish_version", "foo_public", "1.0.0"),
+                        ("yank_version", "foo_public", "1.0.0")]);
+    assert_eq!(json.events[0].user, Some("foo".to_string()));
+    assert_eq!(json.meta.next_since, json.events[1].seq);
+
+    let query = format!("since={}", json.events[0].seq);
+    let mut response = ok_resp!(middle.call(req.with_query(&query)));
+    let events = ::json::<R>(&mut response).events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].action, "yank_version");
+
+    let query = format!("since={}", json.meta.next_since);
+    let mut response = ok_resp!(middle.call(req.with_query(&query)));
+    let later = ::json::<R>(&mut response);
+    assert!(later.events.is_empty());
+    assert_eq!(later.meta.next_since, json.meta.next_since);
+
+    bad_resp!(middle.call(req.with_query("since=-1")));
 }
 
 #[test]
@@ -1186,6 +2490,79 @@ fn ignored_categories() {
     assert_eq!(json.warnings.invalid_categories, vec!["bar".to_string()]);
 }
 
+#[test]
+fn first_publish_report() {
+    #[derive(RustcDecodable)]
+    struct R { first_publish: Option<u::FirstPublishReport> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/new");
+    ::sign_in(&mut req, &app);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_category("Category 1", "cat1").find_or_create(&conn).unwrap();
+    }
+    let body = ::new_req_body(::krate("foo_first"), "1.0.0", Vec::new(),
+                              vec!["kw1".into()], vec!["cat1".into(), "bar".into()],
+                              HashMap::new());
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let json: R = ::json(&mut response);
+    let report = json.first_publish.expect("the first publish should be reported");
+    assert_eq!(report.files, 0);
+    assert!(!report.readme_rendered);
+    assert_eq!(report.license, Some("MIT".to_string()));
+    assert!(report.license_recognized);
+    assert_eq!(report.keywords, vec!["kw1".to_string()]);
+    assert_eq!(report.categories, vec!["cat1".to_string()]);
+
+    // Neither a later version nor another crate is a first publish
+    let body = ::new_req_body_version_2(::krate("foo_first"));
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let json: R = ::json(&mut response);
+    assert!(json.first_publish.is_none());
+    let body = ::new_req_body(::krate("foo_second"), "1.0.0", Vec::new(), Vec::new(),
+                              Vec::new(), HashMap::new());
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let json: R = ::json(&mut response);
+    assert!(json.first_publish.is_none());
+
+    // Nor is publishing for someone whose versions predate `published_by`
+    let bar = {
+        let conn = app.diesel_database.get().unwrap();
+        let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_legacy").create_or_update(&conn, None, bar.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        bar
+    };
+    ::sign_in_as(&mut req, &bar);
+    let body = ::new_req_body(::krate("foo_third"), "1.0.0", Vec::new(), Vec::new(),
+                              Vec::new(), HashMap::new());
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let json: R = ::json(&mut response);
+    assert!(json.first_publish.is_none());
+}
+
+#[test]
+fn warnings_have_codes_and_fields() {
+    #[derive(RustcDecodable)]
+    struct Warnings { entries: Vec<u::PublishWarning> }
+    #[derive(RustcDecodable)]
+    struct R { warnings: Warnings }
+
+    let (_b, app, middle) = ::app();
+    let cats = vec!["bar".into()];
+    let mut req = ::new_req_with_categories(app.clone(), ::krate("foo_warnings"), "1.0.0", cats);
+    ::sign_in(&mut req, &app);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let entries = ::json::<R>(&mut response).warnings.entries;
+    let codes = entries.iter()
+        .map(|w| (&w.code[..], w.field.as_ref().map(|f| &f[..])))
+        .collect::<Vec<_>>();
+    assert_eq!(codes, [("invalid_category", Some("categories")),
+                       ("missing_readme", Some("readme"))]);
+    assert!(entries[0].message.contains("`bar`"), "{:?}", entries);
+}
+
 #[test]
 fn good_badges() {
     let krate = ::krate("foobadger");
@@ -1384,7 +2761,8 @@ fn yanked_versions_not_included_in_reverse_dependencies() {
     assert_eq!(deps.meta.total, 1);
     assert_eq!(deps.dependencies[0].crate_id, "c2");
 
-    t!(c2v2.yank(req.tx().unwrap(), true));
+    req.tx().unwrap().execute("UPDATE versions SET yanked = TRUE WHERE id = $1",
+                              &[&c2v2.id]).unwrap();
 
     let mut response = ok_resp!(middle.call(&mut req));
     let deps = ::json::<RevDeps>(&mut response);
@@ -1402,7 +2780,7 @@ fn author_license_and_description_required() {
     new_crate.license = None;
     new_crate.description = None;
     new_crate.authors = Vec::new();
-    req.with_body(&::new_crate_to_body(&new_crate, &[]));
+    req.with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL));
     let json = bad_resp!(middle.call(&mut req));
     assert!(json.errors[0].detail.contains("author") &&
             json.errors[0].detail.contains("description") &&
@@ -1411,7 +2789,7 @@ fn author_license_and_description_required() {
 
     new_crate.license = Some("MIT".to_string());
     new_crate.authors.push("".to_string());
-    req.with_body(&::new_crate_to_body(&new_crate, &[]));
+    req.with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL));
     let json = bad_resp!(middle.call(&mut req));
     assert!(json.errors[0].detail.contains("author") &&
             json.errors[0].detail.contains("description") &&
@@ -1421,7 +2799,7 @@ fn author_license_and_description_required() {
     new_crate.license = None;
     new_crate.license_file = Some("foo".to_string());
     new_crate.authors.push("foo".to_string());
-    req.with_body(&::new_crate_to_body(&new_crate, &[]));
+    req.with_body(&::new_crate_to_body(&new_crate, ::EMPTY_TARBALL));
     let json = bad_resp!(middle.call(&mut req));
     assert!(!json.errors[0].detail.contains("author") &&
             json.errors[0].detail.contains("description") &&
@@ -1429,3 +2807,87 @@ fn author_license_and_description_required() {
             "{:?}", json.errors);
 }
 
+
+#[test]
+fn restore_keywords_and_categories() {
+    #[derive(RustcDecodable)]
+    struct History { snapshots: Vec<EncodableTagSnapshot> }
+    #[derive(RustcDecodable)]
+    struct Restored { keywords: Vec<String>, categories: Vec<String> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req_with_keywords(app.clone(), ::krate("foo_tags"), "2.0.0",
+                                          vec!["new".into()]);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let krate = ::new_crate("foo_tags").create_or_update(&conn, None, user.id).unwrap();
+        ::new_category("Category 1", "cat1").find_or_create(&conn).unwrap();
+        Keyword::update_crate(&conn, &krate, &["old"]).unwrap();
+        Category::update_crate(&conn, &krate, &["cat1"]).unwrap();
+    }
+    ok_resp!(middle.call(&mut req));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_tags/tag_history")
+                                               .with_method(Method::Get)));
+    let json: History = ::json(&mut response);
+    assert_eq!(json.snapshots.len(), 1);
+    assert_eq!(json.snapshots[0].keywords, vec!["old".to_string()]);
+    assert_eq!(json.snapshots[0].categories, vec!["cat1".to_string()]);
+    assert_eq!(json.snapshots[0].replaced_by_version, Some("2.0.0".to_string()));
+    assert_eq!(json.snapshots[0].replaced_by, Some("foo".to_string()));
+
+    let path = format!("/api/v1/crates/foo_tags/tag_history/{}/restore",
+                       json.snapshots[0].id);
+    let mut response = ok_resp!(middle.call(req.with_path(&path).with_method(Method::Put)));
+    let json: Restored = ::json(&mut response);
+    assert_eq!(json.keywords, vec!["old".to_string()]);
+    assert_eq!(json.categories, vec!["cat1".to_string()]);
+
+    // The restore can be undone too
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_tags/tag_history")
+                                               .with_method(Method::Get)));
+    let json: History = ::json(&mut response);
+    assert_eq!(json.snapshots.len(), 2);
+    assert_eq!(json.snapshots[0].keywords, vec!["new".to_string()]);
+    assert_eq!(json.snapshots[0].replaced_by_version, None);
+
+    let path = format!("/api/v1/crates/foo_tags/tag_history/{}/restore",
+                       json.snapshots[0].id);
+    let mut other = ::req(app.clone(), Method::Put, &path);
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut other, &user);
+    }
+    bad_resp!(middle.call(&mut other));
+}
+
+#[test]
+fn retried_publish_gets_the_first_response() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::new_req(app.clone(), "foo_idempotent", "1.0.0");
+    ::sign_in(&mut req, &app);
+    req.header("Idempotency-Key", "b0e7c1a2-publish-1");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let mut first = String::new();
+    response.body.read_to_string(&mut first).unwrap();
+
+    let body = ::new_req_body(::krate("foo_idempotent"), "1.0.0", Vec::new(), Vec::new(),
+                              Vec::new(), HashMap::new());
+    let mut response = ok_resp!(middle.call(req.with_body(&body)));
+    let mut retry = String::new();
+    response.body.read_to_string(&mut retry).unwrap();
+    assert_eq!(first, retry);
+
+    let body = ::new_req_body(::krate("foo_idempotent"), "1.0.1", Vec::new(), Vec::new(),
+                              Vec::new(), HashMap::new());
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert!(json.errors[0].detail.contains("already used for a different publish"),
+            "{:?}", json.errors);
+
+    req.header("Idempotency-Key", "");
+    let json = bad_resp!(middle.call(req.with_body(&body)));
+    assert!(json.errors[0].detail.contains("Idempotency-Key"), "{:?}", json.errors);
+}
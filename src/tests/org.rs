@@ -0,0 +1,189 @@
+use conduit::{Handler, Method, Request};
+use diesel;
+use diesel::prelude::*;
+
+use cargo_registry::org::{EncodableOrganization, EncodableOrgMember};
+use cargo_registry::token::NewApiToken;
+use cargo_registry::user::EncodableUser;
+
+#[derive(RustcDecodable)]
+struct O { ok: bool }
+#[derive(RustcDecodable)]
+struct OrgResponse { org: EncodableOrganization }
+#[derive(RustcDecodable)]
+struct OrgWithMembers { org: EncodableOrganization, members: Vec<EncodableOrgMember> }
+#[derive(RustcDecodable)]
+struct Owners { users: Vec<EncodableUser> }
+
+#[test]
+fn create_org_and_manage_members() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/orgs");
+    let (foo, bar) = {
+        let conn = app.diesel_database.get().unwrap();
+        let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+        let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+        (foo, bar)
+    };
+
+    ::sign_in_as(&mut req, &foo);
+    let body = r#"{"login":"acme","name":"Acme"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert_eq!(::json::<OrgResponse>(&mut response).org.login, "acme");
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    let body = r#"{"user":"bar","role":"publisher"}"#;
+    ok_resp!(middle.call(req.with_path("/api/v1/orgs/acme/members")
+                            .with_body(body.as_bytes())));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/orgs/ACME")));
+    let json = ::json::<OrgWithMembers>(&mut response);
+    assert_eq!(json.org.name, Some("Acme".to_string()));
+    let members = json.members.iter()
+        .map(|m| (&*m.login, &*m.role))
+        .collect::<Vec<_>>();
+    assert_eq!(members, [("bar", "publisher"), ("foo", "admin")]);
+
+    // Only admins can manage members
+    ::sign_in_as(&mut req, &bar);
+    let body = r#"{"user":"bar","role":"admin"}"#;
+    let json = bad_resp!(middle.call(req.with_method(Method::Put)
+                                        .with_path("/api/v1/orgs/acme/members")
+                                        .with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("only admins"), "{:?}", json.errors);
+
+    // And there is always at least one of them
+    ::sign_in_as(&mut req, &foo);
+    let json = bad_resp!(middle.call(req.with_method(Method::Delete)
+                                        .with_path("/api/v1/orgs/acme/members/foo")));
+    assert!(json.errors[0].detail.contains("at least one admin"), "{:?}", json.errors);
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/orgs/acme/members/bar")));
+    assert!(::json::<O>(&mut response).ok);
+}
+
+#[test]
+fn org_members_get_rights_on_org_crates() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/orgs");
+    let (foo, bar) = {
+        let conn = app.diesel_database.get().unwrap();
+        let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+        let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_crate("foo_org_owned").create_or_update(&conn, None, foo.id).unwrap();
+        (foo, bar)
+    };
+
+    ::sign_in_as(&mut req, &foo);
+    ok_resp!(middle.call(req.with_body(r#"{"login":"acme","name":null}"#.as_bytes())));
+    ok_resp!(middle.call(req.with_path("/api/v1/orgs/acme/members")
+                            .with_body(r#"{"user":"bar","role":"publisher"}"#.as_bytes())));
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_org_owned/owners")
+                            .with_body(r#"{"users":["org:acme"]}"#.as_bytes())));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let owners = ::json::<Owners>(&mut response).users;
+    assert!(owners.iter().any(|o| o.login == "org:acme"));
+
+    // Publishers can change what publishing allows, but not the owners
+    ::sign_in_as(&mut req, &bar);
+    ok_resp!(middle.call(req.with_method(Method::Put)
+                            .with_path("/api/v1/crates/foo_org_owned/settings")
+                            .with_body(r#"{"unlisted":false}"#.as_bytes())));
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_org_owned/owners")
+                                        .with_body(r#"{"users":["bar"]}"#.as_bytes())));
+    assert!(json.errors[0].detail.contains("don't have permission"), "{:?}", json.errors);
+}
+
+#[test]
+fn org_tokens_only_act_on_org_crates() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/orgs");
+    let foo = {
+        let conn = app.diesel_database.get().unwrap();
+        let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_crate("foo_org_token").create_or_update(&conn, None, foo.id).unwrap();
+        ::new_crate("foo_personal").create_or_update(&conn, None, foo.id).unwrap();
+        foo
+    };
+
+    ::sign_in_as(&mut req, &foo);
+    let body = r#"{"login":"acme","name":null}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let org = ::json::<OrgResponse>(&mut response).org;
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_org_token/owners")
+                            .with_body(r#"{"users":["org:acme"]}"#.as_bytes())));
+
+    let token = {
+        let conn = app.diesel_database.get().unwrap();
+        NewApiToken {
+            user_id: foo.id,
+            name: "ci",
+            organization_id: Some(org.id),
+            expires_at: None,
+            allowed_cidrs: Vec::new(),
+        }.save(&conn).unwrap()
+    };
+    req.mut_extensions().insert(token);
+
+    ok_resp!(middle.call(req.with_method(Method::Get)
+                            .with_path("/api/v1/crates/foo_org_token/settings")));
+    // Even though the token's creator owns this one
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_personal/settings")));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+
+    // The token stops working once its creator leaves the organization
+    {
+        use cargo_registry::schema::organization_members;
+
+        let conn = app.diesel_database.get().unwrap();
+        diesel::delete(organization_members::table.find((org.id, foo.id)))
+            .execute(&*conn)
+            .unwrap();
+    }
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_org_token/settings")));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+}
+
+#[test]
+fn org_tokens_dont_act_as_their_creator() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/orgs");
+    let foo = {
+        let conn = app.diesel_database.get().unwrap();
+        ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_user("foo").create_or_update(&conn).unwrap()
+    };
+    ::sign_in_as(&mut req, &foo);
+    let body = r#"{"login":"acme","name":null}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let org = ::json::<OrgResponse>(&mut response).org;
+
+    let token = {
+        let conn = app.diesel_database.get().unwrap();
+        NewApiToken {
+            user_id: foo.id,
+            name: "ci",
+            organization_id: Some(org.id),
+            expires_at: None,
+            allowed_cidrs: Vec::new(),
+        }.save(&conn).unwrap()
+    };
+    req.mut_extensions().insert(token);
+
+    // Neither minting personal tokens...
+    let json = bad_resp!(middle.call(req.with_path("/me/tokens")
+                                        .with_body(br#"{"api_token":{"name":"mine"}}"#)));
+    assert!(json.errors[0].detail.contains("organization API tokens"), "{:?}", json.errors);
+    let json = bad_resp!(middle.call(req.with_method(Method::Get).with_path("/me")));
+    assert!(json.errors[0].detail.contains("organization API tokens"), "{:?}", json.errors);
+
+    // ... nor managing the organization, even though its creator is an admin
+    let body = r#"{"user":"bar","role":"admin"}"#;
+    let json = bad_resp!(middle.call(req.with_method(Method::Put)
+                                        .with_path("/api/v1/orgs/acme/members")
+                                        .with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("organization API tokens"), "{:?}", json.errors);
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/orgs/acme/tokens")
+                                        .with_body(br#"{"api_token":{"name":"more"}}"#)));
+    assert!(json.errors[0].detail.contains("organization API tokens"), "{:?}", json.errors);
+}
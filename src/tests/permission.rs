@@ -0,0 +1,84 @@
+use conduit::{Handler, Method};
+
+use cargo_registry::krate::EncodableCrate;
+use cargo_registry::settings::EncodableCrateSettings;
+use cargo_registry::user::EncodableUser;
+
+#[derive(RustcDecodable)]
+struct CrateResponse { krate: EncodableCrate }
+#[derive(RustcDecodable)]
+struct Settings { settings: EncodableCrateSettings }
+#[derive(RustcDecodable)]
+struct Users { users: Vec<EncodableUser> }
+#[derive(RustcDecodable)]
+struct CrateList { meta: CrateMeta }
+#[derive(RustcDecodable)]
+struct CrateMeta { total: i32 }
+
+#[test]
+fn private_crates_are_only_visible_to_owners_and_grantees() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_private/settings");
+    let (owner, other) = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_crate("foo_private").create_or_update(&conn, None, owner.id).unwrap();
+        (owner, other)
+    };
+
+    ::sign_in_as(&mut req, &other);
+    let body = r#"{"private":true}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+
+    ::sign_in_as(&mut req, &owner);
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(::json::<Settings>(&mut response).settings.private);
+
+    // Owners can still see the crate, but it isn't searched or listed other
+    // than among their own crates
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_private")));
+    assert_eq!(::json::<CrateResponse>(&mut response).krate.name, "foo_private");
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates")
+                                               .with_query("q=foo_private")));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 0);
+    let owned = format!("user_id={}", owner.id);
+    let mut response = ok_resp!(middle.call(req.with_query(&owned)));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 1);
+
+    // Everyone else is told the crate doesn't exist
+    ::sign_in_as(&mut req, &other);
+    let mut response = ok_resp!(middle.call(req.with_query(&owned)));
+    assert_eq!(::json::<CrateList>(&mut response).meta.total, 0);
+    req.with_query("");
+    let response = t_resp!(middle.call(req.with_path("/api/v1/crates/foo_private")));
+    assert_eq!(response.status.0, 404);
+    ::logout(&mut req);
+    let response = t_resp!(middle.call(req.with_path("/api/v1/crates/foo_private/owners")));
+    assert_eq!(response.status.0, 404);
+
+    // Until they are given access
+    ::sign_in_as(&mut req, &owner);
+    let body = r#"{"users":["bar"]}"#;
+    ok_resp!(middle.call(req.with_method(Method::Put)
+                            .with_path("/api/v1/crates/foo_private/permissions")
+                            .with_body(body.as_bytes())));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let users = ::json::<Users>(&mut response).users;
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].login, "bar");
+
+    ::sign_in_as(&mut req, &other);
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_private")));
+
+    ::sign_in_as(&mut req, &owner);
+    ok_resp!(middle.call(req.with_method(Method::Delete)
+                            .with_path("/api/v1/crates/foo_private/permissions")
+                            .with_body(body.as_bytes())));
+    ::sign_in_as(&mut req, &other);
+    let response = t_resp!(middle.call(req.with_method(Method::Get)
+                                          .with_path("/api/v1/crates/foo_private")));
+    assert_eq!(response.status.0, 404);
+}
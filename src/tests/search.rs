@@ -0,0 +1,55 @@
+use conduit::{Handler, Method};
+
+use cargo_registry::keyword::{Keyword, EncodableKeyword};
+use cargo_registry::krate::EncodableCrate;
+use cargo_registry::owner::EncodableOwner;
+use cargo_registry::search::SearchGroup;
+use cargo_registry::user::EncodableUser;
+
+#[derive(RustcDecodable)]
+struct SearchResults {
+    crates: SearchGroup<EncodableCrate>,
+    users: SearchGroup<EncodableUser>,
+    teams: SearchGroup<EncodableOwner>,
+    keywords: SearchGroup<EncodableKeyword>,
+}
+
+#[test]
+fn search_everything() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo_searcher").create_or_update(&conn).unwrap();
+        ::new_user("bar").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_searched").create_or_update(&conn, None, user.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        ::new_crate("bar_searched").create_or_update(&conn, None, user.id).unwrap();
+        Keyword::update_crate(&conn, &krate, &["foo_kw"]).unwrap();
+    }
+
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/search");
+    let json = bad_resp!(middle.call(req.with_query("q=")));
+    assert!(json.errors[0].detail.contains("can't be empty"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_query("q=foo")));
+    let json: SearchResults = ::json(&mut response);
+    assert_eq!((json.crates.kind.as_str(), json.crates.total), ("crate", 1));
+    assert_eq!(json.crates.results[0].name, "foo_searched");
+    assert_eq!(json.crates.results[0].max_version, "1.0.0");
+    assert_eq!((json.users.kind.as_str(), json.users.total), ("user", 1));
+    assert_eq!(json.users.results[0].login, "foo_searcher");
+    assert!(json.users.results[0].email.is_none());
+    assert_eq!((json.teams.kind.as_str(), json.teams.total), ("team", 0));
+    assert_eq!((json.keywords.kind.as_str(), json.keywords.total), ("keyword", 1));
+    assert_eq!(json.keywords.results[0].keyword, "foo_kw");
+
+    // Wildcards are matched literally
+    let mut response = ok_resp!(middle.call(req.with_query("q=%25")));
+    let json: SearchResults = ::json(&mut response);
+    assert_eq!(json.users.total, 0);
+
+    let mut response = ok_resp!(middle.call(req.with_query("q=searched&per_page=1")));
+    let json: SearchResults = ::json(&mut response);
+    assert_eq!(json.crates.total, 2);
+    assert_eq!(json.crates.results.len(), 1);
+}
@@ -0,0 +1,146 @@
+use conduit::{Handler, Method};
+
+use cargo_registry::settings::EncodableCrateSettings;
+
+#[derive(RustcDecodable)]
+struct S { settings: EncodableCrateSettings }
+#[derive(RustcDecodable)]
+struct CrateResponse { deprecation_notice: Option<String> }
+
+#[test]
+fn owners_can_change_settings() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_settings/settings");
+    let (owner, other) = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        ::new_crate("foo_settings").create_or_update(&conn, None, owner.id).unwrap();
+        (owner, other)
+    };
+
+    ::sign_in_as(&mut req, &other);
+    let json = bad_resp!(middle.call(&mut req));
+    assert!(json.errors[0].detail.contains("only owners"), "{:?}", json.errors);
+
+    ::sign_in_as(&mut req, &owner);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let settings = ::json::<S>(&mut response).settings;
+    assert_eq!(settings.deprecation_notice, None);
+    assert!(settings.webhook_urls.is_empty());
+    assert!(!settings.unlisted);
+
+    let body = r#"{
+        "deprecation_notice": " use `bar` instead ",
+        "webhook_urls": ["https://203.0.113.7/hook"],
+        "download_thresholds": [10000, 1000, 10000],
+        "unlisted": true
+    }"#;
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Put)
+                                               .with_body(body.as_bytes())));
+    let settings = ::json::<S>(&mut response).settings;
+    assert_eq!(settings.deprecation_notice, Some("use `bar` instead".to_string()));
+    assert_eq!(settings.webhook_urls, ["https://203.0.113.7/hook"]);
+    assert_eq!(settings.download_thresholds, [1000, 10000]);
+    assert!(settings.unlisted);
+
+    // Settings which aren't given are left alone
+    let body = r#"{"unlisted": false}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let settings = ::json::<S>(&mut response).settings;
+    assert_eq!(settings.webhook_urls, ["https://203.0.113.7/hook"]);
+    assert!(!settings.unlisted);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_settings")));
+    let krate = ::json::<CrateResponse>(&mut response);
+    assert_eq!(krate.deprecation_notice, Some("use `bar` instead".to_string()));
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_settings/settings");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::new_crate("foo_settings").create_or_update(&conn, None, owner.id).unwrap();
+        ::sign_in_as(&mut req, &owner);
+    }
+
+    let body = r#"{"webhook_urls": ["http://example.com/hook"]}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("https"), "{:?}", json.errors);
+
+    for url in &["https://127.0.0.1/hook", "https://169.254.169.254/latest",
+                 "https://[::1]/hook", "https://localhost/hook"] {
+        let body = format!(r#"{{"webhook_urls": ["{}"]}}"#, url);
+        bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    }
+
+    let body = r#"{"download_thresholds": [0]}"#;
+    let json = bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("positive"), "{:?}", json.errors);
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)));
+    let settings = ::json::<S>(&mut response).settings;
+    assert!(settings.webhook_urls.is_empty());
+    assert!(settings.download_thresholds.is_empty());
+}
+
+#[test]
+fn settings_are_handed_off_when_a_crate_changes_hands() {
+    use cargo_registry::handoff::EncodableSettingsHandoff;
+    use cargo_registry::settings::CrateSettings;
+
+    #[derive(RustcDecodable)]
+    struct H { handoff: Option<EncodableSettingsHandoff>, settings: EncodableCrateSettings }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/admin/crates/foo_handoff/transfer");
+    let new = {
+        let conn = app.diesel_database.get().unwrap();
+        let old = ::new_user("old").create_or_update(&conn).unwrap();
+        let new = ::new_user("new").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_handoff").create_or_update(&conn, None, old.id).unwrap();
+        CrateSettings {
+            crate_id: krate.id,
+            deprecation_notice: Some("use `bar` instead".to_string()),
+            webhook_urls: vec!["https://old.example.com/hook".to_string()],
+            ..CrateSettings::default()
+        }.save(&conn).unwrap();
+        let admin = ::new_admin("admin").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &admin);
+        new
+    };
+    let body = r#"{"owner":"new","reason":"issue 1234"}"#;
+    ok_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    ::sign_in_as(&mut req, &new);
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_handoff/settings_handoff")));
+    let handoff = ::json::<H>(&mut response).handoff.unwrap();
+    assert_eq!(handoff.pending, ["webhook_urls", "deprecation_notice"]);
+    assert_eq!(handoff.reason, "transferred to new");
+    assert_eq!(handoff.started_by, "admin");
+
+    let body = r#"{"download_thresholds": "confirm"}"#;
+    let json = bad_resp!(middle.call(req.with_method(Method::Put).with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("isn't waiting"), "{:?}", json.errors);
+
+    let body = r#"{"webhook_urls": "disable"}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let json = ::json::<H>(&mut response);
+    assert_eq!(json.handoff.unwrap().pending, ["deprecation_notice"]);
+    assert!(json.settings.webhook_urls.is_empty());
+
+    // Replacing the last setting finishes the handoff
+    let body = r#"{"deprecation_notice": "use `baz` instead"}"#;
+    ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_handoff/settings")
+                            .with_body(body.as_bytes())));
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/api/v1/crates/foo_handoff/settings_handoff")));
+    let json = ::json::<H>(&mut response);
+    assert!(json.handoff.is_none());
+    assert_eq!(json.settings.deprecation_notice, Some("use `baz` instead".to_string()));
+}
@@ -0,0 +1,352 @@
+use conduit::{Handler, Method, Request};
+
+use cargo_registry::db::RequestTransaction;
+use cargo_registry::token::{self, ApiToken, EncodableApiToken, NewApiToken};
+use cargo_registry::user::User;
+
+#[derive(RustcDecodable)]
+struct TokenList { api_tokens: Vec<EncodableApiToken> }
+#[derive(RustcDecodable)]
+struct NewTokenResponse { api_token: EncodableApiToken }
+
+#[test]
+fn create_list_and_revoke() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/me/tokens");
+    ::sign_in(&mut req, &app);
+
+    let body = r#"{"api_token":{"name":"travis"}}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let created: NewTokenResponse = ::json(&mut response);
+    assert_eq!(created.api_token.name, "travis");
+    assert_eq!(created.api_token.token.map(|t| t.len()), Some(32));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_body(&[])));
+    let json: TokenList = ::json(&mut response);
+    assert_eq!(json.api_tokens.len(), 1);
+    assert_eq!(json.api_tokens[0].name, "travis");
+    assert!(json.api_tokens[0].token.is_none());
+
+    let path = format!("/me/tokens/{}", created.api_token.id);
+    ok_resp!(middle.call(req.with_method(Method::Delete).with_path(&path)));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get)
+                                               .with_path("/me/tokens")));
+    let json: TokenList = ::json(&mut response);
+    assert_eq!(json.api_tokens.len(), 0);
+}
+
+#[test]
+fn tokens_need_a_name() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/me/tokens");
+    ::sign_in(&mut req, &app);
+    let body = r#"{"api_token":{"name":"  "}}"#;
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+}
+
+#[test]
+fn named_tokens_authenticate_until_revoked() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name) \
+                             VALUES ($1, 'laptop') RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    req.header("Authorization", &secret);
+    ok_resp!(middle.call(&mut req));
+    assert_eq!(req.extensions().find::<ApiToken>().map(|t| &t.name[..]), Some("laptop"));
+    ::logout(&mut req);
+
+    req.tx().unwrap().execute("UPDATE api_tokens SET revoked = TRUE", &[]).unwrap();
+    bad_resp!(middle.call(&mut req));
+}
+
+#[test]
+fn token_uses_are_recorded() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name) \
+                             VALUES ($1, 'laptop') RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    req.header("Authorization", &secret);
+    req.header("User-Agent", "cargo 0.18.0 (5db6d64 2017-03-03)");
+    ok_resp!(middle.call(&mut req));
+    ::logout(&mut req);
+
+    let (token, _) = ApiToken::find_active(req.tx().unwrap(), &secret, None).unwrap();
+    assert!(token.last_used_at.is_some());
+    assert!(token.last_used_ip.is_some());
+    assert_eq!(token.last_used_user_agent.as_ref().map(|s| &s[..]), Some("cargo 0.18"));
+}
+
+#[test]
+fn stale_tokens_are_not_active() {
+    let (_b, app, _middle) = ::app();
+    let req = ::req(app, Method::Get, "/me");
+    let tx = req.tx().unwrap();
+    let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+    let rows = tx.query("INSERT INTO api_tokens (user_id, name, created_at) \
+                         VALUES ($1, 'laptop', now() - interval '40 days') \
+                         RETURNING token",
+                        &[&user.id]).unwrap();
+    let secret: String = rows.get(0).get("token");
+
+    assert!(ApiToken::find_active(tx, &secret, None).is_ok());
+    assert!(ApiToken::find_active(tx, &secret, Some(60)).is_ok());
+    assert!(ApiToken::find_active(tx, &secret, Some(30)).is_err());
+
+    // Using a token keeps it from going stale
+    tx.execute("UPDATE api_tokens SET last_used_at = now() - interval '1 day'", &[]).unwrap();
+    assert!(ApiToken::find_active(tx, &secret, Some(30)).is_ok());
+}
+
+#[test]
+fn expired_tokens_are_rejected() {
+    #[derive(RustcDecodable)]
+    struct CodedError { detail: String, code: String }
+    #[derive(RustcDecodable)]
+    struct Bad { errors: Vec<CodedError> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name, expires_at) \
+                             VALUES ($1, 'laptop', now() - interval '1 hour') \
+                             RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    req.header("Authorization", &secret);
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json = ::json::<Bad>(&mut response);
+    assert_eq!(json.errors[0].code, "expired_token");
+    assert!(json.errors[0].detail.contains("expired"), "{}", json.errors[0].detail);
+    assert!(req.extensions().find::<ApiToken>().is_none());
+
+    // Only tokens which expired a while ago are purged
+    assert_eq!(token::purge_expired(req.tx().unwrap(), 1).unwrap(), 0);
+    assert_eq!(token::purge_expired(req.tx().unwrap(), 0).unwrap(), 1);
+}
+
+#[test]
+fn tokens_can_be_created_with_an_expiry() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/me/tokens");
+    ::sign_in(&mut req, &app);
+
+    let body = r#"{"api_token":{"name":"ci","expires_in_days":0}}"#;
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+    let body = r#"{"api_token":{"name":"ci","expires_in_days":4294967295}}"#;
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    let body = r#"{"api_token":{"name":"ci","expires_in_days":30}}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let created: NewTokenResponse = ::json(&mut response);
+    assert!(created.api_token.expires_at.is_some());
+}
+
+#[test]
+fn tokens_only_work_from_allowed_ips() {
+    #[derive(RustcDecodable)]
+    struct CodedError { code: String }
+    #[derive(RustcDecodable)]
+    struct Bad { errors: Vec<CodedError> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name, allowed_cidrs) \
+                             VALUES ($1, 'ci', '{192.0.2.0/24}') RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    // Mock requests come from 127.0.0.1
+    req.header("Authorization", &secret);
+    let mut response = ok_resp!(middle.call(&mut req));
+    assert_eq!(::json::<Bad>(&mut response).errors[0].code, "ip_not_allowed");
+
+    req.tx().unwrap().execute("UPDATE api_tokens SET allowed_cidrs = '{127.0.0.0/8}'", &[])
+        .unwrap();
+    ok_resp!(middle.call(&mut req));
+    assert!(req.extensions().find::<ApiToken>().is_some());
+}
+
+#[test]
+fn allowed_ips_are_checked_against_the_forwarded_client() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name, allowed_cidrs) \
+                             VALUES ($1, 'ci', '{192.0.2.0/24}') RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    // 127.0.0.1 is a trusted proxy in the tests, so the client is the
+    // right-most address it forwarded for
+    req.header("Authorization", &secret);
+    req.header("X-Forwarded-For", "192.0.2.7, 203.0.113.9");
+    ok_resp!(middle.call(&mut req));
+    assert!(req.extensions().find::<ApiToken>().is_none());
+
+    ::logout(&mut req);
+    req.header("X-Forwarded-For", "203.0.113.9, 192.0.2.7");
+    ok_resp!(middle.call(&mut req));
+    assert!(req.extensions().find::<ApiToken>().is_some());
+}
+
+#[test]
+fn token_ip_ranges_are_validated() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/me/tokens");
+    ::sign_in(&mut req, &app);
+
+    let body = r#"{"api_token":{"name":"ci","allowed_cidrs":["192.0.2.0/33"]}}"#;
+    bad_resp!(middle.call(req.with_body(body.as_bytes())));
+
+    let body = r#"{"api_token":{"name":"ci","allowed_cidrs":["192.0.2.7/24"]}}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let created: NewTokenResponse = ::json(&mut response);
+    assert_eq!(created.api_token.allowed_cidrs, ["192.0.2.0/24"]);
+}
+
+#[test]
+fn tokens_can_only_be_revoked_by_their_owner() {
+    let (_b, app, middle) = ::app();
+    let token = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("owner").create_or_update(&conn).unwrap();
+        NewApiToken {
+            user_id: owner.id,
+            name: "ci",
+            organization_id: None,
+            expires_at: None,
+            allowed_cidrs: Vec::new(),
+        }.save(&conn).unwrap()
+    };
+
+    let path = format!("/me/tokens/{}", token.id);
+    let mut req = ::req(app.clone(), Method::Delete, &path);
+    ::sign_in(&mut req, &app);
+    bad_resp!(middle.call(&mut req));
+}
+
+#[test]
+fn bearer_and_bare_tokens_authenticate() {
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    let secret: String = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name) \
+                             VALUES ($1, 'laptop') RETURNING token",
+                            &[&user.id]).unwrap();
+        rows.get(0).get("token")
+    };
+
+    for header in &[secret.clone(),
+                    format!(" {}\t", secret),
+                    format!("Bearer {}", secret),
+                    format!("bearer  {} ", secret)] {
+        req.header("Authorization", header);
+        ok_resp!(middle.call(&mut req));
+        assert!(req.extensions().find::<ApiToken>().is_some(), "{:?}", header);
+        ::logout(&mut req);
+    }
+}
+
+#[test]
+fn missing_malformed_and_invalid_tokens_are_told_apart() {
+    #[derive(RustcDecodable)]
+    struct CodedError { code: String }
+    #[derive(RustcDecodable)]
+    struct Bad { errors: Vec<CodedError> }
+
+    fn code(middle: &::conduit_middleware::MiddlewareBuilder,
+            req: &mut ::conduit_test::MockRequest) -> String {
+        let mut response = t_resp!(middle.call(req));
+        ::json::<Bad>(&mut response).errors.remove(0).code
+    }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/me");
+    assert_eq!(code(&middle, &mut req), "missing_token");
+
+    for header in &["", "Bearer", "Bearer a b", "Basic Zm9vOmJhcg=="] {
+        req.header("Authorization", header);
+        assert_eq!(code(&middle, &mut req), "malformed_token");
+    }
+
+    for header in &["nope", "Bearer nope"] {
+        req.header("Authorization", header);
+        assert_eq!(code(&middle, &mut req), "invalid_token");
+    }
+}
+
+#[test]
+fn usage_is_counted_per_endpoint_class() {
+    use cargo_registry::token::EncodableTokenUsage;
+
+    #[derive(RustcDecodable)]
+    struct R { usage: Vec<EncodableTokenUsage> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates");
+    let (id, secret): (i32, String) = {
+        let tx = req.tx().unwrap();
+        let user = User::find_or_insert(tx, 1, "foo", None, None, None, "bar").unwrap();
+        let rows = tx.query("INSERT INTO api_tokens (user_id, name) \
+                             VALUES ($1, 'ci') RETURNING id, token",
+                            &[&user.id]).unwrap();
+        let row = rows.get(0);
+        (row.get("id"), row.get("token"))
+    };
+
+    req.header("Authorization", &secret);
+    ok_resp!(middle.call(req.with_query("q=foo")));
+    ok_resp!(middle.call(req.with_query("")));
+
+    let path = format!("/me/tokens/{}/usage", id);
+    let mut response = ok_resp!(middle.call(req.with_path(&path)));
+    let usage = ::json::<R>(&mut response).usage;
+    let usage = usage.iter()
+        .map(|u| (&u.endpoint[..], u.requests))
+        .collect::<Vec<_>>();
+    assert_eq!(usage, [("other", 1), ("search", 2)]);
+
+    let path = format!("/me/tokens/{}/usage", id + 1);
+    let response = t_resp!(middle.call(req.with_path(&path)));
+    assert_eq!(response.status.0, 404);
+}
+
+#[test]
+fn endpoint_classes() {
+    assert_eq!(token::endpoint_class(Method::Put, "/api/v1/crates/new"), "publish");
+    assert_eq!(token::endpoint_class(Method::Get, "/api/v1/crates"), "search");
+    assert_eq!(token::endpoint_class(Method::Get, "/api/v1/crates/foo/1.0.0/download"),
+               "download");
+    assert_eq!(token::endpoint_class(Method::Get, "/api/v1/crates/foo"), "other");
+    assert_eq!(token::endpoint_class(Method::Delete, "/api/v1/crates/foo/1.0.0/yank"),
+               "other");
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::io::Read;
+
 use conduit::{Handler, Method};
 use diesel::prelude::*;
 use diesel::insert;
@@ -6,8 +9,9 @@ use cargo_registry::Model;
 use cargo_registry::db::RequestTransaction;
 use cargo_registry::krate::EncodableCrate;
 use cargo_registry::schema::versions;
-use cargo_registry::user::{User, NewUser, EncodableUser};
-use cargo_registry::version::EncodableVersion;
+use cargo_registry::user::{User, NewUser, EncodableUser, EncodableCrateExport};
+use cargo_registry::version::{EncodableVersion, NewVersion};
+use semver;
 
 #[derive(RustcDecodable)]
 struct AuthResponse { url: String, state: String }
@@ -95,6 +99,29 @@ fn show() {
     assert_eq!("bar", json.user.login);
 }
 
+#[test]
+fn avatars_are_proxied() {
+    let (_b, app, middle) = ::app();
+    {
+        let conn = t!(app.diesel_database.get());
+        let avatar = Some("https://avatars.githubusercontent.com/u/1234?v=3");
+        t!(NewUser::new(1, "foo", None, None, avatar, "bar").create_or_update(&conn));
+        t!(NewUser::new(2, "bar", None, None, None, "bar").create_or_update(&conn));
+    }
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/users/foo");
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: UserShowResponse = ::json(&mut response);
+    assert_eq!(json.user.avatar, Some("/api/v1/users/foo/avatar".to_string()));
+
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/users/foo/avatar")
+                                        .with_query("size=10000")));
+    assert!(json.errors[0].detail.contains("avatar size"), "{:?}", json.errors);
+    let response = t_resp!(middle.call(req.with_path("/api/v1/users/bar/avatar")
+                                          .with_query("")));
+    assert_eq!(response.status.0, 404);
+}
+
 #[test]
 fn reset_token() {
     let (_b, app, middle) = ::app();
@@ -132,6 +159,47 @@ fn crates_by_user_id() {
     assert_eq!(response.crates.len(), 1);
 }
 
+#[test]
+fn published_versions() {
+    #[derive(RustcDecodable)]
+    struct R {
+        versions: Vec<EncodableVersion>,
+        meta: Meta,
+    }
+    #[derive(RustcDecodable)] struct Meta { total: i64 }
+
+    let (_b, app, middle) = ::app();
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let foo = ::new_user("foo").create_or_update(&conn).unwrap();
+        let bar = ::new_user("bar").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_published").create_or_update(&conn, None, foo.id)
+            .unwrap();
+        for &(num, user) in &[("1.0.0", &foo), ("1.1.0", &bar), ("1.2.0", &bar)] {
+            let num = semver::Version::parse(num).unwrap();
+            NewVersion::new(krate.id, &num, &HashMap::new(), Some(user.id), None, None).unwrap()
+                .save(&conn, &[]).unwrap();
+        }
+    }
+
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/users/bar/versions");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let r = ::json::<R>(&mut response);
+    assert_eq!(r.meta.total, 2);
+    let nums = r.versions.iter().map(|v| &v.num[..]).collect::<Vec<_>>();
+    assert_eq!(nums, ["1.2.0", "1.1.0"]);
+    assert!(r.versions.iter().all(|v| {
+        v.published_by.as_ref().map(|u| &u.login[..]) == Some("bar")
+    }));
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/users/foo/versions")
+                                               .with_query("per_page=1")));
+    let r = ::json::<R>(&mut response);
+    assert_eq!(r.meta.total, 1);
+    assert_eq!(r.versions[0].num, "1.0.0");
+    assert_eq!(r.versions[0].krate, "foo_published");
+}
+
 #[test]
 fn following() {
     #[derive(RustcDecodable)]
@@ -139,7 +207,7 @@ fn following() {
         versions: Vec<EncodableVersion>,
         meta: Meta,
     }
-    #[derive(RustcDecodable)] struct Meta { more: bool }
+    #[derive(RustcDecodable)] struct Meta { next_page: Option<String> }
 
     let (_b, app, middle) = ::app();
     let mut req = ::req(app.clone(), Method::Get, "/");
@@ -168,7 +236,7 @@ fn following() {
                                                .with_method(Method::Get)));
     let r = ::json::<R>(&mut response);
     assert_eq!(r.versions.len(), 0);
-    assert_eq!(r.meta.more, false);
+    assert_eq!(r.meta.next_page, None);
 
     ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_fighters/follow")
                             .with_method(Method::Put)));
@@ -179,14 +247,14 @@ fn following() {
                                                .with_method(Method::Get)));
     let r = ::json::<R>(&mut response);
     assert_eq!(r.versions.len(), 2);
-    assert_eq!(r.meta.more, false);
+    assert_eq!(r.meta.next_page, None);
 
     let mut response = ok_resp!(middle.call(req.with_path("/me/updates")
                                                .with_method(Method::Get)
                                                .with_query("per_page=1")));
     let r = ::json::<R>(&mut response);
     assert_eq!(r.versions.len(), 1);
-    assert_eq!(r.meta.more, true);
+    assert_eq!(r.meta.next_page, Some("?page=2&per_page=1".to_string()));
 
     ok_resp!(middle.call(req.with_path("/api/v1/crates/bar_fighters/follow")
                             .with_method(Method::Delete)));
@@ -195,7 +263,61 @@ fn following() {
                                                .with_query("page=2&per_page=1")));
     let r = ::json::<R>(&mut response);
     assert_eq!(r.versions.len(), 0);
-    assert_eq!(r.meta.more, false);
+    assert_eq!(r.meta.next_page, None);
 
     bad_resp!(middle.call(req.with_query("page=0")));
 }
+
+#[test]
+fn export_owned_crates() {
+    #[derive(RustcDecodable)]
+    struct R { crates: Vec<EncodableCrateExport> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/me/crates/export");
+    let other = ::mock_user(&mut req, ::user("bar"));
+    let user = ::mock_user(&mut req, ::user("foo"));
+    let (krate, _) = ::mock_crate(&mut req, ::krate("foo_export"));
+    ::mock_crate_vers(&mut req, ::krate("foo_export"),
+                      &semver::Version::parse("1.2.0").unwrap());
+    req.tx().unwrap().execute("INSERT INTO crate_owners (crate_id, owner_id, owner_kind) \
+                               VALUES ($1, $2, 0)", &[&krate.id, &other.id]).unwrap();
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.crates.len(), 1);
+    assert_eq!(json.crates[0].name, "foo_export");
+    assert_eq!(json.crates[0].latest_version, "1.2.0");
+    assert_eq!(json.crates[0].co_owners, vec!["bar".to_string()]);
+
+    let mut response = ok_resp!(middle.call(req.with_query("format=csv")));
+    assert_eq!(response.headers["Content-Disposition"],
+               vec!["attachment; filename=\"crates.csv\"".to_string()]);
+    let mut body = String::new();
+    response.body.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "name,latest_version,downloads,recent_downloads,co_owners\r\n\
+                      foo_export,1.2.0,0,0,bar\r\n");
+
+    let json = bad_resp!(middle.call(req.with_query("format=xml")));
+    assert!(json.errors[0].detail.contains("unknown export format"));
+
+    // Crates owned through an organization are exported too
+    let (org_crate, _) = ::mock_crate(&mut req, ::krate("foo_export_org"));
+    {
+        let tx = req.tx().unwrap();
+        tx.execute("UPDATE crate_owners SET deleted = TRUE WHERE crate_id = $1",
+                   &[&org_crate.id]).unwrap();
+        let org_id: i32 = tx.query("INSERT INTO organizations (login) VALUES ('acme') \
+                                    RETURNING id", &[]).unwrap().get(0).get(0);
+        tx.execute("INSERT INTO organization_members (organization_id, user_id, role) \
+                    VALUES ($1, $2, 2)", &[&org_id, &user.id]).unwrap();
+        tx.execute("INSERT INTO crate_owners (crate_id, owner_id, owner_kind) \
+                    VALUES ($1, $2, 2)", &[&org_crate.id, &org_id]).unwrap();
+    }
+    let mut response = ok_resp!(middle.call(req.with_query("format=json")));
+    let json: R = ::json(&mut response);
+    assert_eq!(json.crates.len(), 2);
+    assert_eq!(json.crates[1].name, "foo_export_org");
+    assert_eq!(json.crates[1].latest_version, "1.0.0");
+    assert_eq!(json.crates[1].co_owners, vec!["acme".to_string()]);
+}
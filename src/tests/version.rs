@@ -5,7 +5,14 @@ use conduit::{Handler, Method};
 use semver;
 
 use cargo_registry::db::RequestTransaction;
-use cargo_registry::version::{EncodableVersion, Version};
+use cargo_registry::dependency::{Dependency, Kind};
+use cargo_registry::diff::{self, EncodableDiff};
+use cargo_registry::files::{self, EncodableVersionFile};
+use cargo_registry::krate::EncodableCrate;
+use cargo_registry::scanner::{self, EncodableFinding, Finding, Severity};
+use cargo_registry::signature::EncodableSignature;
+use cargo_registry::util::{CargoResult, TarballFile};
+use cargo_registry::version::{EncodableDependencyFreshness, EncodableVersion, Version};
 
 #[derive(RustcDecodable)]
 struct VersionList { versions: Vec<EncodableVersion> }
@@ -70,3 +77,259 @@ fn authors() {
     let json = json.as_object().unwrap();
     assert!(json.contains_key(&"users".to_string()));
 }
+
+#[test]
+fn outdated_dependencies() {
+    #[derive(RustcDecodable)]
+    struct R { dependencies: Vec<EncodableDependencyFreshness>, meta: Meta }
+    #[derive(RustcDecodable)]
+    struct Meta { outdated: i64 }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_outdated/1.0.0/outdated");
+    {
+        ::mock_user(&mut req, ::user("foo"));
+        let (_, v) = ::mock_crate(&mut req, ::krate("foo_outdated"));
+        let (bar, _) = ::mock_crate(&mut req, ::krate("bar_outdated"));
+        let (baz, _) = ::mock_crate(&mut req, ::krate("baz_outdated"));
+        let tx = req.tx().unwrap();
+        let m = HashMap::new();
+        Version::insert(tx, bar.id, &sv("2.0.0"), &m, &[]).unwrap();
+        Version::insert(tx, baz.id, &sv("1.1.0-beta.1"), &m, &[]).unwrap();
+        let caret = semver::VersionReq::parse("^1.0").unwrap();
+        for krate in &[bar, baz] {
+            Dependency::insert(tx, v.id, krate.id, &caret, Kind::Normal,
+                               false, true, &[], &None).unwrap();
+        }
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json = ::json::<R>(&mut response);
+    assert_eq!(json.meta.outdated, 1);
+    let bar = json.dependencies.iter().find(|d| d.crate_id == "bar_outdated").unwrap();
+    assert_eq!(bar.latest, Some("2.0.0".to_string()));
+    assert!(bar.outdated);
+    // Prereleases don't count when there's a release
+    let baz = json.dependencies.iter().find(|d| d.crate_id == "baz_outdated").unwrap();
+    assert_eq!(baz.latest, Some("1.0.0".to_string()));
+    assert!(!baz.outdated);
+}
+
+#[test]
+fn findings() {
+    #[derive(RustcDecodable)]
+    struct Findings { findings: Vec<EncodableFinding> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get,
+                        "/api/v1/crates/foo_findings/1.0.0/findings");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_findings").create_or_update(&conn, None, user.id).unwrap();
+        let version = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        let finding = Finding {
+            scanner: "binaries",
+            severity: Severity::Warning,
+            path: Some("foo_findings-1.0.0/bin/tool".to_string()),
+            message: "precompiled binary".to_string(),
+        };
+        scanner::record(&conn, version.id, &[finding]).unwrap();
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Findings = ::json(&mut response);
+    assert_eq!(json.findings.len(), 1);
+    assert_eq!(json.findings[0].scanner, "binaries");
+    assert_eq!(json.findings[0].severity, "warning");
+    assert_eq!(json.findings[0].path.as_ref().unwrap(), "foo_findings-1.0.0/bin/tool");
+
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_findings/2.0.0/findings")));
+    assert!(json.errors[0].detail.contains("does not have a version"), "{:?}", json.errors);
+}
+
+#[test]
+fn file_listing() {
+    #[derive(RustcDecodable)]
+    struct Meta { total: usize, total_size: i64 }
+    #[derive(RustcDecodable)]
+    struct Files { files: Vec<EncodableVersionFile>, meta: Meta }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Get, "/api/v1/crates/foo_files/1.0.0/files");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_files").create_or_update(&conn, None, user.id).unwrap();
+        let version = ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        let file = |path: &str, contents: &[u8]| TarballFile {
+            path: path.to_string(),
+            size: contents.len() as u64,
+            contents: contents.to_vec(),
+        };
+        let tarball = [
+            file("foo_files-1.0.0/src/lib.rs", b""),
+            file("foo_files-1.0.0/Cargo.toml", b"[package]\n"),
+        ];
+        files::record(&conn, version.id, &tarball).unwrap();
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Files = ::json(&mut response);
+    assert_eq!(json.meta.total, 2);
+    assert_eq!(json.meta.total_size, 10);
+    assert_eq!(json.files[0].path, "Cargo.toml");
+    assert_eq!(json.files[0].size, 10);
+    assert_eq!(json.files[1].path, "src/lib.rs");
+    assert_eq!(json.files[1].checksum,
+               "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_files/2.0.0/files")));
+    assert!(json.errors[0].detail.contains("does not have a version"), "{:?}", json.errors);
+}
+
+#[test]
+fn diffs() {
+    #[derive(RustcDecodable)]
+    struct Diff { diff: EncodableDiff }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app, Method::Get, "/api/v1/crates/foo_diff/1.0.0/diff/1.1.0");
+    ::mock_user(&mut req, ::user("foo"));
+    let (_, v1) = ::mock_crate_vers(&mut req, ::krate("foo_diff"), &sv("1.0.0"));
+    let (_, v2) = ::mock_crate_vers(&mut req, ::krate("foo_diff"), &sv("1.1.0"));
+    {
+        let tx = req.tx().unwrap();
+        let files: &[(i32, &str, i64, &str)] = &[
+            (v1.id, "Cargo.toml", 20, "aaaa"),
+            (v1.id, "src/lib.rs", 100, "bbbb"),
+            (v1.id, "src/old.rs", 10, "cccc"),
+            (v2.id, "Cargo.toml", 20, "dddd"),
+            (v2.id, "src/lib.rs", 100, "bbbb"),
+            (v2.id, "src/new.rs", 30, "eeee"),
+        ];
+        for &(version_id, path, size, checksum) in files {
+            tx.execute("INSERT INTO version_files (version_id, path, size, checksum) \
+                        VALUES ($1, $2, $3, $4)",
+                       &[&version_id, &path, &size, &checksum]).unwrap();
+        }
+    }
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Diff = ::json(&mut response);
+    let paths = |changes: &[diff::EncodableFileChange]| {
+        changes.iter().map(|c| c.path.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(paths(&json.diff.added), ["src/new.rs"]);
+    assert_eq!(paths(&json.diff.removed), ["src/old.rs"]);
+    assert_eq!(paths(&json.diff.changed), ["Cargo.toml"]);
+    assert!(json.diff.patches.is_none());
+    assert!(!json.diff.patches_pending);
+
+    // Patches are computed in the background once they're asked for
+    req.with_query("patches=1");
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Diff = ::json(&mut response);
+    assert!(json.diff.patches.is_none());
+    assert!(json.diff.patches_pending);
+
+    let fetch = |name: &str, num: &str| -> CargoResult<Vec<TarballFile>> {
+        assert_eq!(name, "foo_diff");
+        let path = format!("foo_diff-{}/Cargo.toml", num);
+        let contents = format!("[package]\nversion = \"{}\"\n", num).into_bytes();
+        Ok(vec![TarballFile { path: path, size: contents.len() as u64, contents: contents }])
+    };
+    assert_eq!(diff::compute_pending(req.tx().unwrap(), &fetch).unwrap(), 1);
+    assert_eq!(diff::compute_pending(req.tx().unwrap(), &fetch).unwrap(), 0);
+
+    let mut response = ok_resp!(middle.call(&mut req));
+    let json: Diff = ::json(&mut response);
+    assert!(!json.diff.patches_pending);
+    let patches = json.diff.patches.unwrap();
+    assert_eq!(patches.len(), 1);
+    assert!(patches[0].patch.as_ref().unwrap().contains("+version = \"1.1.0\""));
+
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_diff/1.0.0/diff/2.0.0")));
+    assert!(json.errors[0].detail.contains("does not have a version"), "{:?}", json.errors);
+}
+
+#[test]
+fn owners_can_sign_versions() {
+    #[derive(RustcDecodable)]
+    struct Signatures { signatures: Vec<EncodableSignature> }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Put, "/api/v1/crates/foo_signed/1.0.0/signature");
+    let (owner, other) = {
+        let conn = app.diesel_database.get().unwrap();
+        let owner = ::new_user("foo").create_or_update(&conn).unwrap();
+        let other = ::new_user("bar").create_or_update(&conn).unwrap();
+        let krate = ::new_crate("foo_signed").create_or_update(&conn, None, owner.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        (owner, other)
+    };
+    let signature = "-----BEGIN PGP SIGNATURE-----\n\nabcd\n-----END PGP SIGNATURE-----\n";
+
+    ::sign_in_as(&mut req, &other);
+    bad_resp!(middle.call(req.with_body(signature.as_bytes())));
+
+    ::sign_in_as(&mut req, &owner);
+    bad_resp!(middle.call(req.with_body(b"  ")));
+    ok_resp!(middle.call(req.with_body(b"an older signature")));
+    ok_resp!(middle.call(req.with_body(signature.as_bytes())));
+
+    let mut response = ok_resp!(middle.call(req.with_method(Method::Get).with_body(&[])));
+    let json: Signatures = ::json(&mut response);
+    assert_eq!(json.signatures.len(), 1);
+    assert_eq!(json.signatures[0].kind, "owner");
+    assert_eq!(json.signatures[0].signature, signature);
+    assert_eq!(json.signatures[0].uploaded_by.as_ref().unwrap(), "foo");
+    assert!(json.signatures[0].key_fingerprint.is_none());
+}
+
+#[test]
+fn update_metadata() {
+    #[derive(RustcDecodable)]
+    struct Updated { changed: Vec<String>, invalid_categories: Vec<String> }
+    #[derive(RustcDecodable)]
+    struct CrateResponse { krate: EncodableCrate }
+
+    let (_b, app, middle) = ::app();
+    let mut req = ::req(app.clone(), Method::Patch, "/api/v1/crates/foo_meta/1.1.0");
+    {
+        let conn = app.diesel_database.get().unwrap();
+        let user = ::new_user("foo").create_or_update(&conn).unwrap();
+        ::sign_in_as(&mut req, &user);
+        let krate = ::new_crate("foo_meta").create_or_update(&conn, None, user.id).unwrap();
+        ::new_version(krate.id, "1.0.0").save(&conn, &[]).unwrap();
+        ::new_version(krate.id, "1.1.0").save(&conn, &[]).unwrap();
+        ::new_category("Category 1", "cat1").find_or_create(&conn).unwrap();
+    }
+
+    let body = r#"{"documentation":"https://docs.example.com/foo_meta",
+                   "categories":["cat1","nope"]}"#;
+    let mut response = ok_resp!(middle.call(req.with_body(body.as_bytes())));
+    let json: Updated = ::json(&mut response);
+    assert_eq!(json.changed, vec!["documentation".to_string(), "categories".to_string()]);
+    assert_eq!(json.invalid_categories, vec!["nope".to_string()]);
+
+    let mut response = ok_resp!(middle.call(req.with_path("/api/v1/crates/foo_meta")
+                                               .with_method(Method::Get)));
+    let json: CrateResponse = ::json(&mut response);
+    assert_eq!(json.krate.documentation,
+               Some("https://docs.example.com/foo_meta".to_string()));
+    assert_eq!(json.krate.categories, Some(vec!["cat1".to_string()]));
+
+    let body = r#"{"documentation":"ftp://example.com"}"#;
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_meta/1.1.0")
+                                        .with_method(Method::Patch)
+                                        .with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("invalid url scheme"), "{:?}", json.errors);
+
+    // Older versions' metadata is gone, only the newest one can be edited
+    let body = r#"{"render_readme":true}"#;
+    let json = bad_resp!(middle.call(req.with_path("/api/v1/crates/foo_meta/1.0.0")
+                                        .with_body(body.as_bytes())));
+    assert!(json.errors[0].detail.contains("only the metadata of the newest version"),
+            "{:?}", json.errors);
+}
@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::mem;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use conduit::{Method, Request, Response};
+use conduit_middleware::Middleware;
+use conduit_router::RequestParams;
+use diesel;
+use diesel::prelude::*;
+use pg::GenericConnection;
+use pg::rows::Row;
+use rustc_serialize::json;
+use time::Timespec;
+
+use app::{App, RequestApp};
+use db::{self, InstrumentedConnection, RequestTransaction};
+use download::user_agent_family;
+use schema::api_tokens;
+use user::{User, RequestUser};
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, ChainError, Cidr, human};
+use Model;
+
+/// The model representing a row in the `api_tokens` database table.
+///
+/// Users can have any number of named tokens, which lets them tell apart
+/// (and revoke) the tokens used by different machines or CI services.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token: String,
+    pub name: String,
+    pub created_at: Timespec,
+    pub revoked: bool,
+    /// Set for tokens which act on behalf of an organization rather than of
+    /// the user who created them.
+    pub organization_id: Option<i32>,
+    /// When the token was last used to authenticate a request, and where
+    /// that request came from. Uses are written in batches (see
+    /// `PendingTokenUses`), so these can lag behind a little.
+    pub last_used_at: Option<Timespec>,
+    pub last_used_ip: Option<String>,
+    pub last_used_user_agent: Option<String>,
+    /// Tokens stop working once they expire, if they were given an expiry
+    /// when they were created.
+    pub expires_at: Option<Timespec>,
+    /// The IP ranges the token can be used from, in CIDR notation. Tokens
+    /// without any work from everywhere.
+    pub allowed_cidrs: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="api_tokens"]
+pub struct NewApiToken<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub organization_id: Option<i32>,
+    pub expires_at: Option<Timespec>,
+    pub allowed_cidrs: Vec<String>,
+}
+
+/// Left in the request extensions by the authentication middleware when the
+/// request used a token which exists but can't be used, e.g. because it has
+/// expired, so that `RequestUser::user` can tell the client why it isn't
+/// logged in.
+pub struct RejectedToken {
+    pub code: &'static str,
+    pub reason: String,
+}
+
+/// The serialization format for the `ApiToken` model. The secret itself is
+/// only ever sent once, right after the token has been created.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct EncodableApiToken {
+    pub id: i32,
+    pub name: String,
+    pub created_at: String,
+    pub token: Option<String>,
+    pub last_used_at: Option<String>,
+    pub last_used_ip: Option<String>,
+    pub last_used_user_agent: Option<String>,
+    pub expires_at: Option<String>,
+    pub allowed_cidrs: Vec<String>,
+}
+
+impl ApiToken {
+    /// Queries the database for a token that hasn't been revoked, along with
+    /// the user it belongs to.
+    ///
+    /// If `stale_days` is given, tokens which haven't been used for that many
+    /// days (or were never used since they were created that long ago) aren't
+    /// active anymore.
+    pub fn find_active(conn: &GenericConnection,
+                       token: &str,
+                       stale_days: Option<u32>) -> CargoResult<(ApiToken, User)> {
+        let stale_days = stale_days.map(|days| days as i32);
+        let rows = conn.query("SELECT * FROM api_tokens \
+                                WHERE token = $1 AND NOT revoked \
+                                  AND ($2::int4 IS NULL OR \
+                                       COALESCE(last_used_at, created_at) > \
+                                         now() - $2::int4 * INTERVAL '1 day') \
+                                LIMIT 1",
+                              &[&token, &stale_days])?;
+        let token: ApiToken = rows.iter().next()
+            .map(|r| Model::from_row(&r))
+            .chain_error(|| NotFound)?;
+        let user = User::find(conn, token.user_id)?;
+        Ok((token, user))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= ::time::now_utc().to_timespec(),
+            None => false,
+        }
+    }
+
+    /// Whether the token can be used from `ip`.
+    pub fn allows_ip(&self, ip: &IpAddr) -> bool {
+        self.allowed_cidrs.is_empty() || self.allowed_cidrs.iter().any(|cidr| {
+            Cidr::parse(cidr).map(|cidr| cidr.contains(ip)).unwrap_or(false)
+        })
+    }
+
+    /// Converts this `ApiToken` model into an `EncodableApiToken`, leaving
+    /// out the secret.
+    pub fn encodable(self) -> EncodableApiToken {
+        EncodableApiToken {
+            id: self.id,
+            name: self.name,
+            created_at: ::encode_time(self.created_at),
+            token: None,
+            last_used_at: self.last_used_at.map(::encode_time),
+            last_used_ip: self.last_used_ip,
+            last_used_user_agent: self.last_used_user_agent,
+            expires_at: self.expires_at.map(::encode_time),
+            allowed_cidrs: self.allowed_cidrs,
+        }
+    }
+}
+
+impl<'a> NewApiToken<'a> {
+    pub fn save(&self, conn: &InstrumentedConnection) -> CargoResult<ApiToken> {
+        diesel::insert(self).into(api_tokens::table)
+            .get_result(conn)
+            .map_err(Into::into)
+    }
+}
+
+impl Model for ApiToken {
+    fn from_row(row: &Row) -> ApiToken {
+        ApiToken {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            token: row.get("token"),
+            name: row.get("name"),
+            created_at: row.get("created_at"),
+            revoked: row.get("revoked"),
+            organization_id: row.get("organization_id"),
+            last_used_at: row.get("last_used_at"),
+            last_used_ip: row.get("last_used_ip"),
+            last_used_user_agent: row.get("last_used_user_agent"),
+            expires_at: row.get("expires_at"),
+            allowed_cidrs: row.get("allowed_cidrs"),
+        }
+    }
+
+    fn table_name(_: Option<ApiToken>) -> &'static str { "api_tokens" }
+}
+
+/// The longest tokens can be valid for when they're given an expiry, in days.
+pub const MAX_EXPIRY_DAYS: u32 = 365;
+
+/// The expiry of a token which should be valid for `days` days from now.
+pub fn expiry(days: Option<u32>) -> CargoResult<Option<Timespec>> {
+    match days {
+        Some(0) => Err(human("tokens must be valid for at least a day")),
+        Some(days) if days > MAX_EXPIRY_DAYS => {
+            Err(human(&format_args!("tokens can be valid for at most {} days, leave \
+                                     `expires_in_days` out for a token which doesn't \
+                                     expire", MAX_EXPIRY_DAYS)))
+        }
+        Some(days) => {
+            let expires_at = ::time::now_utc().to_timespec() +
+                ::time::Duration::days(days as i64);
+            Ok(Some(expires_at))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Validates the IP ranges a new token is restricted to, and normalizes them.
+pub fn allowed_cidrs(cidrs: Option<Vec<String>>) -> CargoResult<Vec<String>> {
+    let mut allowed = Vec::new();
+    for cidr in cidrs.unwrap_or_default() {
+        let cidr = Cidr::parse(&cidr)?.to_string();
+        if !allowed.contains(&cidr) {
+            allowed.push(cidr);
+        }
+    }
+    Ok(allowed)
+}
+
+/// Deletes tokens which expired more than `days` days ago.
+///
+/// Tokens which were used to publish a version are kept, so that the version
+/// can still say which token was used.
+pub fn purge_expired(conn: &GenericConnection, days: u32) -> CargoResult<u64> {
+    let n = conn.execute("\
+        DELETE FROM api_tokens
+         WHERE expires_at < now() - $1::int4 * INTERVAL '1 day'
+           AND NOT EXISTS (SELECT 1 FROM versions
+                            WHERE versions.published_with_token_id = api_tokens.id)",
+        &[&(days as i32)])?;
+    Ok(n)
+}
+
+/// A use of an API token to authenticate a request.
+#[derive(Clone, Debug)]
+pub struct TokenUse {
+    pub at: Timespec,
+    pub ip: String,
+    /// The user agent family, as counted in the download statistics
+    pub user_agent: String,
+    /// What kind of request it was, see `endpoint_class`
+    pub endpoint: &'static str,
+}
+
+impl TokenUse {
+    pub fn new(req: &Request) -> TokenUse {
+        TokenUse {
+            at: ::time::now_utc().to_timespec(),
+            ip: req.client_ip().to_string(),
+            user_agent: user_agent_family(req.headers().find("User-Agent")
+                                             .and_then(|v| v.first().cloned())),
+            endpoint: endpoint_class(req.method(), req.path()),
+        }
+    }
+}
+
+/// The class of endpoint a request is counted under in a token's usage:
+/// `search`, `download`, `publish` or `other`.
+pub fn endpoint_class(method: Method, path: &str) -> &'static str {
+    let path = if path.starts_with("/api/v1/") { &path[7..] } else { path };
+    match method {
+        Method::Put if path == "/crates/new" => "publish",
+        Method::Get if path == "/crates" => "search",
+        Method::Get if path.starts_with("/crates/") && path.ends_with("/download") => {
+            "download"
+        }
+        _ => "other",
+    }
+}
+
+/// The latest use of each API token which hasn't been written to
+/// `api_tokens` yet, and how many requests each token made to each class of
+/// endpoint since the last write.
+///
+/// Like `PendingDownloads`, this spares every authenticated request a write:
+/// uses are collected in memory and written in one go once the flush
+/// interval has passed or enough tokens are dirty. The request counts are
+/// added to `api_token_usage` under the day they're written on. The server
+/// also writes them periodically and when it shuts down, see `flush_all`.
+/// Uses which are still pending when the process dies are lost.
+pub struct PendingTokenUses {
+    pending: Mutex<PendingUses>,
+    flush_interval: Duration,
+    max_batch_size: usize,
+}
+
+struct PendingUses {
+    uses: HashMap<i32, TokenUse>,
+    requests: HashMap<(i32, &'static str), i32>,
+    last_flush: Instant,
+}
+
+impl PendingTokenUses {
+    pub fn new(flush_interval: Duration, max_batch_size: usize) -> PendingTokenUses {
+        PendingTokenUses {
+            pending: Mutex::new(PendingUses {
+                uses: HashMap::new(),
+                requests: HashMap::new(),
+                last_flush: Instant::now(),
+            }),
+            flush_interval: flush_interval,
+            max_batch_size: max_batch_size,
+        }
+    }
+
+    /// Records that `token_id` was used, replacing any older pending use.
+    pub fn record(&self, token_id: i32, token_use: TokenUse) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.requests.entry((token_id, token_use.endpoint)).or_insert(0) += 1;
+        pending.uses.insert(token_id, token_use);
+    }
+
+    /// The number of tokens with a use waiting to be written.
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().uses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the flush interval has elapsed or the batch has grown past its
+    /// maximum size.
+    pub fn is_due(&self) -> bool {
+        let pending = self.pending.lock().unwrap();
+        pending.last_flush.elapsed() >= self.flush_interval ||
+            pending.uses.len() >= self.max_batch_size
+    }
+
+    /// Writes the pending uses if they are due.
+    pub fn flush_if_needed(&self, conn: &GenericConnection) -> CargoResult<()> {
+        if self.is_due() {
+            self.flush(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every pending use to `api_tokens` in one statement, then adds
+    /// the request counts to `api_token_usage` in another.
+    ///
+    /// If a write fails the batch is put back, unless the tokens have been
+    /// used again in the meantime, so that it will be retried on the next
+    /// flush. The counts are added to the ones collected since.
+    pub fn flush(&self, conn: &GenericConnection) -> CargoResult<()> {
+        let (batch, requests) = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.last_flush = Instant::now();
+            (mem::replace(&mut pending.uses, HashMap::new()),
+             mem::replace(&mut pending.requests, HashMap::new()))
+        };
+        if batch.is_empty() {
+            return Ok(())
+        }
+
+        let mut ids = Vec::with_capacity(batch.len());
+        let mut times = Vec::with_capacity(batch.len());
+        let mut ips = Vec::with_capacity(batch.len());
+        let mut user_agents = Vec::with_capacity(batch.len());
+        for (&id, token_use) in &batch {
+            ids.push(id);
+            times.push(token_use.at);
+            ips.push(token_use.ip.clone());
+            user_agents.push(token_use.user_agent.clone());
+        }
+
+        let mut token_ids = Vec::with_capacity(requests.len());
+        let mut endpoints = Vec::with_capacity(requests.len());
+        let mut counts = Vec::with_capacity(requests.len());
+        for (&(id, endpoint), &count) in &requests {
+            token_ids.push(id);
+            endpoints.push(endpoint);
+            counts.push(count);
+        }
+
+        let res = conn.execute("\
+            UPDATE api_tokens
+               SET last_used_at = u.at,
+                   last_used_ip = u.ip,
+                   last_used_user_agent = u.user_agent
+              FROM UNNEST($1::int4[], $2::timestamp[], $3::varchar[], $4::varchar[])
+                AS u (id, at, ip, user_agent)
+             WHERE api_tokens.id = u.id",
+            &[&ids, &times, &ips, &user_agents]).and_then(|_| conn.execute("\
+            INSERT INTO api_token_usage (token_id, endpoint, requests)
+            SELECT u.token_id, u.endpoint, u.requests
+              FROM UNNEST($1::int4[], $2::varchar[], $3::int4[])
+                AS u (token_id, endpoint, requests)
+             WHERE EXISTS (SELECT 1 FROM api_tokens WHERE api_tokens.id = u.token_id)
+            ON CONFLICT (token_id, date, endpoint) DO UPDATE
+               SET requests = api_token_usage.requests + EXCLUDED.requests",
+            &[&token_ids, &endpoints, &counts]));
+
+        if let Err(e) = res {
+            let mut pending = self.pending.lock().unwrap();
+            for (id, token_use) in batch {
+                pending.uses.entry(id).or_insert(token_use);
+            }
+            for (key, count) in requests {
+                *pending.requests.entry(key).or_insert(0) += count;
+            }
+            return Err(e.into())
+        }
+        Ok(())
+    }
+}
+
+/// Writes the pending token uses once a request is done.
+///
+/// Like `download::FlushMiddleware`, the uses are written on a connection
+/// and in a transaction of their own rather than in the transaction of the
+/// request they became due in, which would lose the uses of every request
+/// since the last flush if it rolled back. Requests made by tests share the
+/// transaction of the test instead. This has to be added before
+/// `db::TransactionMiddleware` as well.
+pub struct FlushMiddleware;
+
+impl Middleware for FlushMiddleware {
+    fn after(&self, req: &mut Request, res: Result<Response, Box<Error+Send>>)
+             -> Result<Response, Box<Error+Send>> {
+        let app = req.app().clone();
+        if !app.pending_token_uses.is_due() {
+            return res
+        }
+
+        let flushed = if req.extensions().find::<db::Transaction>().is_some() {
+            req.tx().and_then(|tx| app.pending_token_uses.flush(tx))
+        } else {
+            flush_all(&app)
+        };
+        if let Err(e) = flushed {
+            println!("failed to write token uses: {}", e);
+        }
+        res
+    }
+}
+
+/// Writes the pending uses on a connection of their own, whether they're
+/// due or not, see `download::flush_all`.
+pub fn flush_all(app: &App) -> CargoResult<()> {
+    let conn = app.database.get()?;
+    let tx = conn.transaction()?;
+    app.pending_token_uses.flush(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Handles the `GET /me/tokens` route.
+pub fn list(req: &mut Request) -> CargoResult<Response> {
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let tokens = ApiToken::belonging_to(user)
+        .filter(api_tokens::revoked.eq(false))
+        .filter(api_tokens::organization_id.is_null())
+        .order(api_tokens::created_at.desc())
+        .load::<ApiToken>(&*conn)?
+        .into_iter()
+        .map(ApiToken::encodable)
+        .collect();
+
+    #[derive(RustcEncodable)]
+    struct R { api_tokens: Vec<EncodableApiToken> }
+    Ok(req.json(&R { api_tokens: tokens }))
+}
+
+/// Handles the `PUT /me/tokens` route.
+///
+/// Tokens are valid until they're revoked, unless they're given a number of
+/// days after which they expire, up to `MAX_EXPIRY_DAYS`. They can also be
+/// restricted to a list of IP ranges, e.g. the addresses CI runs from.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// {
+///     "api_token": {
+///         "name": "travis",
+///         "expires_in_days": 90,
+///         "allowed_cidrs": ["192.0.2.0/24", "2001:db8::/32"]
+///     }
+/// }
+/// ```
+pub fn new(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+
+    #[derive(RustcDecodable)]
+    struct NewToken {
+        name: String,
+        expires_in_days: Option<u32>,
+        allowed_cidrs: Option<Vec<String>>,
+    }
+    #[derive(RustcDecodable)]
+    struct Request { api_token: NewToken }
+
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let name = request.api_token.name.trim();
+    if name.is_empty() {
+        return Err(human("the new token must be given a name"))
+    }
+    let expires_at = expiry(request.api_token.expires_in_days)?;
+    let allowed_cidrs = allowed_cidrs(request.api_token.allowed_cidrs)?;
+
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let token = NewApiToken {
+        user_id: user.id,
+        name: name,
+        organization_id: None,
+        expires_at: expires_at,
+        allowed_cidrs: allowed_cidrs,
+    }.save(&conn)?;
+    let secret = token.token.clone();
+    let mut token = token.encodable();
+    token.token = Some(secret);
+
+    #[derive(RustcEncodable)]
+    struct R { api_token: EncodableApiToken }
+    Ok(req.json(&R { api_token: token }))
+}
+
+/// Handles the `DELETE /me/tokens/:id` route.
+///
+/// Tokens are only ever marked as revoked, never deleted, so that versions
+/// published with them can still say which token was used.
+pub fn revoke(req: &mut Request) -> CargoResult<Response> {
+    let id = req.params()["id"].parse::<i32>().map_err(|_| {
+        human("invalid token id")
+    })?;
+    let user = req.user()?;
+    let conn = req.db_conn()?;
+    let token = ApiToken::belonging_to(user)
+        .filter(api_tokens::id.eq(id))
+        .filter(api_tokens::organization_id.is_null())
+        .first::<ApiToken>(&*conn)?;
+    diesel::update(&token).set(api_tokens::revoked.eq(true))
+        .execute(&*conn)?;
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool }
+    Ok(req.json(&R { ok: true }))
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableTokenUsage {
+    pub date: String,
+    /// `search`, `download`, `publish` or `other`
+    pub endpoint: String,
+    pub requests: i32,
+}
+
+/// Handles the `GET /me/tokens/:id/usage` route.
+///
+/// Returns how many requests the token made to each class of endpoint per
+/// day over the last 90 days, oldest first. Today's counts lag behind a
+/// little since they're written in batches.
+pub fn usage(req: &mut Request) -> CargoResult<Response> {
+    let id = req.params()["id"].parse::<i32>().map_err(|_| {
+        human("invalid token id")
+    })?;
+    let user = req.user()?;
+    let tx = req.tx()?;
+    let rows = tx.query("SELECT 1 FROM api_tokens \
+                         WHERE id = $1 AND user_id = $2 AND organization_id IS NULL",
+                        &[&id, &user.id])?;
+    if rows.is_empty() {
+        return Err(Box::new(NotFound))
+    }
+
+    let usage = tx.query("SELECT date, endpoint, requests FROM api_token_usage
+                           WHERE token_id = $1 AND date > CURRENT_DATE - 90
+                           ORDER BY date, endpoint",
+                         &[&id])?.iter().map(|row| {
+        let date: NaiveDate = row.get("date");
+        EncodableTokenUsage {
+            date: date.to_string(),
+            endpoint: row.get("endpoint"),
+            requests: row.get("requests"),
+        }
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R { usage: Vec<EncodableTokenUsage> }
+    Ok(req.json(&R { usage: usage }))
+}
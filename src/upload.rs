@@ -25,6 +25,63 @@ pub struct NewCrate {
     pub license_file: Option<String>,
     pub repository: Option<String>,
     pub badges: Option<HashMap<String, HashMap<String, String>>>,
+    pub links: Option<String>,
+    pub rust_version: Option<String>,
+    pub provenance: Option<Provenance>,
+}
+
+/// Where the crate file was built, for publishes made from CI.
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug)]
+pub struct Provenance {
+    /// e.g. `github-actions`, see the `provenance` module
+    pub ci_provider: String,
+    pub build_url: String,
+    pub commit_sha: String,
+}
+
+/// Something about a publish which didn't stop it but which the publisher
+/// should know about, in the `warnings.entries` of the publish response.
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq)]
+pub struct PublishWarning {
+    /// What kind of problem it is, e.g. `invalid_category`, for tools to act
+    /// on
+    pub code: String,
+    pub message: String,
+    /// The field of the crate's metadata it's about, e.g. `categories`
+    pub field: Option<String>,
+}
+
+impl PublishWarning {
+    pub fn new(code: &str, field: Option<&str>, message: String) -> PublishWarning {
+        PublishWarning {
+            code: code.to_string(),
+            message: message,
+            field: field.map(|f| f.to_string()),
+        }
+    }
+}
+
+/// What a user's very first publish contained, in the `first_publish` of the
+/// publish response, so that newcomers can see right away whether the crate
+/// came out the way they meant it to.
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq)]
+pub struct FirstPublishReport {
+    /// How many files the crate file contains
+    pub files: u64,
+    /// The size of the crate file, in bytes
+    pub size: u64,
+    /// The size of its files once unpacked, in bytes
+    pub unpacked_size: u64,
+    /// Whether a README was found in the crate file and rendered for the
+    /// crate's page
+    pub readme_rendered: bool,
+    /// `non-standard` when the license is only given as a file
+    pub license: Option<String>,
+    /// Whether the license is an SPDX expression, which tools understand
+    pub license_recognized: bool,
+    pub keywords: Vec<String>,
+    /// The categories which were accepted, the others being warned about
+    pub categories: Vec<String>,
 }
 
 #[derive(PartialEq, Eq, Hash)]
@@ -85,7 +142,7 @@ impl Decodable for Category {
 impl Decodable for Feature {
     fn decode<D: Decoder>(d: &mut D) -> Result<Feature, D::Error> {
         let s = d.read_str()?;
-        if !Crate::valid_feature_name(&s) {
+        if !Crate::valid_feature_name(&s) && !Crate::valid_features2_value(&s) {
             return Err(d.error(&format!("invalid feature name specified: {}", s)))
         }
         Ok(Feature(s))
@@ -1,14 +1,16 @@
 use conduit::Request;
+use curl::easy::Easy;
+use git;
 use krate::Crate;
 use util::{CargoResult, internal, ChainError};
-use util::{LimitErrorReader, HashingReader, read_le_u32};
+use util::HashingReader;
 use s3;
 use semver;
 use app::{App, RequestApp};
 use std::sync::Arc;
 use std::fs::{self, File};
 use std::env;
-use std::io;
+use std::io::{self, Read, Write};
 
 #[derive(Clone)]
 pub enum Uploader {
@@ -25,6 +27,22 @@ pub enum Uploader {
 }
 
 impl Uploader {
+    /// The uploader background jobs read crate files through: the S3 bucket
+    /// named by `S3_BUCKET`, or `dist/local_uploads` if it isn't set.
+    pub fn from_env() -> Uploader {
+        match env::var("S3_BUCKET") {
+            Ok(bucket) => Uploader::S3 {
+                bucket: s3::Bucket::new(bucket,
+                                        env::var("S3_REGION").ok(),
+                                        env::var("S3_ACCESS_KEY").unwrap_or(String::new()),
+                                        env::var("S3_SECRET_KEY").unwrap_or(String::new()),
+                                        "https"),
+                proxy: None,
+            },
+            Err(..) => Uploader::Local,
+        }
+    }
+
     pub fn proxy(&self) -> Option<&str> {
         match *self {
             Uploader::S3 { ref proxy, .. } => proxy.as_ref().map(String::as_str),
@@ -48,25 +66,37 @@ impl Uploader {
         }
     }
 
+    /// Where clients are sent to download a crate file, which is
+    /// `Config::download_url_template` if it's set and otherwise wherever the
+    /// crate file was uploaded to.
+    pub fn download_url(&self, template: Option<&str>, crate_name: &str, version: &str)
+                        -> Option<String> {
+        match template {
+            Some(template) => Some(fill_download_url(template, crate_name, version)),
+            None => self.crate_location(crate_name, version),
+        }
+    }
+
     fn crate_path(name: &str, version: &str) -> String {
         // No slash in front so we can use join
         format!("crates/{}/{}-{}.crate", name, name, version)
     }
 
-    pub fn upload(&self, req: &mut Request, krate: &Crate, max: u64, vers: &semver::Version) -> CargoResult<(Vec<u8>, Bomb)> {
+    /// Stores the crate file, returning its checksum and a way to delete it
+    /// again if the publish fails later on.
+    pub fn upload(&self, req: &mut Request, krate: &Crate, vers: &semver::Version,
+                  tarball: &[u8]) -> CargoResult<(Vec<u8>, Bomb)> {
         match *self {
             Uploader::S3 { ref bucket, .. } => {
                 let mut handle = req.app().handle();
                 let path = format!("/{}", Uploader::crate_path(&krate.name, &vers.to_string()));
                 let (response, cksum) = {
-                    let length = read_le_u32(req.body())?;
-                    let body = LimitErrorReader::new(req.body(), max);
-                    let mut body = HashingReader::new(body);
+                    let mut body = HashingReader::new(tarball);
                     let mut response = Vec::new();
                     {
                         let mut s3req = bucket.put(&mut handle, &path, &mut body,
                                                        "application/x-tar",
-                                                       length as u64);
+                                                       tarball.len() as u64);
                         s3req.write_function(|data| {
                             response.extend(data);
                             Ok(data.len())
@@ -101,10 +131,7 @@ impl Uploader {
                 let mut crate_file = File::create(&crate_filename)?;
 
                 let cksum = {
-                    read_le_u32(req.body())?;
-                    let body = LimitErrorReader::new(req.body(), max);
-                    let mut body = HashingReader::new(body);
-
+                    let mut body = HashingReader::new(tarball);
                     io::copy(&mut body, &mut crate_file)?;
                     body.finalize()
                 };
@@ -118,6 +145,87 @@ impl Uploader {
         }
     }
 
+    /// Reads a previously uploaded crate file back.
+    pub fn fetch(&self, handle: &mut Easy, crate_name: &str, version: &str)
+                 -> CargoResult<Vec<u8>> {
+        self.fetch_file(handle, &Uploader::crate_path(crate_name, version))
+    }
+
+    /// Stores a file other than a crate file at `path`, e.g. a cached avatar.
+    pub fn store_file(&self, handle: &mut Easy, path: &str, content_type: &str, body: &[u8])
+                      -> CargoResult<()> {
+        match *self {
+            Uploader::S3 { ref bucket, .. } => {
+                let path = format!("/{}", path);
+                let mut response = Vec::new();
+                {
+                    let mut reader = body;
+                    let mut s3req = bucket.put(handle, &path, &mut reader, content_type,
+                                               body.len() as u64);
+                    s3req.write_function(|data| {
+                        response.extend(data);
+                        Ok(data.len())
+                    }).unwrap();
+                    s3req.perform().chain_error(|| {
+                        internal(&format_args!("failed to upload to S3: `{}`", path))
+                    })?;
+                }
+                if handle.response_code()? != 200 {
+                    let response = String::from_utf8_lossy(&response);
+                    return Err(internal(&format_args!("failed to get a 200 response from S3: {}",
+                                                      response)))
+                }
+                Ok(())
+            }
+            Uploader::Local => {
+                let filename = env::current_dir().unwrap()
+                                                 .join("dist")
+                                                 .join("local_uploads")
+                                                 .join(path);
+                fs::create_dir_all(filename.parent().unwrap())?;
+                File::create(&filename)?.write_all(body)?;
+                Ok(())
+            }
+            Uploader::NoOp => Ok(()),
+        }
+    }
+
+    /// Reads a file stored with `upload` or `store_file` back.
+    pub fn fetch_file(&self, handle: &mut Easy, path: &str) -> CargoResult<Vec<u8>> {
+        match *self {
+            Uploader::S3 { ref bucket, .. } => {
+                let url = format!("https://{}/{}", bucket.host(), path);
+                let mut body = Vec::new();
+                handle.url(&url)?;
+                {
+                    let mut transfer = handle.transfer();
+                    transfer.write_function(|data| {
+                        body.extend(data);
+                        Ok(data.len())
+                    })?;
+                    transfer.perform().chain_error(|| {
+                        internal(&format_args!("failed to download `{}`", url))
+                    })?;
+                }
+                if handle.response_code()? != 200 {
+                    return Err(internal(&format_args!("failed to get a 200 response \
+                                                       when downloading `{}`", url)))
+                }
+                Ok(body)
+            },
+            Uploader::Local => {
+                let filename = env::current_dir().unwrap()
+                                                 .join("dist")
+                                                 .join("local_uploads")
+                                                 .join(path);
+                let mut body = Vec::new();
+                File::open(&filename)?.read_to_end(&mut body)?;
+                Ok(body)
+            },
+            Uploader::NoOp => Err(internal("files aren't stored by this uploader")),
+        }
+    }
+
     pub fn delete(&self, app: Arc<App>, path: &str) -> CargoResult<()> {
         match *self {
             Uploader::S3 { ref bucket, .. } => {
@@ -148,3 +256,28 @@ impl Drop for Bomb {
         }
     }
 }
+
+/// Fills in the `{crate}`, `{version}` and `{prefix}` placeholders of a
+/// download URL template, `{prefix}` being the crate's directory in the
+/// index, see `git::index_prefix`.
+pub fn fill_download_url(template: &str, crate_name: &str, version: &str) -> String {
+    template.replace("{crate}", crate_name)
+        .replace("{version}", version)
+        .replace("{prefix}", &git::index_prefix(crate_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fill_download_url;
+
+    #[test]
+    fn download_url_templates() {
+        let template = "https://cdn.example.com/{prefix}/{crate}/{crate}-{version}.crate";
+        assert_eq!(fill_download_url(template, "serde", "1.0.0"),
+                   "https://cdn.example.com/se/rd/serde/serde-1.0.0.crate");
+        assert_eq!(fill_download_url(template, "syn", "0.11.0"),
+                   "https://cdn.example.com/3/s/syn/syn-0.11.0.crate");
+        assert_eq!(fill_download_url("/dl/{crate}/{version}", "a", "1.0.0"),
+                   "/dl/a/1.0.0");
+    }
+}
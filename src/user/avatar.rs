@@ -0,0 +1,190 @@
+//! Avatars served from the registry's own domain.
+//!
+//! Linking to GitHub's avatars directly tells GitHub which pages of the
+//! registry people look at, and the links break when a user changes their
+//! avatar. `GET /users/:user_id/avatar?size=` fetches the avatar instead,
+//! at the size asked for, and keeps it with the uploader so that GitHub is
+//! only asked once per avatar and size. Cached avatars are keyed by the URL
+//! GitHub gave for them, so a new avatar is fetched as soon as the user's
+//! `gh_avatar` changes.
+
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+use conduit::{Request, Response};
+use conduit_router::RequestParams;
+use curl::easy::Easy;
+use diesel::prelude::*;
+use openssl::hash::{Hasher, MessageDigest};
+use rustc_serialize::hex::ToHex;
+use url::Url;
+
+use app::RequestApp;
+use db::RequestTransaction;
+use schema::users;
+use util::errors::NotFound;
+use util::{RequestUtils, CargoResult, ChainError, internal, human};
+
+pub const DEFAULT_SIZE: u32 = 64;
+
+/// The largest avatars GitHub serves.
+pub const MAX_SIZE: u32 = 460;
+
+/// Avatars larger than this aren't served.
+const MAX_AVATAR_LEN: usize = 1024 * 1024;
+
+/// How long browsers and proxies can keep an avatar, in seconds.
+const CACHE_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Where `EncodableUser.avatar` points for users with an avatar.
+pub fn path(login: &str) -> String {
+    format!("/api/v1/users/{}/avatar", login)
+}
+
+/// The URL of the avatar at `size`, as long as it's one of GitHub's.
+pub fn source_url(avatar: &str, size: u32) -> CargoResult<Url> {
+    let mut url = Url::parse(avatar).map_err(|_| {
+        internal(&format_args!("invalid avatar url `{}`", avatar))
+    })?;
+    let from_github = url.scheme() == "https" &&
+        url.host_str().map(|h| h.ends_with(".githubusercontent.com")) == Some(true);
+    if !from_github {
+        return Err(internal(&format_args!("avatar `{}` isn't hosted by GitHub", avatar)))
+    }
+    let pairs = url.query_pairs()
+        .filter(|&(ref k, _)| k != "s")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect::<Vec<_>>();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair("s", &size.to_string());
+    Ok(url)
+}
+
+/// Where the avatar at `size` is cached with the uploader.
+pub fn cache_path(avatar: &str, size: u32) -> String {
+    let mut hasher = Hasher::new(MessageDigest::sha256()).unwrap();
+    hasher.update(avatar.as_bytes()).unwrap();
+    format!("avatars/{}/{}", hasher.finish().unwrap().to_hex(), size)
+}
+
+/// The content type of an avatar, if it's an image browsers can show.
+pub fn content_type(body: &[u8]) -> Option<&'static str> {
+    if body.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if body.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else {
+        None
+    }
+}
+
+/// Handles the `GET /users/:user_id/avatar` route.
+pub fn show(req: &mut Request) -> CargoResult<Response> {
+    let size = match req.query().get("size") {
+        Some(size) => {
+            match size.parse::<u32>() {
+                Ok(size) if size >= 1 && size <= MAX_SIZE => size,
+                _ => return Err(human(&format_args!("the avatar size must be a number \
+                                                     between 1 and {}", MAX_SIZE))),
+            }
+        }
+        None => DEFAULT_SIZE,
+    };
+    let avatar = {
+        let conn = req.db_conn()?;
+        users::table.filter(users::gh_login.eq(&req.params()["user_id"]))
+            .select(users::gh_avatar)
+            .first::<Option<String>>(&*conn)?
+            .chain_error(|| NotFound)?
+    };
+
+    let uploader = &req.app().config.uploader;
+    let cache_path = cache_path(&avatar, size);
+    let body = match uploader.fetch_file(&mut req.app().handle(), &cache_path) {
+        Ok(body) => body,
+        Err(..) => {
+            let body = download(&source_url(&avatar, size)?)?;
+            let content_type = content_type(&body).chain_error(|| {
+                internal(&format_args!("avatar `{}` isn't an image", avatar))
+            })?;
+            let stored = uploader.store_file(&mut req.app().handle(), &cache_path,
+                                             content_type, &body);
+            if let Err(e) = stored {
+                println!("unable to cache avatar {}: {}", cache_path, e);
+            }
+            body
+        }
+    };
+    let content_type = content_type(&body).chain_error(|| {
+        internal(&format_args!("cached avatar `{}` isn't an image", cache_path))
+    })?;
+
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), vec![content_type.to_string()]);
+    headers.insert("Content-Length".to_string(), vec![body.len().to_string()]);
+    headers.insert("Cache-Control".to_string(),
+                   vec![format!("public, max-age={}", CACHE_MAX_AGE)]);
+    Ok(Response {
+        status: (200, "OK"),
+        headers: headers,
+        body: Box::new(io::Cursor::new(body)),
+    })
+}
+
+fn download(url: &Url) -> CargoResult<Vec<u8>> {
+    let mut handle = Easy::new();
+    let mut body = Vec::new();
+    let mut too_large = false;
+    // Redirects aren't followed, as they could lead away from GitHub
+    handle.url(url.as_str())?;
+    handle.timeout(Duration::from_secs(10))?;
+    let result = {
+        let mut transfer = handle.transfer();
+        transfer.write_function(|data| {
+            if body.len() + data.len() > MAX_AVATAR_LEN {
+                too_large = true;
+                return Ok(0)
+            }
+            body.extend_from_slice(data);
+            Ok(data.len())
+        })?;
+        transfer.perform()
+    };
+    if too_large {
+        return Err(internal(&format_args!("avatar `{}` is larger than {} bytes",
+                                          url, MAX_AVATAR_LEN)))
+    }
+    result.chain_error(|| internal(&format_args!("failed to download `{}`", url)))?;
+    if handle.response_code()? != 200 {
+        return Err(internal(&format_args!("failed to get a 200 response when \
+                                           downloading `{}`", url)))
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type, source_url};
+
+    #[test]
+    fn avatars_are_fetched_from_github_at_the_size_asked_for() {
+        let url = source_url("https://avatars.githubusercontent.com/u/1234?v=3", 64).unwrap();
+        assert_eq!(url.as_str(), "https://avatars.githubusercontent.com/u/1234?v=3&s=64");
+        let url = source_url("https://avatars2.githubusercontent.com/u/1?s=460", 32).unwrap();
+        assert_eq!(url.as_str(), "https://avatars2.githubusercontent.com/u/1?s=32");
+        assert!(source_url("https://example.com/u/1234", 64).is_err());
+        assert!(source_url("http://avatars.githubusercontent.com/u/1234", 64).is_err());
+    }
+
+    #[test]
+    fn only_images_are_served() {
+        assert_eq!(content_type(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(content_type(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(content_type(b"<html>"), None);
+    }
+}
@@ -1,3 +1,4 @@
+use std::ascii::AsciiExt;
 use std::error::Error;
 
 use conduit_middleware;
@@ -5,9 +6,12 @@ use conduit::Request;
 use conduit_cookie::RequestSession;
 
 use Model;
+use app::RequestApp;
 use db::RequestTransaction;
 use super::User;
-use util::errors::{CargoResult, Unauthorized, ChainError, std_error};
+use token::{ApiToken, RejectedToken, TokenUse};
+use util::RequestUtils;
+use util::errors::{CargoResult, Unauthorized, std_error, human_with_code};
 
 pub struct Middleware;
 
@@ -16,14 +20,14 @@ impl conduit_middleware::Middleware for Middleware {
         // Check if the request has a session cookie with a `user_id` property inside
         let id = { req.session().get("user_id").and_then(|s| s.parse().ok()) };
 
-        let user = match id {
+        let (user, token) = match id {
 
             // `user_id` was found on the session
             Some(id) => {
 
                 // Look for a user in the database with the given `user_id`
                 match User::find(req.tx().map_err(std_error)?, id) {
-                    Ok(user) => user,
+                    Ok(user) => (user, None),
                     Err(..) => return Ok(()),
                 }
             }
@@ -32,33 +36,155 @@ impl conduit_middleware::Middleware for Middleware {
             None => {
 
                 // Look for an `Authorization` header on the request
-                match req.headers().find("Authorization") {
-                    Some(headers) => {
-
-                        // Look for a user in the database with a matching API token
-                        let tx = req.tx().map_err(std_error)?;
-                        match User::find_by_api_token(tx, headers[0]) {
-                            Ok(user) => user,
-                            Err(..) => return Ok(())
-                        }
+                let header = match req.headers().find("Authorization") {
+                    Some(headers) => headers[0].to_string(),
+                    None => return Ok(()),
+                };
+                let secret = match token_from_header(&header) {
+                    Ok(secret) => secret,
+                    Err(rejected) => {
+                        req.mut_extensions().insert(rejected);
+                        return Ok(())
+                    }
+                };
+
+                // Look for a named API token first, falling back to the token
+                // stored on the user itself
+                let found = {
+                    let tx = req.tx().map_err(std_error)?;
+                    let stale_days = req.app().config.stale_token_days;
+                    match ApiToken::find_active(tx, secret, stale_days) {
+                        Ok((token, user)) => Some((user, Some(token))),
+                        Err(..) => User::find_by_api_token(tx, secret).ok()
+                            .map(|user| (user, None)),
+                    }
+                };
+                match found {
+                    Some(found) => found,
+                    None => {
+                        req.mut_extensions().insert(RejectedToken {
+                            code: "invalid_token",
+                            reason: "this API token is invalid, it may have been \
+                                     revoked".to_string(),
+                        });
+                        return Ok(())
                     }
-                    None => return Ok(())
                 }
             }
         };
 
-        // Attach the `User` model from the database to the request
+        if let Some(ref token) = token {
+            // Tokens which can't be used don't log anyone in, but are
+            // remembered so that the client can be told why
+            let rejected = if let (true, Some(expired_at)) = (token.is_expired(),
+                                                               token.expires_at) {
+                Some(RejectedToken {
+                    code: "expired_token",
+                    reason: format!("this API token expired on {}, create a new one",
+                                    ::encode_time(expired_at)),
+                })
+            } else if !token.allows_ip(&req.client_ip()) {
+                Some(RejectedToken {
+                    code: "ip_not_allowed",
+                    reason: format!("this API token can't be used from {}",
+                                    req.client_ip()),
+                })
+            } else {
+                None
+            };
+            if let Some(rejected) = rejected {
+                req.mut_extensions().insert(rejected);
+                return Ok(())
+            }
+
+            // Written by `token::FlushMiddleware` once the request is done
+            req.app().pending_token_uses.record(token.id, TokenUse::new(req));
+        }
+
+        // Attach the `User` model from the database to the request, along
+        // with the token used to authenticate if there was one
         req.mut_extensions().insert(user);
+        if let Some(token) = token {
+            req.mut_extensions().insert(token);
+        }
         Ok(())
     }
 }
 
+/// Reads the API token out of an `Authorization` header, which cargo sends as
+/// the bare token and other clients as `Bearer <token>`.
+fn token_from_header(header: &str) -> Result<&str, RejectedToken> {
+    let header = header.trim();
+    let token = match header.find(char::is_whitespace) {
+        Some(i) if header[..i].eq_ignore_ascii_case("bearer") => header[i..].trim_left(),
+        Some(..) => "",
+        None => header,
+    };
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        return Err(RejectedToken {
+            code: "malformed_token",
+            reason: "the Authorization header must be an API token or \
+                     `Bearer <token>`".to_string(),
+        })
+    }
+    Ok(token)
+}
+
 pub trait RequestUser {
+    /// The user making the request. Requests authenticated with an
+    /// organization's API token are refused, as the token doesn't act on its
+    /// creator's behalf.
     fn user(&self) -> CargoResult<&User>;
+
+    /// The user publishing or yanking crates on behalf of the request, which
+    /// for an organization's API token is whoever created it. What the token
+    /// can do is limited by `owner::request_rights`, so only the publish and
+    /// yank paths should use this.
+    fn publisher(&self) -> CargoResult<&User>;
+
+    /// The named API token the request was authenticated with, if any.
+    fn api_token(&self) -> Option<&ApiToken>;
 }
 
 impl<'a> RequestUser for Request + 'a {
     fn user(&self) -> CargoResult<&User> {
-        self.extensions().find::<User>().chain_error(|| Unauthorized)
+        if self.api_token().and_then(|token| token.organization_id).is_some() {
+            return Err(human_with_code("organization_token",
+                                       "organization API tokens can only be used to publish \
+                                        and yank the organization's crates"))
+        }
+        self.publisher()
+    }
+
+    fn publisher(&self) -> CargoResult<&User> {
+        if let Some(user) = self.extensions().find::<User>() {
+            return Ok(user)
+        }
+        match self.extensions().find::<RejectedToken>() {
+            Some(token) => Err(human_with_code(token.code, &token.reason)),
+            None => Err(Box::new(Unauthorized)),
+        }
+    }
+
+    fn api_token(&self) -> Option<&ApiToken> {
+        self.extensions().find::<ApiToken>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::token_from_header;
+
+    #[test]
+    fn authorization_headers() {
+        assert_eq!(token_from_header("abc123").ok(), Some("abc123"));
+        assert_eq!(token_from_header("  abc123\t").ok(), Some("abc123"));
+        assert_eq!(token_from_header("Bearer abc123").ok(), Some("abc123"));
+        assert_eq!(token_from_header("bearer   abc123 ").ok(), Some("abc123"));
+
+        for header in &["", "   ", "Bearer", "Bearer  ", "Basic abc123", "Bearer a b"] {
+            let rejected = token_from_header(header).unwrap_err();
+            assert!(rejected.code == "malformed_token", "{:?}", header);
+        }
     }
 }
@@ -2,23 +2,28 @@ use conduit::{Request, Response};
 use conduit_cookie::{RequestSession};
 use conduit_router::RequestParams;
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
 use pg::GenericConnection;
 use pg::rows::Row;
 use rand::{thread_rng, Rng};
+use rustc_serialize::json;
+use semver;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
 
 use app::RequestApp;
-use db::RequestTransaction;
+use db::{InstrumentedConnection, RequestTransaction};
 use krate::Follow;
+use owner::{OwnerKind, OwnerRole, Team};
 use schema::*;
 use util::errors::NotFound;
-use util::{RequestUtils, CargoResult, internal, ChainError, human};
+use util::{RequestUtils, CargoResult, internal, ChainError, human, PaginationMeta};
 use version::EncodableVersion;
-use {http, Model, Version};
+use {http, Crate, Model, Version};
 
 pub use self::middleware::{Middleware, RequestUser};
 
+pub mod avatar;
 pub mod middleware;
 
 /// The model representing a row in the `users` database table.
@@ -63,7 +68,7 @@ impl<'a> NewUser<'a> {
     }
 
     /// Inserts the user into the database, or updates an existing one.
-    pub fn create_or_update(&self, conn: &PgConnection) -> CargoResult<User> {
+    pub fn create_or_update(&self, conn: &InstrumentedConnection) -> CargoResult<User> {
         use diesel::insert;
         use diesel::expression::dsl::sql;
         use diesel::types::Integer;
@@ -91,9 +96,8 @@ impl User {
     /// Queries the database for a user with a certain `gh_login` value.
     pub fn find_by_login(conn: &GenericConnection,
                          login: &str) -> CargoResult<User> {
-        let stmt = conn.prepare("SELECT * FROM users
-                                      WHERE gh_login = $1")?;
-        let rows = stmt.query(&[&login])?;
+        let rows = conn.query("SELECT * FROM users
+                                    WHERE gh_login = $1", &[&login])?;
         let row = rows.iter().next().chain_error(|| {
             NotFound
         })?;
@@ -103,9 +107,8 @@ impl User {
     /// Queries the database for a user with a certain `api_token` value.
     pub fn find_by_api_token(conn: &GenericConnection,
                              token: &str) -> CargoResult<User> {
-        let stmt = conn.prepare("SELECT * FROM users \
-                                      WHERE api_token = $1 LIMIT 1")?;
-        let rows = stmt.query(&[&token])?;
+        let rows = conn.query("SELECT * FROM users \
+                                    WHERE api_token = $1 LIMIT 1", &[&token])?;
         rows.iter().next().map(|r| Model::from_row(&r)).chain_error(|| {
             NotFound
         })
@@ -123,34 +126,34 @@ impl User {
         //       interesting! For now just do the racy thing which will report
         //       more errors than it needs to.
 
-        let stmt = conn.prepare("UPDATE users
-                                      SET gh_access_token = $1,
-                                          email = $2,
-                                          name = $3,
-                                          gh_avatar = $4,
-                                          gh_login = $5
-                                      WHERE gh_id = $6
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&access_token,
-            &email,
-            &name,
-            &avatar,
-            &login,
-            &id])?;
+        let rows = conn.query("UPDATE users
+                                    SET gh_access_token = $1,
+                                        email = $2,
+                                        name = $3,
+                                        gh_avatar = $4,
+                                        gh_login = $5
+                                    WHERE gh_id = $6
+                                    RETURNING *",
+                              &[&access_token,
+                                &email,
+                                &name,
+                                &avatar,
+                                &login,
+                                &id])?;
         if let Some(ref row) = rows.iter().next() {
             return Ok(Model::from_row(row));
         }
-        let stmt = conn.prepare("INSERT INTO users
-                                      (email, gh_access_token,
-                                       gh_login, name, gh_avatar, gh_id)
-                                      VALUES ($1, $2, $3, $4, $5, $6)
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&email,
-            &access_token,
-            &login,
-            &name,
-            &avatar,
-            &id])?;
+        let rows = conn.query("INSERT INTO users
+                                    (email, gh_access_token,
+                                     gh_login, name, gh_avatar, gh_id)
+                                    VALUES ($1, $2, $3, $4, $5, $6)
+                                    RETURNING *",
+                              &[&email,
+                                &access_token,
+                                &login,
+                                &name,
+                                &avatar,
+                                &id])?;
         Ok(Model::from_row(&rows.iter().next().chain_error(|| {
             internal("no user with email we just found")
         })?))
@@ -162,7 +165,7 @@ impl User {
         EncodableUser {
             id: id,
             email: email,
-            avatar: gh_avatar,
+            avatar: gh_avatar.map(|_| avatar::path(&gh_login)),
             login: gh_login,
             name: name,
         }
@@ -337,6 +340,57 @@ pub fn show(req: &mut Request) -> CargoResult<Response> {
 }
 
 
+/// Handles the `GET /users/:user_id/versions` route.
+///
+/// Lists the versions the user published themselves, newest first, which
+/// isn't the same as the versions of the crates they own.
+pub fn published_versions(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::sql;
+    use diesel::types::BigInt;
+
+    let (offset, limit) = req.pagination(10, 100)?;
+    let conn = req.db_conn()?;
+    let user = users::table.filter(users::gh_login.eq(&req.params()["user_id"]))
+        .first::<User>(&*conn)?;
+
+    let data = versions::table.inner_join(crates::table)
+        .filter(versions::published_by.eq(user.id))
+        .filter(crates::private.eq(false))
+        .order((versions::created_at.desc(), versions::id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .select((
+            versions::all_columns,
+            crates::name,
+            sql::<BigInt>("COUNT(*) OVER ()"),
+        ))
+        .load::<(Version, String, i64)>(&*conn)?;
+
+    // Pages past the end don't have a row to read the total from
+    let total = match data.get(0) {
+        Some(&(_, _, count)) => count,
+        None => versions::table.inner_join(crates::table)
+            .filter(versions::published_by.eq(user.id))
+            .filter(crates::private.eq(false))
+            .count()
+            .get_result(&*conn)?,
+    };
+    let versions = data.into_iter().map(|(version, crate_name, _)| {
+        let mut version = version.encodable(&crate_name);
+        version.published_by = Some(user.clone().encodable());
+        version
+    }).collect();
+
+    #[derive(RustcEncodable)]
+    struct R {
+        versions: Vec<EncodableVersion>,
+        meta: PaginationMeta,
+    }
+    let meta = req.pagination_meta(offset, limit, total);
+    Ok(req.json(&R{ versions: versions, meta: meta }))
+}
+
+
 /// Handles the `GET /me/updates` route.
 pub fn updates(req: &mut Request) -> CargoResult<Response> {
     use diesel::expression::dsl::{any, sql};
@@ -360,9 +414,15 @@ pub fn updates(req: &mut Request) -> CargoResult<Response> {
         ))
         .load::<(Version, String, i64)>(&*conn)?;
 
-    let more = data.get(0)
-        .map(|&(_, _, count)| count > offset + limit)
-        .unwrap_or(false);
+    // Pages past the end don't have a row to read the total from
+    let total = match data.get(0) {
+        Some(&(_, _, count)) => count,
+        None => versions::table
+            .filter(versions::crate_id.eq(any(Follow::belonging_to(user)
+                                                  .select(follows::crate_id))))
+            .count()
+            .get_result(&*conn)?,
+    };
 
     let versions = data.into_iter().map(|(version, crate_name, _)| {
         version.encodable(&crate_name)
@@ -371,25 +431,173 @@ pub fn updates(req: &mut Request) -> CargoResult<Response> {
     #[derive(RustcEncodable)]
     struct R {
         versions: Vec<EncodableVersion>,
-        meta: Meta,
+        meta: PaginationMeta,
     }
-    #[derive(RustcEncodable)]
-    struct Meta { more: bool }
-    Ok(req.json(&R{ versions: versions, meta: Meta { more: more } }))
+    let meta = req.pagination_meta(offset, limit, total);
+    Ok(req.json(&R{ versions: versions, meta: meta }))
+}
+
+/// One row of `GET /me/crates/export`.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableCrateExport {
+    pub name: String,
+    pub latest_version: String,
+    pub downloads: i32,
+    /// Downloads in the last 90 days
+    pub recent_downloads: i64,
+    /// The logins of the crate's other owners, users, teams and organizations
+    pub co_owners: Vec<String>,
+}
+
+/// Handles the `GET /me/crates/export` route.
+///
+/// Returns every crate the user owns, directly or through a team or an
+/// organization, as a single download, as JSON or, with `?format=csv`, as CSV
+/// with the co-owners separated by spaces.
+pub fn export_crates(req: &mut Request) -> CargoResult<Response> {
+    let format = req.query().get("format").cloned().unwrap_or_else(|| "json".to_string());
+    if format != "json" && format != "csv" {
+        return Err(human(&format_args!("unknown export format `{}`, expected `json` \
+                                        or `csv`", format)))
+    }
+    let user = req.user()?.clone();
+    let conn = req.tx()?;
+
+    // Github is only asked which teams the user is in when teams own crates
+    let team_owned = conn.query("SELECT 1 FROM crate_owners
+                                  WHERE owner_kind = $1 AND role = $2 AND NOT deleted
+                                  LIMIT 1",
+                                &[&(OwnerKind::Team as i32), &(OwnerRole::Owner as i32)])?;
+    let github_team_ids = if team_owned.is_empty() {
+        Vec::new()
+    } else {
+        Team::github_ids_of(req.app(), &user)?
+    };
+
+    // The crates owned by the user, a team they're in or an organization
+    // they're a member of
+    let rows = conn.query("\
+        SELECT crates.*,
+               (SELECT COALESCE(SUM(version_downloads.downloads), 0)
+                  FROM version_downloads
+                 INNER JOIN versions ON versions.id = version_downloads.version_id
+                 WHERE versions.crate_id = crates.id
+                   AND version_downloads.date > CURRENT_DATE - 90)::bigint
+               AS recent_downloads
+          FROM crates
+         WHERE crates.id IN (
+               SELECT crate_id FROM crate_owners
+                WHERE crate_owners.role = $2
+                  AND NOT crate_owners.deleted
+                  AND ((crate_owners.owner_kind = $3 AND crate_owners.owner_id = $1)
+                    OR (crate_owners.owner_kind = $4 AND crate_owners.owner_id IN (
+                        SELECT id FROM teams WHERE github_id = ANY($6)))
+                    OR (crate_owners.owner_kind = $5 AND crate_owners.owner_id IN (
+                        SELECT organization_id FROM organization_members
+                         WHERE user_id = $1))))
+         ORDER BY crates.name",
+                          &[&user.id, &(OwnerRole::Owner as i32), &(OwnerKind::User as i32),
+                            &(OwnerKind::Team as i32), &(OwnerKind::Org as i32),
+                            &github_team_ids])?;
+    let owned = rows.iter().map(|row| {
+        (Model::from_row(&row), row.get("recent_downloads"))
+    }).collect::<Vec<(Crate, i64)>>();
+    let ids = owned.iter().map(|&(ref krate, _)| krate.id).collect::<Vec<_>>();
+
+    let mut versions = HashMap::new();
+    for row in conn.query("SELECT crate_id, num FROM versions
+                            WHERE crate_id = ANY($1) AND NOT yanked", &[&ids])?.iter() {
+        let num = semver::Version::parse(&row.get::<_, String>("num")).unwrap();
+        versions.entry(row.get::<_, i32>("crate_id")).or_insert_with(Vec::new).push(num);
+    }
+
+    let mut co_owners = HashMap::new();
+    for row in conn.query("\
+        SELECT crate_owners.crate_id, crate_owners.owner_kind,
+               COALESCE(users.gh_login, teams.login, organizations.login) AS login
+          FROM crate_owners
+          LEFT JOIN users ON crate_owners.owner_kind = $2
+                         AND users.id = crate_owners.owner_id
+          LEFT JOIN teams ON crate_owners.owner_kind = $3
+                         AND teams.id = crate_owners.owner_id
+          LEFT JOIN organizations ON crate_owners.owner_kind = $4
+                                 AND organizations.id = crate_owners.owner_id
+         WHERE crate_owners.crate_id = ANY($1)
+           AND crate_owners.role = $5
+           AND NOT crate_owners.deleted
+         ORDER BY crate_owners.owner_kind, login",
+                          &[&ids, &(OwnerKind::User as i32), &(OwnerKind::Team as i32),
+                            &(OwnerKind::Org as i32), &(OwnerRole::Owner as i32)])?.iter() {
+        let login: String = row.get("login");
+        if login != user.gh_login {
+            co_owners.entry(row.get::<_, i32>("crate_id")).or_insert_with(Vec::new).push(login);
+        }
+    }
+
+    let crates = owned.into_iter().map(|(krate, recent_downloads)| {
+        EncodableCrateExport {
+            latest_version: Version::max(versions.remove(&krate.id).unwrap_or_default())
+                .to_string(),
+            downloads: krate.downloads,
+            recent_downloads: recent_downloads,
+            co_owners: co_owners.remove(&krate.id).unwrap_or_default(),
+            name: krate.name,
+        }
+    }).collect::<Vec<_>>();
+
+    let (content_type, body) = if format == "csv" {
+        ("text/csv; charset=utf-8", export_csv(&crates))
+    } else {
+        #[derive(RustcEncodable)]
+        struct R { crates: Vec<EncodableCrateExport> }
+        ("application/json; charset=utf-8", json::encode(&R { crates: crates }).unwrap())
+    };
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), vec![content_type.to_string()]);
+    headers.insert("Content-Length".to_string(), vec![body.len().to_string()]);
+    headers.insert("Content-Disposition".to_string(),
+                   vec![format!("attachment; filename=\"crates.{}\"", format)]);
+    Ok(Response {
+        status: (200, "OK"),
+        headers: headers,
+        body: Box::new(io::Cursor::new(body.into_bytes())),
+    })
+}
+
+fn export_csv(crates: &[EncodableCrateExport]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    let mut csv = String::from("name,latest_version,downloads,recent_downloads,co_owners\r\n");
+    for krate in crates {
+        csv.push_str(&[
+            field(&krate.name),
+            field(&krate.latest_version),
+            krate.downloads.to_string(),
+            krate.recent_downloads.to_string(),
+            field(&krate.co_owners.join(" ")),
+        ].join(","));
+        csv.push_str("\r\n");
+    }
+    csv
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use diesel::pg::PgConnection;
     use dotenv::dotenv;
     use std::env;
 
-    fn connection() -> PgConnection {
+    fn connection() -> InstrumentedConnection {
         let _ = dotenv();
         let database_url = env::var("TEST_DATABASE_URL")
             .expect("TEST_DATABASE_URL must be set to run tests");
-        let conn = PgConnection::establish(&database_url).unwrap();
+        let conn = InstrumentedConnection::establish(&database_url).unwrap();
         conn.begin_test_transaction().unwrap();
         conn
     }
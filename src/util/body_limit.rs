@@ -0,0 +1,240 @@
+//! Limits on how large request bodies can be and how long they can take to
+//! arrive, so that a client can't tie up a server thread or its memory for
+//! free.
+//!
+//! Bodies are limited to `Config::max_body_size` bytes, except on the routes
+//! in `route_limits` which need more. A body announcing a larger
+//! `Content-Length` is answered with a 413 before anything is read, and one
+//! which turns out to be larger is answered with a 413 as soon as it crosses
+//! the limit. A body also has to arrive within `Config::body_timeout_secs`
+//! of the request being handled, or it's answered with a 408: civetweb gives
+//! up on connections which send nothing for 30 seconds, but not on ones
+//! trickling in a byte at a time.
+
+use std::cmp;
+use std::error::Error;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use conduit::{self, Handler, Method, Request, Response};
+use conduit_middleware::AroundMiddleware;
+use semver;
+
+use config::Config;
+use util::errors::{CargoError, PayloadTooLarge, RequestTimeout};
+
+/// A route whose bodies are limited differently.
+pub struct RouteLimit {
+    pub method: Method,
+    /// Matched like the router does, `:param` matching any one segment and
+    /// `*rest` whatever is left
+    pub path: &'static str,
+    /// `None` for routes which check how large their body is themselves
+    pub max_bytes: Option<u64>,
+    /// How many times the default timeout the body can take
+    pub timeout_factor: u64,
+}
+
+/// The routes taking larger bodies than the rest.
+pub fn route_limits() -> Vec<RouteLimit> {
+    vec![
+        // Publishes check the crate's own upload limit
+        RouteLimit {
+            method: Method::Put,
+            path: "/api/v1/crates/new",
+            max_bytes: None,
+            timeout_factor: 10,
+        },
+        // Pushes to the index in development
+        RouteLimit {
+            method: Method::Post,
+            path: "/git/index/*path",
+            max_bytes: None,
+            timeout_factor: 10,
+        },
+    ]
+}
+
+pub struct BodyLimits {
+    handler: Option<Box<Handler>>,
+    max_bytes: u64,
+    timeout_secs: u64,
+    routes: Vec<RouteLimit>,
+}
+
+#[derive(Clone, Copy)]
+enum Exceeded {
+    Size,
+    Time,
+}
+
+/// The request handed to the rest of the stack, whose body is read through
+/// the limits.
+struct LimitedRequest<'a> {
+    other: &'a mut (Request + 'a),
+    max_bytes: Option<u64>,
+    read: u64,
+    deadline: Instant,
+    exceeded: Option<Exceeded>,
+}
+
+impl BodyLimits {
+    pub fn new(config: &Config) -> BodyLimits {
+        BodyLimits {
+            handler: None,
+            max_bytes: config.max_body_size,
+            timeout_secs: config.body_timeout_secs,
+            routes: route_limits(),
+        }
+    }
+
+    /// How many bytes of body the route takes, and in how many seconds.
+    fn limits(&self, method: &Method, path: &str) -> (Option<u64>, u64) {
+        match self.routes.iter().find(|r| r.method == *method && matches(r.path, path)) {
+            Some(route) => (route.max_bytes, self.timeout_secs * route.timeout_factor),
+            None => (Some(self.max_bytes), self.timeout_secs),
+        }
+    }
+}
+
+impl AroundMiddleware for BodyLimits {
+    fn with_handler(&mut self, handler: Box<Handler>) {
+        self.handler = Some(handler);
+    }
+}
+
+impl Handler for BodyLimits {
+    fn call(&self, req: &mut Request) -> Result<Response, Box<Error+Send>> {
+        let (max_bytes, timeout_secs) = self.limits(&req.method(), req.path());
+        if let (Some(max), Some(len)) = (max_bytes, req.content_length()) {
+            if len > max {
+                return Ok(PayloadTooLarge { max_bytes: max }.response().unwrap())
+            }
+        }
+
+        let mut req = LimitedRequest {
+            other: req,
+            max_bytes: max_bytes,
+            read: 0,
+            deadline: Instant::now() + Duration::from_secs(timeout_secs),
+            exceeded: None,
+        };
+        let result = self.handler.as_ref().unwrap().call(&mut req);
+        match req.exceeded {
+            Some(Exceeded::Size) => {
+                let max = max_bytes.unwrap_or(0);
+                Ok(PayloadTooLarge { max_bytes: max }.response().unwrap())
+            }
+            Some(Exceeded::Time) => {
+                Ok(RequestTimeout { timeout_secs: timeout_secs }.response().unwrap())
+            }
+            None => result,
+        }
+    }
+}
+
+impl<'a> LimitedRequest<'a> {
+    fn exceed(&mut self, exceeded: Exceeded) -> io::Error {
+        self.exceeded = Some(exceeded);
+        let msg = match exceeded {
+            Exceeded::Size => "request body too large",
+            Exceeded::Time => "request body not sent in time",
+        };
+        io::Error::new(io::ErrorKind::Other, msg)
+    }
+}
+
+impl<'a> Read for LimitedRequest<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(exceeded) = self.exceeded {
+            return Err(self.exceed(exceeded))
+        }
+        if Instant::now() >= self.deadline {
+            return Err(self.exceed(Exceeded::Time))
+        }
+
+        // Reading one byte more than allowed tells whether there's more
+        let len = match self.max_bytes {
+            Some(max) => cmp::min(buf.len() as u64, max + 1 - self.read) as usize,
+            None => buf.len(),
+        };
+        let n = match self.other.body().read(&mut buf[..len]) {
+            Ok(n) => n,
+            // civetweb's reads fail once the socket times out
+            Err(..) if Instant::now() >= self.deadline => {
+                return Err(self.exceed(Exceeded::Time))
+            }
+            Err(e) => return Err(e),
+        };
+        self.read += n as u64;
+        if self.max_bytes.map(|max| self.read > max).unwrap_or(false) {
+            return Err(self.exceed(Exceeded::Size))
+        }
+        Ok(n)
+    }
+}
+
+impl<'a> Request for LimitedRequest<'a> {
+    fn http_version(&self) -> semver::Version {
+        self.other.http_version()
+    }
+    fn conduit_version(&self) -> semver::Version {
+        self.other.conduit_version()
+    }
+    fn method(&self) -> conduit::Method { self.other.method() }
+    fn scheme(&self) -> conduit::Scheme { self.other.scheme() }
+    fn host(&self) -> conduit::Host { self.other.host() }
+    fn virtual_root(&self) -> Option<&str> {
+        self.other.virtual_root()
+    }
+    fn path(&self) -> &str { self.other.path() }
+    fn query_string(&self) -> Option<&str> {
+        self.other.query_string()
+    }
+    fn remote_addr(&self) -> SocketAddr { self.other.remote_addr() }
+    fn content_length(&self) -> Option<u64> {
+        self.other.content_length()
+    }
+    fn headers(&self) -> &conduit::Headers {
+        self.other.headers()
+    }
+    fn body(&mut self) -> &mut Read { self }
+    fn extensions(&self) -> &conduit::Extensions {
+        self.other.extensions()
+    }
+    fn mut_extensions(&mut self) -> &mut conduit::Extensions {
+        self.other.mut_extensions()
+    }
+}
+
+/// Whether `path` is one of the paths `pattern` stands for.
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut segments = path.trim_matches('/').split('/');
+    for part in pattern.trim_matches('/').split('/') {
+        if part.starts_with('*') {
+            return true
+        }
+        match segments.next() {
+            Some(segment) if part.starts_with(':') || part == segment => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn route_patterns() {
+        assert!(matches("/api/v1/crates/new", "/api/v1/crates/new"));
+        assert!(!matches("/api/v1/crates/new", "/api/v1/crates/new/extra"));
+        assert!(!matches("/api/v1/crates/new", "/api/v1/crates"));
+        assert!(matches("/api/v1/crates/:crate_id/follow", "/api/v1/crates/foo/follow"));
+        assert!(!matches("/api/v1/crates/:crate_id/follow", "/api/v1/crates/foo/owners"));
+        assert!(matches("/git/index/*path", "/git/index/info/refs"));
+        assert!(!matches("/git/index/*path", "/git/other"));
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use util::{CargoResult, human};
+
+/// A range of IP addresses in CIDR notation, e.g. `192.0.2.0/24`. A plain
+/// address is a range containing just that address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> CargoResult<Cidr> {
+        let invalid = || human(&format_args!("`{}` is not a valid IP range", s));
+        let mut parts = s.trim().splitn(2, '/');
+        let addr = parts.next().unwrap().parse::<IpAddr>().map_err(|_| invalid())?;
+        let max = match addr {
+            IpAddr::V4(..) => 32,
+            IpAddr::V6(..) => 128,
+        };
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid())
+        }
+
+        // Normalize the address so that e.g. 10.1.2.3/8 reads as 10.0.0.0/8
+        let addr = match addr {
+            IpAddr::V4(v4) => {
+                let mut octets = v4.octets();
+                mask(&mut octets, prefix);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            IpAddr::V6(v6) => {
+                let mut octets = v6.octets();
+                mask(&mut octets, prefix);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        };
+        Ok(Cidr { addr: addr, prefix: prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, *ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mut octets = ip.octets();
+                mask(&mut octets, self.prefix);
+                octets == net.octets()
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mut octets = ip.octets();
+                mask(&mut octets, self.prefix);
+                octets == net.octets()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Clears every bit of `octets` past the first `prefix` bits.
+fn mask(octets: &mut [u8], prefix: u8) {
+    let prefix = prefix as usize;
+    for (i, octet) in octets.iter_mut().enumerate() {
+        let kept = prefix.saturating_sub(i * 8);
+        if kept < 8 {
+            *octet &= !(0xffu8 >> kept);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::Cidr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_normalizes_the_address() {
+        assert_eq!(Cidr::parse("10.1.2.3/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(Cidr::parse("192.0.2.1").unwrap().to_string(), "192.0.2.1/32");
+        assert_eq!(Cidr::parse("2001:db8::1/32").unwrap().to_string(), "2001:db8::/32");
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("10.0.0/8").is_err());
+        assert!(Cidr::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn contains() {
+        let range = Cidr::parse("192.0.2.0/23").unwrap();
+        assert!(range.contains(&ip("192.0.2.10")));
+        assert!(range.contains(&ip("192.0.3.255")));
+        assert!(!range.contains(&ip("192.0.4.0")));
+        assert!(!range.contains(&ip("::1")));
+
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&ip("203.0.113.7")));
+        assert!(Cidr::parse("2001:db8::/32").unwrap().contains(&ip("2001:db8:1::1")));
+        assert!(!Cidr::parse("2001:db8::/32").unwrap().contains(&ip("2001:db9::1")));
+    }
+}
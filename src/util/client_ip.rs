@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+
+use conduit::Request;
+use conduit_middleware;
+
+use util::Cidr;
+
+/// The address of the client which made the request, as determined by
+/// `ClientIpMiddleware`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Works out which address a request really came from when the app runs
+/// behind proxies or load balancers.
+///
+/// `X-Forwarded-For` is only believed when the connection comes from one of
+/// the trusted proxies, and even then it's read from the right: every proxy
+/// appends the address it received the request from, so the client is the
+/// right-most address which isn't a trusted proxy. Anything further left was
+/// sent by the client itself and could be made up.
+pub struct ClientIpMiddleware {
+    trusted_proxies: Vec<Cidr>,
+}
+
+impl ClientIpMiddleware {
+    pub fn new(trusted_proxies: Vec<Cidr>) -> ClientIpMiddleware {
+        ClientIpMiddleware { trusted_proxies: trusted_proxies }
+    }
+}
+
+impl conduit_middleware::Middleware for ClientIpMiddleware {
+    fn before(&self, req: &mut Request) -> Result<(), Box<Error+Send>> {
+        let ip = {
+            let forwarded_for = req.headers().find("X-Forwarded-For")
+                .unwrap_or_default();
+            client_ip(req.remote_addr().ip(), &forwarded_for, &self.trusted_proxies)
+        };
+        req.mut_extensions().insert(ClientIp(ip));
+        Ok(())
+    }
+}
+
+/// Picks the client address out of the peer address of the connection and
+/// the values of the `X-Forwarded-For` headers, oldest first.
+pub fn client_ip(remote: IpAddr, forwarded_for: &[&str], trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|range| range.contains(ip));
+
+    let mut ip = remote;
+    let hops = forwarded_for.iter().flat_map(|value| value.split(',')).rev();
+    for hop in hops {
+        if !is_trusted(&ip) {
+            break
+        }
+        // A malformed entry means that nothing left of it can be relied on,
+        // so the last proxy is as close to the client as we can tell
+        match parse_hop(hop.trim()) {
+            Some(hop) => ip = hop,
+            None => break,
+        }
+    }
+    ip
+}
+
+/// Some proxies include the port, e.g. `192.0.2.1:4711` or `[2001:db8::1]:4711`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>().ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use util::Cidr;
+    use super::client_ip;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn trusted() -> Vec<Cidr> {
+        vec![Cidr::parse("10.0.0.0/8").unwrap(), Cidr::parse("2001:db8::/32").unwrap()]
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let peer = ip("198.51.100.1");
+        assert_eq!(client_ip(peer, &["203.0.113.7"], &trusted()), peer);
+        assert_eq!(client_ip(peer, &[], &[]), peer);
+    }
+
+    #[test]
+    fn skips_trusted_proxies_from_the_right() {
+        let peer = ip("10.0.0.1");
+        assert_eq!(client_ip(peer, &[], &trusted()), peer);
+        assert_eq!(client_ip(peer, &["203.0.113.7"], &trusted()), ip("203.0.113.7"));
+        assert_eq!(client_ip(peer, &["203.0.113.7, 10.1.2.3"], &trusted()),
+                   ip("203.0.113.7"));
+        assert_eq!(client_ip(peer, &["203.0.113.7", "10.1.2.3"], &trusted()),
+                   ip("203.0.113.7"));
+        assert_eq!(client_ip(peer, &["10.9.9.9, 10.1.2.3"], &trusted()), ip("10.9.9.9"));
+    }
+
+    #[test]
+    fn ignores_addresses_made_up_by_the_client() {
+        let peer = ip("10.0.0.1");
+        assert_eq!(client_ip(peer, &["192.0.2.66, 203.0.113.7"], &trusted()),
+                   ip("203.0.113.7"));
+        assert_eq!(client_ip(peer, &["203.0.113.7, unknown, 10.1.2.3"], &trusted()),
+                   ip("10.1.2.3"));
+        assert_eq!(client_ip(peer, &[""], &trusted()), peer);
+    }
+
+    #[test]
+    fn accepts_ports_and_ipv6() {
+        let peer = ip("2001:db8::1");
+        assert_eq!(client_ip(peer, &["203.0.113.7:4711"], &trusted()), ip("203.0.113.7"));
+        assert_eq!(client_ip(peer, &["[2001:db9::7]:4711"], &trusted()), ip("2001:db9::7"));
+        assert_eq!(client_ip(peer, &["2001:db9::7"], &trusted()), ip("2001:db9::7"));
+    }
+}
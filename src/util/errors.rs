@@ -9,6 +9,8 @@ use util::json_response;
 
 #[derive(RustcEncodable)] struct StringError { detail: String }
 #[derive(RustcEncodable)] struct Bad { errors: Vec<StringError> }
+#[derive(RustcEncodable)] struct CodedStringError { detail: String, code: &'static str }
+#[derive(RustcEncodable)] struct CodedBad { errors: Vec<CodedStringError> }
 
 // =============================================================================
 // CargoError trait
@@ -189,9 +191,10 @@ impl CargoError for Unauthorized {
     fn description(&self) -> &str { "unauthorized" }
 
     fn response(&self) -> Option<Response> {
-        let mut response = json_response(&Bad {
-            errors: vec![StringError {
+        let mut response = json_response(&CodedBad {
+            errors: vec![CodedStringError {
                 detail: "must be logged in to perform that action".to_string(),
+                code: "missing_token",
             }],
         });
         response.status = (403, "Forbidden");
@@ -205,6 +208,79 @@ impl fmt::Display for Unauthorized {
     }
 }
 
+/// A request body longer than the route accepts.
+pub struct PayloadTooLarge {
+    pub max_bytes: u64,
+}
+
+impl CargoError for PayloadTooLarge {
+    fn description(&self) -> &str { "payload too large" }
+
+    fn response(&self) -> Option<Response> {
+        let mut response = json_response(&Bad {
+            errors: vec![StringError { detail: self.to_string() }],
+        });
+        response.status = (413, "Payload Too Large");
+        Some(response)
+    }
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the request body can be at most {} bytes", self.max_bytes)
+    }
+}
+
+/// A request body which wasn't sent in time.
+pub struct RequestTimeout {
+    pub timeout_secs: u64,
+}
+
+impl CargoError for RequestTimeout {
+    fn description(&self) -> &str { "request timeout" }
+
+    fn response(&self) -> Option<Response> {
+        let mut response = json_response(&Bad {
+            errors: vec![StringError { detail: self.to_string() }],
+        });
+        response.status = (408, "Request Timeout");
+        Some(response)
+    }
+}
+
+impl fmt::Display for RequestTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the request body has to be sent within {} seconds", self.timeout_secs)
+    }
+}
+
+/// A human readable error which also carries a stable, machine readable code
+/// so that clients can react to it without matching on the message.
+pub struct CodedError {
+    code: &'static str,
+    description: String,
+}
+
+impl CargoError for CodedError {
+    fn description(&self) -> &str { &self.description }
+    fn human(&self) -> bool { true }
+
+    fn response(&self) -> Option<Response> {
+        Some(json_response(&CodedBad {
+            errors: vec![CodedStringError {
+                detail: self.description.clone(),
+                code: self.code,
+            }],
+        }))
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.description, self.code)
+    }
+}
+
 pub fn internal_error(error: &str, detail: &str) -> Box<CargoError> {
     Box::new(ConcreteCargoError {
         description: error.to_string(),
@@ -232,6 +308,14 @@ pub fn human<S: ToString + ?Sized>(error: &S) -> Box<CargoError> {
     })
 }
 
+pub fn human_with_code<S: ToString + ?Sized>(code: &'static str,
+                                             error: &S) -> Box<CargoError> {
+    Box::new(CodedError {
+        code: code,
+        description: error.to_string(),
+    })
+}
+
 pub fn std_error(e: Box<CargoError>) -> Box<Error+Send> {
     #[derive(Debug)]
     struct E(Box<CargoError>);
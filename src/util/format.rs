@@ -0,0 +1,20 @@
+//! Formatting of dates for output, so that everything which writes them out
+//! formats them the same way.
+
+use time::{self, Timespec};
+
+/// `2017-03-01T12:00:00Z`
+pub fn rfc3339(ts: Timespec) -> String {
+    time::at_utc(ts).rfc3339().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Timespec;
+    use super::*;
+
+    #[test]
+    fn rfc3339_dates() {
+        assert_eq!(rfc3339(Timespec::new(1488369600, 0)), "2017-03-01T12:00:00Z");
+    }
+}
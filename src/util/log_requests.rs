@@ -0,0 +1,53 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+use conduit::{Request, Response};
+use conduit_middleware;
+use log::LogLevel;
+use time;
+
+use db::{self, QueryTimer};
+use util::{RequestUtils, RouteName};
+
+/// Logs a line of `key=value` pairs for every request, including which route
+/// handled it and how much of its time was spent in the database.
+pub struct LogRequests;
+
+struct RequestStart(Instant);
+
+impl conduit_middleware::Middleware for LogRequests {
+    fn before(&self, req: &mut Request) -> Result<(), Box<Error+Send>> {
+        req.mut_extensions().insert(RequestStart(Instant::now()));
+        req.mut_extensions().insert(Arc::new(QueryTimer::default()));
+        Ok(())
+    }
+
+    fn after(&self, req: &mut Request, res: Result<Response, Box<Error+Send>>)
+             -> Result<Response, Box<Error+Send>> {
+        let service = req.extensions().find::<RequestStart>()
+            .map(|start| start.0.elapsed())
+            .unwrap_or_default();
+        let (db_time, queries) = req.mut_extensions().pop::<Arc<QueryTimer>>()
+            .map(|timer| timer.totals())
+            .unwrap_or_default();
+        let route = req.extensions().find::<RouteName>().map(|r| r.0).unwrap_or("-");
+        let (level, status, error) = match res {
+            Ok(ref res) => (LogLevel::Info, res.status.0, String::new()),
+            Err(ref e) => (LogLevel::Error, 500, format!(" error={:?}", e.to_string())),
+        };
+        log!(level, "at={} method={:?} path={} route={} status={} service={}ms \
+                     db={}ms queries={} ip={}{}",
+             time::now().rfc3339(),
+             req.method(),
+             req.path(),
+             route,
+             status,
+             db::millis(service),
+             db::millis(db_time),
+             queries,
+             req.client_ip(),
+             error);
+        res
+    }
+}
@@ -1,53 +1,82 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{self, Cursor};
+use std::io::{self, Cursor, Read};
+use std::net::IpAddr;
 use std::sync::Arc;
 
-use rustc_serialize::{json, Encodable};
+use rustc_serialize::{json, Decodable, Encodable};
 use rustc_serialize::json::Json;
 use url;
 
+use app::RequestApp;
 use conduit::{Request, Response, Handler};
 use conduit_router::{RouteBuilder, RequestParams};
 use db::RequestTransaction;
 use self::errors::NotFound;
 
+pub use self::body_limit::{BodyLimits, RouteLimit};
 pub use self::errors::{CargoError, CargoResult, internal, human, internal_error};
+pub use self::errors::human_with_code;
+pub use self::cidr::Cidr;
+pub use self::client_ip::{ClientIp, ClientIpMiddleware};
 pub use self::errors::{ChainError, std_error};
 pub use self::hasher::{HashingReader};
 pub use self::head::Head;
 pub use self::io_util::{LimitErrorReader, read_le_u32, read_fill};
 pub use self::lazy_cell::LazyCell;
+pub use self::log_requests::LogRequests;
+pub use self::named_routes::{NamedRouteBuilder, RouteName};
+pub use self::pagination::{PaginationLimits, PaginationMeta};
+pub use self::public_host::check_public_host;
 pub use self::request_proxy::RequestProxy;
+pub use self::tarball::{TarballFile, unpack};
+pub use self::timed_cache::TimedCache;
+pub use self::unified_diff::unified_diff;
 
+mod body_limit;
+mod cidr;
+mod client_ip;
 pub mod errors;
+pub mod format;
 mod hasher;
 mod head;
 mod io_util;
 mod lazy_cell;
+mod log_requests;
+mod named_routes;
+mod pagination;
+mod public_host;
 mod request_proxy;
+mod tarball;
+mod timed_cache;
+mod unified_diff;
 
 pub trait RequestUtils {
     fn redirect(&self, url: String) -> Response;
+    fn redirect_permanent(&self, url: String) -> Response;
 
     fn json<T: Encodable>(&self, t: &T) -> Response;
     fn query(&self) -> HashMap<String, String>;
     fn wants_json(&self) -> bool;
+    /// The offset and limit of the page asked for with `page` and
+    /// `per_page`. `max` is capped by `Config::max_per_page`.
     fn pagination(&self, default: usize, max: usize) -> CargoResult<(i64, i64)>;
+    /// The `meta` of the page returned for `pagination`'s offset and limit.
+    fn pagination_meta(&self, offset: i64, limit: i64, total: i64) -> PaginationMeta;
+
+    /// The address of the client, which differs from the peer address when
+    /// the request was forwarded by a trusted proxy.
+    fn client_ip(&self) -> IpAddr;
 }
 
 pub fn json_response<T: Encodable>(t: &T) -> Response {
+    raw_json_response(json_body(t))
+}
+
+/// `t` encoded as `json_response` would send it.
+pub fn json_body<T: Encodable>(t: &T) -> String {
     let s = json::encode(t).unwrap();
-    let json = fixup(s.parse().unwrap()).to_string();
-    let mut headers = HashMap::new();
-    headers.insert("Content-Type".to_string(),
-                   vec!["application/json; charset=utf-8".to_string()]);
-    headers.insert("Content-Length".to_string(), vec![json.len().to_string()]);
-    return Response {
-        status: (200, "OK"),
-        headers: headers,
-        body: Box::new(Cursor::new(json.into_bytes())),
-    };
+    return fixup(s.parse().unwrap()).to_string();
 
     fn fixup(json: Json) -> Json {
         match json {
@@ -69,6 +98,25 @@ pub fn json_response<T: Encodable>(t: &T) -> Response {
     }
 }
 
+/// Decodes the JSON body of a request.
+pub fn decode_body<T: Decodable>(req: &mut Request) -> CargoResult<T> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    json::decode(&body).map_err(|_| human("invalid json request"))
+}
+
+/// A response with `json` as its body, e.g. one made by `json_body` earlier.
+pub fn raw_json_response(json: String) -> Response {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(),
+                   vec!["application/json; charset=utf-8".to_string()]);
+    headers.insert("Content-Length".to_string(), vec![json.len().to_string()]);
+    Response {
+        status: (200, "OK"),
+        headers: headers,
+        body: Box::new(Cursor::new(json.into_bytes())),
+    }
+}
 
 impl<'a> RequestUtils for Request + 'a {
     fn json<T: Encodable>(&self, t: &T) -> Response {
@@ -92,6 +140,12 @@ impl<'a> RequestUtils for Request + 'a {
         }
     }
 
+    fn redirect_permanent(&self, url: String) -> Response {
+        let mut response = self.redirect(url);
+        response.status = (301, "Moved Permanently");
+        response
+    }
+
     fn wants_json(&self) -> bool {
         self.headers().find("Accept").map(|accept| {
             accept.iter().any(|s| s.contains("json"))
@@ -99,18 +153,20 @@ impl<'a> RequestUtils for Request + 'a {
     }
 
     fn pagination(&self, default: usize, max: usize) -> CargoResult<(i64, i64)> {
-        let query = self.query();
-        let page = query.get("page").and_then(|s| s.parse::<usize>().ok())
-                        .unwrap_or(1);
-        let limit = query.get("per_page").and_then(|s| s.parse::<usize>().ok())
-                         .unwrap_or(default);
-        if limit > max {
-            return Err(human(&format_args!("cannot request more than {} items", max)))
-        }
-        if page == 0 {
-            return Err(human("page indexing starts from 1, page 0 is invalid"))
+        PaginationLimits::new(default, max)
+            .capped(self.app().config.max_per_page)
+            .parse(&self.query())
+    }
+
+    fn pagination_meta(&self, offset: i64, limit: i64, total: i64) -> PaginationMeta {
+        PaginationMeta::new(&self.query(), offset, limit, total)
+    }
+
+    fn client_ip(&self) -> IpAddr {
+        match self.extensions().find::<ClientIp>() {
+            Some(&ClientIp(ip)) => ip,
+            None => self.remote_addr().ip(),
         }
-        Ok((((page - 1) * limit) as i64, limit as i64))
     }
 }
 
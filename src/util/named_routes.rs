@@ -0,0 +1,69 @@
+use std::error::Error;
+
+use conduit::{Handler, Method, Request, Response};
+use conduit_router::RouteBuilder;
+
+/// The pattern of the route a request was routed to, e.g.
+/// `/crates/:crate_id/owners`, for telling in logs which handler a request
+/// or a query was about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteName(pub &'static str);
+
+/// A `RouteBuilder` which records the pattern of the matched route in the
+/// request as a `RouteName`.
+pub struct NamedRouteBuilder(RouteBuilder);
+
+struct Named<H>(&'static str, H);
+
+impl<H: Handler> Handler for Named<H> {
+    fn call(&self, req: &mut Request) -> Result<Response, Box<Error+Send>> {
+        req.mut_extensions().insert(RouteName(self.0));
+        self.1.call(req)
+    }
+}
+
+impl NamedRouteBuilder {
+    pub fn new() -> NamedRouteBuilder {
+        NamedRouteBuilder(RouteBuilder::new())
+    }
+
+    pub fn map<H: Handler>(&mut self, method: Method, pattern: &'static str, handler: H)
+                           -> &mut NamedRouteBuilder {
+        self.0.map(method, pattern, Named(pattern, handler));
+        self
+    }
+
+    pub fn get<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                           -> &mut NamedRouteBuilder {
+        self.map(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                            -> &mut NamedRouteBuilder {
+        self.map(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                           -> &mut NamedRouteBuilder {
+        self.map(Method::Put, pattern, handler)
+    }
+
+    pub fn patch<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                             -> &mut NamedRouteBuilder {
+        self.map(Method::Patch, pattern, handler)
+    }
+
+    pub fn delete<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                              -> &mut NamedRouteBuilder {
+        self.map(Method::Delete, pattern, handler)
+    }
+
+    pub fn head<H: Handler>(&mut self, pattern: &'static str, handler: H)
+                            -> &mut NamedRouteBuilder {
+        self.map(Method::Head, pattern, handler)
+    }
+
+    pub fn into_inner(self) -> RouteBuilder {
+        self.0
+    }
+}
@@ -0,0 +1,171 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use url::form_urlencoded;
+
+use util::{CargoResult, human};
+
+/// How an endpoint's lists are paginated when the request doesn't say.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaginationLimits {
+    /// Items per page when `per_page` isn't given
+    pub default: usize,
+    /// The most items per page the endpoint allows
+    pub max: usize,
+}
+
+impl PaginationLimits {
+    pub fn new(default: usize, max: usize) -> PaginationLimits {
+        PaginationLimits { default: default, max: max }
+    }
+
+    /// Caps the endpoint's limits with the registry-wide
+    /// `Config::max_per_page`.
+    pub fn capped(self, max_per_page: usize) -> PaginationLimits {
+        let max = cmp::min(self.max, max_per_page);
+        PaginationLimits { default: cmp::min(self.default, max), max: max }
+    }
+
+    /// Reads `page` and `per_page` from a query string, returning the offset
+    /// and the limit to query with.
+    pub fn parse(&self, query: &HashMap<String, String>) -> CargoResult<(i64, i64)> {
+        let page = match query.get("page") {
+            Some(page) => positive(page, "page")?,
+            None => 1,
+        };
+        if page == 0 {
+            return Err(human("page indexing starts from 1, page 0 is invalid"))
+        }
+        let limit = match query.get("per_page") {
+            Some(per_page) => positive(per_page, "per_page")?,
+            None => self.default,
+        };
+        if limit == 0 {
+            return Err(human("per_page must be at least 1"))
+        }
+        if limit > self.max {
+            return Err(human(&format_args!("cannot request more than {} items", self.max)))
+        }
+        match (page - 1).checked_mul(limit) {
+            Some(offset) if offset <= i64::max_value() as usize => {
+                Ok((offset as i64, limit as i64))
+            }
+            _ => Err(human("page is too large")),
+        }
+    }
+}
+
+/// The `meta` of every paginated list.
+#[derive(RustcEncodable, RustcDecodable, Debug, PartialEq, Eq)]
+pub struct PaginationMeta {
+    /// How many items there are on all the pages
+    pub total: i64,
+    /// The query string of the next page, e.g. `?page=3&per_page=10`,
+    /// unless this is the last one. Other parameters of the request are kept.
+    pub next_page: Option<String>,
+    /// The query string of the previous page, unless this is the first one
+    pub prev_page: Option<String>,
+}
+
+impl PaginationMeta {
+    /// The meta of the page at `offset` of the request with `query`.
+    pub fn new(query: &HashMap<String, String>, offset: i64, limit: i64, total: i64)
+               -> PaginationMeta {
+        let page = offset / cmp::max(limit, 1) + 1;
+        PaginationMeta {
+            total: total,
+            next_page: if offset + limit < total {
+                Some(page_query(query, page + 1, limit))
+            } else {
+                None
+            },
+            prev_page: if page > 1 { Some(page_query(query, page - 1, limit)) } else { None },
+        }
+    }
+
+    /// The meta of lists which are always returned in full.
+    pub fn unpaginated(total: i64) -> PaginationMeta {
+        PaginationMeta { total: total, next_page: None, prev_page: None }
+    }
+}
+
+fn page_query(query: &HashMap<String, String>, page: i64, limit: i64) -> String {
+    let mut params = query.iter()
+        .filter(|&(k, _)| k != "page" && k != "per_page")
+        .collect::<Vec<_>>();
+    params.sort();
+    let mut serializer = form_urlencoded::Serializer::new(String::from("?"));
+    for (k, v) in params {
+        serializer.append_pair(k, v);
+    }
+    serializer.append_pair("page", &page.to_string());
+    serializer.append_pair("per_page", &limit.to_string());
+    serializer.finish()
+}
+
+fn positive(value: &str, name: &str) -> CargoResult<usize> {
+    value.parse().map_err(|_| {
+        human(&format_args!("{} must be a positive integer, got `{}`", name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{PaginationLimits, PaginationMeta};
+
+    fn parse(limits: PaginationLimits, query: &[(&str, &str)]) -> Result<(i64, i64), String> {
+        let query = query.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        limits.parse(&query).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn defaults_and_offsets() {
+        let limits = PaginationLimits::new(10, 100);
+        assert_eq!(parse(limits, &[]), Ok((0, 10)));
+        assert_eq!(parse(limits, &[("page", "3")]), Ok((20, 10)));
+        assert_eq!(parse(limits, &[("page", "2"), ("per_page", "100")]), Ok((100, 100)));
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        let limits = PaginationLimits::new(10, 100);
+        assert!(parse(limits, &[("page", "0")]).unwrap_err().contains("page 0"));
+        assert!(parse(limits, &[("per_page", "0")]).is_err());
+        assert!(parse(limits, &[("per_page", "101")]).unwrap_err().contains("100 items"));
+        assert!(parse(limits, &[("per_page", "-1")]).unwrap_err().contains("per_page"));
+        assert!(parse(limits, &[("page", "abc")]).unwrap_err().contains("page"));
+        let huge = format!("{}", usize::max_value());
+        assert!(parse(limits, &[("page", &huge)]).unwrap_err().contains("too large"));
+    }
+
+    #[test]
+    fn the_registry_wide_cap_wins() {
+        let limits = PaginationLimits::new(52, 520).capped(100);
+        assert_eq!(limits, PaginationLimits::new(52, 100));
+        let limits = PaginationLimits::new(10, 100).capped(5);
+        assert_eq!(limits, PaginationLimits::new(5, 5));
+    }
+
+    #[test]
+    fn page_links() {
+        let mut query = HashMap::new();
+        query.insert("q".to_string(), "serde json".to_string());
+        query.insert("page".to_string(), "2".to_string());
+
+        let meta = PaginationMeta::new(&query, 10, 10, 35);
+        assert_eq!(meta.total, 35);
+        assert_eq!(meta.next_page, Some("?q=serde+json&page=3&per_page=10".to_string()));
+        assert_eq!(meta.prev_page, Some("?q=serde+json&page=1&per_page=10".to_string()));
+
+        let meta = PaginationMeta::new(&HashMap::new(), 30, 10, 35);
+        assert_eq!(meta.next_page, None);
+        assert_eq!(meta.prev_page, Some("?page=3&per_page=10".to_string()));
+
+        let meta = PaginationMeta::new(&HashMap::new(), 0, 10, 10);
+        assert_eq!(meta, PaginationMeta::unpaginated(10));
+    }
+}
@@ -0,0 +1,86 @@
+use std::net::{IpAddr, ToSocketAddrs};
+
+use url::{Host, Url};
+
+use util::{CargoResult, human};
+
+/// Fails unless every address the host of `url` resolves to right now is a
+/// public one, so that urls given by users can't be used to reach the
+/// registry's own network, e.g. `localhost`, private ranges or the cloud
+/// provider's metadata service.
+pub fn check_public_host(url: &Url) -> CargoResult<()> {
+    let addrs = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            (domain, port).to_socket_addrs()
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .unwrap_or_else(|_| Vec::new())
+        }
+        None => Vec::new(),
+    };
+    if addrs.is_empty() {
+        return Err(human(&format_args!("the host of `{}` couldn't be resolved", url)))
+    }
+    if !addrs.iter().all(is_public) {
+        return Err(human(&format_args!("`{}` doesn't point to a public address", url)))
+    }
+    Ok(())
+}
+
+/// Whether `ip` is reachable on the public internet, rather than being a
+/// loopback, private, link-local or otherwise special address.
+pub fn is_public(ip: &IpAddr) -> bool {
+    match *ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() ||
+              ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() ||
+              // 0.0.0.0/8 and the shared address space, 100.64.0.0/10
+              octets[0] == 0 || (octets[0] == 100 && octets[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4() {
+                // IPv4-mapped and -compatible addresses, except for `::` and
+                // `::1`, which are checked below
+                if !ip.is_unspecified() && !ip.is_loopback() {
+                    return is_public(&IpAddr::V4(v4))
+                }
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() ||
+              // Unique local, fc00::/7, and link-local, fe80::/10
+              first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{check_public_host, is_public};
+
+    #[test]
+    fn special_addresses_are_not_public() {
+        for ip in &["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254",
+                    "0.0.0.0", "100.64.0.1", "255.255.255.255", "::1", "::", "fd00::1",
+                    "fe80::1", "::ffff:127.0.0.1", "::ffff:10.0.0.1"] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &["203.0.113.7", "8.8.8.8", "2001:db8::1", "::ffff:8.8.8.8"] {
+            assert!(is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn urls_must_point_to_public_hosts() {
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert!(check_public_host(&url("https://203.0.113.7/hook")).is_ok());
+        assert!(check_public_host(&url("https://[2001:db8::1]/hook")).is_ok());
+        assert!(check_public_host(&url("https://127.0.0.1/hook")).is_err());
+        assert!(check_public_host(&url("https://[::1]:8443/hook")).is_err());
+        assert!(check_public_host(&url("https://localhost/hook")).is_err());
+    }
+}
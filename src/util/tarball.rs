@@ -0,0 +1,163 @@
+use std::io::Read;
+use std::str;
+
+use flate2::read::GzDecoder;
+
+use util::{CargoResult, human};
+
+/// Tarballs which unpack to more than this are rejected.
+const MAX_UNPACKED_SIZE: u64 = 100 * 1024 * 1024;
+
+/// A regular file in a crate's tarball.
+pub struct TarballFile {
+    pub path: String,
+    pub size: u64,
+    pub contents: Vec<u8>,
+}
+
+/// Reads the regular files out of a gzipped tarball as produced by
+/// `cargo package`.
+///
+/// Only the parts of the format cargo uses are understood: ustar headers and
+/// GNU long names. Directories, links and any other kind of entry are skipped.
+/// Tarballs which can't be read, or which unpack to more than
+/// `MAX_UNPACKED_SIZE`, are errors, and publishing them is refused since
+/// their contents can't be scanned.
+pub fn unpack(tarball: &[u8]) -> CargoResult<Vec<TarballFile>> {
+    let invalid = || human("the crate file is not a valid gzipped tarball");
+
+    let decoder = GzDecoder::new(tarball).map_err(|_| invalid())?;
+    let mut data = Vec::new();
+    decoder.take(MAX_UNPACKED_SIZE + 1).read_to_end(&mut data).map_err(|_| invalid())?;
+    if data.len() as u64 > MAX_UNPACKED_SIZE {
+        return Err(human("the crate file unpacks to too much data"))
+    }
+
+    let mut files = Vec::new();
+    let mut long_name = None;
+    let mut pos = 0;
+    while pos + 512 <= data.len() {
+        let header = &data[pos..pos + 512];
+        if header.iter().all(|&b| b == 0) {
+            break
+        }
+        let size = octal(&header[124..136]).ok_or_else(|| invalid())?;
+        let start = pos + 512;
+        let end = start + size as usize;
+        if size > data.len() as u64 || end > data.len() {
+            return Err(invalid())
+        }
+        let contents = &data[start..end];
+
+        match header[156] {
+            b'L' => long_name = Some(until_nul(contents).to_string()),
+            b'0' | b'\0' => {
+                let path = match long_name.take() {
+                    Some(path) => path,
+                    None => header_path(header),
+                };
+                files.push(TarballFile {
+                    path: path,
+                    size: size,
+                    contents: contents.to_vec(),
+                });
+            }
+            _ => long_name = None,
+        }
+        pos = start + (size as usize + 511) / 512 * 512;
+    }
+    Ok(files)
+}
+
+fn header_path(header: &[u8]) -> String {
+    let name = until_nul(&header[..100]);
+    // Only POSIX ustar headers have a prefix, GNU ones use the space for
+    // other things
+    let prefix = if &header[257..263] == b"ustar\0" {
+        until_nul(&header[345..500])
+    } else {
+        ""
+    };
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+fn until_nul(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..end]).unwrap_or("")
+}
+
+fn octal(field: &[u8]) -> Option<u64> {
+    let s = until_nul(field).trim();
+    if s.is_empty() {
+        return Some(0)
+    }
+    u64::from_str_radix(s, 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::iter::repeat;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::unpack;
+
+    fn header(path: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        let size = format!("{:011o}", size);
+        header[124..135].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        header[257..265].copy_from_slice(b"ustar  \0");
+        header
+    }
+
+    fn entry(tar: &mut Vec<u8>, path: &str, contents: &[u8], kind: u8) {
+        tar.extend(header(path, contents.len(), kind));
+        tar.extend(contents);
+        let padding = (512 - contents.len() % 512) % 512;
+        tar.extend(vec![0; padding]);
+    }
+
+    fn gzip(tar: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(tar).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn unpacks_files() {
+        let long = format!("foo-0.1.0/{}.rs", repeat("a").take(120).collect::<String>());
+        let mut tar = Vec::new();
+        entry(&mut tar, "foo-0.1.0/", b"", b'5');
+        entry(&mut tar, "foo-0.1.0/Cargo.toml", b"[package]\nname = \"foo\"\n", b'0');
+        entry(&mut tar, "././@LongLink", format!("{}\0", long).as_bytes(), b'L');
+        entry(&mut tar, "foo-0.1.0/aaaa", &[1; 600], b'0');
+        tar.extend(vec![0; 1024]);
+
+        let files = unpack(&gzip(&tar)).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "foo-0.1.0/Cargo.toml");
+        assert_eq!(files[0].contents, b"[package]\nname = \"foo\"\n");
+        assert_eq!(files[1].path, long);
+        assert_eq!(files[1].size, 600);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(unpack(b"not a tarball").is_err());
+
+        let mut tar = Vec::new();
+        entry(&mut tar, "foo-0.1.0/src/lib.rs", b"", b'0');
+        let truncated = tar.len();
+        tar.extend(header("foo-0.1.0/src/main.rs", 4096, b'0'));
+        assert!(unpack(&gzip(&tar)).is_err());
+        assert!(unpack(&gzip(&tar[..truncated])).is_ok());
+    }
+}
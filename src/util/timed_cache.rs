@@ -0,0 +1,47 @@
+//! A single value cache whose contents expire after a fixed duration.
+//!
+//! This is meant for expensive, registry-wide queries (rankings, statistics)
+//! where serving slightly stale data is much better than recomputing the
+//! value on every request.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use util::CargoResult;
+
+pub struct TimedCache<T> {
+    ttl: Duration,
+    inner: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> TimedCache<T> {
+    /// Creates a new empty cache whose values live for `ttl`.
+    pub fn new(ttl: Duration) -> TimedCache<T> {
+        TimedCache { ttl: ttl, inner: Mutex::new(None) }
+    }
+
+    /// Returns the cached value if it hasn't expired yet, otherwise computes a
+    /// new one with `f` and caches it.
+    ///
+    /// Errors from `f` are returned as-is and leave the cache untouched.
+    pub fn get_or_try_insert_with<F>(&self, f: F) -> CargoResult<T>
+        where F: FnOnce() -> CargoResult<T>
+    {
+        if let Some((filled_at, ref value)) = *self.inner.lock().unwrap() {
+            if filled_at.elapsed() < self.ttl {
+                return Ok(value.clone())
+            }
+        }
+
+        // Note that the lock isn't held while computing the value, so two
+        // requests may race to fill the cache. That's fine, the last one wins.
+        let value = f()?;
+        *self.inner.lock().unwrap() = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+
+    /// Throws away the cached value, if any.
+    pub fn clear(&self) {
+        *self.inner.lock().unwrap() = None;
+    }
+}
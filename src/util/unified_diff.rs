@@ -0,0 +1,167 @@
+use std::cmp;
+
+/// Line pairs beyond which the longest common subsequence isn't computed,
+/// the differing middle of the files is shown as entirely replaced instead.
+const MAX_TABLE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// A unified diff of two texts with `context` lines around each change,
+/// without the `---`/`+++` file header. Empty if the texts have the same
+/// lines.
+pub fn unified_diff(old: &str, new: &str, context: usize) -> String {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let ops = edit_script(&old, &new);
+
+    let changes = ops.iter().enumerate()
+        .filter(|&(_, op)| match *op { Op::Equal(_) => false, _ => true })
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let lo = i.saturating_sub(context);
+        let hi = cmp::min(ops.len(), i + 1 + context);
+        if let Some(last) = hunks.last_mut() {
+            if lo <= last.1 {
+                last.1 = hi;
+                continue
+            }
+        }
+        hunks.push((lo, hi));
+    }
+
+    // How many lines of each text come before each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in &ops {
+        positions.push((old_pos, new_pos));
+        match *op {
+            Op::Equal(_) => { old_pos += 1; new_pos += 1; }
+            Op::Delete(_) => old_pos += 1,
+            Op::Insert(_) => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut out = String::new();
+    for (lo, hi) in hunks {
+        let (old_start, new_start) = positions[lo];
+        let (old_end, new_end) = positions[hi];
+        out.push_str(&format!("@@ -{} +{} @@\n",
+                              range(old_start, old_end - old_start),
+                              range(new_start, new_end - new_start)));
+        for op in &ops[lo..hi] {
+            let (prefix, line) = match *op {
+                Op::Equal(line) => (' ', line),
+                Op::Delete(line) => ('-', line),
+                Op::Insert(line) => ('+', line),
+            };
+            out.push(prefix);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn range(start: usize, count: usize) -> String {
+    // Empty ranges name the line before them, like GNU diff does
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn edit_script<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = old[..prefix].iter().map(|&l| Op::Equal(l)).collect::<Vec<_>>();
+    if (old_mid.len() + 1).saturating_mul(new_mid.len() + 1) > MAX_TABLE_SIZE {
+        ops.extend(old_mid.iter().map(|&l| Op::Delete(l)));
+        ops.extend(new_mid.iter().map(|&l| Op::Insert(l)));
+    } else {
+        ops.extend(lcs_script(old_mid, new_mid));
+    }
+    ops.extend(old[old.len() - suffix..].iter().map(|&l| Op::Equal(l)));
+    ops
+}
+
+fn lcs_script<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (old.len(), new.len());
+    // lengths[i * (m + 1) + j] is the length of the longest common
+    // subsequence of old[i..] and new[j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if old[i] == new[j] {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                cmp::max(lengths[(i + 1) * (m + 1) + j], lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1] {
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|&l| Op::Delete(l)));
+    ops.extend(new[j..].iter().map(|&l| Op::Insert(l)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified_diff;
+
+    #[test]
+    fn identical_texts_have_no_hunks() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", 3), "");
+    }
+
+    #[test]
+    fn changes_get_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        assert_eq!(unified_diff(old, new, 2), "@@ -3,5 +3,5 @@\n 3\n 4\n-5\n+five\n 6\n 7\n");
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let old = "a\n1\n2\n3\n4\n5\nb\n";
+        let new = "A\n1\n2\n3\n4\n5\nB\n";
+        assert_eq!(unified_diff(old, new, 1),
+                   "@@ -1,2 +1,2 @@\n-a\n+A\n 1\n@@ -6,2 +6,2 @@\n 5\n-b\n+B\n");
+        // Close enough changes share a hunk
+        assert_eq!(unified_diff(old, new, 3).matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn added_and_removed_files() {
+        assert_eq!(unified_diff("", "a\nb\n", 3), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(unified_diff("a\n", "", 3), "@@ -1 +0,0 @@\n-a\n");
+    }
+}
@@ -1,9 +1,10 @@
 use std::collections::HashMap;
+use std::io::Read;
 
 use conduit::{Request, Response};
 use conduit_router::RequestParams;
 use diesel;
-use diesel::pg::{Pg, PgConnection};
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use pg::GenericConnection;
 use pg::rows::Row;
@@ -12,18 +13,24 @@ use semver;
 use time::{Duration, Timespec, now_utc, strptime};
 use url;
 
+use admin;
 use app::RequestApp;
-use db::RequestTransaction;
-use dependency::{Dependency, EncodableDependency, Kind};
-use download::{VersionDownload, EncodableVersionDownload};
+use audit;
+use db::{InstrumentedConnection, RequestTransaction};
+use dependency::{self, Dependency, EncodableDependency, Kind};
+use download::{self, VersionDownload, EncodableVersionDownload};
 use git;
-use owner::{rights, Rights};
+use krate::validate_url;
+use owner::{request_rights, Rights};
+use permission;
+use readme;
 use schema::*;
+use tag_history;
 use upload;
-use user::RequestUser;
+use user::{User, EncodableUser, RequestUser};
 use util::errors::CargoError;
-use util::{RequestUtils, CargoResult, ChainError, internal, human};
-use {Model, Crate};
+use util::{self, RequestUtils, CargoResult, ChainError, internal, human, human_with_code};
+use {Model, Category, Crate};
 
 #[derive(Clone, Identifiable, Associations)]
 #[belongs_to(Crate)]
@@ -36,6 +43,15 @@ pub struct Version {
     pub downloads: i32,
     pub features: HashMap<String, Vec<String>>,
     pub yanked: bool,
+    pub published_by: Option<i32>,
+    pub published_with_token_id: Option<i32>,
+    /// Held back for review by an admin, see the `quarantine` module
+    pub quarantined: bool,
+    /// Published with a provenance its CI provider confirmed, see the
+    /// `provenance` module
+    pub verified_build: bool,
+    /// The crate's license as of this version
+    pub license: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -44,12 +60,28 @@ pub struct NewVersion {
     crate_id: i32,
     num: String,
     features: String,
+    published_by: Option<i32>,
+    published_with_token_id: Option<i32>,
+    license: Option<String>,
 }
 
 pub struct Author {
     pub name: String
 }
 
+/// How a dependency's requirement compares with its newest release.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct EncodableDependencyFreshness {
+    pub crate_id: String,
+    pub req: String,
+    pub kind: Kind,
+    pub optional: bool,
+    /// The newest release of the dependency, if it has any
+    pub latest: Option<String>,
+    /// Whether `req` doesn't accept `latest`
+    pub outdated: bool,
+}
+
 #[derive(RustcEncodable, RustcDecodable)]
 pub struct EncodableVersion {
     pub id: i32,
@@ -61,6 +93,10 @@ pub struct EncodableVersion {
     pub downloads: i32,
     pub features: HashMap<String, Vec<String>>,
     pub yanked: bool,
+    pub published_by: Option<EncodableUser>,
+    pub published_with_token: Option<String>,
+    pub quarantined: bool,
+    pub verified_build: bool,
     pub links: VersionLinks,
 }
 
@@ -77,12 +113,23 @@ impl Version {
                        num: &semver::Version)
                        -> CargoResult<Option<Version>> {
         let num = num.to_string();
-        let stmt = conn.prepare("SELECT * FROM versions \
-                                      WHERE crate_id = $1 AND num = $2")?;
-        let rows = stmt.query(&[&crate_id, &num])?;
+        let rows = conn.query("SELECT * FROM versions \
+                                    WHERE crate_id = $1 AND num = $2", &[&crate_id, &num])?;
         Ok(rows.iter().next().map(|r| Model::from_row(&r)))
     }
 
+    /// The version of the crate which comes right before `num` in semver
+    /// order, whenever it was published.
+    pub fn preceding(conn: &InstrumentedConnection,
+                     crate_id: i32,
+                     num: &semver::Version) -> CargoResult<Option<Version>> {
+        let others = versions::table.filter(versions::crate_id.eq(crate_id))
+            .load::<Version>(conn)?;
+        Ok(others.into_iter()
+            .filter(|version| version.num < *num)
+            .max_by(|a, b| a.num.cmp(&b.num)))
+    }
+
     pub fn insert(conn: &GenericConnection,
                   crate_id: i32,
                   num: &semver::Version,
@@ -91,11 +138,10 @@ impl Version {
                   -> CargoResult<Version> {
         let num = num.to_string();
         let features = json::encode(features).unwrap();
-        let stmt = conn.prepare("INSERT INTO versions \
-                                      (crate_id, num, features) \
-                                      VALUES ($1, $2, $3) \
-                                      RETURNING *")?;
-        let rows = stmt.query(&[&crate_id, &num, &features])?;
+        let rows = conn.query("INSERT INTO versions \
+                                    (crate_id, num, features) \
+                                    VALUES ($1, $2, $3) \
+                                    RETURNING *", &[&crate_id, &num, &features])?;
         let ret: Version = Model::from_row(&rows.iter().next().chain_error(|| {
             internal("no version returned")
         })?);
@@ -111,7 +157,8 @@ impl Version {
 
     pub fn encodable(self, crate_name: &str) -> EncodableVersion {
         let Version { id, num, updated_at, created_at,
-                      downloads, features, yanked, .. } = self;
+                      downloads, features, yanked, quarantined, verified_build,
+                      .. } = self;
         let num = num.to_string();
         EncodableVersion {
             dl_path: format!("/api/v1/crates/{}/{}/download", crate_name, num),
@@ -123,6 +170,10 @@ impl Version {
             downloads: downloads,
             features: features,
             yanked: yanked,
+            published_by: None,
+            published_with_token: None,
+            quarantined: quarantined,
+            verified_build: verified_build,
             links: VersionLinks {
                 dependencies: format!("/api/v1/crates/{}/{}/dependencies",
                                       crate_name, num),
@@ -133,6 +184,41 @@ impl Version {
         }
     }
 
+    /// Encodes a list of versions of the crate `crate_name`, filling in who
+    /// published each version and the name of the API token they used.
+    pub fn encodable_with_publishers(conn: &GenericConnection,
+                                     versions: Vec<Version>,
+                                     crate_name: &str)
+                                     -> CargoResult<Vec<EncodableVersion>> {
+        let user_ids = versions.iter()
+            .filter_map(|v| v.published_by)
+            .collect::<Vec<i32>>();
+        let token_ids = versions.iter()
+            .filter_map(|v| v.published_with_token_id)
+            .collect::<Vec<i32>>();
+
+        let users = conn.query("SELECT * FROM users WHERE id = ANY($1)",
+                               &[&user_ids])?.iter().map(|row| {
+            let user: User = Model::from_row(&row);
+            (user.id, user)
+        }).collect::<HashMap<_, _>>();
+        let token_names = conn.query("SELECT id, name FROM api_tokens \
+                                           WHERE id = ANY($1)",
+                                     &[&token_ids])?.iter().map(|row| {
+            (row.get::<_, i32>("id"), row.get::<_, String>("name"))
+        }).collect::<HashMap<_, _>>();
+
+        Ok(versions.into_iter().map(|v| {
+            let published_by = v.published_by.and_then(|id| users.get(&id).cloned());
+            let token_name = v.published_with_token_id
+                .and_then(|id| token_names.get(&id).cloned());
+            let mut encoded = v.encodable(crate_name);
+            encoded.published_by = published_by.map(User::encodable);
+            encoded.published_with_token = token_name;
+            encoded
+        }).collect())
+    }
+
     /// Add a dependency to this version, returning both the dependency and the
     /// crate that the dependency points to
     pub fn add_dependency(&mut self,
@@ -165,24 +251,22 @@ impl Version {
     /// Returns (dependency, crate dependency name)
     pub fn dependencies(&self, conn: &GenericConnection)
                         -> CargoResult<Vec<(Dependency, String)>> {
-        let stmt = conn.prepare("SELECT dependencies.*,
-                                             crates.name AS crate_name
-                                      FROM dependencies
-                                      LEFT JOIN crates
-                                        ON crates.id = dependencies.crate_id
-                                      WHERE dependencies.version_id = $1
-                                      ORDER BY optional, name")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT dependencies.*,
+                                           crates.name AS crate_name
+                                    FROM dependencies
+                                    LEFT JOIN crates
+                                      ON crates.id = dependencies.crate_id
+                                    WHERE dependencies.version_id = $1
+                                    ORDER BY optional, name", &[&self.id])?;
         Ok(rows.iter().map(|r| {
             (Model::from_row(&r), r.get("crate_name"))
         }).collect())
     }
 
     pub fn authors(&self, conn: &GenericConnection) -> CargoResult<Vec<Author>> {
-        let stmt = conn.prepare("SELECT * FROM version_authors
-                                       WHERE version_id = $1
-                                       ORDER BY name ASC")?;
-        let rows = stmt.query(&[&self.id])?;
+        let rows = conn.query("SELECT * FROM version_authors
+                                     WHERE version_id = $1
+                                     ORDER BY name ASC", &[&self.id])?;
         Ok(rows.into_iter().map(|row| {
             Author { name: row.get("name") }
         }).collect())
@@ -196,12 +280,6 @@ impl Version {
         Ok(())
     }
 
-    pub fn yank(&self, conn: &GenericConnection, yanked: bool) -> CargoResult<()> {
-        conn.execute("UPDATE versions SET yanked = $1 WHERE id = $2",
-                     &[&yanked, &self.id])?;
-        Ok(())
-    }
-
     pub fn max<T>(versions: T) -> semver::Version where
         T: IntoIterator<Item=semver::Version>,
     {
@@ -222,18 +300,25 @@ impl NewVersion {
         crate_id: i32,
         num: &semver::Version,
         features: &HashMap<String, Vec<String>>,
+        published_by: Option<i32>,
+        published_with_token_id: Option<i32>,
+        license: Option<String>,
     ) -> CargoResult<Self> {
         let features = json::encode(features)?;
         Ok(NewVersion {
             crate_id: crate_id,
             num: num.to_string(),
             features: features,
+            published_by: published_by,
+            published_with_token_id: published_with_token_id,
+            license: license,
         })
     }
 
-    pub fn save(&self, conn: &PgConnection, authors: &[String]) -> CargoResult<Version> {
+    pub fn save(&self, conn: &InstrumentedConnection, authors: &[String]) -> CargoResult<Version> {
         use diesel::{select, insert};
         use diesel::expression::dsl::exists;
+        use diesel::pg::upsert::*;
         use schema::versions::dsl::*;
 
         let already_uploaded = versions.filter(crate_id.eq(self.crate_id))
@@ -247,6 +332,26 @@ impl NewVersion {
             let version = insert(self).into(versions)
                 .get_result::<Version>(conn)?;
 
+            // Every version number ever published is remembered, even if the
+            // version itself is later removed, so that it can never be reused
+            // for different contents.
+            let crate_name = crates::table.find(self.crate_id)
+                .select(crates::name)
+                .first::<String>(conn)?;
+            let published = PublishedVersion {
+                crate_name: &crate_name,
+                num: &self.num,
+            };
+            let recorded = insert(&published.on_conflict_do_nothing())
+                .into(versions_published::table)
+                .execute(conn)?;
+            if recorded == 0 {
+                return Err(human_with_code("version_previously_published",
+                    &format_args!("crate version `{}` was published before and \
+                                   can never be published again, please bump \
+                                   the version number", self.num)));
+            }
+
             let new_authors = authors.iter().map(|s| NewAuthor {
                 version_id: version.id,
                 name: &*s,
@@ -254,11 +359,19 @@ impl NewVersion {
 
             insert(&new_authors).into(version_authors::table)
                 .execute(conn)?;
+            update_all_yanked(conn, self.crate_id)?;
             Ok(version)
         })
     }
 }
 
+#[derive(Insertable)]
+#[table_name="versions_published"]
+struct PublishedVersion<'a> {
+    crate_name: &'a str,
+    num: &'a str,
+}
+
 #[derive(Insertable)]
 #[table_name="version_authors"]
 struct NewAuthor<'a> {
@@ -266,8 +379,16 @@ struct NewAuthor<'a> {
     name: &'a str,
 }
 
+#[derive(Insertable)]
+#[table_name="version_yanks"]
+struct NewVersionYank {
+    version_id: i32,
+    yanked: bool,
+}
+
 impl Queryable<versions::SqlType, Pg> for Version {
-    type Row = (i32, i32, String, Timespec, Timespec, i32, Option<String>, bool);
+    type Row = (i32, i32, String, Timespec, Timespec, i32, Option<String>, bool,
+                Option<i32>, Option<i32>, bool, bool, Option<String>);
 
     fn build(row: Self::Row) -> Self {
         let features = row.6.map(|s| {
@@ -282,6 +403,11 @@ impl Queryable<versions::SqlType, Pg> for Version {
             downloads: row.5,
             features: features,
             yanked: row.7,
+            published_by: row.8,
+            published_with_token_id: row.9,
+            quarantined: row.10,
+            verified_build: row.11,
+            license: row.12,
         }
     }
 }
@@ -302,6 +428,11 @@ impl Model for Version {
             downloads: row.get("downloads"),
             features: features,
             yanked: row.get("yanked"),
+            published_by: row.get("published_by"),
+            published_with_token_id: row.get("published_with_token_id"),
+            quarantined: row.get("quarantined"),
+            verified_build: row.get("verified_build"),
+            license: row.get("license"),
         }
     }
     fn table_name(_: Option<Version>) -> &'static str { "versions" }
@@ -327,16 +458,18 @@ pub fn index(req: &mut Request) -> CargoResult<Response> {
     // TODO: can rust-postgres do this for us?
     let mut versions = Vec::new();
     if !ids.is_empty() {
-        let stmt = conn.prepare("\
+        let rows = conn.query("\
             SELECT versions.*, crates.name AS crate_name
               FROM versions
             LEFT JOIN crates ON crates.id = versions.crate_id
             WHERE versions.id = ANY($1)
-        ")?;
-        for row in stmt.query(&[&ids])?.iter() {
+        ", &[&ids])?;
+        for row in rows.iter() {
             let v: Version = Model::from_row(&row);
             let crate_name: String = row.get("crate_name");
-            versions.push(v.encodable(&crate_name));
+            if permission::can_read(req, &crate_name)? {
+                versions.push(v.encodable(&crate_name));
+            }
         }
     }
 
@@ -359,6 +492,7 @@ pub fn show(req: &mut Request) -> CargoResult<Response> {
                 .first(&*conn)?
         }
     };
+    permission::ensure_readable(req, &krate.name)?;
 
     #[derive(RustcEncodable)]
     struct R { version: EncodableVersion }
@@ -371,6 +505,7 @@ fn version_and_crate_old(req: &mut Request) -> CargoResult<(Version, Crate)> {
     let semver = semver::Version::parse(semver).map_err(|_| {
         human(&format_args!("invalid semver: {}", semver))
     })?;
+    permission::ensure_readable(req, crate_name)?;
     let tx = req.tx()?;
     let krate = Crate::find_by_name(tx, crate_name)?;
     let version = Version::find_by_num(tx, krate.id, &semver)?;
@@ -381,20 +516,24 @@ fn version_and_crate_old(req: &mut Request) -> CargoResult<(Version, Crate)> {
     Ok((version, krate))
 }
 
-fn version_and_crate(req: &mut Request) -> CargoResult<(Version, Crate)> {
+/// The version and the crate named in the request's `crate_id` and `version`
+/// parameters, if the crate is readable by the user.
+pub(crate) fn version_and_crate(req: &mut Request) -> CargoResult<(Version, Crate)> {
     let crate_name = &req.params()["crate_id"];
     let semver = &req.params()["version"];
     if semver::Version::parse(semver).is_err() {
         return Err(human(&format_args!("invalid semver: {}", semver)));
     };
+    permission::ensure_readable(req, crate_name)?;
     let conn = req.db_conn()?;
     let krate = Crate::by_name(crate_name).first::<Crate>(&*conn)?;
     let version = Version::belonging_to(&krate)
         .filter(versions::num.eq(semver))
-        .first(&*conn)
-        .map_err(|_| {
+        .first::<Version>(&*conn)
+        .optional()?
+        .ok_or_else(|| {
             human(&format_args!("crate `{}` does not have a version `{}`",
-                          crate_name, semver))
+                                crate_name, semver))
         })?;
     Ok((version, krate))
 }
@@ -413,7 +552,62 @@ pub fn dependencies(req: &mut Request) -> CargoResult<Response> {
     Ok(req.json(&R{ dependencies: deps }))
 }
 
+/// Handles the `GET /crates/:crate_id/:version/outdated` route.
+///
+/// Compares the requirement of each dependency with the newest release of
+/// the dependency which isn't yanked, prereleases only counting for crates
+/// which haven't had any other release.
+pub fn outdated(req: &mut Request) -> CargoResult<Response> {
+    let (version, _) = version_and_crate_old(req)?;
+    let tx = req.tx()?;
+    let deps = version.dependencies(tx)?;
+    let crate_ids = deps.iter().map(|&(ref dep, _)| dep.crate_id).collect::<Vec<_>>();
+    let rows = tx.query("SELECT crate_id, num FROM versions
+                          WHERE crate_id = ANY($1)
+                            AND NOT yanked
+                            AND NOT quarantined", &[&crate_ids])?;
+    let mut releases = HashMap::new();
+    for row in rows.iter() {
+        let num = semver::Version::parse(&row.get::<_, String>("num")).unwrap();
+        releases.entry(row.get::<_, i32>("crate_id")).or_insert_with(Vec::new).push(num);
+    }
+
+    let mut outdated = 0;
+    let dependencies = deps.into_iter().map(|(dep, crate_name)| {
+        let latest = releases.remove(&dep.crate_id).and_then(latest_release);
+        let is_outdated = latest.as_ref().map(|v| !dep.req.matches(v)).unwrap_or(false);
+        if is_outdated {
+            outdated += 1;
+        }
+        EncodableDependencyFreshness {
+            crate_id: crate_name,
+            req: dep.req.to_string(),
+            kind: dep.kind,
+            optional: dep.optional,
+            latest: latest.map(|v| v.to_string()),
+            outdated: is_outdated,
+        }
+    }).collect::<Vec<_>>();
+
+    #[derive(RustcEncodable)]
+    struct R { dependencies: Vec<EncodableDependencyFreshness>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta { outdated: i64 }
+    Ok(req.json(&R { dependencies: dependencies, meta: Meta { outdated: outdated } }))
+}
+
+/// The newest of a crate's releases, preferring ones which aren't
+/// prereleases.
+fn latest_release(versions: Vec<semver::Version>) -> Option<semver::Version> {
+    let stable = versions.iter().filter(|v| !v.is_prerelease()).max().cloned();
+    stable.or_else(|| versions.into_iter().max())
+}
+
 /// Handles the `GET /crates/:crate_id/:version/downloads` route.
+///
+/// A client's repeated downloads of the version within
+/// `meta.dedupe_window_secs` of the last one counted are counted once, see
+/// `download::RecentDownloads`.
 pub fn downloads(req: &mut Request) -> CargoResult<Response> {
     let (version, _) = version_and_crate_old(req)?;
     let cutoff_end_date = req.query().get("before_date")
@@ -422,15 +616,18 @@ pub fn downloads(req: &mut Request) -> CargoResult<Response> {
     let cutoff_start_date = cutoff_end_date + Duration::days(-89);
 
     let tx = req.tx()?;
-    let stmt = tx.prepare("SELECT * FROM version_downloads
-                                WHERE date BETWEEN date($1) AND date($2) AND version_id = $3
-                                ORDER BY date ASC")?;
-    let downloads = stmt.query(&[&cutoff_start_date, &cutoff_end_date, &version.id])?
+    let downloads = tx.query("SELECT * FROM version_downloads
+                                   WHERE date BETWEEN date($1) AND date($2) AND version_id = $3
+                                   ORDER BY date ASC",
+                             &[&cutoff_start_date, &cutoff_end_date, &version.id])?
         .iter().map(|row| VersionDownload::from_row(&row).encodable()).collect();
 
     #[derive(RustcEncodable)]
-    struct R { version_downloads: Vec<EncodableVersionDownload> }
-    Ok(req.json(&R{ version_downloads: downloads }))
+    struct R { version_downloads: Vec<EncodableVersionDownload>, meta: Meta }
+    #[derive(RustcEncodable)]
+    struct Meta { dedupe_window_secs: u64 }
+    let meta = Meta { dedupe_window_secs: req.app().config.download_dedupe_secs };
+    Ok(req.json(&R{ version_downloads: downloads, meta: meta }))
 }
 
 /// Handles the `GET /crates/:crate_id/:version/authors` route.
@@ -449,6 +646,34 @@ pub fn authors(req: &mut Request) -> CargoResult<Response> {
     Ok(req.json(&R{ users: vec![], meta: Meta { names: names } }))
 }
 
+/// Keeps `crates.all_yanked` in sync after a version of the crate has been
+/// published, yanked or unyanked. The crate row is only touched when the flag
+/// actually changes, so that its `updated_at` isn't bumped needlessly.
+pub fn update_all_yanked(conn: &InstrumentedConnection, crate_id: i32) -> CargoResult<()> {
+    use diesel::expression::dsl::exists;
+
+    let available = versions::table
+        .filter(versions::crate_id.eq(crate_id))
+        .filter(versions::yanked.eq(false));
+    let any_available = diesel::select(exists(available)).get_result::<bool>(conn)?;
+    diesel::update(crates::table.find(crate_id).filter(crates::all_yanked.eq(any_available)))
+        .set(crates::all_yanked.eq(!any_available))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Records that the versions were yanked or unyanked, which is what crates
+/// are replayed from to show them as they were at some point in the past.
+pub fn record_yanks(conn: &InstrumentedConnection,
+                    version_ids: &[i32],
+                    yanked: bool) -> CargoResult<()> {
+    let yanks = version_ids.iter().map(|&version_id| {
+        NewVersionYank { version_id: version_id, yanked: yanked }
+    }).collect::<Vec<_>>();
+    diesel::insert(&yanks).into(version_yanks::table).execute(conn)?;
+    Ok(())
+}
+
 /// Handles the `DELETE /crates/:crate_id/:version/yank` route.
 pub fn yank(req: &mut Request) -> CargoResult<Response> {
     modify_yank(req, true)
@@ -461,17 +686,34 @@ pub fn unyank(req: &mut Request) -> CargoResult<Response> {
 
 fn modify_yank(req: &mut Request, yanked: bool) -> CargoResult<Response> {
     let (version, krate) = version_and_crate(req)?;
-    let user = req.user()?;
     let conn = req.db_conn()?;
     let owners = krate.owners(&conn)?;
-    if rights(req.app(), &owners, user)? < Rights::Publish {
+    if request_rights(req, &conn, &owners)? < Rights::Publish {
+        let collaborators = krate.collaborators(&conn)?;
+        if request_rights(req, &conn, &collaborators)? >= Rights::Publish {
+            return Err(human("collaborators can publish new versions but \
+                              can't yank or unyank them"))
+        }
         return Err(human("must already be an owner to yank or unyank"))
     }
 
+    if version.quarantined {
+        return Err(human("this version is held for review by the registry admins"))
+    }
+
     if version.yanked != yanked {
+        let user = req.publisher()?;
         conn.transaction::<_, Box<CargoError>, _>(|| {
+            let dependencies_before = dependency::newest_dependencies(&conn, krate.id)?;
             diesel::update(&version).set(versions::yanked.eq(yanked))
                 .execute(&*conn)?;
+            record_yanks(&conn, &[version.id], yanked)?;
+            update_all_yanked(&conn, krate.id)?;
+            let dependencies_after = dependency::newest_dependencies(&conn, krate.id)?;
+            dependency::update_dependents_cnt(&conn, &dependencies_before,
+                                              &dependencies_after)?;
+            let action = if yanked { "yank_version" } else { "unyank_version" };
+            audit::record(&conn, user.id, action, Some(&krate.name), &version.num)?;
             git::yank(&**req.app(), &krate.name, &version.num, yanked)?;
             Ok(())
         })?;
@@ -481,3 +723,187 @@ fn modify_yank(req: &mut Request, yanked: bool) -> CargoResult<Response> {
     struct R { ok: bool }
     Ok(req.json(&R{ ok: true }))
 }
+
+/// Handles the `DELETE /crates/:crate_id/yank` route.
+///
+/// Yanks every version of the crate matching a semver range at once, e.g.
+/// all the ones affected by a security issue, with a single index commit and
+/// a single `yank_versions` entry in the audit log recording the range.
+/// Versions which are already yanked or held for review are left alone, and
+/// as with cargo, prereleases only match ranges which mention a prerelease.
+/// Admins can yank the versions of any crate.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// { "range": "< 0.9.3" }
+/// ```
+pub fn yank_range(req: &mut Request) -> CargoResult<Response> {
+    use diesel::expression::dsl::any;
+
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request {
+        range: String,
+    }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    let range = request.range.trim();
+    let version_req = semver::VersionReq::parse(range).map_err(|_| {
+        human(&format_args!("`{}` is not a valid semver range", range))
+    })?;
+
+    let user = req.publisher()?;
+    let conn = req.db_conn()?;
+    let krate = Crate::by_name(&req.params()["crate_id"]).first::<Crate>(&*conn)?;
+    let owners = krate.owners(&conn)?;
+    // Admins can't yank through an organization's token
+    let is_admin = req.user().map(|user| admin::is_admin(req.app(), user)).unwrap_or(false);
+    if request_rights(req, &conn, &owners)? < Rights::Publish && !is_admin {
+        let collaborators = krate.collaborators(&conn)?;
+        if request_rights(req, &conn, &collaborators)? >= Rights::Publish {
+            return Err(human("collaborators can publish new versions but \
+                              can't yank or unyank them"))
+        }
+        return Err(human("must already be an owner to yank"))
+    }
+
+    let mut matching = Version::belonging_to(&krate)
+        .filter(versions::yanked.eq(false))
+        .filter(versions::quarantined.eq(false))
+        .load::<Version>(&*conn)?
+        .into_iter()
+        .filter(|version| version_req.matches(&version.num))
+        .collect::<Vec<_>>();
+    matching.sort_by(|a, b| a.num.cmp(&b.num));
+    let ids = matching.iter().map(|version| version.id).collect::<Vec<_>>();
+    let nums = matching.iter().map(|version| version.num.to_string()).collect::<Vec<_>>();
+
+    if !matching.is_empty() {
+        conn.transaction::<_, Box<CargoError>, _>(|| {
+            let dependencies_before = dependency::newest_dependencies(&conn, krate.id)?;
+            diesel::update(versions::table.filter(versions::id.eq(any(ids.clone()))))
+                .set(versions::yanked.eq(true))
+                .execute(&*conn)?;
+            record_yanks(&conn, &ids, true)?;
+            update_all_yanked(&conn, krate.id)?;
+            let dependencies_after = dependency::newest_dependencies(&conn, krate.id)?;
+            dependency::update_dependents_cnt(&conn, &dependencies_before,
+                                              &dependencies_after)?;
+            let details = format!("{}: {}", range, nums.join(", "));
+            audit::record(&conn, user.id, "yank_versions", Some(&krate.name), &details)?;
+            git::yank_all(&**req.app(), &krate.name, &nums, range)?;
+            Ok(())
+        })?;
+    }
+
+    #[derive(RustcEncodable)]
+    struct R { ok: bool, yanked: Vec<String> }
+    Ok(req.json(&R { ok: true, yanked: nums }))
+}
+
+/// Handles the `PATCH /crates/:crate_id/:version` route.
+///
+/// Lets owners correct the metadata which came with the crate's newest
+/// version without publishing another one. The crate file and its checksum
+/// are never changed. Fields which aren't in the body are left alone, and an
+/// empty `documentation` removes it. With `render_readme` the version's
+/// README is rendered again from its crate file.
+///
+/// ## Request Body Example
+///
+/// ```json
+/// {
+///     "documentation": "https://docs.rs/foo",
+///     "render_readme": true,
+///     "categories": ["parsing"]
+/// }
+/// ```
+pub fn update_metadata(req: &mut Request) -> CargoResult<Response> {
+    let mut body = String::new();
+    req.body().read_to_string(&mut body)?;
+    #[derive(RustcDecodable)]
+    struct Request {
+        documentation: Option<String>,
+        render_readme: Option<bool>,
+        categories: Option<Vec<String>>,
+    }
+    let request: Request = json::decode(&body).map_err(|_| {
+        human("invalid json request")
+    })?;
+    fn non_empty(s: Option<String>) -> Option<Option<String>> {
+        s.map(|s| if s.trim().is_empty() { None } else { Some(s) })
+    }
+    let documentation = non_empty(request.documentation);
+    if let Some(Some(ref url)) = documentation {
+        validate_url(Some(url), "documentation")?;
+    }
+
+    let (version, krate) = version_and_crate(req)?;
+    let conn = req.db_conn()?;
+    let owners = krate.owners(&conn)?;
+    if request_rights(req, &conn, &owners)? < Rights::Publish {
+        return Err(human("must already be an owner to change a version's metadata"))
+    }
+    // The crate only keeps the metadata of its newest version
+    let newest = krate.max_version(&conn)?;
+    if version.yanked || version.num != newest {
+        return Err(human(&format_args!("only the metadata of the newest version of `{}`, \
+                                        {}, can be changed", krate.name, newest)))
+    }
+
+    let files = if request.render_readme == Some(true) {
+        let app = req.app().clone();
+        let tarball = app.config.uploader.fetch(&mut app.handle(), &krate.name,
+                                                &version.num.to_string())?;
+        Some(util::unpack(&tarball)?)
+    } else {
+        None
+    };
+
+    let user = req.user()?;
+    let mut changed = Vec::new();
+    let invalid_categories = conn.transaction(|| -> CargoResult<Vec<String>> {
+        if let Some(ref documentation) = documentation {
+            diesel::update(&krate).set(crates::documentation.eq(documentation.clone()))
+                .execute(&*conn)?;
+            changed.push("documentation");
+        }
+        if let Some(ref files) = files {
+            if !readme::render_version(&conn, version.id, files)? {
+                return Err(human(&format_args!("version {} of `{}` has no README which \
+                                                could be rendered", version.num, krate.name)))
+            }
+            changed.push("readme");
+        }
+        let mut invalid = Vec::new();
+        if let Some(ref categories) = request.categories {
+            let categories = categories.iter().map(|s| &s[..]).collect::<Vec<_>>();
+            let (keywords, _) = tag_history::current(&conn, &krate)?;
+            let keywords = keywords.iter().map(|s| &s[..]).collect::<Vec<_>>();
+            tag_history::record(&conn, &krate, &keywords, &categories, None, user.id)?;
+            invalid = Category::update_crate(&conn, &krate, &categories)?
+                .into_iter()
+                .map(|s| s.to_string())
+                .collect();
+            changed.push("categories");
+        }
+        if !changed.is_empty() {
+            let details = format!("{}: changed {}", version.num, changed.join(", "));
+            audit::record(&conn, user.id, "update_version_metadata", Some(&krate.name),
+                          &details)?;
+        }
+        Ok(invalid)
+    })?;
+
+    #[derive(RustcEncodable)]
+    struct R {
+        ok: bool,
+        changed: Vec<&'static str>,
+        /// Categories which aren't category slugs and were ignored
+        invalid_categories: Vec<String>,
+    }
+    Ok(req.json(&R { ok: true, changed: changed, invalid_categories: invalid_categories }))
+}